@@ -0,0 +1,203 @@
+use anyhow::Result;
+use tokio_stream::Stream;
+use tonic::transport::Channel;
+use tonic::{Request, Status, Streaming};
+
+use crate::md5rs::md5rs_client::Md5rsClient;
+use crate::md5rs::{
+    AuthRequest, AuthResponse, DetectRequest, DetectResponse, HealthRequest, HealthResponse,
+};
+use crate::{channel, is_transient, Backoff, ConnectOptions, StreamCompression};
+
+/// Version of `proto/md5rs.proto`, bumped whenever a message changes in a way an older
+/// peer can't decode.
+pub const PROTO_VERSION: u32 = 2;
+/// Request metadata sent with every call so the server can refuse outdated clients.
+pub const PROTO_VERSION_HEADER: &str = "x-md5rs-proto-version";
+
+/// A connection to an md5rs server. Every call carries the protocol version and the
+/// metadata added with [`Client::with_metadata`].
+#[derive(Debug, Clone)]
+pub struct Client {
+    url: String,
+    options: ConnectOptions,
+    compression: StreamCompression,
+    inner: Md5rsClient<Channel>,
+    metadata: Vec<(&'static str, String)>,
+}
+
+impl Client {
+    pub async fn connect(
+        url: &str,
+        options: &ConnectOptions,
+        compression: StreamCompression,
+    ) -> Result<Self> {
+        let channel = channel(url, options).await?;
+        Ok(Self::with_channel(url, options, compression, channel))
+    }
+
+    fn with_channel(
+        url: &str,
+        options: &ConnectOptions,
+        compression: StreamCompression,
+        channel: Channel,
+    ) -> Self {
+        Self {
+            url: url.to_string(),
+            options: options.clone(),
+            compression,
+            inner: compression.apply(Md5rsClient::new(channel)),
+            metadata: vec![(PROTO_VERSION_HEADER, PROTO_VERSION.to_string())],
+        }
+    }
+
+    /// Sends `value` as `key` with every call, such as the version of the calling app.
+    pub fn with_metadata(mut self, key: &'static str, value: impl Into<String>) -> Self {
+        self.metadata.push((key, value.into()));
+        self
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Connects to the same server anew, keeping the metadata.
+    pub async fn reconnect(&mut self) -> Result<()> {
+        let channel = channel(&self.url, &self.options).await?;
+        self.inner = self.compression.apply(Md5rsClient::new(channel));
+        Ok(())
+    }
+
+    fn request<T>(&self, message: T) -> Request<T> {
+        let mut request = Request::new(message);
+        for (key, value) in &self.metadata {
+            match value.parse() {
+                Ok(value) => {
+                    request.metadata_mut().insert(*key, value);
+                }
+                Err(_) => log::warn!("Leaving out metadata {} that isn't a valid header", key),
+            }
+        }
+        request
+    }
+
+    /// The server's status with the versions and limits it advertises.
+    pub async fn health(&mut self) -> Result<HealthResponse> {
+        let request = self.request(HealthRequest {});
+        let response = self.inner.health(request).await?.into_inner();
+        if response.status {
+            Ok(response)
+        } else {
+            log::error!("Health check failed");
+            Err(anyhow::anyhow!("Check failed"))
+        }
+    }
+
+    /// Authenticates an access token, answering with the session token and the quota left.
+    pub async fn auth(&mut self, token: &str) -> Result<AuthResponse> {
+        let request = self.request(AuthRequest {
+            token: token.to_string(),
+        });
+        let response = self.inner.auth(request).await?.into_inner();
+        if response.success {
+            Ok(response)
+        } else {
+            Err(anyhow::anyhow!("Auth failed"))
+        }
+    }
+
+    /// Streams `requests` with `session`, the token [`Client::auth`] answered with. The
+    /// status is passed on as it is, so a spent quota or an expired session can be told
+    /// apart from a dropped connection.
+    pub async fn detect<S>(
+        &mut self,
+        requests: S,
+        session: &str,
+    ) -> std::result::Result<Streaming<DetectResponse>, Status>
+    where
+        S: Stream<Item = DetectRequest> + Send + 'static,
+    {
+        let session = session
+            .parse()
+            .map_err(|_| Status::invalid_argument("Invalid session token"))?;
+        let mut request = self.request(requests);
+        request.metadata_mut().insert("authorization", session);
+        Ok(self.inner.detect(request).await?.into_inner())
+    }
+
+    /// Authenticates `token` and opens the detect stream, connecting again after every
+    /// dropped connection as long as `backoff` allows. `requests` makes the requests of
+    /// each try. Answers with the stream and the session it was opened with.
+    pub async fn detect_stream<S, F>(
+        &mut self,
+        token: &str,
+        mut requests: F,
+        backoff: &mut Backoff,
+    ) -> Result<(Streaming<DetectResponse>, String)>
+    where
+        F: FnMut() -> S,
+        S: Stream<Item = DetectRequest> + Send + 'static,
+    {
+        let mut reconnect = false;
+        loop {
+            let opened = async {
+                if reconnect {
+                    self.reconnect().await?;
+                }
+                let session = self.auth(token).await?.token;
+                let stream = self.detect(requests(), &session).await?;
+                anyhow::Ok((stream, session))
+            };
+            let error = match opened.await {
+                Ok(opened) => {
+                    backoff.reset();
+                    return Ok(opened);
+                }
+                Err(e) => e,
+            };
+            // refused tokens and requests don't get any further on a new connection
+            let transient = match error.downcast_ref::<Status>() {
+                Some(status) => is_transient(status),
+                None => error.downcast_ref::<tonic::transport::Error>().is_some(),
+            };
+            let Some(delay) = backoff.next_delay().filter(|_| transient) else {
+                return Err(error);
+            };
+            log::warn!(
+                "Failed to stream to {}, reconnecting in {:?}: {}",
+                self.url,
+                delay,
+                error
+            );
+            tokio::time::sleep(delay).await;
+            reconnect = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_request_metadata() {
+        let channel = Channel::from_static("http://127.0.0.1:50051").connect_lazy();
+        let client = Client::with_channel(
+            "http://127.0.0.1:50051",
+            &ConnectOptions::default(),
+            StreamCompression::Gzip,
+            channel,
+        )
+        .with_metadata("x-megascops-version", "0.2.1")
+        .with_metadata("x-note", "two\nlines");
+        let request = client.request(());
+        let metadata = request.metadata();
+        assert_eq!(
+            metadata.get(PROTO_VERSION_HEADER).unwrap(),
+            &PROTO_VERSION.to_string()
+        );
+        assert_eq!(metadata.get("x-megascops-version").unwrap(), "0.2.1");
+        assert!(metadata.get("x-note").is_none());
+        assert_eq!(client.url(), "http://127.0.0.1:50051");
+    }
+}
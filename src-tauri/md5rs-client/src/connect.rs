@@ -0,0 +1,128 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tonic::codec::CompressionEncoding;
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Identity};
+use url::Url;
+
+use crate::md5rs::md5rs_client::Md5rsClient;
+use crate::{proxy, tls};
+
+/// How the channel to the server is set up, beside its address.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ConnectOptions {
+    /// `http://` or `socks5://` proxy to reach the server through, `direct` for none.
+    /// Unset, the proxy environment variables apply.
+    pub proxy: Option<String>,
+    /// PEM certificate the client authenticates with, for servers requiring mutual TLS.
+    pub client_cert: Option<String>,
+    /// PEM private key of `client_cert`.
+    pub client_key: Option<String>,
+}
+
+impl ConnectOptions {
+    /// Client certificate and key read from their files, `None` without a certificate.
+    fn identity(&self) -> Result<Option<Identity>> {
+        let path = |p: &Option<String>| {
+            p.as_deref()
+                .map(str::trim)
+                .filter(|p| !p.is_empty())
+                .map(str::to_string)
+        };
+        match (path(&self.client_cert), path(&self.client_key)) {
+            (None, None) => Ok(None),
+            (Some(cert), Some(key)) => {
+                let cert = std::fs::read(&cert)
+                    .with_context(|| format!("Failed to read client certificate {}", cert))?;
+                let key = std::fs::read(&key)
+                    .with_context(|| format!("Failed to read client key {}", key))?;
+                Ok(Some(Identity::from_pem(cert, key)))
+            }
+            _ => Err(anyhow::anyhow!(
+                "Client certificate and key have to be given together"
+            )),
+        }
+    }
+}
+
+/// gRPC compression of the frames uploaded and the responses, for slow links. Costs CPU
+/// time on both ends, servers without it refuse the calls.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+pub enum StreamCompression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl StreamCompression {
+    pub(crate) fn apply(self, client: Md5rsClient<Channel>) -> Md5rsClient<Channel> {
+        let encoding = match self {
+            StreamCompression::None => return client,
+            StreamCompression::Gzip => CompressionEncoding::Gzip,
+            StreamCompression::Zstd => CompressionEncoding::Zstd,
+        };
+        client.send_compressed(encoding).accept_compressed(encoding)
+    }
+}
+
+/// Connects to the server at `grpc_url`, through the configured proxy or the one of the
+/// environment when one applies.
+pub async fn channel(grpc_url: &str, connect: &ConnectOptions) -> Result<Channel> {
+    let url = Url::parse(grpc_url)?;
+    let proxy = proxy::proxy_for(&url, connect.proxy.as_deref())?;
+    let identity = connect.identity()?;
+
+    let mut channel_builder = Channel::from_shared(url.to_string()).context("Invalid URL")?;
+
+    // TLS only applies to https
+    if url.scheme() == "https" {
+        let host = url
+            .host_str()
+            .ok_or_else(|| anyhow::anyhow!("Missing host in URL"))?;
+
+        // the certificate the server presents is trusted, IP addresses still need SNI
+        let pem = tls::get_tls_certificate(grpc_url, proxy.as_ref())?;
+        let ca = Certificate::from_pem(pem);
+        let mut tls = ClientTlsConfig::new().ca_certificate(ca).domain_name(host);
+        // servers requiring mutual TLS check the client certificate
+        if let Some(identity) = identity {
+            tls = tls.identity(identity);
+        }
+
+        channel_builder = channel_builder
+            .tls_config(tls)
+            .context("Failed to configure TLS")?;
+    }
+
+    let Some(proxy) = proxy else {
+        return channel_builder
+            .connect()
+            .await
+            .context("Failed to connect to server");
+    };
+    let host = url
+        .host_str()
+        .ok_or_else(|| anyhow::anyhow!("Missing host in URL"))?
+        .trim_matches(['[', ']'])
+        .to_string();
+    let port = url.port_or_known_default().unwrap_or(443);
+    log::info!("Connecting through proxy {}:{}", proxy.host, proxy.port);
+    // every connection is tunneled through the proxy, tonic adds TLS on top
+    let connector = tower::service_fn(move |_: tonic::transport::Uri| {
+        let (proxy, host) = (proxy.clone(), host.clone());
+        async move {
+            let stream = tokio::task::spawn_blocking(move || proxy.connect(&host, port))
+                .await
+                .map_err(std::io::Error::other)?
+                .map_err(std::io::Error::other)?;
+            stream.set_nonblocking(true)?;
+            let stream = tokio::net::TcpStream::from_std(stream)?;
+            Ok::<_, std::io::Error>(hyper_util::rt::TokioIo::new(stream))
+        }
+    });
+    channel_builder
+        .connect_with_connector(connector)
+        .await
+        .context("Failed to connect to server")
+}
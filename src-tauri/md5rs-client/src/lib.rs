@@ -0,0 +1,17 @@
+//! Client for md5rs detection servers: connecting through proxies and TLS, authenticating
+//! access tokens and streaming frames, reconnecting when the connection drops.
+
+pub mod md5rs {
+    tonic::include_proto!("md5rs");
+}
+
+mod client;
+mod connect;
+pub mod proxy;
+mod retry;
+mod tls;
+
+pub use client::{Client, PROTO_VERSION, PROTO_VERSION_HEADER};
+pub use connect::{channel, ConnectOptions, StreamCompression};
+pub use retry::{is_transient, Backoff, INITIAL_BACKOFF, MAX_BACKOFF};
+pub use tls::get_tls_certificate;
@@ -0,0 +1,82 @@
+use std::time::Duration;
+
+/// Wait before the first reconnect after a stream error.
+pub const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+/// Longest wait between two reconnects.
+pub const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Waits between reconnects to a failing server, twice as long after every failure.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    retries: u32,
+    max_retries: u32,
+    initial: Duration,
+    max: Duration,
+}
+
+impl Backoff {
+    pub fn new(max_retries: u32, initial: Duration, max: Duration) -> Self {
+        Self {
+            retries: 0,
+            max_retries,
+            initial,
+            max,
+        }
+    }
+
+    /// Wait before the next reconnect, `None` once the retries are used up.
+    pub fn next_delay(&mut self) -> Option<Duration> {
+        if self.retries >= self.max_retries {
+            return None;
+        }
+        let delay = self
+            .initial
+            .saturating_mul(2u32.saturating_pow(self.retries))
+            .min(self.max);
+        self.retries += 1;
+        Some(delay)
+    }
+
+    pub fn retries(&self) -> u32 {
+        self.retries
+    }
+
+    /// Gives every retry back once the server answers again.
+    pub fn reset(&mut self) {
+        self.retries = 0;
+    }
+}
+
+/// Stream errors that a new connection may get past, such as a dropped connection or a
+/// restarting server.
+pub fn is_transient(status: &tonic::Status) -> bool {
+    matches!(
+        status.code(),
+        tonic::Code::Unavailable
+            | tonic::Code::Unknown
+            | tonic::Code::Internal
+            | tonic::Code::Aborted
+            | tonic::Code::DeadlineExceeded
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let mut backoff = Backoff::new(4, Duration::from_secs(1), Duration::from_secs(5));
+        let delays: Vec<u64> = std::iter::from_fn(|| backoff.next_delay())
+            .map(|d| d.as_secs())
+            .collect();
+        assert_eq!(delays, [1, 2, 4, 5]);
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), Some(Duration::from_secs(1)));
+        assert!(Backoff::new(0, INITIAL_BACKOFF, MAX_BACKOFF)
+            .next_delay()
+            .is_none());
+        assert!(is_transient(&tonic::Status::unavailable("restarting")));
+        assert!(!is_transient(&tonic::Status::unauthenticated("expired")));
+    }
+}
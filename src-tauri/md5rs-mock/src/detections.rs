@@ -0,0 +1,213 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use md5rs_client::md5rs::{Bbox, DetectRequest, DetectResponse};
+
+/// Classes of MegaDetector, in the order of their ids.
+pub const CLASS_NAMES: [&str; 3] = ["Animal", "Person", "Vehicle"];
+/// Length of the embeddings made up for clients asking for them.
+pub const EMBEDDING_DIM: usize = 128;
+
+/// A box of a canned frame, in normalized frame coordinates.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct CannedBbox {
+    pub x1: f32,
+    pub y1: f32,
+    pub x2: f32,
+    pub y2: f32,
+    pub class: i32,
+    pub score: f32,
+}
+
+/// The boxes answered for one frame, none for a blank.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct CannedFrame {
+    pub bboxs: Vec<CannedBbox>,
+}
+
+/// Where the answered boxes come from.
+#[derive(Debug, Clone)]
+pub enum Detections {
+    /// Boxes of random classes at random places. A `seed` answers a run the same way
+    /// every time.
+    Random { seed: u64, blank_ratio: f32 },
+    /// The frames of a file, in turn and from the start again after the last.
+    Canned(Vec<CannedFrame>),
+}
+
+impl Detections {
+    /// Canned frames from a JSON array such as
+    /// `[{"bboxs": [{"x1": 0.1, "y1": 0.2, "x2": 0.4, "y2": 0.6, "class": 0, "score": 0.9}]}, {}]`.
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let frames: Vec<CannedFrame> = serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        if frames.is_empty() {
+            return Err(anyhow::anyhow!("{} has no frames", path.display()));
+        }
+        Ok(Self::Canned(frames))
+    }
+}
+
+/// Answers frames one after the other as [`Detections`] has them.
+#[derive(Debug)]
+pub struct Detector {
+    detections: Detections,
+    rng: fastrand::Rng,
+    answered: usize,
+}
+
+impl Detector {
+    pub fn new(detections: Detections) -> Self {
+        let rng = match &detections {
+            Detections::Random { seed, .. } => fastrand::Rng::with_seed(*seed),
+            Detections::Canned(_) => fastrand::Rng::new(),
+        };
+        Self {
+            detections,
+            rng,
+            answered: 0,
+        }
+    }
+
+    fn random_bboxs(&mut self, blank_ratio: f32) -> Vec<CannedBbox> {
+        if self.rng.f32() < blank_ratio {
+            return Vec::new();
+        }
+        (0..self.rng.usize(1..=3))
+            .map(|_| {
+                let (x1, y1) = (self.rng.f32() * 0.8, self.rng.f32() * 0.8);
+                let (w, h) = (0.05 + self.rng.f32() * 0.15, 0.05 + self.rng.f32() * 0.15);
+                // mostly animals, as in camera trap data
+                let class = match self.rng.u8(0..10) {
+                    0 => 1,
+                    1 => 2,
+                    _ => 0,
+                };
+                CannedBbox {
+                    x1,
+                    y1,
+                    x2: x1 + w,
+                    y2: y1 + h,
+                    class,
+                    score: 0.1 + self.rng.f32() * 0.89,
+                }
+            })
+            .collect()
+    }
+
+    fn embedding(&mut self) -> Vec<f32> {
+        let embedding: Vec<f32> = (0..EMBEDDING_DIM).map(|_| self.rng.f32() - 0.5).collect();
+        let norm = embedding
+            .iter()
+            .map(|v| v * v)
+            .sum::<f32>()
+            .sqrt()
+            .max(f32::EPSILON);
+        embedding.into_iter().map(|v| v / norm).collect()
+    }
+
+    /// The answer to a single frame, its boxes below the score threshold of the request
+    /// left out.
+    pub fn detect(&mut self, request: &DetectRequest) -> DetectResponse {
+        let bboxs = match &self.detections {
+            Detections::Random { blank_ratio, .. } => self.random_bboxs(*blank_ratio),
+            Detections::Canned(frames) => frames[self.answered % frames.len()].bboxs.clone(),
+        };
+        self.answered += 1;
+        let bboxs: Vec<Bbox> = bboxs
+            .into_iter()
+            .filter(|b| b.score >= request.score)
+            .map(|b| Bbox {
+                x1: b.x1,
+                y1: b.y1,
+                x2: b.x2,
+                y2: b.y2,
+                class: b.class,
+                score: b.score,
+                embedding: if request.embeddings {
+                    self.embedding()
+                } else {
+                    Vec::new()
+                },
+            })
+            .collect();
+        let mut label: Vec<String> = Vec::new();
+        for bbox in &bboxs {
+            let name = CLASS_NAMES
+                .get(bbox.class as usize)
+                .map_or_else(|| format!("Class {}", bbox.class), |n| n.to_string());
+            if !label.contains(&name) {
+                label.push(name);
+            }
+        }
+        if label.is_empty() {
+            label.push("Blank".to_string());
+        }
+        DetectResponse {
+            uuid: request.uuid.clone(),
+            label,
+            bboxs,
+            iframe: request.iframe,
+            embedding: if request.embeddings {
+                self.embedding()
+            } else {
+                Vec::new()
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(uuid: &str, score: f32) -> DetectRequest {
+        DetectRequest {
+            uuid: uuid.to_string(),
+            score,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_detections() {
+        let random = Detections::Random {
+            seed: 7,
+            blank_ratio: 0.5,
+        };
+        let answers = |detections: Detections| {
+            let mut detector = Detector::new(detections);
+            (0..20)
+                .map(|i| detector.detect(&request(&i.to_string(), 0.0)))
+                .collect::<Vec<_>>()
+        };
+        let first = answers(random.clone());
+        assert_eq!(first, answers(random));
+        assert!(first.iter().any(|r| r.label == ["Blank"]));
+        assert!(first.iter().any(|r| !r.bboxs.is_empty()));
+        assert!(first
+            .iter()
+            .all(|r| r.bboxs.is_empty() == (r.label == ["Blank"])));
+
+        let path = std::env::temp_dir().join(format!("md5rs-mock-{}.json", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"[{"bboxs": [{"x1": 0.1, "y1": 0.2, "x2": 0.4, "y2": 0.6, "class": 1, "score": 0.5}]}, {}]"#,
+        )
+        .unwrap();
+        let mut detector = Detector::new(Detections::load(&path).unwrap());
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(detector.detect(&request("a", 0.2)).label, ["Person"]);
+        assert_eq!(detector.detect(&request("b", 0.2)).label, ["Blank"]);
+        let mut embedded = request("c", 0.6);
+        embedded.embeddings = true;
+        let response = detector.detect(&embedded);
+        assert!(response.bboxs.is_empty());
+        assert_eq!(response.embedding.len(), EMBEDDING_DIM);
+    }
+}
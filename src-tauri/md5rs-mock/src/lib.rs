@@ -0,0 +1,9 @@
+//! An md5rs server that answers with canned or random detections instead of running a
+//! model, so the app can be demoed and its pipeline tested end to end without a real
+//! detection server.
+
+mod detections;
+mod server;
+
+pub use detections::{CannedBbox, CannedFrame, Detections, Detector, CLASS_NAMES, EMBEDDING_DIM};
+pub use server::{MockOptions, MockServer};
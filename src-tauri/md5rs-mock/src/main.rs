@@ -0,0 +1,69 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::Result;
+use clap::Parser;
+
+use md5rs_mock::{Detections, MockOptions, MockServer};
+
+/// Serves canned or random detections over the md5rs protocol. Point Megascops at
+/// `http://<addr>` and use any access token.
+#[derive(Debug, Parser)]
+#[command(version)]
+struct Args {
+    /// Address to listen on.
+    #[arg(long, default_value = "127.0.0.1:50051")]
+    addr: SocketAddr,
+    /// JSON array of frames answered in turn instead of random boxes.
+    #[arg(long)]
+    canned: Option<PathBuf>,
+    /// Seed of the random boxes, the same seed answers a run the same way.
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+    /// Share of frames answered as blank by the random boxes.
+    #[arg(long, default_value_t = 0.3)]
+    blank_ratio: f32,
+    /// Frames each access token may send.
+    #[arg(long, default_value_t = 1_000_000)]
+    quota: i32,
+    /// Frames one request may pack, 0 to take no batches.
+    #[arg(long, default_value_t = 8)]
+    max_batch: u32,
+    /// Milliseconds each frame takes to answer.
+    #[arg(long, default_value_t = 0)]
+    delay_ms: u64,
+    /// Seconds after which sessions expire, 0 for never.
+    #[arg(long, default_value_t = 0)]
+    session_ttl: u64,
+    /// Frames answered before the server goes down and refuses every call.
+    #[arg(long)]
+    down_after: Option<u32>,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    let args = Args::parse();
+    let detections = match &args.canned {
+        Some(path) => Detections::load(path)?,
+        None => Detections::Random {
+            seed: args.seed,
+            blank_ratio: args.blank_ratio,
+        },
+    };
+    let options = MockOptions {
+        detections,
+        quota: args.quota,
+        max_batch: args.max_batch,
+        delay: Duration::from_millis(args.delay_ms),
+        session_ttl: (args.session_ttl > 0).then(|| Duration::from_secs(args.session_ttl)),
+        down_after: args.down_after,
+    };
+    log::info!("md5rs-mock listening on {}", args.addr);
+    tonic::transport::Server::builder()
+        .add_service(MockServer::new(options).into_service())
+        .serve(args.addr)
+        .await?;
+    Ok(())
+}
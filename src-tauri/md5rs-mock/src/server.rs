@@ -0,0 +1,301 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::StreamExt;
+use tonic::codec::CompressionEncoding;
+use tonic::{Request, Response, Status, Streaming};
+
+use md5rs_client::md5rs::md5rs_server::{Md5rs, Md5rsServer};
+use md5rs_client::md5rs::{
+    AuthRequest, AuthResponse, DetectRequest, DetectResponse, HealthRequest, HealthResponse,
+};
+use md5rs_client::PROTO_VERSION;
+
+use crate::detections::{Detections, Detector};
+
+/// gRPC's default receive limit, which the server keeps.
+const MAX_MESSAGE_SIZE: u64 = 4 * 1024 * 1024;
+
+/// How the mock server behaves.
+#[derive(Debug, Clone)]
+pub struct MockOptions {
+    pub detections: Detections,
+    /// Frames each access token may send.
+    pub quota: i32,
+    /// Frames one request may pack, 0 to take no batches.
+    pub max_batch: u32,
+    /// Time each frame takes to answer, as a real detector would.
+    pub delay: Duration,
+    /// Sessions expire this long after they were opened, never when unset.
+    pub session_ttl: Option<Duration>,
+    /// Frames answered before the server goes down, every later call is refused as
+    /// unavailable. Never when unset.
+    pub down_after: Option<u32>,
+}
+
+impl Default for MockOptions {
+    fn default() -> Self {
+        Self {
+            detections: Detections::Random {
+                seed: 0,
+                blank_ratio: 0.3,
+            },
+            quota: 1_000_000,
+            max_batch: 8,
+            delay: Duration::ZERO,
+            session_ttl: None,
+            down_after: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Session {
+    token: String,
+    opened: Instant,
+}
+
+/// An md5rs server that answers without looking at the images. Access tokens are taken
+/// as they are, each with the quota of the options.
+#[derive(Debug, Clone)]
+pub struct MockServer {
+    options: MockOptions,
+    detector: Arc<Mutex<Detector>>,
+    /// Quota left by access token.
+    quotas: Arc<Mutex<HashMap<String, i32>>>,
+    sessions: Arc<Mutex<HashMap<String, Session>>>,
+    /// Frames answered so far.
+    answered: Arc<AtomicU32>,
+}
+
+impl MockServer {
+    pub fn new(options: MockOptions) -> Self {
+        Self {
+            detector: Arc::new(Mutex::new(Detector::new(options.detections.clone()))),
+            options,
+            quotas: Arc::default(),
+            sessions: Arc::default(),
+            answered: Arc::default(),
+        }
+    }
+
+    /// Refuses the call once `down_after` frames were answered.
+    fn check_up(&self) -> Result<(), Status> {
+        match self.options.down_after {
+            Some(limit) if self.answered.load(Ordering::SeqCst) >= limit => {
+                Err(Status::unavailable("Server is down"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The gRPC service, taking and sending compressed messages like the real server.
+    pub fn into_service(self) -> Md5rsServer<Self> {
+        Md5rsServer::new(self)
+            .accept_compressed(CompressionEncoding::Gzip)
+            .accept_compressed(CompressionEncoding::Zstd)
+            .send_compressed(CompressionEncoding::Gzip)
+            .send_compressed(CompressionEncoding::Zstd)
+    }
+
+    /// Access token of the session a detect call was made with, or why it is refused.
+    fn session_token<T>(&self, request: &Request<T>) -> Result<String, &'static str> {
+        let session = request
+            .metadata()
+            .get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .ok_or("Missing session")?;
+        let sessions = self.sessions.lock().unwrap();
+        let session = sessions.get(session).ok_or("Unknown session")?;
+        if self
+            .options
+            .session_ttl
+            .is_some_and(|ttl| session.opened.elapsed() > ttl)
+        {
+            return Err("Session expired");
+        }
+        Ok(session.token.clone())
+    }
+
+    /// Takes a frame off the quota of `token`, `false` once there is none left.
+    fn spend(&self, token: &str) -> bool {
+        let mut quotas = self.quotas.lock().unwrap();
+        let quota = quotas
+            .entry(token.to_string())
+            .or_insert(self.options.quota);
+        if *quota <= 0 {
+            return false;
+        }
+        *quota -= 1;
+        true
+    }
+}
+
+#[tonic::async_trait]
+impl Md5rs for MockServer {
+    type DetectStream = ReceiverStream<Result<DetectResponse, Status>>;
+
+    async fn health(
+        &self,
+        _request: Request<HealthRequest>,
+    ) -> Result<Response<HealthResponse>, Status> {
+        Ok(Response::new(HealthResponse {
+            status: true,
+            proto_version: PROTO_VERSION,
+            min_proto_version: 1,
+            server_version: format!("md5rs-mock {}", env!("CARGO_PKG_VERSION")),
+            max_message_size: MAX_MESSAGE_SIZE,
+            image_codecs: ["jpeg", "png", "avif"].map(String::from).to_vec(),
+            max_batch: self.options.max_batch,
+        }))
+    }
+
+    async fn auth(&self, request: Request<AuthRequest>) -> Result<Response<AuthResponse>, Status> {
+        self.check_up()?;
+        let token = request.into_inner().token.trim().to_string();
+        if token.is_empty() {
+            return Ok(Response::new(AuthResponse::default()));
+        }
+        let quota = *self
+            .quotas
+            .lock()
+            .unwrap()
+            .entry(token.clone())
+            .or_insert(self.options.quota);
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = format!("mock-session-{}", sessions.len() + 1);
+        sessions.insert(
+            session.clone(),
+            Session {
+                token,
+                opened: Instant::now(),
+            },
+        );
+        log::info!("Opened {} with a quota of {}", session, quota);
+        Ok(Response::new(AuthResponse {
+            success: true,
+            token: session,
+            quota,
+        }))
+    }
+
+    async fn detect(
+        &self,
+        request: Request<Streaming<DetectRequest>>,
+    ) -> Result<Response<Self::DetectStream>, Status> {
+        self.check_up()?;
+        let token = self
+            .session_token(&request)
+            .map_err(Status::unauthenticated)?;
+        let mut requests = request.into_inner();
+        let (response_s, response_r) = mpsc::channel(32);
+        let server = self.clone();
+        tokio::spawn(async move {
+            while let Some(request) = requests.next().await {
+                let request = match request {
+                    Ok(request) => request,
+                    Err(status) => {
+                        log::warn!("Detect stream broke off: {}", status);
+                        return;
+                    }
+                };
+                let frames = if request.batch.is_empty() {
+                    vec![request]
+                } else {
+                    request.batch
+                };
+                for frame in frames {
+                    if let Err(status) = server.check_up() {
+                        let _ = response_s.send(Err(status)).await;
+                        return;
+                    }
+                    if !server.spend(&token) {
+                        let _ = response_s
+                            .send(Err(Status::resource_exhausted("Quota exhausted")))
+                            .await;
+                        return;
+                    }
+                    if !server.options.delay.is_zero() {
+                        tokio::time::sleep(server.options.delay).await;
+                    }
+                    let response = server.detector.lock().unwrap().detect(&frame);
+                    server.answered.fetch_add(1, Ordering::SeqCst);
+                    if response_s.send(Ok(response)).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(response_r)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use md5rs_client::{Client, ConnectOptions, StreamCompression};
+    use tokio_stream::wrappers::TcpListenerStream;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_mock_server() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = MockServer::new(MockOptions {
+            quota: 3,
+            ..Default::default()
+        });
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(server.into_service())
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+
+        let url = format!("http://{}", addr);
+        let options = ConnectOptions {
+            proxy: Some("direct".to_string()),
+            ..Default::default()
+        };
+        let mut client = Client::connect(&url, &options, StreamCompression::Gzip)
+            .await
+            .unwrap();
+        assert_eq!(client.health().await.unwrap().proto_version, PROTO_VERSION);
+        assert!(client.auth("").await.is_err());
+        let auth = client.auth("demo").await.unwrap();
+        assert_eq!(auth.quota, 3);
+
+        let frame = |uuid: &str| DetectRequest {
+            uuid: uuid.to_string(),
+            ..Default::default()
+        };
+        let requests = tokio_stream::iter(vec![
+            frame("a"),
+            DetectRequest {
+                batch: vec![frame("b"), frame("c")],
+                ..Default::default()
+            },
+            frame("d"),
+        ]);
+        let mut responses = client.detect(requests, &auth.token).await.unwrap();
+        let mut uuids = Vec::new();
+        let status = loop {
+            match responses.message().await {
+                Ok(Some(response)) => uuids.push(response.uuid),
+                Ok(None) => panic!("the quota wasn't enforced"),
+                Err(status) => break status,
+            }
+        };
+        assert_eq!(uuids, ["a", "b", "c"]);
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+
+        let status = client
+            .detect(tokio_stream::iter(vec![frame("e")]), "unknown")
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unauthenticated);
+    }
+}
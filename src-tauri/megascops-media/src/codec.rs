@@ -0,0 +1,391 @@
+use anyhow::{anyhow, Result};
+use image::codecs::avif::AvifEncoder;
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::{ColorType, DynamicImage};
+use serde::{Deserialize, Serialize};
+use webp::{Encoder, WebPConfig};
+
+use crate::MediaError;
+
+const MIN_WEBP_QUALITY: f32 = 10.0;
+/// Shortest side a frame is scaled down to when even the lowest quality is too large.
+const MIN_SHRINK_SIDE: u32 = 64;
+/// libwebp's own default effort, kept for images.
+const IMAGE_WEBP_METHOD: u8 = 4;
+/// Video frames outnumber images by far, a little size buys a much faster encode.
+const VIDEO_WEBP_METHOD: u8 = 1;
+/// AVIF speed from 1, slowest, to 10. Even fast AVIF encodes are slower than WebP.
+const AVIF_SPEED: u8 = 8;
+
+/// Encoding of the image of a `DetectRequest`. Servers from before the fast path for
+/// pre-resized archives only decode WebP, which goes out with an empty codec.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ImageCodec {
+    Webp,
+    Jpeg,
+    Png,
+    /// Smaller than WebP at the same quality, especially on dark night shots, but only
+    /// encoded, never read from disk.
+    Avif,
+}
+
+impl ImageCodec {
+    /// Codecs images are read from disk in.
+    pub const ALL: [ImageCodec; 3] = [ImageCodec::Webp, ImageCodec::Jpeg, ImageCodec::Png];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ImageCodec::Webp => "webp",
+            ImageCodec::Jpeg => "jpeg",
+            ImageCodec::Png => "png",
+            ImageCodec::Avif => "avif",
+        }
+    }
+
+    /// Value of `DetectRequest::codec`.
+    pub fn request_codec(&self) -> String {
+        match self {
+            ImageCodec::Webp => String::new(),
+            codec => codec.as_str().to_string(),
+        }
+    }
+
+    pub fn from_request(codec: &str) -> Option<Self> {
+        match codec.to_lowercase().as_str() {
+            "" | "webp" => Some(ImageCodec::Webp),
+            "jpeg" | "jpg" => Some(ImageCodec::Jpeg),
+            "png" => Some(ImageCodec::Png),
+            "avif" => Some(ImageCodec::Avif),
+            _ => None,
+        }
+    }
+}
+
+/// Why a frame was not encoded with the configured settings, in the order the encoder
+/// falls back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum EncodeFallback {
+    /// libwebp rejected the settings, the frame went out at the plain encoder's quality.
+    SimpleWebp,
+    /// WebP doesn't take the frame's color type, it was converted to 8 bit RGB.
+    Rgb8,
+    /// WebP couldn't encode the frame at all.
+    Jpeg,
+}
+
+impl EncodeFallback {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EncodeFallback::SimpleWebp => "simpleWebp",
+            EncodeFallback::Rgb8 => "rgb8",
+            EncodeFallback::Jpeg => "jpeg",
+        }
+    }
+}
+
+impl std::str::FromStr for EncodeFallback {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "simpleWebp" => Ok(EncodeFallback::SimpleWebp),
+            "rgb8" => Ok(EncodeFallback::Rgb8),
+            "jpeg" => Ok(EncodeFallback::Jpeg),
+            _ => Err(anyhow!("Invalid encode fallback: {}", s)),
+        }
+    }
+}
+
+/// An encoded frame.
+#[derive(Debug, Clone)]
+pub struct Encoded {
+    pub data: Vec<u8>,
+    pub codec: ImageCodec,
+    /// `None` when the configured settings took the frame.
+    pub fallback: Option<EncodeFallback>,
+}
+
+/// Settings of the WebP encoder.
+#[derive(Debug, Clone, Copy)]
+pub struct WebpOptions {
+    /// Encode AVIF instead, only once the server said it decodes it.
+    pub avif: bool,
+    pub quality: f32,
+    /// Effort from 0, fastest, to 6, smallest. Unset, videos use a faster one than images.
+    pub method: Option<u8>,
+    /// Bytes to aim for instead of the quality, 0 to use the quality.
+    pub target_size: usize,
+    /// Slower but sharper RGB to YUV conversion.
+    pub sharp_yuv: bool,
+    /// Send JPEG when WebP can't encode a frame, only once the server said it decodes it.
+    pub jpeg_fallback: bool,
+}
+
+impl WebpOptions {
+    fn config(&self, video: bool) -> Option<WebPConfig> {
+        let mut config = WebPConfig::new().ok()?;
+        let method = match (self.method, video) {
+            (Some(method), _) => method,
+            (None, true) => VIDEO_WEBP_METHOD,
+            (None, false) => IMAGE_WEBP_METHOD,
+        };
+        config.quality = self.quality;
+        config.method = method.min(6) as i32;
+        config.target_size = self.target_size.min(i32::MAX as usize) as i32;
+        config.use_sharp_yuv = self.sharp_yuv as i32;
+        Some(config)
+    }
+
+    /// Encodes `img` with these settings. WebP falls back to the plain encoder at the same
+    /// quality where libwebp rejects them, then to `img` converted to 8 bit RGB, then to
+    /// JPEG with `jpeg_fallback`, so a frame WebP can't take isn't lost. The fallback taken
+    /// is reported with the data.
+    pub fn encode(&self, img: &DynamicImage, video: bool) -> Result<Encoded> {
+        if self.avif {
+            let mut data = Vec::new();
+            let quality = self.quality.clamp(1.0, 100.0) as u8;
+            img.write_with_encoder(AvifEncoder::new_with_speed_quality(
+                &mut data, AVIF_SPEED, quality,
+            ))?;
+            return Ok(Encoded {
+                data,
+                codec: ImageCodec::Avif,
+                fallback: None,
+            });
+        }
+        let error = match self.encode_webp(img, video) {
+            Ok((data, fallback)) => {
+                return Ok(Encoded {
+                    data,
+                    codec: ImageCodec::Webp,
+                    fallback,
+                })
+            }
+            Err(error) => error,
+        };
+        // the webp crate only takes 8 bit RGB and RGBA, gray and 16 bit images are converted
+        let rgb = DynamicImage::ImageRgb8(img.to_rgb8());
+        let error = if img.color() == ColorType::Rgb8 {
+            error
+        } else {
+            match self.encode_webp(&rgb, video) {
+                Ok((data, _)) => {
+                    log::warn!(
+                        "WebP can't encode a {:?} frame, sent it converted to 8 bit RGB",
+                        img.color()
+                    );
+                    return Ok(Encoded {
+                        data,
+                        codec: ImageCodec::Webp,
+                        fallback: Some(EncodeFallback::Rgb8),
+                    });
+                }
+                Err(error) => error,
+            }
+        };
+        if !self.jpeg_fallback {
+            return Err(error);
+        }
+        let mut data = Vec::new();
+        let quality = self.quality.clamp(1.0, 100.0) as u8;
+        rgb.write_with_encoder(JpegEncoder::new_with_quality(&mut data, quality))?;
+        log::warn!("{}, sent the frame as JPEG", error);
+        Ok(Encoded {
+            data,
+            codec: ImageCodec::Jpeg,
+            fallback: Some(EncodeFallback::Jpeg),
+        })
+    }
+
+    fn encode_webp(
+        &self,
+        img: &DynamicImage,
+        video: bool,
+    ) -> Result<(Vec<u8>, Option<EncodeFallback>)> {
+        let encoder =
+            Encoder::from_image(img).map_err(|e| MediaError::WebpEncodeError(e.to_string()))?;
+        let advanced =
+            self.config(video)
+                .and_then(|config| match encoder.encode_advanced(&config) {
+                    Ok(webp) => Some(webp),
+                    Err(e) => {
+                        log::warn!("Advanced WebP encoding failed: {:?}", e);
+                        None
+                    }
+                });
+        // the plain encoder panics where libwebp refuses the image, its checked form doesn't
+        match advanced {
+            Some(webp) => Ok((webp.to_vec(), None)),
+            None => {
+                let webp = encoder
+                    .encode_simple(false, self.quality)
+                    .map_err(|e| MediaError::WebpEncodeError(format!("{:?}", e)))?;
+                Ok((webp.to_vec(), Some(EncodeFallback::SimpleWebp)))
+            }
+        }
+    }
+}
+
+/// Re-encodes a frame as WebP at decreasing quality until it fits in `limit` bytes, then
+/// at decreasing size. Boxes are normalized to the image, so they still map onto the
+/// original when it had to be scaled down. AVIF frames can't be decoded to re-encode.
+pub fn shrink_webp(data: &[u8], codec: ImageCodec, limit: usize, quality: f32) -> Result<Vec<u8>> {
+    let img = match codec {
+        ImageCodec::Webp => webp::Decoder::new(data)
+            .decode()
+            .ok_or_else(|| MediaError::VideoDecodeError("Invalid WebP frame".to_string()))?
+            .to_image(),
+        // image only decodes AVIF with the system's dav1d
+        ImageCodec::Avif => {
+            return Err(MediaError::WebpEncodeError(format!(
+                "AVIF frame exceeds the server message limit of {} bytes, lower the quality",
+                limit
+            ))
+            .into())
+        }
+        _ => DynamicImage::ImageRgb8(image::load_from_memory(data)?.to_rgb8()),
+    };
+    let encoder =
+        Encoder::from_image(&img).map_err(|e| MediaError::WebpEncodeError(e.to_string()))?;
+    let mut quality = quality;
+    while quality > MIN_WEBP_QUALITY {
+        quality = (quality * 0.7).max(MIN_WEBP_QUALITY);
+        let data = encoder.encode(quality).to_vec();
+        if data.len() <= limit {
+            log::debug!(
+                "Re-encoded frame at quality {} to {} bytes",
+                quality,
+                data.len()
+            );
+            return Ok(data);
+        }
+    }
+    let mut scale = 1.0;
+    loop {
+        scale *= 0.7;
+        let width = (img.width() as f32 * scale).round() as u32;
+        let height = (img.height() as f32 * scale).round() as u32;
+        if width.min(height) < MIN_SHRINK_SIDE {
+            break;
+        }
+        let scaled = DynamicImage::ImageRgb8(
+            img.resize_exact(width, height, FilterType::Triangle)
+                .to_rgb8(),
+        );
+        let encoder =
+            Encoder::from_image(&scaled).map_err(|e| MediaError::WebpEncodeError(e.to_string()))?;
+        let data = encoder.encode(MIN_WEBP_QUALITY).to_vec();
+        if data.len() <= limit {
+            log::debug!(
+                "Re-encoded frame scaled down to {}x{} to {} bytes",
+                width,
+                height,
+                data.len()
+            );
+            return Ok(data);
+        }
+    }
+    Err(MediaError::WebpEncodeError(format!(
+        "Frame exceeds the server message limit of {} bytes",
+        limit
+    ))
+    .into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        let noise = image::RgbImage::from_fn(256, 192, |x, y| {
+            let v = ((x * 7919 + y * 104729) % 251) as u8;
+            image::Rgb([v, v.wrapping_mul(3), v.wrapping_add(91)])
+        });
+        let img = DynamicImage::ImageRgb8(noise);
+        let options = WebpOptions {
+            avif: false,
+            quality: 90.0,
+            method: None,
+            target_size: 0,
+            sharp_yuv: false,
+            jpeg_fallback: false,
+        };
+        let encoded = options.encode(&img, false).unwrap();
+        assert_eq!((encoded.codec, encoded.fallback), (ImageCodec::Webp, None));
+        let data = encoded.data;
+        let decoded = image::load_from_memory(&data).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (256, 192));
+
+        let codec = encoded.codec;
+        let smaller = shrink_webp(&data, codec, data.len() - 1, options.quality).unwrap();
+        assert!(smaller.len() < data.len());
+        assert!(shrink_webp(&data, codec, 10, options.quality).is_err());
+        // beyond the lowest quality the frame is scaled down, keeping its aspect ratio
+        let frame = webp::Decoder::new(&data).decode().unwrap().to_image();
+        let lowest = Encoder::from_image(&frame)
+            .unwrap()
+            .encode(MIN_WEBP_QUALITY)
+            .len();
+        let scaled = shrink_webp(&data, codec, lowest - 1, options.quality).unwrap();
+        assert!(scaled.len() < lowest);
+        let decoded = image::load_from_memory(&scaled).unwrap();
+        assert!(decoded.width() < 256);
+        let ratio = decoded.width() as f32 / decoded.height() as f32;
+        assert!((ratio - 256.0 / 192.0).abs() < 0.01);
+        let error = shrink_webp(&data, ImageCodec::Avif, 10, options.quality).unwrap_err();
+        assert!(error.to_string().contains("AVIF"));
+
+        // 16 bit gray, as some TIFFs are, is converted rather than refused
+        let gray = DynamicImage::ImageLuma16(image::ImageBuffer::from_pixel(
+            64,
+            48,
+            image::Luma([40_000]),
+        ));
+        let encoded = options.encode(&gray, false).unwrap();
+        assert_eq!(
+            (encoded.codec, encoded.fallback),
+            (ImageCodec::Webp, Some(EncodeFallback::Rgb8))
+        );
+        let decoded = image::load_from_memory(&encoded.data).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (64, 48));
+
+        // beyond the largest side WebP takes only JPEG goes
+        let wide = DynamicImage::ImageRgb8(image::RgbImage::new(16_400, 2));
+        assert!(options.encode(&wide, false).is_err());
+        let options = WebpOptions {
+            jpeg_fallback: true,
+            ..options
+        };
+        let encoded = options.encode(&wide, false).unwrap();
+        assert_eq!(
+            (encoded.codec, encoded.fallback),
+            (ImageCodec::Jpeg, Some(EncodeFallback::Jpeg))
+        );
+        assert_eq!(
+            image::load_from_memory(&encoded.data).unwrap().width(),
+            16_400
+        );
+        for fallback in [
+            EncodeFallback::SimpleWebp,
+            EncodeFallback::Rgb8,
+            EncodeFallback::Jpeg,
+        ] {
+            assert_eq!(
+                fallback.as_str().parse::<EncodeFallback>().unwrap(),
+                fallback
+            );
+            assert_eq!(
+                serde_json::to_string(&fallback).unwrap(),
+                format!("\"{}\"", fallback.as_str())
+            );
+        }
+
+        assert_eq!(ImageCodec::Webp.request_codec(), "");
+        assert_eq!(ImageCodec::from_request("JPG"), Some(ImageCodec::Jpeg));
+        assert_eq!(ImageCodec::from_request("heic"), None);
+    }
+}
@@ -0,0 +1,31 @@
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum MediaError {
+    #[error("Failed to open file: {0}")]
+    IoError(#[from] std::io::Error),
+
+    #[error("Failed to decode: {0}")]
+    ImageDecodeError(#[from] jpeg_decoder::Error),
+
+    #[error("Failed to decode: {0}")]
+    VideoDecodeError(String),
+
+    #[error("Failed to develop RAW file {0}")]
+    RawDecodeError(String),
+
+    #[error("Failed to decode TIFF {0}")]
+    TiffDecodeError(String),
+
+    #[error("Failed to encode: {0}")]
+    WebpEncodeError(String),
+
+    #[error("Ffmpeg error when decoding {1}: {0}")]
+    FfmpegError(String, String),
+
+    #[error("Failed to resize: {0}")]
+    ResizeError(String),
+
+    #[error("Pipeline closed before the frame was sent")]
+    ChannelClosed,
+}
@@ -0,0 +1,74 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use ffmpeg_sidecar::child::FfmpegChild;
+use ffmpeg_sidecar::command::FfmpegCommand;
+use ffmpeg_sidecar::event::{FfmpegEvent, LogLevel};
+use image::DynamicImage;
+
+use crate::MediaError;
+
+/// Whether `path` is a HEIF image, such as the HEIC files of newer trail cameras and
+/// phones. Neither `image` nor `jpeg_decoder` read them, ffmpeg does.
+pub fn is_heif(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| matches!(e.to_lowercase().as_str(), "heic" | "heif"))
+}
+
+/// Starts ffmpeg decoding the HEIF image at `path` to RGB at full size.
+pub fn spawn_heif_decoder(path: &Path) -> Result<FfmpegChild> {
+    let child = FfmpegCommand::new()
+        .input(path.to_string_lossy())
+        .args(["-frames:v", "1", "-f", "rawvideo", "-pix_fmt", "rgb24"])
+        .output("-")
+        .spawn()?;
+    Ok(child)
+}
+
+/// Reads the image a decoder from [`spawn_heif_decoder`] puts out.
+pub fn read_heif(child: &mut FfmpegChild, path: &Path) -> Result<DynamicImage> {
+    let mut errors = Vec::new();
+    for event in child.iter()? {
+        match event {
+            FfmpegEvent::OutputFrame(frame) => {
+                let img = image::RgbImage::from_raw(frame.width, frame.height, frame.data)
+                    .context("Invalid frame size")?;
+                return Ok(DynamicImage::ImageRgb8(img));
+            }
+            FfmpegEvent::Error(e) | FfmpegEvent::Log(LogLevel::Error, e) => errors.push(e),
+            _ => (),
+        }
+    }
+    let error = match errors.pop() {
+        Some(e) => MediaError::FfmpegError(e, path.to_string_lossy().into_owned()),
+        None => MediaError::VideoDecodeError(path.to_string_lossy().into_owned()),
+    };
+    Err(error.into())
+}
+
+/// Decodes the HEIF image at `path` to RGB.
+pub fn decode_heif(path: &Path) -> Result<DynamicImage> {
+    let mut child = spawn_heif_decoder(path)?;
+    read_heif(&mut child, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_heif() {
+        assert!(is_heif(Path::new("DCIM/IMG_0001.HEIC")));
+        assert!(is_heif(Path::new("a.heif")));
+        assert!(!is_heif(Path::new("a.jpg")));
+        assert!(!is_heif(Path::new("heic")));
+
+        let dir = std::env::temp_dir().join(format!("megascops-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("broken.heic");
+        std::fs::write(&path, b"not an image").unwrap();
+        assert!(decode_heif(&path).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,47 @@
+use std::path::Path;
+
+/// Extensions of the images read, in lowercase.
+pub const IMAGE_EXTENSIONS: [&str; 14] = [
+    "jpg", "jpeg", "png", "tif", "tiff", "bmp", "webp", "gif", "heic", "heif", "cr2", "nef", "arw",
+    "dng",
+];
+/// Extensions of the videos read, in lowercase.
+pub const VIDEO_EXTENSIONS: [&str; 4] = ["mp4", "avi", "mkv", "mov"];
+
+/// Whether a file is read as an image or a video.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaKind {
+    Image,
+    Video,
+}
+
+impl MediaKind {
+    /// What the file at `path` is read as by its extension, `None` for any other file.
+    pub fn of(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_string_lossy().to_lowercase();
+        if IMAGE_EXTENSIONS.contains(&extension.as_str()) {
+            Some(Self::Image)
+        } else if VIDEO_EXTENSIONS.contains(&extension.as_str()) {
+            Some(Self::Video)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_media_kind() {
+        assert_eq!(
+            MediaKind::of(Path::new("a/IMG_0001.JPG")),
+            Some(MediaKind::Image)
+        );
+        assert_eq!(MediaKind::of(Path::new("a/b.heic")), Some(MediaKind::Image));
+        assert_eq!(MediaKind::of(Path::new("a/b.Mp4")), Some(MediaKind::Video));
+        assert_eq!(MediaKind::of(Path::new("a/result.json")), None);
+        assert_eq!(MediaKind::of(Path::new("a/mp4")), None);
+    }
+}
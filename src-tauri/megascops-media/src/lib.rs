@@ -0,0 +1,44 @@
+//! Preparing camera trap media for detection: decoding images and videos, reading when
+//! they were shot, sampling the frames of videos and animations, resizing and encoding
+//! what is sent.
+//!
+//! Videos and HEIF images are decoded with the ffmpeg binaries of `ffmpeg-sidecar`, which
+//! have to be installed or downloaded before any of them is read. Camera RAW files are
+//! developed with `imagepipe`. Every page of a multi-page TIFF is read with `tiff`, every
+//! frame of an animated GIF or PNG with `image`.
+
+mod codec;
+mod error;
+mod heif;
+mod kind;
+mod multipage;
+mod picture;
+mod raw;
+mod sample;
+mod sampling;
+mod shoot_time;
+mod video;
+
+pub use codec::{shrink_webp, EncodeFallback, Encoded, ImageCodec, WebpOptions};
+pub use error::MediaError;
+pub use fast_image_resize::Resizer;
+pub use heif::{decode_heif, is_heif, read_heif, spawn_heif_decoder};
+pub use kind::{MediaKind, IMAGE_EXTENSIONS, VIDEO_EXTENSIONS};
+pub use multipage::{
+    decode_animation, decode_tiff_page, decode_tiff_pages, is_animation, is_apng, is_gif, is_tiff,
+};
+pub use picture::{
+    decode_image, image_dimensions, image_orientation, is_bmp, probe_image, resize_encode,
+    resize_image,
+};
+pub use raw::{decode_raw, is_raw, raw_dimensions};
+pub use sample::{sample_evenly, sample_indices};
+pub use sampling::{decode_samples, sample_animation, FrameSampling};
+pub use shoot_time::{
+    get_image_date, get_image_metadata, get_video_date, parse_iso6709, CameraInfo, ImageMetadata,
+};
+pub use video::{
+    extract_frame, get_video_dimensions, probe_hdr, probe_video, read_frames, read_scene_frames,
+    spawn_decoder, spawn_scene_decoder, spawn_seek_decoder, spawn_stride_decoder, DecodedVideo,
+    VideoInfo,
+};
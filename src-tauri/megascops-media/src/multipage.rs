@@ -0,0 +1,222 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use image::codecs::gif::GifDecoder;
+use image::codecs::png::PngDecoder;
+use image::{AnimationDecoder, DynamicImage, ImageBuffer};
+use tiff::decoder::{Decoder, DecodingResult};
+use tiff::ColorType;
+
+use crate::MediaError;
+
+/// Whether `path` is a TIFF, which may hold several pages.
+pub fn is_tiff(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| matches!(e.to_lowercase().as_str(), "tif" | "tiff"))
+}
+
+fn tiff_error(path: &Path, e: impl std::fmt::Display) -> MediaError {
+    MediaError::TiffDecodeError(format!("{}: {}", path.display(), e))
+}
+
+/// A decoded page as RGB. Gray, RGB and RGBA pages of 8 or 16 bits are read, the layouts
+/// camera firmwares write.
+fn page_to_rgb(
+    width: u32,
+    height: u32,
+    color: ColorType,
+    data: DecodingResult,
+) -> Option<DynamicImage> {
+    let img = match (color, data) {
+        (ColorType::Gray(8), DecodingResult::U8(data)) => {
+            DynamicImage::ImageLuma8(ImageBuffer::from_raw(width, height, data)?)
+        }
+        (ColorType::Gray(16), DecodingResult::U16(data)) => {
+            DynamicImage::ImageLuma16(ImageBuffer::from_raw(width, height, data)?)
+        }
+        (ColorType::RGB(8), DecodingResult::U8(data)) => {
+            DynamicImage::ImageRgb8(ImageBuffer::from_raw(width, height, data)?)
+        }
+        (ColorType::RGB(16), DecodingResult::U16(data)) => {
+            DynamicImage::ImageRgb16(ImageBuffer::from_raw(width, height, data)?)
+        }
+        (ColorType::RGBA(8), DecodingResult::U8(data)) => {
+            DynamicImage::ImageRgba8(ImageBuffer::from_raw(width, height, data)?)
+        }
+        (ColorType::RGBA(16), DecodingResult::U16(data)) => {
+            DynamicImage::ImageRgba16(ImageBuffer::from_raw(width, height, data)?)
+        }
+        _ => return None,
+    };
+    Some(DynamicImage::ImageRgb8(img.to_rgb8()))
+}
+
+fn open_tiff(path: &Path) -> Result<Decoder<BufReader<File>>> {
+    let file = File::open(path).map_err(MediaError::IoError)?;
+    Ok(Decoder::new(BufReader::new(file)).map_err(|e| tiff_error(path, e))?)
+}
+
+/// The page `decoder` is at as RGB.
+fn read_page(decoder: &mut Decoder<BufReader<File>>, path: &Path) -> Result<DynamicImage> {
+    let (width, height) = decoder.dimensions().map_err(|e| tiff_error(path, e))?;
+    let color = decoder.colortype().map_err(|e| tiff_error(path, e))?;
+    let data = decoder.read_image().map_err(|e| tiff_error(path, e))?;
+    Ok(page_to_rgb(width, height, color, data)
+        .ok_or_else(|| tiff_error(path, format!("unsupported page layout {:?}", color)))?)
+}
+
+/// The first page of the TIFF at `path` as RGB, the one a camera shot, later ones are
+/// usually thumbnails or scans of the back.
+pub fn decode_tiff_page(path: &Path) -> Result<DynamicImage> {
+    read_page(&mut open_tiff(path)?, path)
+}
+
+/// Every page of the TIFF at `path` as RGB, in file order. The `image` crate only reads
+/// the first one.
+pub fn decode_tiff_pages(path: &Path) -> Result<Vec<DynamicImage>> {
+    let mut decoder = open_tiff(path)?;
+    let mut pages = Vec::new();
+    loop {
+        pages.push(read_page(&mut decoder, path)?);
+        if !decoder.more_images() {
+            break;
+        }
+        decoder.next_image().map_err(|e| tiff_error(path, e))?;
+    }
+    Ok(pages)
+}
+
+/// Whether `path` is a GIF by its extension.
+pub fn is_gif(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("gif"))
+}
+
+/// Whether `path` is a GIF or PNG, either of which may be animated.
+pub fn is_animation(path: &Path) -> bool {
+    is_gif(path)
+        || path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("png"))
+}
+
+/// Whether the PNG at `path` is animated, read from its header.
+pub fn is_apng(path: &Path) -> bool {
+    File::open(path)
+        .ok()
+        .and_then(|file| PngDecoder::new(BufReader::new(file)).ok())
+        .and_then(|decoder| decoder.is_apng().ok())
+        .unwrap_or(false)
+}
+
+/// Every frame of an animated GIF or PNG as RGB, with the time into the animation it is
+/// shown at, as cellular cameras send short clips. A still GIF is one frame, a still PNG
+/// `None` to be decoded as any image.
+pub fn decode_animation(path: &Path) -> Result<Option<Vec<(DynamicImage, Duration)>>> {
+    let context = || format!("Failed to decode {}", path.display());
+    let reader = BufReader::new(File::open(path).map_err(MediaError::IoError)?);
+    let frames = if is_gif(path) {
+        GifDecoder::new(reader).with_context(context)?.into_frames()
+    } else {
+        let decoder = PngDecoder::new(reader).with_context(context)?;
+        if !decoder.is_apng().with_context(context)? {
+            return Ok(None);
+        }
+        decoder.apng().with_context(context)?.into_frames()
+    };
+    let mut start = Duration::ZERO;
+    let mut decoded = Vec::new();
+    for frame in frames {
+        let frame = frame.with_context(context)?;
+        let delay = Duration::from(frame.delay());
+        let rgb = DynamicImage::ImageRgba8(frame.into_buffer()).to_rgb8();
+        decoded.push((DynamicImage::ImageRgb8(rgb), start));
+        start += delay;
+    }
+    anyhow::ensure!(!decoded.is_empty(), "{} has no frames", path.display());
+    Ok(Some(decoded))
+}
+
+#[cfg(test)]
+mod tests {
+    use image::codecs::gif::GifEncoder;
+    use image::{Delay, Frame, RgbaImage};
+    use tiff::encoder::{colortype, TiffEncoder};
+
+    use super::*;
+
+    #[test]
+    fn test_tiff_pages() {
+        assert!(is_tiff(Path::new("IMAG0001.TIF")));
+        assert!(is_tiff(Path::new("a.tiff")));
+        assert!(!is_tiff(Path::new("a.jpg")));
+
+        let dir = std::env::temp_dir().join(format!("megascops-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("burst.tif");
+        let mut encoder = TiffEncoder::new(File::create(&path).unwrap()).unwrap();
+        encoder
+            .write_image::<colortype::RGB8>(4, 2, &[200; 4 * 2 * 3])
+            .unwrap();
+        encoder
+            .write_image::<colortype::Gray16>(3, 5, &[u16::MAX; 3 * 5])
+            .unwrap();
+        drop(encoder);
+
+        let pages = decode_tiff_pages(&path).unwrap();
+        assert_eq!(pages.len(), 2);
+        assert_eq!((pages[0].width(), pages[0].height()), (4, 2));
+        assert_eq!(pages[0].to_rgb8().get_pixel(0, 0).0, [200, 200, 200]);
+        assert_eq!((pages[1].width(), pages[1].height()), (3, 5));
+        assert_eq!(pages[1].to_rgb8().get_pixel(2, 4).0, [255, 255, 255]);
+        let first = decode_tiff_page(&path).unwrap();
+        assert_eq!((first.width(), first.height()), (4, 2));
+
+        let broken = dir.join("broken.tif");
+        std::fs::write(&broken, b"not a tiff").unwrap();
+        assert!(decode_tiff_pages(&broken).is_err());
+        assert!(decode_tiff_page(&broken).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_animation() {
+        assert!(is_animation(Path::new("CLIP0001.GIF")));
+        assert!(is_animation(Path::new("a.png")));
+        assert!(!is_animation(Path::new("a.tif")));
+
+        let dir = std::env::temp_dir().join(format!("megascops-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("clip.gif");
+        let mut encoder = GifEncoder::new(File::create(&path).unwrap());
+        let frames = [(10, 500), (120, 500), (240, 1000)].map(|(v, ms)| {
+            Frame::from_parts(
+                RgbaImage::from_pixel(6, 4, image::Rgba([v, v, v, 255])),
+                0,
+                0,
+                Delay::from_numer_denom_ms(ms, 1),
+            )
+        });
+        encoder.encode_frames(frames).unwrap();
+        drop(encoder);
+
+        let frames = decode_animation(&path).unwrap().unwrap();
+        let starts: Vec<u128> = frames.iter().map(|(_, start)| start.as_millis()).collect();
+        assert_eq!(starts, [0, 500, 1000]);
+        assert_eq!((frames[2].0.width(), frames[2].0.height()), (6, 4));
+        assert_eq!(frames[1].0.to_rgb8().get_pixel(0, 0).0, [120, 120, 120]);
+
+        let still = dir.join("still.png");
+        image::RgbImage::new(4, 4).save(&still).unwrap();
+        assert!(!is_apng(&still));
+        assert!(decode_animation(&still).unwrap().is_none());
+        let broken = dir.join("broken.gif");
+        std::fs::write(&broken, b"not a gif").unwrap();
+        assert!(decode_animation(&broken).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
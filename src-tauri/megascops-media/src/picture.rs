@@ -0,0 +1,235 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use anyhow::Result;
+use fast_image_resize::{ResizeAlg, ResizeOptions, Resizer};
+use image::metadata::Orientation;
+use image::{DynamicImage, GenericImageView, ImageDecoder, ImageFormat, ImageReader};
+use jpeg_decoder::Decoder;
+
+use crate::{
+    decode_heif, decode_raw, get_video_dimensions, is_apng, is_heif, is_raw, raw_dimensions,
+    Encoded, ImageCodec, MediaError, WebpOptions,
+};
+
+/// Whether `path` is a BMP, which has nowhere to keep EXIF data.
+pub fn is_bmp(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("bmp"))
+}
+
+/// Orientation the EXIF data of the image at `path` asks for, none without EXIF data.
+pub fn image_orientation(path: &Path) -> Orientation {
+    ImageReader::open(path)
+        .ok()
+        .and_then(|reader| reader.into_decoder().ok())
+        .and_then(|mut decoder| decoder.orientation().ok())
+        .unwrap_or(Orientation::NoTransforms)
+}
+
+/// Whether `orientation` turns the image on its side, swapping width and height.
+fn swaps_sides(orientation: Orientation) -> bool {
+    matches!(
+        orientation,
+        Orientation::Rotate90
+            | Orientation::Rotate270
+            | Orientation::Rotate90FlipH
+            | Orientation::Rotate270FlipH
+    )
+}
+
+fn decode_upright(reader: ImageReader<BufReader<File>>) -> image::ImageResult<DynamicImage> {
+    let mut decoder = reader.into_decoder()?;
+    let orientation = decoder.orientation().unwrap_or(Orientation::NoTransforms);
+    let mut img = DynamicImage::from_decoder(decoder)?;
+    img.apply_orientation(orientation);
+    Ok(img)
+}
+
+/// Decodes the image at `path` to RGB, upright as its EXIF orientation says, so boxes
+/// are in the frame of the picture as it is shown. JPEGs the `image` crate rejects, such
+/// as some truncated camera files, are tried again with `jpeg_decoder`. HEIF images go
+/// to ffmpeg, RAW files are developed.
+pub fn decode_image(path: &Path) -> Result<DynamicImage> {
+    if is_heif(path) {
+        return decode_heif(path);
+    }
+    if is_raw(path) {
+        return decode_raw(path);
+    }
+    let img = match decode_upright(ImageReader::open(path).map_err(MediaError::IoError)?) {
+        Ok(img) => DynamicImage::ImageRgb8(img.to_rgb8()),
+        Err(_e) => {
+            log::warn!(
+                "Failed to decode image with ImageReader. Trying jpeg_decoder. {:?}",
+                _e
+            );
+            let img_reader = File::open(path).map_err(MediaError::IoError)?;
+            let mut decoder = Decoder::new(BufReader::new(img_reader));
+            let pixels = decoder.decode().map_err(MediaError::ImageDecodeError)?;
+            let info = decoder
+                .info()
+                .ok_or_else(|| MediaError::VideoDecodeError("Missing JPEG info".to_string()))?;
+            let img = image::ImageBuffer::from_raw(info.width as u32, info.height as u32, pixels)
+                .ok_or_else(|| {
+                MediaError::VideoDecodeError(format!("Unexpected pixel data of {}", path.display()))
+            })?;
+            let mut img = DynamicImage::ImageRgb8(img);
+            img.apply_orientation(image_orientation(path));
+            img
+        }
+    };
+    Ok(img)
+}
+
+/// Codec and size of the image at `path`, read from its header without decoding. `None`
+/// for formats other than those of [`ImageCodec::ALL`], for images stored rotated, which
+/// are decoded to be sent upright, for 16 bit PNGs, which are decoded to 8 bit RGB, and
+/// for animated PNGs, whose frames are sent one by one.
+pub fn probe_image(path: &Path) -> Option<(ImageCodec, u32, u32)> {
+    let reader = ImageReader::open(path).ok()?.with_guessed_format().ok()?;
+    let codec = match reader.format()? {
+        ImageFormat::WebP => ImageCodec::Webp,
+        ImageFormat::Jpeg => ImageCodec::Jpeg,
+        ImageFormat::Png => ImageCodec::Png,
+        _ => return None,
+    };
+    let mut decoder = reader.into_decoder().ok()?;
+    if decoder.orientation().ok()? != Orientation::NoTransforms {
+        return None;
+    }
+    if decoder.color_type().bytes_per_pixel() > decoder.color_type().channel_count() {
+        return None;
+    }
+    if codec == ImageCodec::Png && is_apng(path) {
+        return None;
+    }
+    let (width, height) = decoder.dimensions();
+    Some((codec, width, height))
+}
+
+/// Width and height of the image at `path` upright, read from its header. HEIF images are
+/// probed with ffprobe, RAW files with `rawloader`.
+pub fn image_dimensions(path: &Path) -> Result<(u32, u32)> {
+    if is_heif(path) {
+        let (width, height) = get_video_dimensions(&path.to_string_lossy())?;
+        return Ok((width as u32, height as u32));
+    }
+    if is_raw(path) {
+        return raw_dimensions(path);
+    }
+    let (width, height) = image::image_dimensions(path)?;
+    Ok(if swaps_sides(image_orientation(path)) {
+        (height, width)
+    } else {
+        (width, height)
+    })
+}
+
+/// Scales `img` so its longer side is `imgsz`, the other side rounded up to even.
+pub fn resize_image(img: &DynamicImage, imgsz: u32, resizer: &mut Resizer) -> Result<DynamicImage> {
+    let (width, height) = img.dimensions();
+    let mut resized_width = imgsz;
+    let mut resized_height = imgsz;
+    let ratio: f32;
+
+    if width > height {
+        ratio = width as f32 / imgsz as f32;
+        resized_height = (height as f32 / ratio) as u32;
+        resized_height = resized_height % 2 + resized_height;
+    } else {
+        ratio = height as f32 / imgsz as f32;
+        resized_width = (width as f32 / ratio) as u32;
+        resized_width = resized_width % 2 + resized_width;
+    }
+
+    let mut resized_img = DynamicImage::new(resized_width, resized_height, img.color());
+
+    let resize_option = ResizeOptions::new().resize_alg(ResizeAlg::Nearest);
+
+    resizer
+        .resize(img, &mut resized_img, &resize_option)
+        .map_err(|e| MediaError::ResizeError(e.to_string()))?;
+    Ok(resized_img)
+}
+
+/// Scales `img` like [`resize_image`] and encodes it as an image, not a video frame.
+pub fn resize_encode(
+    img: &DynamicImage,
+    imgsz: u32,
+    webp: WebpOptions,
+    resizer: &mut Resizer,
+) -> Result<Encoded> {
+    let resized_img = resize_image(img, imgsz, resizer)?;
+    webp.encode(&resized_img, false).map_err(|e| {
+        log::error!("Failed to encode image: {:?}", e);
+        e
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_and_resize() {
+        let dir = std::env::temp_dir().join(format!("megascops-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("a.png");
+        image::RgbImage::new(300, 201).save(&path).unwrap();
+        for name in ["b.bmp", "c.webp", "d.tif"] {
+            image::RgbImage::new(40, 30).save(dir.join(name)).unwrap();
+            assert_eq!(
+                decode_image(&dir.join(name)).unwrap().dimensions(),
+                (40, 30)
+            );
+            assert_eq!(image_dimensions(&dir.join(name)).unwrap(), (40, 30));
+        }
+        assert_eq!(
+            probe_image(&dir.join("c.webp")),
+            Some((ImageCodec::Webp, 40, 30))
+        );
+        assert!(probe_image(&dir.join("b.bmp")).is_none());
+        // a JPEG shot in portrait, stored landscape with EXIF orientation 6
+        let mut jpeg = Vec::new();
+        DynamicImage::new_rgb8(40, 30)
+            .write_to(&mut std::io::Cursor::new(&mut jpeg), ImageFormat::Jpeg)
+            .unwrap();
+        let exif: &[u8] = b"\xFF\xE1\x00\x22Exif\x00\x00MM\x00\x2A\x00\x00\x00\x08\x00\x01\x01\x12\x00\x03\x00\x00\x00\x01\x00\x06\x00\x00\x00\x00\x00\x00";
+        jpeg.splice(2..2, exif.iter().copied());
+        let rotated = dir.join("rotated.jpg");
+        std::fs::write(&rotated, jpeg).unwrap();
+        assert_eq!(image_orientation(&rotated), Orientation::Rotate90);
+        assert_eq!(decode_image(&rotated).unwrap().dimensions(), (30, 40));
+        assert_eq!(image_dimensions(&rotated).unwrap(), (30, 40));
+        assert!(probe_image(&rotated).is_none());
+
+        // 16 bit images aren't sent as they are but brought down to 8 bit RGB
+        let deep = dir.join("deep.png");
+        image::ImageBuffer::from_pixel(8, 6, image::Luma([u16::MAX / 2]))
+            .save(&deep)
+            .unwrap();
+        assert!(probe_image(&deep).is_none());
+        let img = decode_image(&deep).unwrap();
+        assert_eq!(img.color(), image::ColorType::Rgb8);
+        assert_eq!(img.to_rgb8().get_pixel(3, 3).0, [127, 127, 127]);
+
+        let broken = dir.join("broken.jpg");
+        std::fs::write(&broken, b"not a jpeg").unwrap();
+
+        assert_eq!(probe_image(&path), Some((ImageCodec::Png, 300, 201)));
+        assert!(probe_image(&broken).is_none());
+        assert!(decode_image(&broken).is_err());
+        assert!(decode_image(&dir.join("missing.jpg")).is_err());
+
+        let img = decode_image(&path).unwrap();
+        let mut resizer = Resizer::new();
+        let resized = resize_image(&img, 100, &mut resizer).unwrap();
+        assert_eq!(resized.dimensions(), (100, 68));
+        let portrait = DynamicImage::new_rgb8(201, 300);
+        let resized = resize_image(&portrait, 100, &mut resizer).unwrap();
+        assert_eq!(resized.dimensions(), (68, 100));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
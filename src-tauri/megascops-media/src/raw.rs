@@ -0,0 +1,67 @@
+use std::path::Path;
+
+use anyhow::Result;
+use image::DynamicImage;
+
+use crate::MediaError;
+
+/// Whether `path` is a camera RAW file of the DSLRs some camera traps are built from.
+pub fn is_raw(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| matches!(e.to_lowercase().as_str(), "cr2" | "nef" | "arw" | "dng"))
+}
+
+/// Develops the RAW file at `path` to RGB at full size, with the white balance and color
+/// matrix of the camera and in its orientation.
+pub fn decode_raw(path: &Path) -> Result<DynamicImage> {
+    let developed = imagepipe::simple_decode_8bit(path, 0, 0)
+        .map_err(|e| MediaError::RawDecodeError(format!("{}: {}", path.display(), e)))?;
+    let img = image::RgbImage::from_raw(
+        developed.width as u32,
+        developed.height as u32,
+        developed.data,
+    )
+    .ok_or_else(|| {
+        MediaError::RawDecodeError(format!("Unexpected pixel data of {}", path.display()))
+    })?;
+    Ok(DynamicImage::ImageRgb8(img))
+}
+
+/// Width and height the RAW file at `path` develops to, read without developing it.
+pub fn raw_dimensions(path: &Path) -> Result<(u32, u32)> {
+    let raw = rawloader::decode_file(path)
+        .map_err(|e| MediaError::RawDecodeError(format!("{}: {}", path.display(), e)))?;
+    let [top, right, bottom, left] = raw.crops;
+    let width = (raw.width - left - right) as u32;
+    let height = (raw.height - top - bottom) as u32;
+    let (transpose, _, _) = raw.orientation.to_flips();
+    Ok(if transpose {
+        (height, width)
+    } else {
+        (width, height)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_raw() {
+        assert!(is_raw(Path::new("DCIM/_DSC0001.NEF")));
+        assert!(is_raw(Path::new("IMG_0001.cr2")));
+        assert!(is_raw(Path::new("a.ARW")));
+        assert!(is_raw(Path::new("a.dng")));
+        assert!(!is_raw(Path::new("a.jpg")));
+        assert!(!is_raw(Path::new("nef")));
+
+        let dir = std::env::temp_dir().join(format!("megascops-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("broken.nef");
+        std::fs::write(&path, b"not a raw file").unwrap();
+        assert!(decode_raw(&path).is_err());
+        assert!(raw_dimensions(&path).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
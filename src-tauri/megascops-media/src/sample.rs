@@ -0,0 +1,36 @@
+/// Indices of `sample_size` elements spread evenly over `len`, the first one always
+/// among them.
+pub fn sample_indices(len: usize, sample_size: usize) -> Vec<usize> {
+    if sample_size == 0 || len == 0 {
+        return Vec::new();
+    }
+
+    let step = len as f64 / sample_size as f64;
+    (0..sample_size)
+        .map(|i| (i as f64 * step).floor() as usize)
+        .collect()
+}
+
+/// `sample_size` elements spread evenly over `list`, the first one always among them.
+pub fn sample_evenly<T: Clone>(list: &[T], sample_size: usize) -> Vec<T> {
+    sample_indices(list.len(), sample_size)
+        .into_iter()
+        .map(|index| list[index].clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample_evenly() {
+        let frames: Vec<usize> = (0..10).collect();
+        assert_eq!(sample_evenly(&frames, 5), [0, 2, 4, 6, 8]);
+        assert_eq!(sample_evenly(&frames, 3), [0, 3, 6]);
+        assert_eq!(sample_evenly(&frames, 10), frames);
+        assert!(sample_evenly(&frames, 0).is_empty());
+        assert!(sample_evenly::<usize>(&[], 4).is_empty());
+        assert_eq!(sample_indices(54_000, 4), [0, 13_500, 27_000, 40_500]);
+    }
+}
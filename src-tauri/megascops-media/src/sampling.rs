@@ -0,0 +1,373 @@
+use std::process::Child;
+use std::time::Duration;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    probe_hdr, probe_video, read_frames, read_scene_frames, sample_evenly, sample_indices,
+    spawn_decoder, spawn_scene_decoder, spawn_seek_decoder, spawn_stride_decoder, DecodedVideo,
+    VideoInfo,
+};
+
+/// Frames of a video per sampled frame above which the samples are seeked to instead of
+/// decoding the whole video.
+const SEEK_SPACING: usize = 100;
+
+/// How frames are picked from a video.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(
+    tag = "mode",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum FrameSampling {
+    /// Every `n`th frame from the first one.
+    EveryNthFrame { n: usize },
+    /// About `fps` frames for each second of video.
+    FramesPerSecond { fps: f64 },
+    /// A frame every `seconds` of video.
+    SecondsInterval { seconds: f64 },
+    /// Key frames only, at most `max_frames` of them spread evenly when set.
+    IFramesOnly {
+        #[serde(default)]
+        max_frames: Option<usize>,
+    },
+    /// `frames` frames spread evenly over the video, every frame of a shorter one.
+    MaxFrames { frames: usize },
+    /// The first frame and those where the scene changes by more than `threshold`, from 0
+    /// to 1, such as an animal walking in or out, so a long static clip sends few frames.
+    /// At most `max_frames` of them spread evenly when set.
+    SceneChange {
+        threshold: f32,
+        #[serde(default)]
+        max_frames: Option<usize>,
+    },
+}
+
+impl FrameSampling {
+    /// The sampling the `iframe_only` and `max_frames` options stand for.
+    pub fn legacy(iframe: bool, max_frames: Option<usize>) -> Self {
+        match (iframe, max_frames) {
+            (true, max_frames) => Self::IFramesOnly { max_frames },
+            (false, Some(frames)) => Self::MaxFrames { frames },
+            (false, None) => Self::EveryNthFrame { n: 1 },
+        }
+    }
+
+    /// Folder policies set `iframe_only` and `max_frames`, whatever they set turns the
+    /// sampling into the one those options stand for.
+    pub fn with_policy(self, iframe_only: Option<bool>, max_frames: Option<usize>) -> Self {
+        if iframe_only.is_none() && max_frames.is_none() {
+            return self;
+        }
+        Self::legacy(
+            iframe_only.unwrap_or(self.iframe()),
+            max_frames.or(self.max_frames()),
+        )
+    }
+
+    pub fn iframe(&self) -> bool {
+        matches!(self, Self::IFramesOnly { .. })
+    }
+
+    /// Most frames kept of a video, spread evenly over those decoded.
+    pub fn max_frames(&self) -> Option<usize> {
+        match *self {
+            Self::IFramesOnly { max_frames } | Self::SceneChange { max_frames, .. } => max_frames,
+            Self::MaxFrames { frames } => Some(frames),
+            _ => None,
+        }
+    }
+
+    /// Frames taken of a video of `info`, at least one. Key frames and scene changes
+    /// aren't known before decoding, they count as `max_frames` or once.
+    pub fn expected_frames(&self, info: &VideoInfo) -> usize {
+        let every = |seconds: f64| info.frames.div_ceil(frames_apart(info.frame_rate, seconds));
+        let frames = match *self {
+            Self::EveryNthFrame { n } => info.frames.div_ceil(n.max(1)),
+            Self::FramesPerSecond { fps } => every(1.0 / fps),
+            Self::SecondsInterval { seconds } => every(seconds),
+            Self::MaxFrames { frames } => frames.min(info.frames),
+            Self::IFramesOnly { max_frames } | Self::SceneChange { max_frames, .. } => {
+                max_frames.unwrap_or(1).min(info.frames)
+            }
+        };
+        frames.max(1)
+    }
+}
+
+/// Frames `seconds` apart at `frame_rate`, at least one.
+fn frames_apart(frame_rate: f64, seconds: f64) -> usize {
+    let frames = frame_rate * seconds;
+    if frames.is_finite() && frames >= 1.0 {
+        frames.round() as usize
+    } else {
+        1
+    }
+}
+
+/// Frames between two samples of the video at `video_path`, `None` for samplings that
+/// don't take every so many frames.
+fn video_stride(video_path: &str, sampling: FrameSampling) -> Option<usize> {
+    let seconds = match sampling {
+        FrameSampling::EveryNthFrame { n } => return Some(n.max(1)),
+        FrameSampling::FramesPerSecond { fps } => 1.0 / fps,
+        FrameSampling::SecondsInterval { seconds } => seconds,
+        _ => return None,
+    };
+    match probe_video(video_path) {
+        Ok(info) => Some(frames_apart(info.frame_rate, seconds)),
+        Err(e) => {
+            log::warn!("Sampling every frame of {}: {}", video_path, e);
+            Some(1)
+        }
+    }
+}
+
+/// Decodes the frames `sampling` takes of the video at `video_path`, scaled to fit
+/// `imgsz` and numbered as in the whole video. `spawned` is called with every ffmpeg
+/// started, such as to run it at the priority of the caller.
+pub fn decode_samples(
+    video_path: &str,
+    imgsz: usize,
+    sampling: FrameSampling,
+    spawned: &dyn Fn(&Child),
+) -> Result<DecodedVideo> {
+    let iframe = sampling.iframe();
+    let stride = video_stride(video_path, sampling).filter(|&n| n > 1);
+    let hdr = probe_hdr(video_path);
+    let sampled = match sampling {
+        FrameSampling::SceneChange { threshold, .. } => {
+            scene_samples(video_path, imgsz, threshold, hdr, spawned)
+        }
+        _ => seek_samples(
+            video_path,
+            imgsz,
+            iframe,
+            sampling.max_frames(),
+            hdr,
+            spawned,
+        ),
+    };
+    if let Some(decoded) = sampled {
+        return Ok(decoded);
+    }
+    let decode = |hdr| -> Result<DecodedVideo> {
+        let mut child = match stride {
+            Some(stride) => spawn_stride_decoder(video_path, imgsz, stride, hdr)?,
+            None => spawn_decoder(video_path, imgsz, iframe, hdr)?,
+        };
+        spawned(child.as_inner());
+        let mut decoded = read_frames(&mut child)?;
+        // frames a stride kept are numbered as in the whole video
+        for frame in &mut decoded.frames {
+            frame.frame_num *= stride.unwrap_or(1) as u32;
+        }
+        Ok(decoded)
+    };
+    match decode(hdr)? {
+        // an ffmpeg without zscale can't tone map, pale frames beat none
+        decoded if hdr && decoded.frames.is_empty() => {
+            log::warn!("Failed to tone map {}, decoding it as is", video_path);
+            decode(false)
+        }
+        decoded => Ok(decoded),
+    }
+}
+
+/// Decodes only the `max_frames` frames sampled from a long video, seeking to each of
+/// them. `None` when the video is short enough to be decoded whole, or when seeking
+/// gave nothing. Key frame runs decode whole, they skip most frames anyway.
+fn seek_samples(
+    video_path: &str,
+    imgsz: usize,
+    iframe: bool,
+    max_frames: Option<usize>,
+    hdr: bool,
+    spawned: &dyn Fn(&Child),
+) -> Option<DecodedVideo> {
+    let max_frames = max_frames.filter(|&n| n > 0 && !iframe)?;
+    let info = match probe_video(video_path) {
+        Ok(info) => info,
+        Err(e) => {
+            log::warn!("Decoding {} whole: {}", video_path, e);
+            return None;
+        }
+    };
+    if info.frames < max_frames.saturating_mul(SEEK_SPACING) {
+        return None;
+    }
+    let mut decoded = DecodedVideo::default();
+    for index in sample_indices(info.frames, max_frames) {
+        let output =
+            spawn_seek_decoder(video_path, &info, index, imgsz, hdr).and_then(|mut child| {
+                spawned(child.as_inner());
+                read_frames(&mut child)
+            });
+        match output {
+            Ok(mut output) => {
+                decoded.errors.append(&mut output.errors);
+                if let Some(mut frame) = output.frames.into_iter().next() {
+                    frame.frame_num = index as u32;
+                    decoded.frames.push(frame);
+                }
+            }
+            Err(e) => decoded.errors.push(e.to_string()),
+        }
+    }
+    if decoded.frames.is_empty() {
+        log::warn!(
+            "Seeking in {} gave no frames, decoding it whole",
+            video_path
+        );
+        return None;
+    }
+    Some(decoded)
+}
+
+/// Decodes only the frames where the scene changes by more than `threshold`, such as an
+/// animal walking in or out, and the first one. `None` when that gave no frames.
+fn scene_samples(
+    video_path: &str,
+    imgsz: usize,
+    threshold: f32,
+    hdr: bool,
+    spawned: &dyn Fn(&Child),
+) -> Option<DecodedVideo> {
+    let decoded = spawn_scene_decoder(video_path, imgsz, threshold, hdr).and_then(|mut child| {
+        spawned(child.as_inner());
+        read_scene_frames(&mut child)
+    });
+    match decoded {
+        Ok(decoded) if !decoded.frames.is_empty() => Some(decoded),
+        Ok(_) => {
+            log::warn!(
+                "Scene detection in {} gave no frames, decoding it whole",
+                video_path
+            );
+            None
+        }
+        Err(e) => {
+            log::warn!("Decoding {} whole: {}", video_path, e);
+            None
+        }
+    }
+}
+
+/// The frames of an animation starting at `starts` that `sampling` keeps, by index.
+/// Animations have no key frames and aren't scored for scene changes, sampling those
+/// keeps every frame.
+pub fn sample_animation(starts: &[Duration], sampling: FrameSampling) -> Vec<usize> {
+    let interval = match sampling {
+        FrameSampling::EveryNthFrame { n } => return (0..starts.len()).step_by(n.max(1)).collect(),
+        FrameSampling::FramesPerSecond { fps } => 1.0 / fps,
+        FrameSampling::SecondsInterval { seconds } => seconds,
+        FrameSampling::IFramesOnly { .. }
+        | FrameSampling::MaxFrames { .. }
+        | FrameSampling::SceneChange { .. } => 0.0,
+    };
+    let interval = Duration::try_from_secs_f64(interval).unwrap_or_default();
+    let mut due = Duration::ZERO;
+    let mut kept = Vec::new();
+    for (index, &start) in starts.iter().enumerate() {
+        if start >= due {
+            kept.push(index);
+            due = start + interval;
+        }
+    }
+    match sampling.max_frames() {
+        Some(n) => sample_evenly(&kept, n.min(kept.len())),
+        None => kept,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_sampling() {
+        let sampling: FrameSampling =
+            serde_json::from_str(r#"{"mode": "secondsInterval", "seconds": 2.0}"#).unwrap();
+        assert_eq!(sampling, FrameSampling::SecondsInterval { seconds: 2.0 });
+        let sampling: FrameSampling = serde_json::from_str(r#"{"mode": "iFramesOnly"}"#).unwrap();
+        assert_eq!(sampling, FrameSampling::IFramesOnly { max_frames: None });
+        assert_eq!(
+            serde_json::to_string(&FrameSampling::EveryNthFrame { n: 5 }).unwrap(),
+            r#"{"mode":"everyNthFrame","n":5}"#
+        );
+        let scene: FrameSampling =
+            serde_json::from_str(r#"{"mode": "sceneChange", "threshold": 0.3, "maxFrames": 8}"#)
+                .unwrap();
+        assert!(!scene.iframe());
+        assert_eq!(scene.max_frames(), Some(8));
+
+        let legacy = FrameSampling::legacy(true, Some(3));
+        assert!(legacy.iframe());
+        assert_eq!(legacy.max_frames(), Some(3));
+        assert_eq!(
+            FrameSampling::legacy(false, None),
+            FrameSampling::EveryNthFrame { n: 1 }
+        );
+        // a folder policy only changes what it sets
+        let stride = FrameSampling::FramesPerSecond { fps: 1.0 };
+        assert_eq!(stride.with_policy(None, None), stride);
+        assert_eq!(
+            stride.with_policy(None, Some(5)),
+            FrameSampling::MaxFrames { frames: 5 }
+        );
+        assert_eq!(
+            legacy.with_policy(Some(false), None),
+            FrameSampling::MaxFrames { frames: 3 }
+        );
+
+        assert_eq!(frames_apart(29.97, 1.0), 30);
+        assert_eq!(frames_apart(30.0, 1.0 / 4.0), 8);
+        assert_eq!(frames_apart(30.0, 0.001), 1);
+        assert_eq!(frames_apart(30.0, f64::INFINITY), 1);
+        assert_eq!(
+            video_stride("missing.mp4", stride.with_policy(Some(true), None)),
+            None
+        );
+        assert_eq!(
+            video_stride("missing.mp4", FrameSampling::EveryNthFrame { n: 0 }),
+            Some(1)
+        );
+
+        // a minute at 30 fps
+        let info = VideoInfo {
+            frame_rate: 30.0,
+            frames: 1800,
+        };
+        let expected = |sampling: FrameSampling| sampling.expected_frames(&info);
+        assert_eq!(expected(FrameSampling::EveryNthFrame { n: 0 }), 1800);
+        assert_eq!(expected(FrameSampling::FramesPerSecond { fps: 2.0 }), 120);
+        assert_eq!(expected(FrameSampling::SecondsInterval { seconds: 7.0 }), 9);
+        assert_eq!(expected(FrameSampling::MaxFrames { frames: 5 }), 5);
+        assert_eq!(expected(FrameSampling::IFramesOnly { max_frames: None }), 1);
+        assert_eq!(expected(scene), 8);
+    }
+
+    #[test]
+    fn test_sample_animation() {
+        // a clip of 10 frames shown 250 ms each
+        let starts: Vec<Duration> = (0..10).map(|i| Duration::from_millis(i * 250)).collect();
+        let sample = |sampling| sample_animation(&starts, sampling);
+        assert_eq!(sample(FrameSampling::EveryNthFrame { n: 4 }), [0, 4, 8]);
+        assert_eq!(
+            sample(FrameSampling::FramesPerSecond { fps: 2.0 }),
+            [0, 2, 4, 6, 8]
+        );
+        assert_eq!(
+            sample(FrameSampling::SecondsInterval { seconds: 1.0 }),
+            [0, 4, 8]
+        );
+        assert_eq!(sample(FrameSampling::MaxFrames { frames: 3 }), [0, 3, 6]);
+        assert_eq!(sample(FrameSampling::MaxFrames { frames: 20 }).len(), 10);
+        assert_eq!(
+            sample(FrameSampling::IFramesOnly { max_frames: None }),
+            (0..10).collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,222 @@
+use std::fs::metadata;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, TimeZone};
+use nom_exif::{EntryValue, Exif, ExifIter, ExifTag, MediaParser, MediaSource};
+use serde::{Deserialize, Serialize};
+
+use crate::video::probe_creation_time;
+
+/// EXIF `BodySerialNumber` tag.
+const BODY_SERIAL_NUMBER_TAG: u16 = 0xa431;
+
+/// The camera an image was shot with, so the cameras of a deployment can be told apart.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct CameraInfo {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub make: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// `BodySerialNumber`, which not every firmware writes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serial_number: Option<String>,
+}
+
+/// When, where and with what an image was shot, as far as its EXIF tells.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ImageMetadata {
+    pub shoot_time: Option<DateTime<Local>>,
+    /// Latitude and longitude in decimal degrees, from `GPSLatitude` and `GPSLongitude`.
+    pub position: Option<(f64, f64)>,
+    pub camera: CameraInfo,
+}
+
+/// A text tag without the padding firmwares leave, `None` when nothing is left.
+fn entry_text(value: &EntryValue) -> Option<String> {
+    let text = value.to_string();
+    let text = text.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+/// Latitude and longitude of an ISO 6709 position like `+22.53113+114.02148/`.
+pub fn parse_iso6709(position: &str) -> Option<(f64, f64)> {
+    fn signed(s: &str) -> Option<(f64, &str)> {
+        let end = s
+            .char_indices()
+            .skip(1)
+            .find(|(_, c)| !(c.is_ascii_digit() || *c == '.'))
+            .map_or(s.len(), |(i, _)| i);
+        Some((s[..end].parse().ok()?, &s[end..]))
+    }
+    let (latitude, rest) = signed(position.trim())?;
+    // an altitude or CRS may follow the longitude
+    let (longitude, _) = signed(rest)?;
+    ((-90.0..=90.0).contains(&latitude) && (-180.0..=180.0).contains(&longitude))
+        .then_some((latitude, longitude))
+}
+
+/// When, where and with what an image was shot, fails only when its EXIF can't be read
+/// at all.
+pub fn get_image_metadata(parser: &mut MediaParser, image: &Path) -> Result<ImageMetadata> {
+    let ms = MediaSource::file_path(image)?;
+    let iter: ExifIter = parser.parse(ms)?;
+    let mut metadata = ImageMetadata {
+        position: iter
+            .parse_gps_info()
+            .ok()
+            .flatten()
+            .and_then(|gps| parse_iso6709(&gps.format_iso6709())),
+        ..Default::default()
+    };
+    let (mut original, mut modified) = (None, None);
+    // the tags of the main image come before the thumbnail's
+    for entry in iter {
+        let Some(value) = entry.get_value() else {
+            continue;
+        };
+        let camera = &mut metadata.camera;
+        match entry.tag() {
+            Some(ExifTag::DateTimeOriginal) if original.is_none() => {
+                original = Some(entry_time(value, image))
+            }
+            Some(ExifTag::ModifyDate) if modified.is_none() => {
+                modified = Some(entry_time(value, image))
+            }
+            Some(ExifTag::Make) if camera.make.is_none() => camera.make = entry_text(value),
+            Some(ExifTag::Model) if camera.model.is_none() => camera.model = entry_text(value),
+            _ if entry.tag_code() == BODY_SERIAL_NUMBER_TAG && camera.serial_number.is_none() => {
+                camera.serial_number = entry_text(value)
+            }
+            _ => (),
+        }
+    }
+    metadata.shoot_time = match original.or(modified) {
+        Some(Ok(shoot_time)) => Some(shoot_time),
+        Some(Err(e)) => {
+            log::debug!("No shoot time in {}: {}", image.display(), e);
+            None
+        }
+        None => None,
+    };
+    Ok(metadata)
+}
+
+/// When an image was shot from its EXIF `DateTimeOriginal`, or `ModifyDate` without one.
+pub fn get_image_date(parser: &mut MediaParser, image: &Path) -> Result<DateTime<Local>> {
+    let ms = MediaSource::file_path(image)?;
+    let iter: ExifIter = parser.parse(ms)?;
+    let exif: Exif = iter.into();
+    let shoot_time_tag = exif
+        .get(ExifTag::DateTimeOriginal)
+        .or_else(|| exif.get(ExifTag::ModifyDate))
+        .context("Neither DateTimeOriginal nor ModifyDate found")?;
+    entry_time(shoot_time_tag, image)
+}
+
+/// A time tag in the local time zone when it has none.
+fn entry_time(value: &EntryValue, image: &Path) -> Result<DateTime<Local>> {
+    let shoot_time = match value {
+        EntryValue::Time(time) => time.with_timezone(&Local),
+        EntryValue::NaiveDateTime(time) => {
+            Local.from_local_datetime(time).single().ok_or_else(|| {
+                anyhow::anyhow!("Ambiguous local time for image: {}", image.display())
+            })?
+        }
+        _ => {
+            return Err(anyhow::anyhow!(
+                "Unexpected EXIF time data format for image: {}",
+                image.display()
+            ))
+        }
+    };
+    Ok(shoot_time)
+}
+
+/// When a video was shot, from the `creation_time` tag of its container, or the file
+/// times for the cameras that don't tag their videos.
+pub fn get_video_date(video: &Path) -> Result<DateTime<Local>> {
+    let metadata = metadata(video)?;
+    if let Some(shoot_time) = probe_creation_time(&video.to_string_lossy()) {
+        return Ok(shoot_time);
+    }
+    #[cfg(target_os = "windows")]
+    {
+        let m_time = metadata.modified()?;
+        let shoot_time: DateTime<Local> = m_time.clone().into();
+
+        Ok(shoot_time)
+    }
+
+    #[cfg(target_os = "linux")]
+    #[allow(deprecated)]
+    {
+        use chrono::NaiveDateTime;
+        use std::os::linux::fs::MetadataExt;
+        let m_time: i64 = metadata.st_mtime();
+        let c_time: i64 = metadata.st_ctime();
+        let shoot_time = m_time.min(c_time);
+        let offset = Local::now().offset().to_owned();
+        let shoot_time = NaiveDateTime::from_timestamp(shoot_time, 0);
+        let shoot_time = DateTime::<Local>::from_naive_utc_and_offset(shoot_time, offset);
+
+        Ok(shoot_time)
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        use chrono::NaiveDateTime;
+        use std::os::unix::fs::MetadataExt;
+        let m_time: i64 = metadata.mtime();
+        let c_time: i64 = metadata.ctime();
+        let shoot_time = m_time.min(c_time);
+        let offset = Local::now().offset().to_owned();
+        let shoot_time = NaiveDateTime::from_timestamp(shoot_time, 0);
+        let shoot_time = DateTime::<Local>::from_naive_utc_and_offset(shoot_time, offset);
+
+        Ok(shoot_time)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shoot_time() {
+        let dir = std::env::temp_dir().join(format!("megascops-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("untagged.png");
+        image::RgbImage::new(8, 8).save(&path).unwrap();
+        let mut parser = MediaParser::new();
+        assert!(get_image_date(&mut parser, &path).is_err());
+        assert!(get_image_metadata(&mut parser, &path).map_or(true, |m| m.position.is_none()));
+        let shot = get_video_date(&path).unwrap();
+        assert!((Local::now() - shot).num_seconds().abs() < 60);
+        assert!(get_video_date(&dir.join("missing.mp4")).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_entry_text() {
+        assert_eq!(
+            entry_text(&EntryValue::Text("Browning  \0\0".to_string())),
+            Some("Browning".to_string())
+        );
+        assert_eq!(entry_text(&EntryValue::Text(" \0".to_string())), None);
+    }
+
+    #[test]
+    fn test_parse_iso6709() {
+        assert_eq!(
+            parse_iso6709("+22.53113+114.02148/"),
+            Some((22.53113, 114.02148))
+        );
+        assert_eq!(
+            parse_iso6709("-33.9+018.4+12.0CRSWGS_84/"),
+            Some((-33.9, 18.4))
+        );
+        assert_eq!(parse_iso6709("+95.0+010.0/"), None);
+        assert_eq!(parse_iso6709("garbage"), None);
+    }
+}
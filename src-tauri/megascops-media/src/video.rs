@@ -0,0 +1,535 @@
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::str;
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Datelike, Local, NaiveDateTime, TimeZone};
+use ffmpeg_sidecar::child::FfmpegChild;
+use ffmpeg_sidecar::command::FfmpegCommand;
+use ffmpeg_sidecar::event::{FfmpegEvent, LogLevel, OutputVideoFrame};
+use ffmpeg_sidecar::ffprobe::ffprobe_path;
+use image::DynamicImage;
+
+use crate::MediaError;
+
+/// Transfer functions of HDR video, PQ and HLG.
+const HDR_TRANSFERS: [&str; 2] = ["smpte2084", "arib-std-b67"];
+/// Tone maps HDR video to 8 bit BT.709, which a plain conversion to RGB leaves pale and
+/// flat. zscale needs an ffmpeg built with zimg, as the released builds are.
+const TONE_MAP_FILTER: &str = "zscale=t=linear:npl=100,format=gbrpf32le,zscale=p=bt709,\
+                               tonemap=tonemap=hable:desat=0,zscale=t=bt709:m=bt709:r=tv,\
+                               format=yuv420p";
+
+/// Width and height of the first video stream, read with ffprobe.
+pub fn get_video_dimensions(video_path: &str) -> Result<(usize, usize)> {
+    let mut command = Command::new(ffprobe_path());
+
+    command.args([
+        "-v",
+        "error",
+        "-select_streams",
+        "v:0",
+        "-show_entries",
+        "stream=width,height",
+        "-of",
+        "csv=s=x:p=0",
+        video_path,
+    ]);
+
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        const CREATE_NO_WINDOW: u32 = 0x08000000;
+        command.creation_flags(CREATE_NO_WINDOW);
+    }
+
+    let output = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output()?;
+
+    let dimensions = str::from_utf8(&output.stdout)?;
+    let parts: Vec<&str> = dimensions.trim().split('x').collect();
+
+    if parts.len() == 2 {
+        let width = parts[0].parse::<usize>()?;
+        let height = parts[1].parse::<usize>()?;
+        Ok((width, height))
+    } else {
+        Err(anyhow!(
+            "Invalid video dimensions: {}, video path: {}",
+            dimensions,
+            video_path
+        ))
+    }
+}
+
+/// Frame rate and length of the first video stream.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VideoInfo {
+    pub frame_rate: f64,
+    pub frames: usize,
+}
+
+/// `num/den` or a plain number as ffprobe writes rates.
+fn parse_rate(rate: &str) -> Option<f64> {
+    let rate = match rate.split_once('/') {
+        Some((num, den)) => num.parse::<f64>().ok()? / den.parse::<f64>().ok()?,
+        None => rate.parse().ok()?,
+    };
+    (rate.is_finite() && rate > 0.0).then_some(rate)
+}
+
+/// Reads the `key=value` lines of ffprobe. Containers that don't store the number of
+/// frames have it estimated from the duration.
+fn parse_video_info(output: &str) -> Option<VideoInfo> {
+    let (mut frame_rate, mut frames, mut duration) = (None, None, None);
+    for line in output.lines() {
+        match line.trim().split_once('=') {
+            Some(("avg_frame_rate", rate)) => frame_rate = frame_rate.or(parse_rate(rate)),
+            Some(("nb_frames", count)) => {
+                frames = frames.or(count.parse::<usize>().ok().filter(|&n| n > 0))
+            }
+            // the stream's duration comes first, the container's after it
+            Some(("duration", seconds)) => {
+                duration = duration.or(seconds.parse::<f64>().ok().filter(|&d| d > 0.0))
+            }
+            _ => (),
+        }
+    }
+    let frame_rate = frame_rate?;
+    let frames = frames.or_else(|| duration.map(|d| (d * frame_rate).round() as usize))?;
+    Some(VideoInfo { frame_rate, frames })
+}
+
+/// Frame rate and number of frames of the first video stream, read with ffprobe.
+pub fn probe_video(video_path: &str) -> Result<VideoInfo> {
+    let mut command = Command::new(ffprobe_path());
+
+    command.args([
+        "-v",
+        "error",
+        "-select_streams",
+        "v:0",
+        "-show_entries",
+        "stream=avg_frame_rate,nb_frames,duration:format=duration",
+        "-of",
+        "default=nw=1",
+        video_path,
+    ]);
+
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        const CREATE_NO_WINDOW: u32 = 0x08000000;
+        command.creation_flags(CREATE_NO_WINDOW);
+    }
+
+    let output = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output()?;
+    parse_video_info(str::from_utf8(&output.stdout)?)
+        .ok_or_else(|| anyhow!("Failed to read the frame rate of {}", video_path))
+}
+
+/// Whether the first video stream is HDR, read with ffprobe. 10 bit SDR video needs no
+/// more than the conversion to RGB every decode does.
+pub fn probe_hdr(video_path: &str) -> bool {
+    let mut command = Command::new(ffprobe_path());
+
+    command.args([
+        "-v",
+        "error",
+        "-select_streams",
+        "v:0",
+        "-show_entries",
+        "stream=color_transfer",
+        "-of",
+        "default=nw=1:nk=1",
+        video_path,
+    ]);
+
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        const CREATE_NO_WINDOW: u32 = 0x08000000;
+        command.creation_flags(CREATE_NO_WINDOW);
+    }
+
+    command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output()
+        .ok()
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .is_some_and(|transfer| HDR_TRANSFERS.contains(&transfer.trim()))
+}
+
+/// `filter` after the tone mapping of HDR video.
+fn with_tone_map(filter: &str, hdr: bool) -> String {
+    if hdr {
+        format!("{},{}", TONE_MAP_FILTER, filter)
+    } else {
+        filter.to_string()
+    }
+}
+
+/// Scales to fit in `imgsz` as every decoder for detection does.
+fn scale_filter(imgsz: usize, hdr: bool) -> String {
+    with_tone_map(
+        &format!(
+            "scale=w={}:h={}:force_original_aspect_ratio=decrease",
+            imgsz, imgsz
+        ),
+        hdr,
+    )
+}
+
+/// Containers whose recording time wasn't set hold the epoch of their format instead.
+const MIN_CREATION_YEAR: i32 = 1980;
+
+/// The first `creation_time` tag ffprobe printed that is a real time. Tags are UTC in
+/// QuickTime and Matroska, a time without a zone is taken as local.
+fn parse_creation_time(output: &str) -> Option<DateTime<Local>> {
+    output.lines().map(str::trim).find_map(|tag| {
+        let time = match DateTime::parse_from_rfc3339(tag) {
+            Ok(time) => time.with_timezone(&Local),
+            Err(_) => {
+                let time = NaiveDateTime::parse_from_str(tag, "%Y-%m-%d %H:%M:%S").ok()?;
+                Local.from_local_datetime(&time).earliest()?
+            }
+        };
+        (time.year() >= MIN_CREATION_YEAR).then_some(time)
+    })
+}
+
+/// When the container says the video was recorded, from the `creation_time` tag of the
+/// format or else of a stream. Unlike the file times it survives copying off the card.
+pub(crate) fn probe_creation_time(video_path: &str) -> Option<DateTime<Local>> {
+    let mut command = Command::new(ffprobe_path());
+
+    command.args([
+        "-v",
+        "error",
+        "-show_entries",
+        "format_tags=creation_time:stream_tags=creation_time",
+        "-of",
+        "default=nw=1:nk=1",
+        video_path,
+    ]);
+
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        const CREATE_NO_WINDOW: u32 = 0x08000000;
+        command.creation_flags(CREATE_NO_WINDOW);
+    }
+
+    let output = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output()
+        .ok()?;
+    parse_creation_time(str::from_utf8(&output.stdout).ok()?)
+}
+
+/// Decodes frame `index` of a video at full resolution, counting frames the same way
+/// as [`spawn_decoder`] so exported frame indices can be looked up again.
+pub fn extract_frame(video_path: &Path, index: usize, iframe: bool) -> Result<DynamicImage> {
+    let hdr = probe_hdr(&video_path.to_string_lossy());
+    let mut ffmpeg_command = FfmpegCommand::new();
+    if iframe {
+        ffmpeg_command.args(["-skip_frame", "nokey"]);
+    }
+    let iter = ffmpeg_command
+        .input(video_path.to_string_lossy())
+        .args([
+            "-an",
+            "-vf",
+            &with_tone_map(&format!("select=eq(n\\,{})", index), hdr),
+            "-frames:v",
+            "1",
+            "-f",
+            "rawvideo",
+            "-pix_fmt",
+            "rgb24",
+            "-vsync",
+            "vfr",
+        ])
+        .output("-")
+        .spawn()?
+        .iter()?;
+    for event in iter {
+        if let FfmpegEvent::OutputFrame(frame) = event {
+            let img = image::RgbImage::from_raw(frame.width, frame.height, frame.data)
+                .context("Invalid frame size")?;
+            return Ok(DynamicImage::ImageRgb8(img));
+        }
+    }
+    Err(MediaError::VideoDecodeError(video_path.to_string_lossy().into_owned()).into())
+}
+
+/// Starts ffmpeg decoding every frame of a video to RGB, scaled to fit in `imgsz`, or
+/// only the key frames with `iframe`. `hdr` video, see [`probe_hdr`], is tone mapped
+/// first. The child is handed out so its priority can be set before [`read_frames`]
+/// drains it.
+pub fn spawn_decoder(
+    video_path: &str,
+    imgsz: usize,
+    iframe: bool,
+    hdr: bool,
+) -> Result<FfmpegChild> {
+    let mut ffmpeg_command = FfmpegCommand::new();
+    if iframe {
+        ffmpeg_command.args(["-skip_frame", "nokey"]);
+    }
+    let child = ffmpeg_command
+        .input(video_path)
+        .args([
+            "-an",
+            "-vf",
+            &scale_filter(imgsz, hdr),
+            "-f",
+            "rawvideo",
+            "-pix_fmt",
+            "rgb24",
+            "-vsync",
+            "vfr",
+        ])
+        .output("-")
+        .spawn()?;
+    Ok(child)
+}
+
+/// Starts ffmpeg decoding every `stride`th frame of a video from the first, scaled like
+/// [`spawn_decoder`]. The frames come numbered in the order they were kept, the frame
+/// number in the whole video is that times `stride`.
+pub fn spawn_stride_decoder(
+    video_path: &str,
+    imgsz: usize,
+    stride: usize,
+    hdr: bool,
+) -> Result<FfmpegChild> {
+    let filters = with_tone_map(
+        &format!(
+            "select=not(mod(n\\,{})),{}",
+            stride,
+            scale_filter(imgsz, false)
+        ),
+        hdr,
+    );
+    let child = FfmpegCommand::new()
+        .input(video_path)
+        .args([
+            "-an", "-vf", &filters, "-f", "rawvideo", "-pix_fmt", "rgb24", "-vsync", "vfr",
+        ])
+        .output("-")
+        .spawn()?;
+    Ok(child)
+}
+
+/// Starts ffmpeg decoding the single frame `index` of a video at `info`'s frame rate,
+/// scaled like [`spawn_decoder`]. ffmpeg seeks to the key frame before it and decodes
+/// from there only, far less than the whole video when few frames of a long one are
+/// sampled.
+pub fn spawn_seek_decoder(
+    video_path: &str,
+    info: &VideoInfo,
+    index: usize,
+    imgsz: usize,
+    hdr: bool,
+) -> Result<FfmpegChild> {
+    let time = index as f64 / info.frame_rate;
+    let child = FfmpegCommand::new()
+        .args(["-ss", &format!("{:.6}", time)])
+        .input(video_path)
+        .args([
+            "-an",
+            "-vf",
+            &scale_filter(imgsz, hdr),
+            "-frames:v",
+            "1",
+            "-f",
+            "rawvideo",
+            "-pix_fmt",
+            "rgb24",
+        ])
+        .output("-")
+        .spawn()?;
+    Ok(child)
+}
+
+/// Starts ffmpeg decoding only the first frame of a video and those whose scene change
+/// score, from 0 to 1, is above `threshold`, scaled like [`spawn_decoder`]. Every frame
+/// is still decoded to be scored, but only the kept ones are converted and sent. Read it
+/// with [`read_scene_frames`] to get their frame numbers.
+pub fn spawn_scene_decoder(
+    video_path: &str,
+    imgsz: usize,
+    threshold: f32,
+    hdr: bool,
+) -> Result<FfmpegChild> {
+    // the timestamps are set to the frame numbers so showinfo reports those of the input,
+    // scenes are scored on the tone mapped frames
+    let filters = with_tone_map(
+        &format!(
+            "setpts=N,select=eq(n\\,0)+gt(scene\\,{}),showinfo,{}",
+            threshold,
+            scale_filter(imgsz, false)
+        ),
+        hdr,
+    );
+    let child = FfmpegCommand::new()
+        .input(video_path)
+        .args([
+            "-an", "-vf", &filters, "-f", "rawvideo", "-pix_fmt", "rgb24", "-vsync", "vfr",
+        ])
+        .output("-")
+        .spawn()?;
+    Ok(child)
+}
+
+/// Frame number of a frame showinfo describes, its `pts` once set to the frame number.
+fn parse_showinfo_frame(line: &str) -> Option<u32> {
+    if !line.contains("Parsed_showinfo") {
+        return None;
+    }
+    let (_, rest) = line.split_once(" pts:")?;
+    rest.split_whitespace().next()?.parse().ok()
+}
+
+/// Reads the frames of [`spawn_scene_decoder`], numbered as in the whole video rather
+/// than in the order they were kept.
+pub fn read_scene_frames(child: &mut FfmpegChild) -> Result<DecodedVideo> {
+    let mut decoded = DecodedVideo::default();
+    let mut numbers = Vec::new();
+    for event in child.iter()? {
+        match event {
+            FfmpegEvent::Error(e) | FfmpegEvent::Log(LogLevel::Error, e) => {
+                decoded.errors.push(e);
+            }
+            FfmpegEvent::Log(_, line) => numbers.extend(parse_showinfo_frame(&line)),
+            FfmpegEvent::OutputFrame(frame) => {
+                decoded.frames.push(frame);
+            }
+            _ => (),
+        }
+    }
+    for (frame, number) in decoded.frames.iter_mut().zip(numbers) {
+        frame.frame_num = number;
+    }
+    Ok(decoded)
+}
+
+/// Frames a decoder put out, with the errors ffmpeg reported on the way.
+#[derive(Debug, Default)]
+pub struct DecodedVideo {
+    pub frames: Vec<OutputVideoFrame>,
+    pub errors: Vec<String>,
+}
+
+/// Reads every frame `child` decodes, until ffmpeg exits.
+pub fn read_frames(child: &mut FfmpegChild) -> Result<DecodedVideo> {
+    let mut decoded = DecodedVideo::default();
+    for event in child.iter()? {
+        match event {
+            FfmpegEvent::Error(e) | FfmpegEvent::Log(LogLevel::Error, e) => {
+                decoded.errors.push(e);
+            }
+            FfmpegEvent::OutputFrame(frame) => {
+                decoded.frames.push(frame);
+            }
+            _ => (),
+        }
+    }
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_broken_video() {
+        let dir = std::env::temp_dir().join(format!("megascops-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("broken.mp4");
+        std::fs::write(&path, b"not a video").unwrap();
+        assert!(get_video_dimensions(&path.to_string_lossy()).is_err());
+        assert!(probe_video(&path.to_string_lossy()).is_err());
+        assert!(extract_frame(&path, 0, false).is_err());
+        // without ffmpeg installed the decoder can't even start
+        assert!(!probe_hdr(&path.to_string_lossy()));
+        if let Ok(mut child) = spawn_decoder(&path.to_string_lossy(), 640, false, false) {
+            assert!(read_frames(&mut child).unwrap().frames.is_empty());
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_showinfo_frame() {
+        let line = "[Parsed_showinfo_2 @ 0x55d0c8a0] n:   3 pts:   1520 pts_time:1520    \
+                    duration:      1 fmt:yuv420p sar:1/1 s:1920x1080 i:P iskey:0 type:P";
+        assert_eq!(parse_showinfo_frame(line), Some(1520));
+        let line = "[Parsed_showinfo_2 @ 0x55d0c8a0] n:1204 pts:987654 pts_time:987654";
+        assert_eq!(parse_showinfo_frame(line), Some(987_654));
+        // side data lines of a frame carry no timestamp
+        let line = "[Parsed_showinfo_2 @ 0x55d0c8a0]   color_range:tv color_space:bt709";
+        assert_eq!(parse_showinfo_frame(line), None);
+        assert_eq!(
+            parse_showinfo_frame("frame=  12 fps=0.0 q=-0.0 pts: 4"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_creation_time() {
+        let time =
+            parse_creation_time("2024-05-03T10:22:11.000000Z\n2024-05-03T10:22:11.000000Z\n");
+        assert_eq!(
+            time.map(|t| t.with_timezone(&chrono::Utc).to_rfc3339()),
+            Some("2024-05-03T10:22:11+00:00".to_string())
+        );
+        let time = parse_creation_time("2023-11-20 06:41:02\n").unwrap();
+        assert_eq!(time.naive_local().to_string(), "2023-11-20 06:41:02");
+        // unset fields hold the epoch, the stream's tag is used instead
+        let time = parse_creation_time("1970-01-01T00:00:00.000000Z\n2021-07-14T21:05:00Z\n");
+        assert_eq!(time.map(|t| t.year()), Some(2021));
+        assert_eq!(parse_creation_time("1904-01-01T00:00:00Z\n"), None);
+        assert_eq!(parse_creation_time(""), None);
+    }
+
+    #[test]
+    fn test_tone_map() {
+        assert_eq!(
+            scale_filter(640, false),
+            "scale=w=640:h=640:force_original_aspect_ratio=decrease"
+        );
+        let filter = scale_filter(640, true);
+        assert!(filter.starts_with("zscale=t=linear:npl=100,format=gbrpf32le,zscale=p=bt709,"));
+        assert!(filter
+            .ends_with(",format=yuv420p,scale=w=640:h=640:force_original_aspect_ratio=decrease"));
+        assert!(!filter.contains(' '));
+    }
+
+    #[test]
+    fn test_video_info() {
+        let info = parse_video_info(
+            "avg_frame_rate=30000/1001\nnb_frames=107892\nduration=3600.033\nduration=3600.100\n",
+        );
+        assert_eq!(info.unwrap().frames, 107_892);
+        assert!((info.unwrap().frame_rate - 29.97).abs() < 0.01);
+        // Matroska keeps no frame count, nor a stream duration
+        let info =
+            parse_video_info("avg_frame_rate=25/1\nnb_frames=N/A\nduration=N/A\nduration=7200.0\n");
+        assert_eq!(
+            info,
+            Some(VideoInfo {
+                frame_rate: 25.0,
+                frames: 180_000
+            })
+        );
+        assert_eq!(parse_video_info("avg_frame_rate=0/0\nnb_frames=10\n"), None);
+    }
+}
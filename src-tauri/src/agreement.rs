@@ -0,0 +1,182 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::review::{session_store, FrameRef, Verdict};
+
+/// Agreement of two reviewers on whether frames show `class`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClassAgreement {
+    pub class: String,
+    /// Share of the frames both gave the same answer on.
+    pub observed: f64,
+    /// Cohen's kappa, `None` when chance agreement is certain, e.g. neither saw the class.
+    pub kappa: Option<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PairAgreement {
+    pub reviewers: (String, String),
+    /// Frames both reviewed.
+    pub frames: usize,
+    pub classes: Vec<ClassAgreement>,
+}
+
+/// A frame reviewers labeled differently, to adjudicate.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Disagreement {
+    pub frame: FrameRef,
+    /// Labels by reviewer.
+    pub labels: BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AgreementReport {
+    pub pairs: Vec<PairAgreement>,
+    pub disagreements: Vec<Disagreement>,
+}
+
+fn cohen_kappa(a: &[bool], b: &[bool]) -> (f64, Option<f64>) {
+    let n = a.len() as f64;
+    let agreed = a.iter().zip(b).filter(|(x, y)| x == y).count() as f64;
+    let yes_a = a.iter().filter(|x| **x).count() as f64 / n;
+    let yes_b = b.iter().filter(|x| **x).count() as f64 / n;
+    let observed = agreed / n;
+    let chance = yes_a * yes_b + (1.0 - yes_a) * (1.0 - yes_b);
+    let kappa = (chance < 1.0).then(|| (observed - chance) / (1.0 - chance));
+    (observed, kappa)
+}
+
+/// Cohen's kappa per class for every pair of reviewers over the frames both reviewed, and
+/// the frames where their labels differ.
+pub fn agreement(verdicts: &[Verdict]) -> AgreementReport {
+    let mut frames: BTreeMap<&FrameRef, BTreeMap<&str, BTreeSet<&str>>> = BTreeMap::new();
+    for verdict in verdicts {
+        frames.entry(&verdict.frame).or_default().insert(
+            &verdict.reviewer,
+            verdict.labels.iter().map(String::as_str).collect(),
+        );
+    }
+    let reviewers: BTreeSet<&str> = verdicts.iter().map(|v| v.reviewer.as_str()).collect();
+    let reviewers: Vec<&str> = reviewers.into_iter().collect();
+
+    let mut pairs = Vec::new();
+    for (i, first) in reviewers.iter().enumerate() {
+        for second in &reviewers[i + 1..] {
+            let shared: Vec<(&BTreeSet<&str>, &BTreeSet<&str>)> = frames
+                .values()
+                .filter_map(|by| Some((by.get(first)?, by.get(second)?)))
+                .collect();
+            if shared.is_empty() {
+                continue;
+            }
+            let classes: BTreeSet<&str> = shared
+                .iter()
+                .flat_map(|(a, b)| a.iter().chain(b.iter()))
+                .copied()
+                .collect();
+            pairs.push(PairAgreement {
+                reviewers: (first.to_string(), second.to_string()),
+                frames: shared.len(),
+                classes: classes
+                    .into_iter()
+                    .map(|class| {
+                        let a: Vec<bool> = shared.iter().map(|(a, _)| a.contains(class)).collect();
+                        let b: Vec<bool> = shared.iter().map(|(_, b)| b.contains(class)).collect();
+                        let (observed, kappa) = cohen_kappa(&a, &b);
+                        ClassAgreement {
+                            class: class.to_string(),
+                            observed,
+                            kappa,
+                        }
+                    })
+                    .collect(),
+            });
+        }
+    }
+
+    let disagreements = frames
+        .into_iter()
+        .filter(|(_, by)| by.values().any(|labels| Some(labels) != by.values().next()))
+        .map(|(frame, by)| Disagreement {
+            frame: frame.clone(),
+            labels: by
+                .into_iter()
+                .map(|(reviewer, labels)| {
+                    let labels = labels.into_iter().map(str::to_string).collect();
+                    (reviewer.to_string(), labels)
+                })
+                .collect(),
+        })
+        .collect();
+    AgreementReport {
+        pairs,
+        disagreements,
+    }
+}
+
+/// Agreement report of the verdicts recorded for a result file.
+pub fn agreement_report(result: &Path) -> Result<AgreementReport> {
+    let verdicts = session_store(result)?.verdicts()?;
+    log::info!(
+        "Comparing {} verdicts of {}",
+        verdicts.len(),
+        result.display()
+    );
+    Ok(agreement(&verdicts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn verdict(reviewer: &str, file_path: &str, labels: &[&str]) -> Verdict {
+        Verdict {
+            reviewer: reviewer.to_string(),
+            frame: FrameRef {
+                file_path: file_path.to_string(),
+                frame_index: 0,
+            },
+            labels: labels.iter().map(|l| l.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_agreement() {
+        let verdicts = vec![
+            verdict("ann", "a.jpg", &["Deer"]),
+            verdict("bob", "a.jpg", &["Deer"]),
+            verdict("ann", "b.jpg", &["Deer"]),
+            verdict("bob", "b.jpg", &["Boar"]),
+            verdict("ann", "c.jpg", &["Blank"]),
+            verdict("bob", "c.jpg", &["Blank"]),
+            verdict("ann", "d.jpg", &["Boar"]),
+            verdict("bob", "d.jpg", &["Boar"]),
+            // only one reviewer saw these
+            verdict("ann", "e.jpg", &["Deer"]),
+            verdict("cat", "f.jpg", &["Deer"]),
+        ];
+        let report = agreement(&verdicts);
+        assert_eq!(report.pairs.len(), 1);
+        let pair = &report.pairs[0];
+        assert_eq!(pair.reviewers, ("ann".to_string(), "bob".to_string()));
+        assert_eq!(pair.frames, 4);
+        let deer = pair.classes.iter().find(|c| c.class == "Deer").unwrap();
+        // ann said deer twice, bob once, on four frames
+        assert_eq!(deer.observed, 0.75);
+        assert!((deer.kappa.unwrap() - 0.5).abs() < 1e-9);
+        let blank = pair.classes.iter().find(|c| c.class == "Blank").unwrap();
+        assert_eq!(blank.kappa, Some(1.0));
+
+        assert_eq!(report.disagreements.len(), 1);
+        assert_eq!(report.disagreements[0].frame.file_path, "b.jpg");
+        assert_eq!(report.disagreements[0].labels["bob"], ["Boar"]);
+        assert_eq!(cohen_kappa(&[false, false], &[false, false]), (1.0, None));
+    }
+}
@@ -0,0 +1,320 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use image::{DynamicImage, Rgb, RgbImage};
+use serde::Serialize;
+
+use crate::annotation::preview_name;
+use crate::contact_sheet::{draw_rect, is_positive, load_frame, BOX_COLOR};
+use crate::export::{load_export, Bbox, ExportFrame};
+use crate::organize::file_label;
+use crate::template::{self, TemplateContext};
+use crate::utils::{is_video, portable_path};
+
+/// Folder of the annotated copies with the default template.
+pub const ANNOTATED_DIR: &str = "annotated";
+
+const TEXT_COLOR: Rgb<u8> = Rgb([255, 255, 255]);
+const GLYPH_WIDTH: u32 = 5;
+const GLYPH_HEIGHT: u32 = 7;
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AnnotateSummary {
+    pub output: PathBuf,
+    pub written: usize,
+    pub failed: Vec<(String, String)>,
+}
+
+/// Rows of a 5x7 glyph, the high bit of the five on the left. Letters are drawn upper
+/// case, characters without a glyph as `?`.
+fn glyph(c: char) -> [u8; 7] {
+    match c.to_ascii_uppercase() {
+        'A' => [
+            0b01110, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001,
+        ],
+        'B' => [
+            0b11110, 0b10001, 0b10001, 0b11110, 0b10001, 0b10001, 0b11110,
+        ],
+        'C' => [
+            0b01110, 0b10001, 0b10000, 0b10000, 0b10000, 0b10001, 0b01110,
+        ],
+        'D' => [
+            0b11110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b11110,
+        ],
+        'E' => [
+            0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b11111,
+        ],
+        'F' => [
+            0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b10000,
+        ],
+        'G' => [
+            0b01110, 0b10001, 0b10000, 0b10111, 0b10001, 0b10001, 0b01111,
+        ],
+        'H' => [
+            0b10001, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001,
+        ],
+        'I' => [
+            0b01110, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110,
+        ],
+        'J' => [
+            0b00111, 0b00010, 0b00010, 0b00010, 0b00010, 0b10010, 0b01100,
+        ],
+        'K' => [
+            0b10001, 0b10010, 0b10100, 0b11000, 0b10100, 0b10010, 0b10001,
+        ],
+        'L' => [
+            0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b11111,
+        ],
+        'M' => [
+            0b10001, 0b11011, 0b10101, 0b10101, 0b10001, 0b10001, 0b10001,
+        ],
+        'N' => [
+            0b10001, 0b10001, 0b11001, 0b10101, 0b10011, 0b10001, 0b10001,
+        ],
+        'O' => [
+            0b01110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110,
+        ],
+        'P' => [
+            0b11110, 0b10001, 0b10001, 0b11110, 0b10000, 0b10000, 0b10000,
+        ],
+        'Q' => [
+            0b01110, 0b10001, 0b10001, 0b10001, 0b10101, 0b10010, 0b01101,
+        ],
+        'R' => [
+            0b11110, 0b10001, 0b10001, 0b11110, 0b10100, 0b10010, 0b10001,
+        ],
+        'S' => [
+            0b01111, 0b10000, 0b10000, 0b01110, 0b00001, 0b00001, 0b11110,
+        ],
+        'T' => [
+            0b11111, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100,
+        ],
+        'U' => [
+            0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110,
+        ],
+        'V' => [
+            0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01010, 0b00100,
+        ],
+        'W' => [
+            0b10001, 0b10001, 0b10001, 0b10101, 0b10101, 0b10101, 0b01010,
+        ],
+        'X' => [
+            0b10001, 0b10001, 0b01010, 0b00100, 0b01010, 0b10001, 0b10001,
+        ],
+        'Y' => [
+            0b10001, 0b10001, 0b10001, 0b01010, 0b00100, 0b00100, 0b00100,
+        ],
+        'Z' => [
+            0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b11111,
+        ],
+        '0' => [
+            0b01110, 0b10001, 0b10011, 0b10101, 0b11001, 0b10001, 0b01110,
+        ],
+        '1' => [
+            0b00100, 0b01100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110,
+        ],
+        '2' => [
+            0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b01000, 0b11111,
+        ],
+        '3' => [
+            0b11111, 0b00010, 0b00100, 0b00010, 0b00001, 0b10001, 0b01110,
+        ],
+        '4' => [
+            0b00010, 0b00110, 0b01010, 0b10010, 0b11111, 0b00010, 0b00010,
+        ],
+        '5' => [
+            0b11111, 0b10000, 0b11110, 0b00001, 0b00001, 0b10001, 0b01110,
+        ],
+        '6' => [
+            0b00110, 0b01000, 0b10000, 0b11110, 0b10001, 0b10001, 0b01110,
+        ],
+        '7' => [
+            0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b01000, 0b01000,
+        ],
+        '8' => [
+            0b01110, 0b10001, 0b10001, 0b01110, 0b10001, 0b10001, 0b01110,
+        ],
+        '9' => [
+            0b01110, 0b10001, 0b10001, 0b01111, 0b00001, 0b00010, 0b01100,
+        ],
+        '.' => [0, 0, 0, 0, 0, 0b01100, 0b01100],
+        '-' => [0, 0, 0, 0b11111, 0, 0, 0],
+        ' ' => [0; 7],
+        _ => [0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0, 0b00100],
+    }
+}
+
+/// Draws `text` on a tag of the box color with its top left corner at `(x, y)`.
+fn draw_label(img: &mut RgbImage, x: u32, y: u32, text: &str, scale: u32) {
+    let (width, height) = img.dimensions();
+    let advance = (GLYPH_WIDTH + 1) * scale;
+    let tag_width = text.chars().count() as u32 * advance + scale;
+    let tag_height = (GLYPH_HEIGHT + 2) * scale;
+    for py in y..(y + tag_height).min(height) {
+        for px in x..(x + tag_width).min(width) {
+            img.put_pixel(px, py, BOX_COLOR);
+        }
+    }
+    for (i, c) in text.chars().enumerate() {
+        let left = x + scale + i as u32 * advance;
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for col in 0..GLYPH_WIDTH {
+                if bits & (1 << (GLYPH_WIDTH - 1 - col)) == 0 {
+                    continue;
+                }
+                let (gx, gy) = (left + col * scale, y + (row as u32 + 1) * scale);
+                for py in gy..(gy + scale).min(height) {
+                    for px in gx..(gx + scale).min(width) {
+                        img.put_pixel(px, py, TEXT_COLOR);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Copy of `img` with the boxes drawn and tagged with their class and score. Lines and
+/// text grow with the image so they stay readable on full resolution photos.
+pub fn annotate_image(img: &DynamicImage, bboxes: &[Bbox]) -> RgbImage {
+    let mut annotated = img.to_rgb8();
+    let short_side = img.width().min(img.height());
+    let thickness = (short_side / 250).max(2);
+    let scale = (short_side / 300).max(1);
+    let tag_height = (GLYPH_HEIGHT + 2) * scale;
+    for bbox in bboxes {
+        let rect = bbox.pixel_rect(img.width(), img.height());
+        draw_rect(&mut annotated, rect, thickness);
+        let (x, y, _, _) = rect;
+        // above the box unless it touches the top
+        let y = if y >= tag_height { y - tag_height } else { y };
+        let text = format!("{} {:.2}", bbox.class_name(), bbox.score);
+        draw_label(&mut annotated, x, y, &text, scale);
+    }
+    annotated
+}
+
+/// Where the annotated copy of `frame` goes below `folder`, following the previews
+/// template. Photos keep their file name, video frames are named like the annotation
+/// previews.
+fn annotated_path(
+    folder: &Path,
+    path: &Path,
+    frame: &ExportFrame,
+    template: &str,
+) -> Result<PathBuf> {
+    let label = file_label([frame]);
+    let mut context = TemplateContext::new(path, folder, frame.shoot_time.as_deref(), label);
+    if is_video(path) {
+        context.filename = preview_name(&context.filename, frame.frame_index);
+    }
+    Ok(folder.join(template::render(template, &context)?))
+}
+
+/// Writes a copy of every positive image and video frame of `frames` with its boxes drawn
+/// below `folder`, laid out by the previews `template`.
+pub fn annotate_frames(
+    frames: &[ExportFrame],
+    folder: &Path,
+    template: &str,
+) -> Result<AnnotateSummary> {
+    template::validate(template)?;
+    let dir = folder.join(template::base_dir(template));
+    std::fs::create_dir_all(&dir)?;
+    let mut summary = AnnotateSummary {
+        output: dir.clone(),
+        ..Default::default()
+    };
+    for frame in frames.iter().filter(|f| is_positive(f)) {
+        let path = folder.join(&frame.file.file_path);
+        let relative = portable_path(&path, Some(folder));
+        let written = annotated_path(folder, &path, frame, template).and_then(|target| {
+            let img = load_frame(&path, frame)?;
+            if let Some(parent) = target.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let bboxes = frame.bboxes.as_deref().unwrap_or_default();
+            Ok(annotate_image(&img, bboxes).save(&target)?)
+        });
+        match written {
+            Ok(()) => summary.written += 1,
+            Err(e) => {
+                log::warn!("Failed to annotate {}: {}", path.display(), e);
+                summary.failed.push((relative, e.to_string()));
+            }
+        }
+    }
+    log::info!(
+        "Wrote {} annotated images to {}",
+        summary.written,
+        dir.display()
+    );
+    Ok(summary)
+}
+
+/// Annotated copies of the positives of a result file, next to it.
+pub fn annotate_result(result: &Path, template: &str) -> Result<AnnotateSummary> {
+    let folder = result
+        .parent()
+        .ok_or_else(|| anyhow!("Invalid result path: {}", result.display()))?;
+    annotate_frames(&load_export(result)?, folder, template)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::testing;
+    use crate::template::OutputTemplates;
+
+    #[test]
+    fn test_annotate_image() {
+        let img = DynamicImage::new_rgb8(300, 200);
+        let bbox = Bbox {
+            x1: 0.2,
+            y1: 0.5,
+            x2: 0.6,
+            y2: 0.9,
+            ..testing::bbox(0, 0.87)
+        };
+        let annotated = annotate_image(&img, &[bbox]);
+        assert_eq!(annotated.dimensions(), (300, 200));
+        // box edges at (60, 100) to (180, 180)
+        assert_eq!(*annotated.get_pixel(60, 140), BOX_COLOR);
+        assert_eq!(*annotated.get_pixel(120, 140), Rgb([0, 0, 0]));
+        // the tag sits on top of the box, "A" starts with a blank column
+        assert_eq!(*annotated.get_pixel(61, 92), BOX_COLOR);
+        assert_eq!(*annotated.get_pixel(62, 92), TEXT_COLOR);
+        assert_eq!(glyph('a'), glyph('A'));
+    }
+
+    #[test]
+    fn test_annotated_path() {
+        let folder = Path::new("/traps");
+        let template = OutputTemplates::default().previews;
+        let photo = ExportFrame {
+            bboxes: Some(vec![testing::bbox(1, 0.9)]),
+            ..testing::frame("site1/IMG_0001.JPG")
+        };
+        let path = folder.join(&photo.file.file_path);
+        assert_eq!(
+            annotated_path(folder, &path, &photo, &template).unwrap(),
+            folder.join("annotated/site1/IMG_0001.JPG")
+        );
+        assert_eq!(
+            annotated_path(folder, &path, &photo, "{label}/{filename}").unwrap(),
+            folder.join("Person/IMG_0001.JPG")
+        );
+        let frame = ExportFrame {
+            frame_index: 30,
+            total_frames: 3,
+            ..photo.clone()
+        };
+        let video = folder.join("site1/clip.mp4");
+        assert_eq!(
+            annotated_path(folder, &video, &frame, &template).unwrap(),
+            folder.join("annotated/site1/clip_30.jpg")
+        );
+        let outside = Path::new("/elsewhere/IMG_0002.JPG");
+        assert!(annotated_path(folder, outside, &photo, &template).is_err());
+    }
+}
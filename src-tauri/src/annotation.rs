@@ -0,0 +1,709 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::contact_sheet::{is_positive, load_frame};
+use crate::export::{load_export, save_export, Bbox, BboxSpace, ExportFrame, CLASS_NAMES};
+use crate::utils::portable_path;
+
+pub const ANNOTATION_DIR: &str = "annotations";
+pub const PREVIEW_DIR: &str = "images";
+pub const LABEL_STUDIO_TASKS: &str = "tasks.json";
+pub const LABEL_STUDIO_CONFIG: &str = "label_config.xml";
+pub const CVAT_ANNOTATIONS: &str = "annotations.xml";
+
+/// Longest side of the preview images.
+const PREVIEW_SIZE: u32 = 1920;
+const MODEL_VERSION: &str = "megascops";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AnnotationFormat {
+    #[default]
+    LabelStudio,
+    Cvat,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct AnnotationOptions {
+    pub format: AnnotationFormat,
+    /// Put in front of the preview path relative to the result folder to form the image
+    /// URL of a Label Studio task, e.g. the local files storage of the project.
+    pub image_url_prefix: String,
+    /// Detections below this score are not pre-annotated.
+    pub min_score: f32,
+}
+
+impl Default for AnnotationOptions {
+    fn default() -> Self {
+        Self {
+            format: AnnotationFormat::LabelStudio,
+            image_url_prefix: "/data/local-files/?d=".to_string(),
+            min_score: 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AnnotationSummary {
+    /// Tasks file or CVAT annotations.
+    pub output: PathBuf,
+    pub tasks: usize,
+    pub failed: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportSummary {
+    /// Frames marked as verified.
+    pub frames: usize,
+    pub boxes: usize,
+    /// Images of the annotations without a frame in the results.
+    pub unmatched: Vec<String>,
+}
+
+/// Box a person drew, normalized to the image.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HumanBox {
+    pub label: String,
+    pub x1: f32,
+    pub y1: f32,
+    pub x2: f32,
+    pub y2: f32,
+}
+
+impl HumanBox {
+    fn to_bbox(&self) -> Bbox {
+        let class = CLASS_NAMES.iter().position(|n| *n == self.label);
+        Bbox {
+            x1: self.x1.clamp(0.0, 1.0),
+            y1: self.y1.clamp(0.0, 1.0),
+            x2: self.x2.clamp(0.0, 1.0),
+            y2: self.y2.clamp(0.0, 1.0),
+            score: 1.0,
+            // labels beyond the detector classes are species
+            class: class.unwrap_or(0),
+            individual: None,
+            label: class.is_none().then(|| self.label.clone()),
+            space: BboxSpace::Normalized,
+        }
+    }
+}
+
+/// Box in percent of the image, `(x, y, width, height)`.
+fn percent_rect(bbox: &Bbox, width: u32, height: u32) -> (f32, f32, f32, f32) {
+    let (x, y, w, h) = bbox.pixel_rect(width, height);
+    let px = |v: u32| v as f32 / width as f32 * 100.0;
+    let py = |v: u32| v as f32 / height as f32 * 100.0;
+    (px(x), py(y), px(w), py(h))
+}
+
+fn kept(frame: &ExportFrame, min_score: f32) -> impl Iterator<Item = &Bbox> {
+    frame
+        .bboxes
+        .iter()
+        .flatten()
+        .filter(move |b| b.score >= min_score)
+}
+
+/// Label Studio task with the detections of `frame` as predictions. `width` and
+/// `height` are those of the original frame.
+pub fn label_studio_task(
+    frame: &ExportFrame,
+    image_url: &str,
+    source: &str,
+    width: u32,
+    height: u32,
+    min_score: f32,
+) -> Value {
+    let mut score: f32 = 0.0;
+    let result: Vec<Value> = kept(frame, min_score)
+        .enumerate()
+        .map(|(i, bbox)| {
+            score = score.max(bbox.score);
+            let (x, y, w, h) = percent_rect(bbox, width, height);
+            json!({
+                "id": format!("f{}b{}", frame.frame_index, i),
+                "type": "rectanglelabels",
+                "from_name": "label",
+                "to_name": "image",
+                "original_width": width,
+                "original_height": height,
+                "image_rotation": 0,
+                "score": bbox.score,
+                "value": {
+                    "x": x,
+                    "y": y,
+                    "width": w,
+                    "height": h,
+                    "rotation": 0,
+                    "rectanglelabels": [bbox.class_name()],
+                },
+            })
+        })
+        .collect();
+    json!({
+        "data": {
+            "image": image_url,
+            "source": source,
+            "frame_index": frame.frame_index,
+        },
+        "predictions": [{
+            "model_version": MODEL_VERSION,
+            "score": score,
+            "result": result,
+        }],
+    })
+}
+
+/// Labeling interface matching the tasks.
+pub fn label_studio_config() -> String {
+    let mut config = String::from(concat!(
+        "<View>\n",
+        "  <Image name=\"image\" value=\"$image\"/>\n",
+        "  <RectangleLabels name=\"label\" toName=\"image\">\n",
+    ));
+    for name in CLASS_NAMES {
+        let _ = writeln!(config, "    <Label value=\"{}\"/>", name);
+    }
+    config.push_str("  </RectangleLabels>\n</View>\n");
+    config
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// `<image>` element of CVAT for images 1.1, `width` and `height` are those of the preview.
+pub fn cvat_image(
+    id: usize,
+    name: &str,
+    frame: &ExportFrame,
+    width: u32,
+    height: u32,
+    min_score: f32,
+) -> String {
+    let mut xml = format!(
+        "  <image id=\"{}\" name=\"{}\" width=\"{}\" height=\"{}\">\n",
+        id,
+        xml_escape(name),
+        width,
+        height
+    );
+    for bbox in kept(frame, min_score) {
+        let (x, y, w, h) = percent_rect(bbox, width, height);
+        let sx = width as f32 / 100.0;
+        let sy = height as f32 / 100.0;
+        let _ = writeln!(
+            xml,
+            concat!(
+                "    <box label=\"{}\" source=\"auto\" occluded=\"0\" ",
+                "xtl=\"{:.2}\" ytl=\"{:.2}\" xbr=\"{:.2}\" ybr=\"{:.2}\" z_order=\"0\"></box>"
+            ),
+            xml_escape(&bbox.class_name()),
+            x * sx,
+            y * sy,
+            (x + w) * sx,
+            (y + h) * sy
+        );
+    }
+    xml.push_str("  </image>\n");
+    xml
+}
+
+fn cvat_document(images: &[String]) -> String {
+    let mut xml = String::from(concat!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n",
+        "<annotations>\n",
+        "  <version>1.1</version>\n",
+        "  <meta>\n",
+        "    <task>\n",
+        "      <labels>\n",
+    ));
+    for name in CLASS_NAMES {
+        let _ = writeln!(xml, "        <label><name>{}</name></label>", name);
+    }
+    xml.push_str("      </labels>\n    </task>\n  </meta>\n");
+    for image in images {
+        xml.push_str(image);
+    }
+    xml.push_str("</annotations>\n");
+    xml
+}
+
+/// File name of the preview of a frame, unique within the result folder.
+pub(crate) fn preview_name(relative: &str, frame_index: usize) -> String {
+    let stem = relative.rsplit_once('.').map_or(relative, |(stem, _)| stem);
+    format!("{}_{}.jpg", stem.replace(['/', ':'], "__"), frame_index)
+}
+
+fn write_preview(img: &DynamicImage, target: &Path) -> Result<(u32, u32)> {
+    let preview = if img.width().max(img.height()) > PREVIEW_SIZE {
+        img.thumbnail(PREVIEW_SIZE, PREVIEW_SIZE)
+    } else {
+        img.clone()
+    };
+    preview.to_rgb8().save(target)?;
+    Ok((preview.width(), preview.height()))
+}
+
+/// Writes preview images of the positive frames of a result file and tasks with their
+/// detections as pre-annotations, into `annotations/` next to the result.
+pub fn export_annotations(result: &Path, options: &AnnotationOptions) -> Result<AnnotationSummary> {
+    let folder = result
+        .parent()
+        .ok_or_else(|| anyhow!("Invalid result path: {}", result.display()))?;
+    let frames = load_export(result)?;
+    let dir = folder.join(ANNOTATION_DIR);
+    std::fs::create_dir_all(dir.join(PREVIEW_DIR))?;
+
+    let mut summary = AnnotationSummary::default();
+    let mut tasks = Vec::new();
+    let mut images = Vec::new();
+    for frame in frames.iter().filter(|f| is_positive(f)) {
+        if kept(frame, options.min_score).next().is_none() {
+            continue;
+        }
+        let path = folder.join(&frame.file.file_path);
+        let source = portable_path(&path, Some(folder));
+        let name = format!(
+            "{}/{}",
+            PREVIEW_DIR,
+            preview_name(&source, frame.frame_index)
+        );
+        let written = load_frame(&path, frame).and_then(|img| {
+            let size = write_preview(&img, &dir.join(&name))?;
+            Ok(((img.width(), img.height()), size))
+        });
+        let ((width, height), (preview_width, preview_height)) = match written {
+            Ok(sizes) => sizes,
+            Err(e) => {
+                log::warn!("Skipping {} in annotations: {}", path.display(), e);
+                summary.failed.push((source, e.to_string()));
+                continue;
+            }
+        };
+        match options.format {
+            AnnotationFormat::LabelStudio => {
+                let url = format!("{}{}/{}", options.image_url_prefix, ANNOTATION_DIR, name);
+                tasks.push(label_studio_task(
+                    frame,
+                    &url,
+                    &source,
+                    width,
+                    height,
+                    options.min_score,
+                ));
+            }
+            AnnotationFormat::Cvat => {
+                // previews keep the aspect ratio, so the percentages of the original hold
+                images.push(cvat_image(
+                    images.len(),
+                    &name,
+                    frame,
+                    preview_width,
+                    preview_height,
+                    options.min_score,
+                ));
+            }
+        }
+    }
+
+    match options.format {
+        AnnotationFormat::LabelStudio => {
+            summary.tasks = tasks.len();
+            summary.output = dir.join(LABEL_STUDIO_TASKS);
+            std::fs::write(&summary.output, serde_json::to_string_pretty(&tasks)?)?;
+            std::fs::write(dir.join(LABEL_STUDIO_CONFIG), label_studio_config())?;
+        }
+        AnnotationFormat::Cvat => {
+            summary.tasks = images.len();
+            summary.output = dir.join(CVAT_ANNOTATIONS);
+            std::fs::write(&summary.output, cvat_document(&images))?;
+        }
+    }
+    log::info!(
+        "Exported {} annotation tasks to {}",
+        summary.tasks,
+        summary.output.display()
+    );
+    Ok(summary)
+}
+
+fn file_name(path: &str) -> &str {
+    path.rsplit(['/', '\\', '=']).next().unwrap_or(path)
+}
+
+/// Preview names and boxes of the tasks of a Label Studio JSON export, from the last
+/// annotation of each task that was not skipped.
+pub fn parse_label_studio(json: &str) -> Result<Vec<(String, Vec<HumanBox>)>> {
+    let tasks: Vec<Value> = serde_json::from_str(json)?;
+    let mut images = Vec::new();
+    for task in &tasks {
+        let Some(annotation) = task["annotations"]
+            .as_array()
+            .and_then(|a| a.iter().rev().find(|a| a["was_cancelled"] != true))
+        else {
+            continue;
+        };
+        let data = &task["data"];
+        let name = match (data["source"].as_str(), data["frame_index"].as_u64()) {
+            (Some(source), Some(index)) => preview_name(source, index as usize),
+            _ => file_name(data["image"].as_str().unwrap_or_default()).to_string(),
+        };
+        let boxes = annotation["result"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter(|r| r["type"] == "rectanglelabels")
+            .filter_map(|r| {
+                let value = &r["value"];
+                let x = value["x"].as_f64()? as f32 / 100.0;
+                let y = value["y"].as_f64()? as f32 / 100.0;
+                Some(HumanBox {
+                    label: value["rectanglelabels"][0].as_str()?.to_string(),
+                    x1: x,
+                    y1: y,
+                    x2: x + value["width"].as_f64()? as f32 / 100.0,
+                    y2: y + value["height"].as_f64()? as f32 / 100.0,
+                })
+            })
+            .collect();
+        images.push((name, boxes));
+    }
+    Ok(images)
+}
+
+fn xml_unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Value of the attribute `name` of the start tag `tag`.
+fn xml_attr(tag: &str, name: &str) -> Option<String> {
+    let start = tag.find(&format!(" {}=\"", name))? + name.len() + 3;
+    let end = start + tag[start..].find('"')?;
+    Some(xml_unescape(&tag[start..end]))
+}
+
+/// Preview names and boxes of the images of a CVAT for images 1.1 export.
+pub fn parse_cvat(xml: &str) -> Result<Vec<(String, Vec<HumanBox>)>> {
+    let mut images = Vec::new();
+    for element in xml.split("<image ").skip(1) {
+        let element = element.split("</image>").next().unwrap_or(element);
+        let tag = format!(" {}", element.split('>').next().unwrap_or_default());
+        let (Some(name), Some(width), Some(height)) = (
+            xml_attr(&tag, "name"),
+            xml_attr(&tag, "width").and_then(|w| w.parse::<f32>().ok()),
+            xml_attr(&tag, "height").and_then(|h| h.parse::<f32>().ok()),
+        ) else {
+            return Err(anyhow!("Invalid CVAT image element"));
+        };
+        let boxes = element
+            .split("<box ")
+            .skip(1)
+            .filter_map(|b| {
+                let tag = format!(" {}", b.split('>').next()?);
+                let coordinate = |name: &str| xml_attr(&tag, name)?.parse::<f32>().ok();
+                Some(HumanBox {
+                    label: xml_attr(&tag, "label")?,
+                    x1: coordinate("xtl")? / width,
+                    y1: coordinate("ytl")? / height,
+                    x2: coordinate("xbr")? / width,
+                    y2: coordinate("ybr")? / height,
+                })
+            })
+            .collect();
+        images.push((file_name(&name).to_string(), boxes));
+    }
+    Ok(images)
+}
+
+/// Replaces the boxes of the frame with those a person drew and marks it verified.
+pub fn apply_review(frame: &mut ExportFrame, boxes: &[HumanBox]) {
+    let bboxes: Vec<Bbox> = boxes.iter().map(HumanBox::to_bbox).collect();
+    frame.label = Some(frame_labels(&bboxes));
+    frame.bboxes = Some(bboxes);
+    frame.error = None;
+    frame.verified = true;
+}
+
+/// Labels of a frame with `bboxes`, in order of appearance.
+pub(crate) fn frame_labels(bboxes: &[Bbox]) -> Vec<String> {
+    let mut labels: Vec<String> = Vec::new();
+    for bbox in bboxes {
+        let name = bbox.class_name();
+        if !labels.contains(&name) {
+            labels.push(name);
+        }
+    }
+    if labels.is_empty() {
+        labels.push("Blank".to_string());
+    }
+    labels
+}
+
+/// Merges annotations corrected in Label Studio (JSON) or CVAT (XML) back into the result
+/// file they were exported from. Every annotated frame is marked verified.
+pub fn import_annotations(result: &Path, annotations: &Path) -> Result<ImportSummary> {
+    let folder = result
+        .parent()
+        .ok_or_else(|| anyhow!("Invalid result path: {}", result.display()))?;
+    let content = std::fs::read_to_string(annotations)?;
+    let images = match annotations.extension().and_then(|e| e.to_str()) {
+        Some("json") => parse_label_studio(&content)?,
+        Some("xml") => parse_cvat(&content)?,
+        _ => {
+            return Err(anyhow!(
+                "Invalid annotation file extension: {}",
+                annotations.display()
+            ))
+        }
+    };
+    let mut frames = load_export(result)?;
+    let index: HashMap<String, usize> = frames
+        .iter()
+        .enumerate()
+        .map(|(i, frame)| {
+            let source = portable_path(&folder.join(&frame.file.file_path), Some(folder));
+            (preview_name(&source, frame.frame_index), i)
+        })
+        .collect();
+
+    let mut summary = ImportSummary::default();
+    for (name, boxes) in images {
+        match index.get(&name) {
+            Some(&i) => {
+                apply_review(&mut frames[i], &boxes);
+                summary.frames += 1;
+                summary.boxes += boxes.len();
+            }
+            None => summary.unmatched.push(name),
+        }
+    }
+    if summary.frames > 0 {
+        save_export(result, &frames)?;
+    }
+    log::info!(
+        "Imported {} verified frames with {} boxes into {}, {} unmatched",
+        summary.frames,
+        summary.boxes,
+        result.display(),
+        summary.unmatched.len()
+    );
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::testing;
+
+    #[test]
+    fn test_preview_name() {
+        assert_eq!(preview_name("a/1.mp4", 3), "a__1_3.jpg");
+        assert_eq!(preview_name("C:/cam/IMG_1.JPG", 0), "C____cam__IMG_1_0.jpg");
+        assert_eq!(
+            file_name("https://host/data?d=images/a__1_3.jpg"),
+            "a__1_3.jpg"
+        );
+        assert_eq!(file_name(r"C:\images\a.jpg"), "a.jpg");
+    }
+
+    #[test]
+    fn test_annotation_tasks() {
+        let wide = |x1: f32, bbox: Bbox| Bbox {
+            x1,
+            y1: 0.25,
+            x2: x1 + 0.5,
+            y2: 0.75,
+            ..bbox
+        };
+        let frame = ExportFrame {
+            frame_index: 3,
+            total_frames: 5,
+            bboxes: Some(vec![
+                wide(0.1, testing::bbox(0, 0.9)),
+                wide(0.2, testing::bbox(1, 0.1)),
+            ]),
+            label: Some(vec!["Animal".to_string()]),
+            ..testing::frame("/run/a/1.mp4")
+        };
+        let task = label_studio_task(&frame, "/data/x.jpg", "a/1.mp4", 1000, 800, 0.2);
+        assert_eq!(task["data"]["image"], "/data/x.jpg");
+        assert_eq!(task["data"]["frame_index"], 3);
+        let result = task["predictions"][0]["result"].as_array().unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0]["value"]["rectanglelabels"][0], "Animal");
+        assert_eq!(result[0]["value"]["x"], 10.0);
+        assert_eq!(result[0]["value"]["height"], 50.0);
+
+        // nothing left above the threshold still makes a task, without predictions
+        let task = label_studio_task(&frame, "/data/x.jpg", "a/1.mp4", 1000, 800, 0.95);
+        assert_eq!(task["predictions"][0]["result"], json!([]));
+        assert_eq!(task["predictions"][0]["score"], 0.0);
+
+        let xml = cvat_image(0, "images/a&b.jpg", &frame, 200, 100, 0.0);
+        assert!(xml.contains("name=\"images/a&amp;b.jpg\""));
+        assert!(xml.contains("label=\"Animal\""));
+        assert!(xml.contains("xtl=\"20.00\" ytl=\"25.00\" xbr=\"120.00\" ybr=\"75.00\""));
+        assert!(xml.contains("label=\"Person\""));
+
+        let config = label_studio_config();
+        assert!(CLASS_NAMES
+            .iter()
+            .all(|name| config.contains(&format!("<Label value=\"{}\"/>", name))));
+    }
+
+    #[test]
+    fn test_parse_annotations() {
+        let json = r#"[{
+            "data": {"image": "/data/x.jpg", "source": "a/1.mp4", "frame_index": 3},
+            "annotations": [{"was_cancelled": false, "result": [{
+                "type": "rectanglelabels",
+                "value": {"x": 10, "y": 20, "width": 50, "height": 40,
+                    "rectanglelabels": ["Leopard"]}
+            }, {
+                "type": "choices",
+                "value": {"choices": ["Night"]}
+            }]}]
+        }, {
+            "data": {"image": "/data/y.jpg", "source": "a/2.jpg", "frame_index": 0},
+            "annotations": [{"was_cancelled": true, "result": []}]
+        }, {
+            "data": {"image": "/data/local-files/?d=images/a__3_0.jpg"},
+            "annotations": [
+                {"was_cancelled": false, "result": []},
+                {"was_cancelled": true, "result": []}
+            ]
+        }]"#;
+        let images = parse_label_studio(json).unwrap();
+        assert_eq!(images.len(), 2);
+        assert_eq!(images[0].0, "a__1_3.jpg");
+        assert_eq!(images[0].1.len(), 1);
+        let leopard = &images[0].1[0];
+        assert_eq!(leopard.label, "Leopard");
+        assert!((leopard.x2 - 0.6).abs() < 1e-6 && (leopard.y2 - 0.6).abs() < 1e-6);
+        // the later annotation was skipped, the earlier one found nothing
+        assert_eq!(images[1], ("a__3_0.jpg".to_string(), vec![]));
+        assert!(parse_label_studio("{}").is_err());
+
+        let frame = ExportFrame {
+            bboxes: Some(vec![testing::bbox(0, 0.9), testing::bbox(1, 0.1)]),
+            ..testing::frame("/run/a&b.jpg")
+        };
+        let document = cvat_document(&[cvat_image(0, "images/a&b_3.jpg", &frame, 200, 100, 0.5)]);
+        let images = parse_cvat(&document).unwrap();
+        assert_eq!(images.len(), 1);
+        assert_eq!(images[0].0, "a&b_3.jpg");
+        assert_eq!(images[0].1.len(), 1);
+        assert_eq!(images[0].1[0].label, "Animal");
+        assert!((images[0].1[0].x1 - 0.1).abs() < 1e-6);
+        assert!(parse_cvat("<annotations><image id=\"0\"></image></annotations>").is_err());
+        assert!(parse_cvat("<annotations></annotations>")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_apply_review() {
+        let leopard = HumanBox {
+            label: "Leopard".to_string(),
+            x1: -0.1,
+            y1: 0.2,
+            x2: 1.2,
+            y2: 0.6,
+        };
+        let animal = HumanBox {
+            label: "Animal".to_string(),
+            ..leopard.clone()
+        };
+        let mut reviewed = ExportFrame {
+            error: Some("Failed to decode".to_string()),
+            ..testing::frame("/run/a/1.jpg")
+        };
+        apply_review(&mut reviewed, &[leopard, animal]);
+        assert!(reviewed.verified);
+        assert_eq!(reviewed.error, None);
+        assert_eq!(
+            reviewed.label,
+            Some(vec!["Leopard".to_string(), "Animal".to_string()])
+        );
+        assert_eq!(
+            reviewed.verified_labels(),
+            Some(vec![(0, "Leopard".to_string()), (1, "Animal".to_string())])
+        );
+        let bboxes = reviewed.bboxes.as_ref().unwrap();
+        assert_eq!(
+            (bboxes[0].x1, bboxes[0].x2, bboxes[0].score),
+            (0.0, 1.0, 1.0)
+        );
+        assert_eq!((bboxes[1].class, bboxes[1].label.as_deref()), (0, None));
+
+        apply_review(&mut reviewed, &[]);
+        assert_eq!(reviewed.label, Some(vec!["Blank".to_string()]));
+        assert!(reviewed.bboxes.as_ref().is_some_and(Vec::is_empty));
+    }
+
+    #[test]
+    fn test_import_annotations() {
+        let root = testing::temp_dir();
+        let result = root.join("result.json");
+        let frames = vec![
+            ExportFrame {
+                frame_index: 3,
+                total_frames: 5,
+                bboxes: Some(vec![testing::bbox(0, 0.9)]),
+                label: Some(vec!["Animal".to_string()]),
+                ..testing::frame(root.join("a/1.mp4"))
+            },
+            testing::frame(root.join("a/2.jpg")),
+        ];
+        std::fs::write(&result, serde_json::to_string(&frames).unwrap()).unwrap();
+        let tasks = root.join("tasks.json");
+        std::fs::write(
+            &tasks,
+            r#"[{
+                "data": {"image": "/data/x.jpg", "source": "a/1.mp4", "frame_index": 3},
+                "annotations": [{"was_cancelled": false, "result": [{
+                    "type": "rectanglelabels",
+                    "value": {"x": 10, "y": 20, "width": 50, "height": 40,
+                        "rectanglelabels": ["Leopard"]}
+                }]}]
+            }, {
+                "data": {"image": "/data/y.jpg", "source": "a/2.jpg", "frame_index": 0},
+                "annotations": [{"was_cancelled": false, "result": []}]
+            }, {
+                "data": {"image": "/data/z.jpg", "source": "z/9.jpg", "frame_index": 0},
+                "annotations": [{"was_cancelled": false, "result": []}]
+            }]"#,
+        )
+        .unwrap();
+
+        let summary = import_annotations(&result, &tasks).unwrap();
+        assert_eq!((summary.frames, summary.boxes), (2, 1));
+        assert_eq!(summary.unmatched, ["z__9_0.jpg"]);
+        let frames = load_export(&result).unwrap();
+        assert!(frames.iter().all(|f| f.verified));
+        assert_eq!(frames[0].label, Some(vec!["Leopard".to_string()]));
+        assert_eq!(frames[1].label, Some(vec!["Blank".to_string()]));
+
+        let unknown = root.join("tasks.txt");
+        std::fs::write(&unknown, "").unwrap();
+        assert!(import_annotations(&result, &unknown).is_err());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,156 @@
+use std::cmp::Ordering;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+pub const CLIENT_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AnnouncementLevel {
+    #[default]
+    Info,
+    Warning,
+    Critical,
+}
+
+/// A message from the server operators, e.g. a maintenance window or a new model.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Announcement {
+    pub id: String,
+    #[serde(default)]
+    pub level: AnnouncementLevel,
+    pub title: String,
+    #[serde(default)]
+    pub message: String,
+    /// Shown from this time on, always when missing.
+    #[serde(default)]
+    pub starts_at: Option<DateTime<Utc>>,
+    /// Hidden after this time, never when missing.
+    #[serde(default)]
+    pub ends_at: Option<DateTime<Utc>>,
+    /// Only shown to clients in this version range, e.g. to ask old clients to update.
+    #[serde(default)]
+    pub min_client_version: Option<String>,
+    #[serde(default)]
+    pub max_client_version: Option<String>,
+}
+
+/// JSON served at the announcement URL.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AnnouncementFeed {
+    #[serde(default)]
+    pub announcements: Vec<Announcement>,
+    /// Oldest client the server works with, runs of older clients are refused.
+    #[serde(default)]
+    pub min_client_version: Option<String>,
+}
+
+/// Compares dotted versions numerically, `0.10.0` is newer than `0.9.1`. Anything after a
+/// `-` or `+` is ignored and missing parts count as 0.
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let parts = |v: &str| -> Vec<u64> {
+        v.trim()
+            .trim_start_matches('v')
+            .split(['-', '+'])
+            .next()
+            .unwrap_or_default()
+            .split('.')
+            .map(|p| p.parse().unwrap_or(0))
+            .collect()
+    };
+    let (a, b) = (parts(a), parts(b));
+    for i in 0..a.len().max(b.len()) {
+        let ordering = a.get(i).unwrap_or(&0).cmp(b.get(i).unwrap_or(&0));
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    Ordering::Equal
+}
+
+impl Announcement {
+    pub fn is_active(&self, now: DateTime<Utc>, client_version: &str) -> bool {
+        self.starts_at.is_none_or(|t| t <= now)
+            && self.ends_at.is_none_or(|t| now < t)
+            && self
+                .min_client_version
+                .as_deref()
+                .is_none_or(|v| compare_versions(client_version, v) != Ordering::Less)
+            && self
+                .max_client_version
+                .as_deref()
+                .is_none_or(|v| compare_versions(client_version, v) != Ordering::Greater)
+    }
+}
+
+impl AnnouncementFeed {
+    pub fn active(&self, now: DateTime<Utc>, client_version: &str) -> Vec<Announcement> {
+        self.announcements
+            .iter()
+            .filter(|a| a.is_active(now, client_version))
+            .cloned()
+            .collect()
+    }
+
+    /// Fails with a message for the user when the client is too old for the server.
+    pub fn check_client(&self, client_version: &str) -> Result<()> {
+        match &self.min_client_version {
+            Some(min) if compare_versions(client_version, min) == Ordering::Less => Err(anyhow!(
+                "This server requires Megascops {} or newer, you are running {}. Please update the app.",
+                min,
+                client_version
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+pub fn fetch_announcements(url: &str) -> Result<AnnouncementFeed> {
+    let agent = ureq::AgentBuilder::new()
+        .timeout(Duration::from_secs(10))
+        .build();
+    let feed = agent
+        .get(url)
+        .set("User-Agent", &format!("Megascops/{}", CLIENT_VERSION))
+        .call()?
+        .into_json()?;
+    Ok(feed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_gating() {
+        assert_eq!(compare_versions("0.10.0", "0.9.1"), Ordering::Greater);
+        assert_eq!(compare_versions("v1.2", "1.2.0"), Ordering::Equal);
+        assert_eq!(compare_versions("1.2.0-beta", "1.2.1"), Ordering::Less);
+
+        let feed: AnnouncementFeed = serde_json::from_str(
+            r#"{
+                "minClientVersion": "0.3.0",
+                "announcements": [
+                    {"id": "a", "title": "Maintenance", "endsAt": "2024-01-01T00:00:00Z"},
+                    {"id": "b", "level": "critical", "title": "Update", "maxClientVersion": "0.2.9"},
+                    {"id": "c", "title": "New model"}
+                ]
+            }"#,
+        )
+        .unwrap();
+        let now = "2024-06-01T00:00:00Z".parse().unwrap();
+        let ids: Vec<String> = feed
+            .active(now, "0.2.1")
+            .into_iter()
+            .map(|a| a.id)
+            .collect();
+        assert_eq!(ids, ["b", "c"]);
+        assert!(feed.check_client("0.2.1").is_err());
+        assert!(feed.check_client("0.3.0").is_ok());
+    }
+}
@@ -0,0 +1,194 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::export::ExportFrame;
+use crate::utils::portable_path;
+use crate::ExportFormat;
+
+/// Label of frames with a person detected.
+pub const PERSON_LABEL: &str = "Person";
+/// Replaces error messages of public exports, those tend to name local paths.
+const PUBLIC_ERROR: &str = "Failed to process";
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct AnonymizeOptions {
+    /// Formats that also get a public export, none by default.
+    pub formats: Vec<ExportFormat>,
+    /// Leave out frames with a person detected.
+    pub drop_people: bool,
+    /// Replace file paths with a hash of the path below the selected folder.
+    pub hash_paths: bool,
+}
+
+impl Default for AnonymizeOptions {
+    fn default() -> Self {
+        Self {
+            formats: Vec::new(),
+            drop_people: true,
+            hash_paths: true,
+        }
+    }
+}
+
+impl AnonymizeOptions {
+    pub fn applies_to(&self, format: ExportFormat) -> bool {
+        self.formats.contains(&format)
+    }
+
+    /// Copies of `frames` fit for publishing, paths end up relative to `folder_path`.
+    pub fn anonymize(&self, frames: &[ExportFrame], folder_path: &Path) -> Vec<ExportFrame> {
+        let public_path = |path: &Path| {
+            let path = portable_path(path, Some(folder_path));
+            if self.hash_paths {
+                hash_path(&path)
+            } else {
+                path
+            }
+        };
+        frames
+            .iter()
+            .filter(|frame| !(self.drop_people && is_person(frame)))
+            .map(|frame| {
+                let mut frame = frame.clone();
+                frame.file.file_path = public_path(&frame.file.file_path).into();
+                frame.burst_source = frame.burst_source.map(|p| public_path(&p).into());
+                if self.hash_paths {
+                    frame.error = frame
+                        .error
+                        .filter(|e| !e.is_empty())
+                        .map(|_| PUBLIC_ERROR.to_string());
+                }
+                // the masked token is for accounting only
+                frame.token = None;
+                // exact positions lead to the animals, serial numbers to the cameras' owners
+                frame.latitude = None;
+                frame.longitude = None;
+                frame.camera.serial_number = None;
+                frame
+            })
+            .collect()
+    }
+}
+
+pub fn is_person(frame: &ExportFrame) -> bool {
+    frame
+        .label
+        .as_ref()
+        .is_some_and(|labels| labels.iter().any(|l| l == PERSON_LABEL))
+}
+
+/// Stable hash of a portable path, keeping the extension so the media type stays known.
+pub fn hash_path(path: &str) -> String {
+    let digest = Sha256::digest(path.as_bytes());
+    let hash: String = digest[..16].iter().map(|b| format!("{:02x}", b)).collect();
+    match Path::new(path).extension().and_then(|e| e.to_str()) {
+        Some(extension) => format!("{}.{}", hash, extension.to_lowercase()),
+        None => hash,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use megascops_media::CameraInfo;
+
+    use super::*;
+    use crate::export::testing;
+
+    #[test]
+    fn test_anonymize() {
+        let located = ExportFrame {
+            latitude: Some(22.53113),
+            longitude: Some(114.02148),
+            camera: CameraInfo {
+                make: Some("Browning".to_string()),
+                model: Some("BTC-8E".to_string()),
+                serial_number: Some("E1234".to_string()),
+            },
+            label: Some(vec!["Animal".to_string()]),
+            token: Some("abcd…wxyz".to_string()),
+            ..testing::frame("/home/ann/run/a/1.JPG")
+        };
+        let frames = vec![
+            located.clone(),
+            ExportFrame {
+                label: Some(vec!["Animal".to_string(), PERSON_LABEL.to_string()]),
+                ..testing::frame("/home/ann/run/a/2.jpg")
+            },
+            ExportFrame {
+                error: Some("Failed to open /home/ann/run/b/3.mp4".to_string()),
+                ..testing::frame("/home/ann/run/b/3.mp4")
+            },
+        ];
+        let folder = Path::new("/home/ann/run");
+        let options = AnonymizeOptions {
+            formats: vec![ExportFormat::Json],
+            ..Default::default()
+        };
+        assert!(options.applies_to(ExportFormat::Json));
+        assert!(!options.applies_to(ExportFormat::Csv));
+
+        let public = options.anonymize(&frames, folder);
+        assert_eq!(public.len(), 2);
+        assert_eq!(
+            public[0].file.file_path,
+            PathBuf::from(hash_path("a/1.JPG"))
+        );
+        assert!(public[0].file.file_path.to_string_lossy().ends_with(".jpg"));
+        assert_eq!(public[1].error.as_deref(), Some(PUBLIC_ERROR));
+        assert!(public[0].token.is_none());
+        assert_eq!((public[0].latitude, public[0].longitude), (None, None));
+        assert!(public[0].camera.serial_number.is_none());
+        assert_eq!(public[0].camera.model.as_deref(), Some("BTC-8E"));
+        // the same file hashes the same wherever the run folder lives
+        let moved = ExportFrame {
+            file: testing::frame("/mnt/run/a/1.JPG").file,
+            ..located
+        };
+        let moved = options.anonymize(&[moved], Path::new("/mnt/run"));
+        assert_eq!(moved[0].file.file_path, public[0].file.file_path);
+
+        let plain = AnonymizeOptions {
+            drop_people: false,
+            hash_paths: false,
+            ..options
+        };
+        let public = plain.anonymize(&frames, folder);
+        assert_eq!(public.len(), 3);
+        assert_eq!(public[1].file.file_path, PathBuf::from("a/2.jpg"));
+        // without hashing the paths, errors are kept as they are
+        assert_eq!(public[2].error, frames[2].error);
+    }
+
+    #[test]
+    fn test_anonymize_bursts() {
+        let frames = vec![
+            // a burst sibling points at the image that was sent
+            ExportFrame {
+                burst_source: Some("/run/a/1.jpg".into()),
+                error: Some(String::new()),
+                ..testing::frame("/run/a/2.jpg")
+            },
+            // a frame outside the folder keeps its whole path
+            testing::frame("/elsewhere/3.jpg"),
+        ];
+        let public = AnonymizeOptions::default().anonymize(&frames, Path::new("/run"));
+        assert_eq!(
+            public[0].burst_source,
+            Some(PathBuf::from(hash_path("a/1.jpg")))
+        );
+        // an empty error isn't a failure to hide
+        assert_eq!(public[0].error, None);
+        assert_eq!(
+            public[1].file.file_path,
+            PathBuf::from(hash_path("/elsewhere/3.jpg"))
+        );
+
+        assert_eq!(hash_path("a/README").len(), 32);
+        assert_ne!(hash_path("a/1.jpg"), hash_path("b/1.jpg"));
+    }
+}
@@ -0,0 +1,108 @@
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use image::{imageops::FilterType, DynamicImage, GrayImage};
+
+/// Frames are compared at this resolution, small enough that a per-pixel median is cheap
+/// and sensor noise is averaged away.
+const MODEL_WIDTH: u32 = 96;
+const MODEL_HEIGHT: u32 = 72;
+/// Number of recent frames the median is taken over.
+const WINDOW: usize = 15;
+/// Frames needed before the median is trusted enough to skip anything.
+const WARMUP: usize = 5;
+/// Gray level difference for a pixel to count as foreground.
+const PIXEL_THRESHOLD: u8 = 25;
+
+/// Running median background of one camera folder.
+#[derive(Default)]
+struct Background {
+    history: VecDeque<GrayImage>,
+}
+
+impl Background {
+    fn median(&self) -> GrayImage {
+        let mut median = GrayImage::new(MODEL_WIDTH, MODEL_HEIGHT);
+        let mut values = Vec::with_capacity(self.history.len());
+        for (i, pixel) in median.iter_mut().enumerate() {
+            values.clear();
+            values.extend(self.history.iter().map(|frame| frame.as_raw()[i]));
+            values.sort_unstable();
+            *pixel = values[values.len() / 2];
+        }
+        median
+    }
+
+    /// Fraction of pixels that differ from the background, `None` while warming up.
+    fn foreground(&mut self, frame: GrayImage) -> Option<f32> {
+        let mass = if self.history.len() >= WARMUP {
+            let median = self.median();
+            let changed = frame
+                .iter()
+                .zip(median.iter())
+                .filter(|(a, b)| a.abs_diff(**b) > PIXEL_THRESHOLD)
+                .count();
+            Some(changed as f32 / frame.len() as f32)
+        } else {
+            None
+        };
+        if self.history.len() == WINDOW {
+            self.history.pop_front();
+        }
+        self.history.push_back(frame);
+        mass
+    }
+}
+
+/// Per-folder background models, fixed cameras usually write to one folder each.
+#[derive(Default)]
+pub struct BackgroundModels {
+    models: Mutex<HashMap<PathBuf, Background>>,
+}
+
+impl BackgroundModels {
+    /// Adds `img` to the background of `folder` and returns its foreground mass, the
+    /// fraction of pixels that differ from the running median of earlier frames.
+    pub fn foreground(&self, folder: PathBuf, img: &DynamicImage) -> Option<f32> {
+        let frame = img
+            .resize_exact(MODEL_WIDTH, MODEL_HEIGHT, FilterType::Triangle)
+            .to_luma8();
+        self.models
+            .lock()
+            .unwrap()
+            .entry(folder)
+            .or_default()
+            .foreground(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_foreground_mass() {
+        let models = BackgroundModels::default();
+        let folder = PathBuf::from("camera");
+        let empty = DynamicImage::ImageLuma8(GrayImage::from_pixel(192, 144, image::Luma([80])));
+        for _ in 0..WARMUP {
+            assert_eq!(models.foreground(folder.clone(), &empty), None);
+        }
+        assert_eq!(models.foreground(folder.clone(), &empty), Some(0.0));
+
+        let mut animal = GrayImage::from_pixel(192, 144, image::Luma([80]));
+        for x in 0..96 {
+            for y in 0..72 {
+                animal.put_pixel(x, y, image::Luma([200]));
+            }
+        }
+        let mass = models
+            .foreground(folder.clone(), &DynamicImage::ImageLuma8(animal))
+            .unwrap();
+        assert!(mass > 0.2 && mass < 0.3, "{}", mass);
+
+        // other folders keep their own background
+        assert_eq!(models.foreground("other".into(), &empty), None);
+    }
+}
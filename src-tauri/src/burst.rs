@@ -0,0 +1,241 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Local, TimeDelta};
+use megascops_media::{
+    decode_image, get_image_metadata, is_animation, is_apng, is_gif, ImageMetadata,
+};
+use nom_exif::MediaParser;
+use serde::{Deserialize, Serialize};
+
+use crate::export::ExportFrame;
+use crate::utils::{is_video, is_video_photo, FileItem};
+
+/// How to pick the image that is sent for a burst of consecutive shots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+pub enum BurstMode {
+    #[default]
+    Off,
+    Middle,
+    Sharpest,
+}
+
+/// An image of a burst that wasn't sent, with what its own EXIF says.
+#[derive(Debug, Clone)]
+pub struct BurstSibling {
+    pub file: FileItem,
+    pub metadata: ImageMetadata,
+}
+
+/// Siblings of every representative image, keyed by the representative's path.
+pub type BurstMap = Arc<Mutex<HashMap<PathBuf, Vec<BurstSibling>>>>;
+
+/// Groups consecutive images of the same folder whose shoot times are at most `gap` apart
+/// and only lets one image per group through to the pipeline.
+pub struct BurstCollapser {
+    mode: BurstMode,
+    gap: TimeDelta,
+    max_size: usize,
+    parser: MediaParser,
+    group: Vec<(BurstSibling, DateTime<Local>)>,
+    bursts: BurstMap,
+}
+
+impl BurstCollapser {
+    pub fn new(mode: BurstMode, gap_secs: f32, max_size: usize, bursts: BurstMap) -> Self {
+        Self {
+            mode,
+            gap: TimeDelta::milliseconds((gap_secs * 1000.0) as i64),
+            max_size: max_size.max(1),
+            parser: MediaParser::new(),
+            group: Vec::new(),
+            bursts,
+        }
+    }
+
+    pub fn push<F: FnMut(FileItem)>(&mut self, file: FileItem, emit: &mut F) {
+        if self.mode == BurstMode::Off || !is_image(&file) {
+            emit(file);
+            return;
+        }
+        let metadata = get_image_metadata(&mut self.parser, &file.file_path).ok();
+        let Some((metadata, shoot_time)) =
+            metadata.and_then(|m| m.shoot_time.map(|shoot_time| (m, shoot_time)))
+        else {
+            self.finish(emit);
+            emit(file);
+            return;
+        };
+        let continues = self.group.last().is_some_and(|(last, last_time)| {
+            last.file.file_path.parent() == file.file_path.parent()
+                && (shoot_time - *last_time).abs() <= self.gap
+        });
+        if !continues || self.group.len() >= self.max_size {
+            self.finish(emit);
+        }
+        self.group
+            .push((BurstSibling { file, metadata }, shoot_time));
+    }
+
+    /// Emits the pending group, call once the walk is done.
+    pub fn finish<F: FnMut(FileItem)>(&mut self, emit: &mut F) {
+        let mut group: Vec<BurstSibling> = self.group.drain(..).map(|(s, _)| s).collect();
+        if group.len() <= 1 {
+            group.into_iter().for_each(|s| emit(s.file));
+            return;
+        }
+        let index = match self.mode {
+            BurstMode::Sharpest => group
+                .iter()
+                .map(|s| sharpness(&s.file))
+                .enumerate()
+                .max_by(|a, b| a.1.total_cmp(&b.1))
+                .map(|(i, _)| i)
+                .unwrap_or(0),
+            _ => group.len() / 2,
+        };
+        let representative = group.remove(index).file;
+        log::debug!(
+            "Collapsed burst of {} images into {}",
+            group.len() + 1,
+            representative.file_path.display()
+        );
+        self.bursts
+            .lock()
+            .unwrap()
+            .insert(representative.file_path.clone(), group);
+        emit(representative);
+    }
+}
+
+/// Frames of the siblings of `frame`'s burst with their own shoot time, position and
+/// camera, and the detections of the image they came from.
+pub fn sibling_frames(frame: &ExportFrame, bursts: &BurstMap) -> Vec<ExportFrame> {
+    let siblings = bursts.lock().unwrap().remove(&frame.file.file_path);
+    siblings
+        .unwrap_or_default()
+        .into_iter()
+        .map(|sibling| ExportFrame {
+            file: sibling.file,
+            shoot_time: sibling.metadata.shoot_time.map(|t| t.to_string()),
+            latitude: sibling.metadata.position.map(|p| p.0),
+            longitude: sibling.metadata.position.map(|p| p.1),
+            camera: sibling.metadata.camera,
+            frame_index: 0,
+            total_frames: 1,
+            iframe: false,
+            bboxes: frame.bboxes.clone(),
+            label: frame.label.clone(),
+            // the sibling was never sent, it shares the fate of the image that was
+            error: frame.error.as_ref().map(|e| {
+                format!(
+                    "{} of the burst failed: {}",
+                    frame.file.file_path.display(),
+                    e
+                )
+            }),
+            burst_source: Some(frame.file.file_path.clone()),
+            prefilter_score: None,
+            skipped_blank: frame.skipped_blank,
+            encode_fallback: None,
+            token: None,
+            verified: false,
+        })
+        .collect()
+}
+
+/// Stills only, a GIF or animated PNG is a clip of its own.
+fn is_image(file: &FileItem) -> bool {
+    let path = &file.file_path;
+    is_video_photo(path)
+        && !is_video(path)
+        && !(is_animation(path) && (is_gif(path) || is_apng(path)))
+}
+
+/// Variance of the Laplacian on a downscaled grayscale copy, higher is sharper.
+fn sharpness(file: &FileItem) -> f64 {
+    let img = match decode_image(file.tmp_path.as_path()) {
+        Ok(img) => img.thumbnail(512, 512).to_luma8(),
+        Err(_) => return 0.0,
+    };
+    let (width, height) = img.dimensions();
+    if width < 3 || height < 3 {
+        return 0.0;
+    }
+    let px = |x: u32, y: u32| img.get_pixel(x, y)[0] as f64;
+    let mut sum = 0.0;
+    let mut sum_sq = 0.0;
+    for y in 1..height - 1 {
+        for x in 1..width - 1 {
+            let l = 4.0 * px(x, y) - px(x - 1, y) - px(x + 1, y) - px(x, y - 1) - px(x, y + 1);
+            sum += l;
+            sum_sq += l * l;
+        }
+    }
+    let n = ((width - 2) * (height - 2)) as f64;
+    sum_sq / n - (sum / n).powi(2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::testing;
+    use chrono::TimeZone;
+    use megascops_media::CameraInfo;
+
+    #[test]
+    fn test_sibling_frames() {
+        let frame = ExportFrame {
+            shoot_time: Some("2024-05-01 06:30:00 +08:00".to_string()),
+            latitude: Some(22.5),
+            longitude: Some(114.0),
+            bboxes: Some(vec![testing::bbox(0, 0.9)]),
+            label: Some(vec!["Animal".to_string()]),
+            error: None,
+            ..testing::frame("/traps/a/2.jpg")
+        };
+        let shoot_time = Local.with_ymd_and_hms(2024, 5, 1, 6, 30, 1).unwrap();
+        let sibling = BurstSibling {
+            file: FileItem::new(0, 1, "/traps/a/3.jpg".into(), None),
+            metadata: ImageMetadata {
+                shoot_time: Some(shoot_time),
+                position: Some((22.6, 114.1)),
+                camera: CameraInfo {
+                    serial_number: Some("E1234".to_string()),
+                    ..Default::default()
+                },
+            },
+        };
+        let bursts = BurstMap::default();
+        bursts
+            .lock()
+            .unwrap()
+            .insert(frame.file.file_path.clone(), vec![sibling]);
+
+        let siblings = sibling_frames(&frame, &bursts);
+        assert_eq!(siblings.len(), 1);
+        let sibling = &siblings[0];
+        assert_eq!(sibling.file.file_path, PathBuf::from("/traps/a/3.jpg"));
+        assert_eq!(sibling.shoot_time, Some(shoot_time.to_string()));
+        assert_eq!(
+            (sibling.latitude, sibling.longitude),
+            (Some(22.6), Some(114.1))
+        );
+        assert_eq!(sibling.camera.serial_number.as_deref(), Some("E1234"));
+        assert_eq!(sibling.bboxes.as_ref().map(Vec::len), Some(1));
+        assert_eq!(sibling.label, frame.label);
+        assert_eq!(sibling.burst_source.as_ref(), Some(&frame.file.file_path));
+        // taken once
+        assert!(sibling_frames(&frame, &bursts).is_empty());
+
+        let gif = FileItem::new(0, 2, "/traps/a/clip.gif".into(), None);
+        assert!(!is_image(&gif));
+        assert!(is_image(&FileItem::new(
+            0,
+            3,
+            "/traps/a/4.JPG".into(),
+            None
+        )));
+    }
+}
@@ -0,0 +1,205 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::annotation::preview_name;
+use crate::contact_sheet::load_frame;
+use crate::export::{load_export, Bbox, ExportFrame};
+use crate::report::has_error;
+use crate::template::{self, OutputTemplates, TemplateContext};
+use crate::utils::portable_path;
+
+/// Folder of the crops with the default template.
+pub const CROPS_DIR: &str = "crops";
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ChipOptions {
+    /// Margin added on every side, as a fraction of the box size.
+    pub padding: f32,
+    /// Boxes below this score are not cut out.
+    pub min_score: f32,
+    /// Output template of a crop, relative to the selected folder.
+    pub template: String,
+}
+
+impl Default for ChipOptions {
+    fn default() -> Self {
+        Self {
+            padding: 0.1,
+            min_score: 0.5,
+            template: OutputTemplates::default().crops,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChipSummary {
+    pub output: PathBuf,
+    pub chips: usize,
+    pub failed: Vec<(String, String)>,
+}
+
+/// Pixel rectangle of `bbox` grown by `padding` of its size on every side, kept inside
+/// the `width`x`height` image.
+pub fn padded_rect(bbox: &Bbox, width: u32, height: u32, padding: f32) -> (u32, u32, u32, u32) {
+    let (x, y, w, h) = bbox.pixel_rect(width, height);
+    let (px, py) = (
+        (w as f32 * padding).round() as u32,
+        (h as f32 * padding).round() as u32,
+    );
+    let (x1, y1) = (x.saturating_sub(px), y.saturating_sub(py));
+    let (x2, y2) = ((x + w + px).min(width), (y + h + py).min(height));
+    (x1, y1, x2.saturating_sub(x1), y2.saturating_sub(y1))
+}
+
+/// `{stem}_{index}_{score}.jpg`, the stem naming the file and frame.
+pub fn chip_name(relative: &str, frame_index: usize, index: usize, bbox: &Bbox) -> String {
+    let preview = preview_name(relative, frame_index);
+    let stem = preview.trim_end_matches(".jpg");
+    format!("{}_{}_{:.2}.jpg", stem, index, bbox.score)
+}
+
+/// Where the crop of `bbox` goes below `folder`, following the crops template.
+fn chip_path(
+    folder: &Path,
+    path: &Path,
+    shoot_time: Option<&str>,
+    bbox: &Bbox,
+    name: String,
+    template: &str,
+) -> Result<PathBuf> {
+    let mut context = TemplateContext::new(path, folder, shoot_time, &bbox.class_name());
+    context.filename = name;
+    Ok(folder.join(template::render(template, &context)?))
+}
+
+fn write_chips(
+    path: &Path,
+    relative: &str,
+    frame: &ExportFrame,
+    folder: &Path,
+    options: &ChipOptions,
+) -> Result<usize> {
+    let img = load_frame(path, frame)?;
+    let mut written = 0;
+    for (i, bbox) in frame.bboxes.iter().flatten().enumerate() {
+        if bbox.score < options.min_score {
+            continue;
+        }
+        let (x, y, w, h) = padded_rect(bbox, img.width(), img.height(), options.padding);
+        if w == 0 || h == 0 {
+            continue;
+        }
+        let target = chip_path(
+            folder,
+            path,
+            frame.shoot_time.as_deref(),
+            bbox,
+            chip_name(relative, frame.frame_index, i, bbox),
+            &options.template,
+        )?;
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        img.crop_imm(x, y, w, h).to_rgb8().save(target)?;
+        written += 1;
+    }
+    Ok(written)
+}
+
+/// Cuts every box of `frames` out of the original image or video frame to where the crops
+/// template puts it below `folder`, by default `crops/` with a folder per label.
+pub fn export_chips(
+    frames: &[ExportFrame],
+    folder: &Path,
+    options: &ChipOptions,
+) -> Result<ChipSummary> {
+    template::validate(&options.template)?;
+    let dir = folder.join(template::base_dir(&options.template));
+    std::fs::create_dir_all(&dir)?;
+    let mut summary = ChipSummary {
+        output: dir.clone(),
+        ..Default::default()
+    };
+    for frame in frames.iter().filter(|f| !has_error(f)) {
+        if !frame
+            .bboxes
+            .iter()
+            .flatten()
+            .any(|b| b.score >= options.min_score)
+        {
+            continue;
+        }
+        let path = folder.join(&frame.file.file_path);
+        let relative = portable_path(&path, Some(folder));
+        match write_chips(&path, &relative, frame, folder, options) {
+            Ok(written) => summary.chips += written,
+            Err(e) => {
+                log::warn!("Failed to crop {}: {}", path.display(), e);
+                summary.failed.push((relative, e.to_string()));
+            }
+        }
+    }
+    log::info!("Wrote {} crops to {}", summary.chips, dir.display());
+    Ok(summary)
+}
+
+/// Crops of the detections of a result file, next to it.
+pub fn export_result_chips(result: &Path, options: &ChipOptions) -> Result<ChipSummary> {
+    let folder = result
+        .parent()
+        .ok_or_else(|| anyhow!("Invalid result path: {}", result.display()))?;
+    export_chips(&load_export(result)?, folder, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::testing;
+
+    #[test]
+    fn test_chips() {
+        let bbox = Bbox {
+            x1: 0.1,
+            y1: 0.0,
+            x2: 0.5,
+            y2: 0.5,
+            ..testing::bbox(0, 0.876)
+        };
+        assert_eq!(padded_rect(&bbox, 200, 100, 0.0), (20, 0, 80, 50));
+        // the padding stops at the top edge
+        assert_eq!(padded_rect(&bbox, 200, 100, 0.25), (0, 0, 120, 63));
+        assert_eq!(
+            chip_name("site/a.mp4", 12, 1, &bbox),
+            "site__a_12_1_0.88.jpg"
+        );
+        let labeled = Bbox {
+            label: Some("Red/Roe deer".to_string()),
+            ..bbox
+        };
+        let folder = Path::new("/traps");
+        let path = folder.join("site/b.jpg");
+        let name = || chip_name("site/b.jpg", 0, 0, &labeled);
+        let template = ChipOptions::default().template;
+        assert_eq!(
+            chip_path(folder, &path, None, &labeled, name(), &template).unwrap(),
+            folder.join("crops/Red_Roe deer/site__b_0_0_0.88.jpg")
+        );
+        let shoot_time = Some("2024-05-01 06:30:00 +08:00");
+        assert_eq!(
+            chip_path(
+                folder,
+                &path,
+                shoot_time,
+                &labeled,
+                name(),
+                "{date}/{filename}"
+            )
+            .unwrap(),
+            folder.join("2024-05-01/site__b_0_0_0.88.jpg")
+        );
+    }
+}
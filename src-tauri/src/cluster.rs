@@ -0,0 +1,117 @@
+use crate::embedding::cosine_similarity;
+
+const KMEANS_ITERATIONS: usize = 50;
+/// Hamming distance up to which two perceptual hashes land in the same group.
+pub const HASH_DISTANCE: u32 = 10;
+
+/// Cluster count used when the caller doesn't pick one.
+pub fn default_k(n: usize) -> usize {
+    ((n as f32 / 2.0).sqrt().round() as usize).clamp(1, n.max(1))
+}
+
+/// Spherical k-means, returns the cluster of every vector.
+///
+/// Centers are seeded with the farthest-point heuristic so the result is deterministic.
+pub fn kmeans(vectors: &[Vec<f32>], k: usize) -> Vec<usize> {
+    if vectors.is_empty() {
+        return Vec::new();
+    }
+    let k = k.clamp(1, vectors.len());
+    let mut centers = vec![vectors[0].clone()];
+    while centers.len() < k {
+        let farthest = vectors
+            .iter()
+            .map(|v| {
+                centers
+                    .iter()
+                    .map(|c| cosine_similarity(v, c))
+                    .fold(f32::MIN, f32::max)
+            })
+            .enumerate()
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(i, _)| i)
+            .unwrap();
+        centers.push(vectors[farthest].clone());
+    }
+
+    let mut assignment = vec![usize::MAX; vectors.len()];
+    for _ in 0..KMEANS_ITERATIONS {
+        let mut changed = false;
+        for (i, v) in vectors.iter().enumerate() {
+            let nearest = centers
+                .iter()
+                .enumerate()
+                .max_by(|a, b| cosine_similarity(v, a.1).total_cmp(&cosine_similarity(v, b.1)))
+                .map(|(c, _)| c)
+                .unwrap();
+            if assignment[i] != nearest {
+                assignment[i] = nearest;
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+        for (c, center) in centers.iter_mut().enumerate() {
+            let mut sum = vec![0.0; center.len()];
+            for (v, _) in vectors.iter().zip(&assignment).filter(|(_, a)| **a == c) {
+                for (s, x) in sum.iter_mut().zip(v) {
+                    *s += x;
+                }
+            }
+            // empty clusters keep their previous center
+            if sum.iter().any(|s| *s != 0.0) {
+                *center = sum;
+            }
+        }
+    }
+    assignment
+}
+
+/// Groups perceptual hashes, each hash joins the first group whose leader is within
+/// `max_distance` bits.
+pub fn hash_groups(hashes: &[u64], max_distance: u32) -> Vec<usize> {
+    let mut leaders: Vec<u64> = Vec::new();
+    hashes
+        .iter()
+        .map(|hash| {
+            match leaders
+                .iter()
+                .position(|leader| (leader ^ hash).count_ones() <= max_distance)
+            {
+                Some(group) => group,
+                None => {
+                    leaders.push(*hash);
+                    leaders.len() - 1
+                }
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kmeans() {
+        let vectors = vec![
+            vec![1.0, 0.0],
+            vec![0.0, 1.0],
+            vec![0.9, 0.1],
+            vec![0.1, 0.9],
+            vec![0.95, 0.0],
+        ];
+        let clusters = kmeans(&vectors, 2);
+        assert_eq!(clusters[0], clusters[2]);
+        assert_eq!(clusters[0], clusters[4]);
+        assert_eq!(clusters[1], clusters[3]);
+        assert_ne!(clusters[0], clusters[1]);
+    }
+
+    #[test]
+    fn test_hash_groups() {
+        let groups = hash_groups(&[0, u64::MAX, 0b111, u64::MAX << 3], 4);
+        assert_eq!(groups, [0, 1, 0, 1]);
+    }
+}
@@ -0,0 +1,137 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use serde::Serialize;
+
+use crate::export::ExportFrame;
+use crate::organize::file_label;
+use crate::utils::portable_path;
+
+/// Result of a file once all its frames came back, for the frontend to show the files
+/// as they finish.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileResult {
+    pub file: String,
+    /// Animal, Person, Vehicle or Empty, the label folder organizing would pick.
+    pub label: &'static str,
+    pub frames: usize,
+    pub detections: usize,
+    pub max_score: Option<f32>,
+    pub error: Option<String>,
+}
+
+/// Frames of the files still waiting for others. Responses come back in any order and
+/// the frames of a video are spread over the whole stream.
+#[derive(Debug, Default)]
+pub struct FileCompletion {
+    pending: HashMap<PathBuf, Vec<ExportFrame>>,
+}
+
+impl FileCompletion {
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Adds `frame`, returning the result of its file when it was the last frame missing.
+    /// A file that failed as a whole has no frame count and ends with its error.
+    pub fn add(&mut self, frame: &ExportFrame) -> Option<FileResult> {
+        let frames = self
+            .pending
+            .entry(frame.file.file_path.clone())
+            .or_default();
+        frames.push(frame.clone());
+        if frames.len() < frame.total_frames {
+            return None;
+        }
+        let frames = self.pending.remove(&frame.file.file_path)?;
+        let bboxes = || frames.iter().flat_map(|f| f.bboxes.iter().flatten());
+        Some(FileResult {
+            file: portable_path(&frame.file.file_path, None),
+            label: file_label(&frames),
+            frames: frames.len(),
+            detections: bboxes().count(),
+            max_score: bboxes().map(|b| b.score).reduce(f32::max),
+            error: frames.iter().find_map(|f| f.error.clone()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::testing;
+
+    #[test]
+    fn test_file_completion() {
+        let video = ExportFrame {
+            total_frames: 3,
+            ..testing::frame("a.mp4")
+        };
+        let mut completion = FileCompletion::default();
+        let last = ExportFrame {
+            frame_index: 2,
+            bboxes: Some(vec![testing::bbox(0, 0.4)]),
+            ..video.clone()
+        };
+        assert!(completion.add(&last).is_none());
+        let image = completion.add(&testing::frame("b.jpg")).unwrap();
+        assert_eq!((image.label, image.detections), ("Empty", 0));
+        assert_eq!(image.max_score, None);
+        assert!(completion.add(&video).is_none());
+        assert_eq!(completion.pending(), 1);
+
+        let middle = ExportFrame {
+            frame_index: 1,
+            bboxes: Some(vec![testing::bbox(0, 0.9), testing::bbox(0, 0.6)]),
+            ..video
+        };
+        assert_eq!(
+            completion.add(&middle).unwrap(),
+            FileResult {
+                file: "a.mp4".to_string(),
+                label: "Animal",
+                frames: 3,
+                detections: 3,
+                max_score: Some(0.9),
+                error: None,
+            }
+        );
+        assert_eq!(completion.pending(), 0);
+    }
+
+    #[test]
+    fn test_failed_files() {
+        let mut completion = FileCompletion::default();
+        // a file that failed as a whole has no frame count
+        let failed = ExportFrame {
+            total_frames: 0,
+            bboxes: None,
+            error: Some("Invalid data".to_string()),
+            ..testing::frame("c.mp4")
+        };
+        let failed = completion.add(&failed).unwrap();
+        assert_eq!(failed.error.as_deref(), Some("Invalid data"));
+        assert_eq!((failed.frames, failed.detections), (1, 0));
+
+        // one failed frame of a video fails the file, the others still count
+        let video = ExportFrame {
+            total_frames: 2,
+            bboxes: Some(vec![testing::bbox(1, 0.7), testing::bbox(2, 0.8)]),
+            ..testing::frame("d.mp4")
+        };
+        let broken = ExportFrame {
+            frame_index: 1,
+            bboxes: None,
+            error: Some("Frame 1 has an unexpected size".to_string()),
+            ..video.clone()
+        };
+        assert!(completion.add(&video).is_none());
+        let result = completion.add(&broken).unwrap();
+        assert_eq!(result.error, broken.error);
+        // a person wins over a vehicle
+        assert_eq!(result.label, "Person");
+        assert_eq!(result.max_score, Some(0.8));
+        assert_eq!(completion.pending(), 0);
+    }
+}
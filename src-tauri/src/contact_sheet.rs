@@ -0,0 +1,216 @@
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use image::{imageops, DynamicImage, Rgb, RgbImage};
+use megascops_media::{decode_animation, decode_image, decode_tiff_pages, is_animation, is_tiff};
+use serde::{Deserialize, Serialize};
+
+use crate::export::{load_export, Bbox, ExportFrame};
+use crate::media::extract_frame;
+use crate::template::TemplateContext;
+use crate::utils::is_video;
+
+pub const CONTACT_SHEET_DIR: &str = "contact_sheets";
+
+const TILE_WIDTH: u32 = 320;
+const TILE_HEIGHT: u32 = 240;
+const GAP: u32 = 8;
+pub(crate) const BOX_COLOR: Rgb<u8> = Rgb([255, 40, 40]);
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ContactSheetOptions {
+    #[serde(default = "default_columns")]
+    pub columns: u32,
+    /// Tiles per sheet, larger days are split into pages.
+    #[serde(default = "default_per_sheet")]
+    pub per_sheet: usize,
+}
+
+impl Default for ContactSheetOptions {
+    fn default() -> Self {
+        Self {
+            columns: default_columns(),
+            per_sheet: default_per_sheet(),
+        }
+    }
+}
+
+fn default_columns() -> u32 {
+    5
+}
+
+fn default_per_sheet() -> usize {
+    30
+}
+
+pub(crate) fn is_positive(frame: &ExportFrame) -> bool {
+    frame.skipped_blank.is_none() && frame.bboxes.as_ref().is_some_and(|b| !b.is_empty())
+}
+
+/// Groups the first positive frame of every file by `(site, date)`.
+fn group_positives<'a>(
+    frames: &'a [ExportFrame],
+    folder: &Path,
+) -> BTreeMap<(String, String), Vec<&'a ExportFrame>> {
+    let mut seen = HashSet::new();
+    let mut groups: BTreeMap<(String, String), Vec<&ExportFrame>> = BTreeMap::new();
+    for frame in frames.iter().filter(|f| is_positive(f)) {
+        if !seen.insert(&frame.file.file_path) {
+            continue;
+        }
+        let ctx = TemplateContext::from_frame(frame, folder, "");
+        groups.entry((ctx.site, ctx.date)).or_default().push(frame);
+    }
+    for frames in groups.values_mut() {
+        frames.sort_by(|a, b| a.shoot_time.cmp(&b.shoot_time));
+    }
+    groups
+}
+
+pub(crate) fn draw_rect(img: &mut RgbImage, (x, y, w, h): (u32, u32, u32, u32), thickness: u32) {
+    let (width, height) = img.dimensions();
+    for py in y..(y + h).min(height) {
+        for px in x..(x + w).min(width) {
+            let edge = px < x + thickness
+                || py < y + thickness
+                || px + thickness >= x + w
+                || py + thickness >= y + h;
+            if edge {
+                img.put_pixel(px, py, BOX_COLOR);
+            }
+        }
+    }
+}
+
+/// Thumbnail of a frame with its boxes drawn, letterboxed to the tile size.
+pub(crate) fn tile(img: &DynamicImage, bboxes: &[Bbox]) -> RgbImage {
+    let mut thumb = img.thumbnail(TILE_WIDTH, TILE_HEIGHT).to_rgb8();
+    let (w, h) = thumb.dimensions();
+    for bbox in bboxes {
+        // boxes are relative to the original, scale them onto the thumbnail
+        let (x, y, bw, bh) = bbox.pixel_rect(img.width(), img.height());
+        let sx = w as f32 / img.width() as f32;
+        let sy = h as f32 / img.height() as f32;
+        let rect = (
+            (x as f32 * sx) as u32,
+            (y as f32 * sy) as u32,
+            (bw as f32 * sx) as u32,
+            (bh as f32 * sy) as u32,
+        );
+        draw_rect(&mut thumb, rect, 2);
+    }
+    let mut tile = RgbImage::new(TILE_WIDTH, TILE_HEIGHT);
+    imageops::overlay(
+        &mut tile,
+        &thumb,
+        ((TILE_WIDTH - w) / 2) as i64,
+        ((TILE_HEIGHT - h) / 2) as i64,
+    );
+    tile
+}
+
+pub(crate) fn load_frame(path: &Path, frame: &ExportFrame) -> Result<DynamicImage> {
+    if is_video(path) {
+        extract_frame(path, frame.frame_index, frame.iframe)
+    } else if is_tiff(path) {
+        // each page of a multi-page TIFF is a frame of its own
+        decode_tiff_pages(path)?
+            .into_iter()
+            .nth(frame.frame_index)
+            .ok_or_else(|| anyhow!("{} has no page {}", path.display(), frame.frame_index))
+    } else {
+        // each frame of an animated GIF or PNG is too, a still PNG decodes as any image
+        if is_animation(path) {
+            if let Some(frames) = decode_animation(path)? {
+                return frames
+                    .into_iter()
+                    .nth(frame.frame_index)
+                    .map(|(img, _)| img)
+                    .ok_or_else(|| {
+                        anyhow!("{} has no frame {}", path.display(), frame.frame_index)
+                    });
+            }
+        }
+        // upright, the frame the boxes were detected in
+        decode_image(path)
+    }
+}
+
+fn sheet(tiles: &[RgbImage], columns: u32) -> RgbImage {
+    let columns = columns.max(1);
+    let rows = (tiles.len() as u32).div_ceil(columns);
+    let mut sheet = RgbImage::from_pixel(
+        columns * (TILE_WIDTH + GAP) + GAP,
+        rows * (TILE_HEIGHT + GAP) + GAP,
+        Rgb([255, 255, 255]),
+    );
+    for (i, tile) in tiles.iter().enumerate() {
+        let col = i as u32 % columns;
+        let row = i as u32 / columns;
+        imageops::overlay(
+            &mut sheet,
+            tile,
+            (GAP + col * (TILE_WIDTH + GAP)) as i64,
+            (GAP + row * (TILE_HEIGHT + GAP)) as i64,
+        );
+    }
+    sheet
+}
+
+/// Writes contact sheets of the positive detections of a result file, one per site and
+/// day, to `contact_sheets/{site}/{date}_{page}.jpg` next to the result.
+pub fn generate_contact_sheets(
+    result: &Path,
+    options: &ContactSheetOptions,
+) -> Result<Vec<PathBuf>> {
+    let folder = result
+        .parent()
+        .ok_or_else(|| anyhow!("Invalid result path: {}", result.display()))?;
+    let frames = load_export(result)?;
+    let mut written = Vec::new();
+    for ((site, date), frames) in group_positives(&frames, folder) {
+        for (page, frames) in frames.chunks(options.per_sheet.max(1)).enumerate() {
+            let tiles: Vec<RgbImage> = frames
+                .iter()
+                .filter_map(|frame| {
+                    let path = folder.join(&frame.file.file_path);
+                    match load_frame(&path, frame) {
+                        Ok(img) => Some(tile(&img, frame.bboxes.as_deref().unwrap_or_default())),
+                        Err(e) => {
+                            log::warn!("Skipping {} in contact sheet: {}", path.display(), e);
+                            None
+                        }
+                    }
+                })
+                .collect();
+            if tiles.is_empty() {
+                continue;
+            }
+            let dir = folder.join(CONTACT_SHEET_DIR).join(&site);
+            std::fs::create_dir_all(&dir)?;
+            let path = dir.join(format!("{}_{}.jpg", date, page + 1));
+            sheet(&tiles, options.columns).save(&path)?;
+            written.push(path);
+        }
+    }
+    log::info!("Wrote {} contact sheets", written.len());
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sheet_layout() {
+        let tiles = vec![RgbImage::new(TILE_WIDTH, TILE_HEIGHT); 7];
+        let sheet = sheet(&tiles, 3);
+        assert_eq!(sheet.width(), 3 * (TILE_WIDTH + GAP) + GAP);
+        assert_eq!(sheet.height(), 3 * (TILE_HEIGHT + GAP) + GAP);
+        // the gap stays white, tiles are drawn over it
+        assert_eq!(sheet.get_pixel(0, 0), &Rgb([255, 255, 255]));
+        assert_eq!(sheet.get_pixel(GAP, GAP), &Rgb([0, 0, 0]));
+    }
+}
@@ -0,0 +1,300 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+
+use crate::launch::PROCESS_ARG;
+
+const MENU_LABEL: &str = "Send to Megascops";
+
+/// The program file managers should launch, the AppImage rather than its mounted binary.
+pub fn launcher_path() -> Result<PathBuf> {
+    if let Some(appimage) = std::env::var_os("APPIMAGE") {
+        return Ok(PathBuf::from(appimage));
+    }
+    Ok(std::env::current_exe()?)
+}
+
+/// Quotes an argument of a desktop entry `Exec` key.
+fn desktop_quote(arg: &str) -> String {
+    let mut quoted = String::from('"');
+    for c in arg.chars() {
+        if matches!(c, '"' | '`' | '$' | '\\') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Dolphin service menu, shown on folders in KDE.
+pub fn service_menu(exe: &Path) -> String {
+    format!(
+        "[Desktop Entry]\n\
+         Type=Service\n\
+         MimeType=inode/directory;\n\
+         Actions=megascops;\n\
+         X-KDE-ServiceTypes=KonqPopupMenu/Plugin\n\
+         \n\
+         [Desktop Action megascops]\n\
+         Name={}\n\
+         Icon=megascops\n\
+         Exec={} {} %f\n",
+        MENU_LABEL,
+        desktop_quote(&exe.to_string_lossy()),
+        PROCESS_ARG
+    )
+}
+
+/// Application entry for folders, listed under "Open With" in GNOME, Xfce and Cinnamon.
+pub fn application_entry(exe: &Path) -> String {
+    format!(
+        "[Desktop Entry]\n\
+         Type=Application\n\
+         Name={}\n\
+         Icon=megascops\n\
+         Exec={} {} %f\n\
+         MimeType=inode/directory;\n\
+         NoDisplay=true\n\
+         Terminal=false\n",
+        MENU_LABEL,
+        desktop_quote(&exe.to_string_lossy()),
+        PROCESS_ARG
+    )
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Quick Action running the app once per selected folder.
+pub fn workflow_document(exe: &Path) -> String {
+    let exe = exe.to_string_lossy().replace('\'', r"'\''");
+    let command = format!(
+        "for f in \"$@\"; do '{}' {} \"$f\" & done",
+        exe, PROCESS_ARG
+    );
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+	<key>AMDocumentVersion</key>
+	<string>2</string>
+	<key>actions</key>
+	<array>
+		<dict>
+			<key>action</key>
+			<dict>
+				<key>ActionBundlePath</key>
+				<string>/System/Library/Automator/Run Shell Script.action</string>
+				<key>ActionName</key>
+				<string>Run Shell Script</string>
+				<key>ActionParameters</key>
+				<dict>
+					<key>COMMAND_STRING</key>
+					<string>{}</string>
+					<key>CheckedForUserDefaultShell</key>
+					<true/>
+					<key>inputMethod</key>
+					<integer>1</integer>
+					<key>shell</key>
+					<string>/bin/sh</string>
+					<key>source</key>
+					<string></string>
+				</dict>
+				<key>BundleIdentifier</key>
+				<string>com.apple.RunShellScript</string>
+				<key>Class Name</key>
+				<string>RunShellScriptAction</string>
+			</dict>
+		</dict>
+	</array>
+	<key>workflowMetaData</key>
+	<dict>
+		<key>serviceInputTypeIdentifier</key>
+		<string>com.apple.Automator.fileSystemObject.folder</string>
+		<key>serviceOutputTypeIdentifier</key>
+		<string>com.apple.Automator.nothing</string>
+		<key>workflowTypeIdentifier</key>
+		<string>com.apple.Automator.servicesMenu</string>
+	</dict>
+</dict>
+</plist>
+"#,
+        xml_escape(&command)
+    )
+}
+
+pub fn workflow_info() -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+	<key>NSServices</key>
+	<array>
+		<dict>
+			<key>NSMenuItem</key>
+			<dict>
+				<key>default</key>
+				<string>{}</string>
+			</dict>
+			<key>NSMessage</key>
+			<string>runWorkflowAsService</string>
+			<key>NSRequiredContext</key>
+			<dict>
+				<key>NSApplicationIdentifier</key>
+				<string>com.apple.finder</string>
+			</dict>
+			<key>NSSendFileTypes</key>
+			<array>
+				<string>public.folder</string>
+			</array>
+		</dict>
+	</array>
+</dict>
+</plist>
+"#,
+        MENU_LABEL
+    )
+}
+
+fn home_dir() -> Result<PathBuf> {
+    std::env::var_os("HOME")
+        .map(PathBuf::from)
+        .ok_or_else(|| anyhow!("Home folder not found"))
+}
+
+/// Files written for the menu, per platform.
+fn menu_files() -> Result<Vec<PathBuf>> {
+    if cfg!(target_os = "macos") {
+        Ok(vec![home_dir()?
+            .join("Library/Services")
+            .join(format!("{}.workflow", MENU_LABEL))])
+    } else if cfg!(target_os = "linux") {
+        let data = match std::env::var_os("XDG_DATA_HOME") {
+            Some(data) => PathBuf::from(data),
+            None => home_dir()?.join(".local/share"),
+        };
+        Ok(vec![
+            data.join("kio/servicemenus/megascops.desktop"),
+            data.join("applications/megascops-process.desktop"),
+        ])
+    } else {
+        Ok(Vec::new())
+    }
+}
+
+/// Registry keys of the entry on folders and on the background of an open folder, with
+/// the placeholder Explorer passes the folder in.
+#[cfg(target_os = "windows")]
+const REGISTRY_KEYS: [(&str, &str); 2] = [
+    (r"HKCU\Software\Classes\Directory\shell\Megascops", "%1"),
+    (
+        r"HKCU\Software\Classes\Directory\Background\shell\Megascops",
+        "%V",
+    ),
+];
+
+#[cfg(target_os = "windows")]
+fn reg(args: &[&str]) -> Result<bool> {
+    use std::os::windows::process::CommandExt;
+    const CREATE_NO_WINDOW: u32 = 0x08000000;
+    let output = std::process::Command::new("reg")
+        .args(args)
+        .creation_flags(CREATE_NO_WINDOW)
+        .output()?;
+    Ok(output.status.success())
+}
+
+pub fn is_installed() -> Result<bool> {
+    #[cfg(target_os = "windows")]
+    if reg(&["query", REGISTRY_KEYS[0].0])? {
+        return Ok(true);
+    }
+    Ok(menu_files()?.iter().any(|file| file.exists()))
+}
+
+/// Adds the entry for the current user, launching `exe` with the selected folder.
+pub fn install(exe: &Path) -> Result<()> {
+    #[cfg(target_os = "windows")]
+    {
+        let exe = exe.to_string_lossy();
+        for (key, placeholder) in REGISTRY_KEYS {
+            let command = format!("\"{}\" {} \"{}\"", exe, PROCESS_ARG, placeholder);
+            let command_key = format!(r"{}\command", key);
+            let ok = reg(&["add", key, "/ve", "/d", MENU_LABEL, "/f"])?
+                && reg(&["add", key, "/v", "Icon", "/d", &exe, "/f"])?
+                && reg(&["add", &command_key, "/ve", "/d", &command, "/f"])?;
+            if !ok {
+                return Err(anyhow!("Failed to write registry key {}", key));
+            }
+        }
+    }
+    #[cfg(target_os = "macos")]
+    {
+        let workflow = &menu_files()?[0];
+        let contents = workflow.join("Contents");
+        std::fs::create_dir_all(&contents)?;
+        std::fs::write(contents.join("document.wflow"), workflow_document(exe))?;
+        std::fs::write(contents.join("Info.plist"), workflow_info())?;
+        // make Finder pick up the new service without logging out
+        let _ = std::process::Command::new("/System/Library/CoreServices/pbs")
+            .arg("-update")
+            .status();
+    }
+    #[cfg(target_os = "linux")]
+    {
+        let files = menu_files()?;
+        for (file, contents) in files
+            .iter()
+            .zip([service_menu(exe), application_entry(exe)])
+        {
+            std::fs::create_dir_all(file.parent().unwrap_or(Path::new(".")))?;
+            std::fs::write(file, contents)?;
+        }
+    }
+    log::info!("Installed the context menu entry for {}", exe.display());
+    Ok(())
+}
+
+pub fn uninstall() -> Result<()> {
+    #[cfg(target_os = "windows")]
+    for (key, _) in REGISTRY_KEYS {
+        if reg(&["query", key])? && !reg(&["delete", key, "/f"])? {
+            return Err(anyhow!("Failed to delete registry key {}", key));
+        }
+    }
+    for file in menu_files()? {
+        if file.is_dir() {
+            std::fs::remove_dir_all(&file)?;
+        } else if file.exists() {
+            std::fs::remove_file(&file)?;
+        }
+    }
+    log::info!("Removed the context menu entry");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_menu_entries() {
+        let exe = Path::new("/opt/My $Apps/megascops");
+        let entry = service_menu(exe);
+        assert!(entry.contains("Exec=\"/opt/My \\$Apps/megascops\" --process %f\n"));
+        assert!(application_entry(exe).contains("MimeType=inode/directory;"));
+
+        let workflow = workflow_document(Path::new("/Applications/It's Megascops.app/megascops"));
+        assert!(workflow.contains(
+            "for f in &quot;$@&quot;; do '/Applications/It'\\''s Megascops.app/megascops' --process &quot;$f&quot; &amp; done"
+        ));
+        assert!(workflow_info().contains(MENU_LABEL));
+    }
+}
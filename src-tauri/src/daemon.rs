@@ -0,0 +1,992 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::events::EventSink;
+use crate::remote::{self, RemoteInfo, RemoteOptions};
+use crate::{
+    cancel_run, pause_run, start_next_job, watch_folder, Config, Host, SharedQueue, SharedRun,
+};
+
+/// Argument starting the daemon instead of the app.
+pub const DAEMON_ARG: &str = "--daemon";
+/// Loopback port the daemon takes commands on.
+pub const DAEMON_PORT: u16 = 47291;
+
+const SERVICE_NAME: &str = "megascops";
+const LAUNCHD_LABEL: &str = "org.megascops.daemon";
+const STATE_FILE: &str = "daemon.json";
+const TOKEN_FILE: &str = "daemon.token";
+const LOG_FILE: &str = "daemon.log";
+/// Events kept for a subscriber that falls behind, older ones are dropped.
+const EVENT_BACKLOG: usize = 256;
+/// Longest request line, a job's config is a few kilobytes.
+const MAX_REQUEST_LINE: usize = 1 << 20;
+
+fn home_dir() -> Result<PathBuf> {
+    std::env::var_os("HOME")
+        .map(PathBuf::from)
+        .ok_or_else(|| anyhow!("Home folder not found"))
+}
+
+/// Folder of the daemon's state, access token and log. The Windows service runs as
+/// LocalSystem, so it lives below ProgramData, restricted to SYSTEM and the administrators.
+pub fn daemon_dir() -> Result<PathBuf> {
+    let base = if cfg!(target_os = "windows") {
+        std::env::var_os("ProgramData")
+            .map(PathBuf::from)
+            .ok_or_else(|| anyhow!("ProgramData folder not found"))?
+    } else if cfg!(target_os = "macos") {
+        home_dir()?.join("Library/Application Support")
+    } else {
+        match std::env::var_os("XDG_CONFIG_HOME") {
+            Some(config) => PathBuf::from(config),
+            None => home_dir()?.join(".config"),
+        }
+    };
+    Ok(base.join("megascops"))
+}
+
+/// What the daemon restores when it starts again.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct DaemonState {
+    /// Configurations of the watched folders.
+    pub watches: Vec<Config>,
+    /// Configuration of the jobs queued without one.
+    pub config: Option<Config>,
+    /// Settings of the remote control API, off when unset.
+    pub remote: Option<RemoteOptions>,
+}
+
+/// Commands of the control connection.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "command", rename_all = "camelCase")]
+pub enum DaemonRequest {
+    Status,
+    /// Watches the selected folder of `config`, replacing an earlier watch of it.
+    Watch {
+        config: Box<Config>,
+    },
+    Unwatch {
+        folder: PathBuf,
+    },
+    /// Queues the selected folder of `config` as a job run with that configuration.
+    Enqueue {
+        config: Box<Config>,
+    },
+    /// Queues files and folders as a job run with the default configuration.
+    QueuePaths {
+        paths: Vec<PathBuf>,
+    },
+    RemoveJob {
+        id: String,
+    },
+    /// Sets the default configuration.
+    SetConfig {
+        config: Box<Config>,
+    },
+    /// Starts the remote control API with `options`, or stops it when unset.
+    SetRemote {
+        options: Option<RemoteOptions>,
+    },
+    Pause,
+    Resume,
+    Cancel,
+    /// Keeps the connection open and sends the events of the daemon as they happen.
+    Subscribe,
+    Shutdown,
+}
+
+/// A request line, with the access token of the daemon folder.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DaemonMessage {
+    pub token: String,
+    #[serde(flatten)]
+    pub request: DaemonRequest,
+}
+
+/// A reply line. A subscribed connection gets an `Ok` and then its events.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DaemonReply {
+    Ok(Value),
+    Error(String),
+    Event { event: String, payload: Value },
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DaemonStatus {
+    pub version: String,
+    pub watching: Vec<PathBuf>,
+    pub jobs: Vec<crate::queue::Job>,
+    pub running: bool,
+    pub paused: bool,
+    pub has_config: bool,
+    /// Address of the remote control API when it is running.
+    pub remote: Option<String>,
+}
+
+struct Watch {
+    config: Config,
+    stop: CancellationToken,
+}
+
+struct Inner {
+    dir: PathBuf,
+    token: String,
+    runs: SharedRun,
+    queue: SharedQueue,
+    watches: Mutex<BTreeMap<PathBuf, Watch>>,
+    config: Mutex<Option<Config>>,
+    remote: Mutex<Option<(RemoteOptions, CancellationToken)>>,
+    events: broadcast::Sender<(String, Value)>,
+    stop: CancellationToken,
+}
+
+/// The watches and the job queue, run without a window.
+#[derive(Clone)]
+pub struct Daemon(Arc<Inner>);
+
+impl EventSink for Daemon {
+    fn emit_value(&self, event: &str, payload: Value) {
+        if event != "detect-progress" && event != "job-progress" {
+            log::debug!("{}: {}", event, payload);
+        }
+        // no subscriber is fine
+        let _ = self.0.events.send((event.to_string(), payload));
+    }
+}
+
+impl Host for Daemon {
+    fn sink(&self) -> Arc<dyn EventSink> {
+        Arc::new(self.clone())
+    }
+
+    fn runs(&self) -> &SharedRun {
+        &self.0.runs
+    }
+
+    fn queue(&self) -> &SharedQueue {
+        &self.0.queue
+    }
+
+    fn default_config(&self) -> Result<Config> {
+        self.0
+            .config
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or_else(|| anyhow!("No default configuration set on the daemon"))
+    }
+
+    fn result_written(&self, result: &Path) {
+        log::info!("Results written to {}", result.display());
+    }
+}
+
+/// The access token of `dir`, created on first use.
+fn load_token(dir: &Path) -> Result<String> {
+    let path = dir.join(TOKEN_FILE);
+    if let Ok(token) = std::fs::read_to_string(&path) {
+        if !token.trim().is_empty() {
+            return Ok(token.trim().to_string());
+        }
+    }
+    let token = Uuid::new_v4().simple().to_string();
+    write_private(&path, &token).with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(token)
+}
+
+/// Limits `dir` and the files in it to SYSTEM and the administrators, ProgramData lets
+/// every user read them otherwise. Elsewhere the folder is below the user's home already.
+fn restrict_access(dir: &Path) -> Result<()> {
+    #[cfg(target_os = "windows")]
+    {
+        const SYSTEM: &str = "*S-1-5-18:(OI)(CI)F";
+        const ADMINISTRATORS: &str = "*S-1-5-32-544:(OI)(CI)F";
+        let folder = dir.to_string_lossy();
+        service_command(
+            "icacls.exe",
+            &[
+                folder.as_ref(),
+                "/inheritance:r",
+                "/grant:r",
+                SYSTEM,
+                ADMINISTRATORS,
+            ],
+        )?;
+        // files written before inherit the folder's entries again
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            service_command("icacls.exe", &[path.to_string_lossy().as_ref(), "/reset"])?;
+        }
+    }
+    #[cfg(not(target_os = "windows"))]
+    let _ = dir;
+    Ok(())
+}
+
+/// Writes a file only its owner may read, as it holds the access tokens of the daemon.
+/// On Windows it inherits the entries of the restricted daemon folder.
+fn write_private(path: &Path, contents: &str) -> Result<()> {
+    std::fs::write(path, contents)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    }
+    Ok(())
+}
+
+impl Daemon {
+    /// Opens the daemon of `dir`, stopped by `stop`. Its watches start on [`serve`].
+    pub fn open(dir: &Path, stop: CancellationToken) -> Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        restrict_access(dir)
+            .with_context(|| format!("Failed to restrict access to {}", dir.display()))?;
+        let (events, _) = broadcast::channel(EVENT_BACKLOG);
+        Ok(Self(Arc::new(Inner {
+            dir: dir.to_path_buf(),
+            token: load_token(dir)?,
+            runs: SharedRun::default(),
+            queue: SharedQueue::default(),
+            watches: Mutex::default(),
+            config: Mutex::default(),
+            remote: Mutex::default(),
+            events,
+            stop,
+        })))
+    }
+
+    fn load_state(&self) -> Result<DaemonState> {
+        let path = self.0.dir.join(STATE_FILE);
+        if !path.exists() {
+            return Ok(DaemonState::default());
+        }
+        let content = std::fs::read_to_string(&path)?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    fn save_state(&self) -> Result<()> {
+        let state = DaemonState {
+            watches: self
+                .0
+                .watches
+                .lock()
+                .unwrap()
+                .values()
+                .map(|w| w.config.clone())
+                .collect(),
+            config: self.0.config.lock().unwrap().clone(),
+            remote: self
+                .0
+                .remote
+                .lock()
+                .unwrap()
+                .as_ref()
+                .map(|(options, _)| options.clone()),
+        };
+        let path = self.0.dir.join(STATE_FILE);
+        let temp = path.with_extension("json.tmp");
+        write_private(&temp, &serde_json::to_string_pretty(&state)?)?;
+        std::fs::rename(&temp, &path)?;
+        Ok(())
+    }
+
+    /// Restores the default configuration and the watches of the last session.
+    async fn restore(&self) -> Result<()> {
+        let state = self.load_state()?;
+        *self.0.config.lock().unwrap() = state.config;
+        for config in state.watches {
+            self.watch(config)?;
+        }
+        if let Some(options) = state.remote {
+            self.set_remote(Some(options)).await?;
+        }
+        Ok(())
+    }
+
+    /// Starts the remote API with `options` in place of the one running, or only stops
+    /// it when unset.
+    async fn set_remote(&self, options: Option<RemoteOptions>) -> Result<Option<RemoteInfo>> {
+        if let Some((_, stop)) = self.0.remote.lock().unwrap().take() {
+            stop.cancel();
+        }
+        let Some(options) = options.map(RemoteOptions::with_token).transpose()? else {
+            self.save_state()?;
+            return Ok(None);
+        };
+        let listener = remote::bind(&options).await?;
+        let stop = self.0.stop.child_token();
+        *self.0.remote.lock().unwrap() = Some((options.clone(), stop.clone()));
+        self.save_state()?;
+        let (daemon, token) = (self.clone(), options.token.clone());
+        tauri::async_runtime::spawn(async move {
+            if let Err(e) = remote::serve(daemon, listener, token, stop).await {
+                log::error!("Remote API failed: {}", e);
+            }
+        });
+        Ok(Some(RemoteInfo {
+            address: options.address,
+            token: options.token,
+        }))
+    }
+
+    fn watch(&self, config: Config) -> Result<()> {
+        let folder = PathBuf::from(&config.detect_options.selected_folder);
+        if !folder.is_dir() {
+            return Err(anyhow!("{} is not a folder", folder.display()));
+        }
+        let stop = self.0.stop.child_token();
+        let previous = self.0.watches.lock().unwrap().insert(
+            folder.clone(),
+            Watch {
+                config: config.clone(),
+                stop: stop.clone(),
+            },
+        );
+        if let Some(previous) = previous {
+            previous.stop.cancel();
+        }
+        log::info!("Watching {}", folder.display());
+        tauri::async_runtime::spawn(watch_folder(self.clone(), config, stop));
+        self.save_state()
+    }
+
+    fn unwatch(&self, folder: &Path) -> Result<bool> {
+        let Some(watch) = self.0.watches.lock().unwrap().remove(folder) else {
+            return Ok(false);
+        };
+        watch.stop.cancel();
+        self.save_state()?;
+        Ok(true)
+    }
+
+    pub fn status(&self) -> DaemonStatus {
+        let paused = self
+            .0
+            .runs
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|run| run.gate.is_paused());
+        DaemonStatus {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            watching: self.0.watches.lock().unwrap().keys().cloned().collect(),
+            jobs: self.0.queue.lock().unwrap().jobs().to_vec(),
+            running: paused.is_some(),
+            paused: paused.unwrap_or(false),
+            has_config: self.0.config.lock().unwrap().is_some(),
+            remote: self
+                .0
+                .remote
+                .lock()
+                .unwrap()
+                .as_ref()
+                .map(|(options, _)| options.address.clone()),
+        }
+    }
+
+    fn queued(&self, job: Result<crate::queue::Job>) -> Result<Value> {
+        let job = job?;
+        self.emit("job-queued", &job);
+        start_next_job(self.clone());
+        Ok(serde_json::to_value(job)?)
+    }
+
+    fn emit<S: Serialize>(&self, event: &str, payload: S) {
+        let sink: &dyn EventSink = self;
+        sink.emit(event, payload);
+    }
+
+    async fn handle(&self, request: DaemonRequest) -> Result<Value> {
+        match request {
+            DaemonRequest::Status => Ok(serde_json::to_value(self.status())?),
+            DaemonRequest::Watch { config } => {
+                self.watch(*config)?;
+                Ok(Value::Null)
+            }
+            DaemonRequest::Unwatch { folder } => Ok(self.unwatch(&folder)?.into()),
+            DaemonRequest::Enqueue { config } => {
+                let folder = PathBuf::from(&config.detect_options.selected_folder);
+                let job = self
+                    .0
+                    .queue
+                    .lock()
+                    .unwrap()
+                    .add_folder(&folder, Some(*config));
+                self.queued(job)
+            }
+            DaemonRequest::QueuePaths { paths } => {
+                let job = self.0.queue.lock().unwrap().add_paths(&paths, None);
+                self.queued(job)
+            }
+            DaemonRequest::RemoveJob { id } => {
+                let job = self.0.queue.lock().unwrap().remove(&id)?;
+                self.emit("job-removed", &job.id);
+                Ok(Value::Null)
+            }
+            DaemonRequest::SetConfig { config } => {
+                *self.0.config.lock().unwrap() = Some(*config);
+                self.save_state()?;
+                Ok(Value::Null)
+            }
+            DaemonRequest::SetRemote { options } => {
+                Ok(serde_json::to_value(self.set_remote(options).await?)?)
+            }
+            DaemonRequest::Pause => Ok(pause_run(self, &self.0.runs, true).into()),
+            DaemonRequest::Resume => Ok(pause_run(self, &self.0.runs, false).into()),
+            DaemonRequest::Cancel => Ok(cancel_run(self, &self.0.runs).await.into()),
+            DaemonRequest::Shutdown => {
+                log::info!("Shutdown requested");
+                self.0.stop.cancel();
+                Ok(Value::Null)
+            }
+            DaemonRequest::Subscribe => Err(anyhow!("Subscribe opens its own connection")),
+        }
+    }
+}
+
+async fn send_reply(write: &mut OwnedWriteHalf, reply: &DaemonReply) -> Result<()> {
+    let mut line = serde_json::to_vec(reply)?;
+    line.push(b'\n');
+    write.write_all(&line).await?;
+    Ok(())
+}
+
+/// Forwards the events of the daemon until the client hangs up or the daemon stops.
+async fn subscribe(daemon: &Daemon, mut write: OwnedWriteHalf) -> Result<()> {
+    let mut events = daemon.0.events.subscribe();
+    send_reply(&mut write, &DaemonReply::Ok(Value::Null)).await?;
+    loop {
+        let (event, payload) = tokio::select! {
+            _ = daemon.0.stop.cancelled() => return Ok(()),
+            event = events.recv() => match event {
+                Ok(event) => event,
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    log::warn!("A subscriber missed {} events", missed);
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => return Ok(()),
+            },
+        };
+        send_reply(&mut write, &DaemonReply::Event { event, payload }).await?;
+    }
+}
+
+/// Reads a line of at most `limit` bytes into `line`, `false` at the end of the stream.
+async fn read_request<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    line: &mut String,
+    limit: usize,
+) -> Result<bool> {
+    line.clear();
+    let read = reader.take(limit as u64 + 1).read_line(line).await?;
+    if read > limit && !line.ends_with('\n') {
+        return Err(anyhow!("Request exceeds {} bytes", limit));
+    }
+    Ok(read > 0)
+}
+
+async fn connection(daemon: Daemon, stream: TcpStream) -> Result<()> {
+    let (read, mut write) = stream.into_split();
+    let mut read = BufReader::new(read);
+    let mut line = String::new();
+    loop {
+        match read_request(&mut read, &mut line, MAX_REQUEST_LINE).await {
+            Ok(true) => (),
+            Ok(false) => break,
+            Err(e) => {
+                // the rest of the line can't be told from the next request
+                send_reply(&mut write, &DaemonReply::Error(e.to_string())).await?;
+                return Err(e);
+            }
+        }
+        let reply = match serde_json::from_str::<DaemonMessage>(&line) {
+            Err(e) => DaemonReply::Error(format!("Invalid request: {}", e)),
+            Ok(message) if !remote::tokens_match(&message.token, &daemon.0.token) => {
+                DaemonReply::Error("Invalid token".to_string())
+            }
+            Ok(DaemonMessage {
+                request: DaemonRequest::Subscribe,
+                ..
+            }) => return subscribe(&daemon, write).await,
+            Ok(message) => match daemon.handle(message.request).await {
+                Ok(value) => DaemonReply::Ok(value),
+                Err(e) => DaemonReply::Error(e.to_string()),
+            },
+        };
+        send_reply(&mut write, &reply).await?;
+    }
+    Ok(())
+}
+
+/// Restores the last session and takes commands on `listener` until the daemon stops,
+/// then cancels the run in progress.
+pub async fn serve(daemon: Daemon, listener: TcpListener) -> Result<()> {
+    if let Err(e) = daemon.restore().await {
+        log::error!("Failed to restore the daemon state: {}", e);
+    }
+    log::info!("Daemon listening on {}", listener.local_addr()?);
+    loop {
+        tokio::select! {
+            _ = daemon.0.stop.cancelled() => break,
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => {
+                    let daemon = daemon.clone();
+                    tauri::async_runtime::spawn(async move {
+                        if let Err(e) = connection(daemon, stream).await {
+                            log::warn!("Control connection failed: {}", e);
+                        }
+                    });
+                }
+                Err(e) => log::warn!("Failed to accept a control connection: {}", e),
+            }
+        }
+    }
+    cancel_run(&daemon, &daemon.0.runs).await;
+    log::info!("Daemon stopped");
+    Ok(())
+}
+
+/// Writes the log of the daemon, in the format of the app's log.
+struct FileLog(Mutex<std::fs::File>);
+
+impl log::Log for FileLog {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::Level::Info && metadata.target() != "hyper"
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let mut file = self.0.lock().unwrap();
+        let _ = writeln!(
+            file,
+            "[{}][{}][{}] {}",
+            chrono::Local::now().format("%Y-%m-%d][%H:%M:%S"),
+            record.level(),
+            record.target(),
+            record.args()
+        );
+    }
+
+    fn flush(&self) {
+        let _ = self.0.lock().unwrap().flush();
+    }
+}
+
+fn init_log(dir: &Path) -> Result<()> {
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join(LOG_FILE))?;
+    // a logger set before is kept, the daemon's lives as long as the process
+    let logger = Box::leak(Box::new(FileLog(Mutex::new(file))));
+    if log::set_logger(logger).is_ok() {
+        log::set_max_level(log::LevelFilter::Info);
+    }
+    Ok(())
+}
+
+/// Runs the daemon in the foreground until `stop` is cancelled or a client shuts it down.
+pub fn run(stop: CancellationToken) -> Result<()> {
+    let dir = daemon_dir()?;
+    std::fs::create_dir_all(&dir)?;
+    init_log(&dir)?;
+    let daemon = Daemon::open(&dir, stop)?;
+    tauri::async_runtime::block_on(async move {
+        let listener = TcpListener::bind(("127.0.0.1", DAEMON_PORT))
+            .await
+            .with_context(|| format!("Failed to listen on port {}", DAEMON_PORT))?;
+        serve(daemon, listener).await
+    })
+}
+
+async fn send(stream: TcpStream, token: &str, request: DaemonRequest) -> Result<Value> {
+    let (read, mut write) = stream.into_split();
+    let mut line = serde_json::to_vec(&DaemonMessage {
+        token: token.to_string(),
+        request,
+    })?;
+    line.push(b'\n');
+    write.write_all(&line).await?;
+    let reply = BufReader::new(read)
+        .lines()
+        .next_line()
+        .await?
+        .ok_or_else(|| anyhow!("The daemon closed the connection"))?;
+    match serde_json::from_str(&reply)? {
+        DaemonReply::Ok(value) => Ok(value),
+        DaemonReply::Error(e) => Err(anyhow!(e)),
+        DaemonReply::Event { .. } => Err(anyhow!("Unexpected event reply")),
+    }
+}
+
+fn client_token() -> Result<String> {
+    let path = daemon_dir()?.join(TOKEN_FILE);
+    let token = std::fs::read_to_string(&path).with_context(|| {
+        format!(
+            "The daemon never ran or only administrators may read {}",
+            path.display()
+        )
+    })?;
+    Ok(token.trim().to_string())
+}
+
+async fn connect() -> Result<TcpStream> {
+    TcpStream::connect(("127.0.0.1", DAEMON_PORT))
+        .await
+        .context("The daemon isn't running")
+}
+
+/// Sends a command to the daemon of this machine and returns its reply.
+pub async fn request(request: DaemonRequest) -> Result<Value> {
+    let token = client_token()?;
+    send(connect().await?, &token, request).await
+}
+
+/// Re-emits the events of the daemon to `sink` until the connection closes.
+pub async fn forward_events(sink: Arc<dyn EventSink>) -> Result<()> {
+    let token = client_token()?;
+    let (read, mut write) = connect().await?.into_split();
+    let mut line = serde_json::to_vec(&DaemonMessage {
+        token,
+        request: DaemonRequest::Subscribe,
+    })?;
+    line.push(b'\n');
+    write.write_all(&line).await?;
+    let mut lines = BufReader::new(read).lines();
+    while let Some(line) = lines.next_line().await? {
+        match serde_json::from_str(&line)? {
+            DaemonReply::Ok(_) => {}
+            DaemonReply::Error(e) => return Err(anyhow!(e)),
+            DaemonReply::Event { event, payload } => sink.emit_value(&event, payload),
+        }
+    }
+    Ok(())
+}
+
+/// systemd user unit starting the daemon with the session.
+pub fn systemd_unit(exe: &Path) -> String {
+    let exe = exe
+        .to_string_lossy()
+        .replace('\\', r"\\")
+        .replace('"', "\\\"");
+    format!(
+        "[Unit]\n\
+         Description=Megascops ingest daemon\n\
+         After=network-online.target\n\
+         \n\
+         [Service]\n\
+         ExecStart=\"{}\" {}\n\
+         Restart=on-failure\n\
+         RestartSec=10\n\
+         \n\
+         [Install]\n\
+         WantedBy=default.target\n",
+        exe, DAEMON_ARG
+    )
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// LaunchAgent starting the daemon at login and again when it exits on failure.
+pub fn launchd_plist(exe: &Path) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+	<key>Label</key>
+	<string>{}</string>
+	<key>ProgramArguments</key>
+	<array>
+		<string>{}</string>
+		<string>{}</string>
+	</array>
+	<key>RunAtLoad</key>
+	<true/>
+	<key>KeepAlive</key>
+	<dict>
+		<key>SuccessfulExit</key>
+		<false/>
+	</dict>
+	<key>ProcessType</key>
+	<string>Background</string>
+</dict>
+</plist>
+"#,
+        LAUNCHD_LABEL,
+        xml_escape(&exe.to_string_lossy()),
+        DAEMON_ARG
+    )
+}
+
+/// File the service is defined in, none on Windows where the service manager keeps it.
+fn service_file() -> Result<Option<PathBuf>> {
+    if cfg!(target_os = "macos") {
+        Ok(Some(
+            home_dir()?
+                .join("Library/LaunchAgents")
+                .join(format!("{}.plist", LAUNCHD_LABEL)),
+        ))
+    } else if cfg!(target_os = "linux") {
+        let config = match std::env::var_os("XDG_CONFIG_HOME") {
+            Some(config) => PathBuf::from(config),
+            None => home_dir()?.join(".config"),
+        };
+        Ok(Some(
+            config
+                .join("systemd/user")
+                .join(format!("{}.service", SERVICE_NAME)),
+        ))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Runs a service manager command, failing with its error output.
+fn service_command(program: &str, args: &[&str]) -> Result<()> {
+    let mut command = std::process::Command::new(program);
+    command.args(args);
+    #[cfg(target_os = "windows")]
+    {
+        use std::os::windows::process::CommandExt;
+        const CREATE_NO_WINDOW: u32 = 0x08000000;
+        command.creation_flags(CREATE_NO_WINDOW);
+    }
+    let output = command
+        .output()
+        .with_context(|| format!("Failed to run {}", program))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let message = if stderr.trim().is_empty() {
+            stdout
+        } else {
+            stderr
+        };
+        return Err(anyhow!(
+            "{} {} failed: {}",
+            program,
+            args.join(" "),
+            message.trim()
+        ));
+    }
+    Ok(())
+}
+
+pub fn is_installed() -> Result<bool> {
+    if cfg!(target_os = "windows") {
+        return Ok(service_command("sc.exe", &["query", SERVICE_NAME]).is_ok());
+    }
+    Ok(service_file()?.is_some_and(|file| file.exists()))
+}
+
+/// Installs the daemon running `exe` and starts it: a user unit on Linux, a LaunchAgent
+/// on macOS and a service started with Windows, which needs administrator rights.
+pub fn install(exe: &Path) -> Result<()> {
+    #[cfg(target_os = "windows")]
+    {
+        let bin_path = format!("\"{}\" {}", exe.display(), DAEMON_ARG);
+        service_command(
+            "sc.exe",
+            &[
+                "create",
+                SERVICE_NAME,
+                "binPath=",
+                &bin_path,
+                "start=",
+                "auto",
+                "DisplayName=",
+                "Megascops ingest daemon",
+            ],
+        )?;
+        service_command("sc.exe", &["start", SERVICE_NAME])?;
+    }
+    #[cfg(target_os = "macos")]
+    {
+        let file = service_file()?.unwrap();
+        std::fs::create_dir_all(file.parent().unwrap_or(Path::new(".")))?;
+        std::fs::write(&file, launchd_plist(exe))?;
+        service_command("launchctl", &["load", "-w", &file.to_string_lossy()])?;
+    }
+    #[cfg(target_os = "linux")]
+    {
+        let file = service_file()?.unwrap();
+        std::fs::create_dir_all(file.parent().unwrap_or(Path::new(".")))?;
+        std::fs::write(&file, systemd_unit(exe))?;
+        service_command("systemctl", &["--user", "daemon-reload"])?;
+        service_command("systemctl", &["--user", "enable", "--now", SERVICE_NAME])?;
+    }
+    log::info!("Installed the daemon for {}", exe.display());
+    Ok(())
+}
+
+/// Stops the daemon and removes it from the service manager.
+pub fn uninstall() -> Result<()> {
+    #[cfg(target_os = "windows")]
+    if is_installed()? {
+        // stopping fails when it isn't running, which is fine
+        let _ = service_command("sc.exe", &["stop", SERVICE_NAME]);
+        service_command("sc.exe", &["delete", SERVICE_NAME])?;
+    }
+    #[cfg(target_os = "macos")]
+    if let Some(file) = service_file()?.filter(|file| file.exists()) {
+        let _ = service_command("launchctl", &["unload", "-w", &file.to_string_lossy()]);
+        std::fs::remove_file(&file)?;
+    }
+    #[cfg(target_os = "linux")]
+    if let Some(file) = service_file()?.filter(|file| file.exists()) {
+        let _ = service_command("systemctl", &["--user", "disable", "--now", SERVICE_NAME]);
+        std::fs::remove_file(&file)?;
+        let _ = service_command("systemctl", &["--user", "daemon-reload"]);
+    }
+    log::info!("Removed the daemon");
+    Ok(())
+}
+
+#[cfg(target_os = "windows")]
+mod service {
+    use std::ffi::OsString;
+    use std::time::Duration;
+
+    use tokio_util::sync::CancellationToken;
+    use windows_service::service::{
+        ServiceControl, ServiceControlAccept, ServiceExitCode, ServiceState, ServiceStatus,
+        ServiceType,
+    };
+    use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+    use windows_service::{define_windows_service, service_dispatcher};
+
+    use super::SERVICE_NAME;
+
+    define_windows_service!(ffi_service_main, service_main);
+
+    fn service_main(_arguments: Vec<OsString>) {
+        if let Err(e) = run_service() {
+            log::error!("Service failed: {}", e);
+        }
+    }
+
+    fn status(state: ServiceState, exit_code: u32) -> ServiceStatus {
+        ServiceStatus {
+            service_type: ServiceType::OWN_PROCESS,
+            current_state: state,
+            controls_accepted: if state == ServiceState::Running {
+                ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN
+            } else {
+                ServiceControlAccept::empty()
+            },
+            exit_code: ServiceExitCode::Win32(exit_code),
+            checkpoint: 0,
+            wait_hint: Duration::default(),
+            process_id: None,
+        }
+    }
+
+    fn run_service() -> windows_service::Result<()> {
+        let stop = CancellationToken::new();
+        let handler_stop = stop.clone();
+        let handle =
+            service_control_handler::register(SERVICE_NAME, move |control| match control {
+                ServiceControl::Stop | ServiceControl::Shutdown => {
+                    handler_stop.cancel();
+                    ServiceControlHandlerResult::NoError
+                }
+                ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+                _ => ServiceControlHandlerResult::NotImplemented,
+            })?;
+        handle.set_service_status(status(ServiceState::Running, 0))?;
+        let exit_code = match super::run(stop) {
+            Ok(()) => 0,
+            Err(e) => {
+                log::error!("Daemon failed: {}", e);
+                1
+            }
+        };
+        handle.set_service_status(status(ServiceState::Stopped, exit_code))
+    }
+
+    /// Hands the process to the service manager, failing when it wasn't started by it.
+    pub fn dispatch() -> windows_service::Result<()> {
+        service_dispatcher::start(SERVICE_NAME, ffi_service_main)
+    }
+}
+
+/// Runs the daemon as the Windows service when started by the service manager, in the
+/// foreground otherwise.
+pub fn main() -> Result<()> {
+    #[cfg(target_os = "windows")]
+    if service::dispatch().is_ok() {
+        return Ok(());
+    }
+    run(CancellationToken::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_daemon_files() {
+        let unit = systemd_unit(Path::new("/opt/My Apps/megascops"));
+        assert!(unit.contains("ExecStart=\"/opt/My Apps/megascops\" --daemon\n"));
+        assert!(unit.contains("WantedBy=default.target"));
+        let plist = launchd_plist(Path::new("/Applications/A&B.app/megascops"));
+        assert!(plist.contains("<string>/Applications/A&amp;B.app/megascops</string>"));
+        assert!(plist.contains(LAUNCHD_LABEL));
+
+        let message: DaemonMessage =
+            serde_json::from_str(r#"{"token": "t", "command": "removeJob", "id": "j"}"#).unwrap();
+        assert_eq!(message.token, "t");
+        assert!(matches!(message.request, DaemonRequest::RemoveJob { id } if id == "j"));
+        let line = serde_json::to_string(&DaemonMessage {
+            token: "t".to_string(),
+            request: DaemonRequest::Status,
+        })
+        .unwrap();
+        assert_eq!(line, r#"{"token":"t","command":"status"}"#);
+        let reply: DaemonReply = serde_json::from_str(r#"{"error": "Invalid token"}"#).unwrap();
+        assert!(matches!(reply, DaemonReply::Error(e) if e == "Invalid token"));
+    }
+
+    #[tokio::test]
+    async fn test_read_request() {
+        let mut line = String::new();
+        let mut reader: &[u8] = b"{\"a\":1}\n0123456789\nlast";
+        assert!(read_request(&mut reader, &mut line, 10).await.unwrap());
+        assert_eq!(line, "{\"a\":1}\n");
+        // a line of exactly the limit still fits
+        assert!(read_request(&mut reader, &mut line, 10).await.unwrap());
+        assert_eq!(line, "0123456789\n");
+        assert!(read_request(&mut reader, &mut line, 10).await.unwrap());
+        assert_eq!(line, "last");
+        assert!(!read_request(&mut reader, &mut line, 10).await.unwrap());
+
+        let mut reader: &[u8] = b"01234567890\n";
+        let error = read_request(&mut reader, &mut line, 10).await.unwrap_err();
+        assert_eq!(error.to_string(), "Request exceeds 10 bytes");
+    }
+}
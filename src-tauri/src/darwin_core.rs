@@ -0,0 +1,370 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use chrono::DateTime;
+use csv::WriterBuilder;
+use megascops_media::parse_iso6709;
+use nom_exif::MediaParser;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::anonymize::hash_path;
+use crate::contact_sheet::is_positive;
+use crate::embedding::{EmbeddingStore, ReviewLabels, EMBEDDING_DB};
+use crate::export::{load_export, ExportFrame};
+use crate::metadata::read_metadata;
+use crate::report::by_file;
+use crate::utils::{portable_path, FileItem};
+
+pub const OCCURRENCE_FILE: &str = "occurrence.csv";
+
+const BASIS_OF_RECORD: &str = "MachineObservation";
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct DarwinCoreOptions {
+    /// Scientific names of detector and review labels. Detector labels without one are
+    /// left out, review labels without one are taken as scientific names already.
+    pub taxa: BTreeMap<String, String>,
+    pub dataset_name: Option<String>,
+    /// Only files whose boxes were labeled in review.
+    pub verified_only: bool,
+}
+
+impl Default for DarwinCoreOptions {
+    fn default() -> Self {
+        Self {
+            taxa: BTreeMap::from([
+                ("Animal".to_string(), "Animalia".to_string()),
+                ("Person".to_string(), "Homo sapiens".to_string()),
+            ]),
+            dataset_name: None,
+            verified_only: false,
+        }
+    }
+}
+
+/// One row of the occurrence core, a taxon seen in one file.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Occurrence {
+    #[serde(rename = "occurrenceID")]
+    pub occurrence_id: String,
+    #[serde(rename = "basisOfRecord")]
+    pub basis_of_record: &'static str,
+    #[serde(rename = "eventDate")]
+    pub event_date: Option<String>,
+    #[serde(rename = "decimalLatitude")]
+    pub decimal_latitude: Option<f64>,
+    #[serde(rename = "decimalLongitude")]
+    pub decimal_longitude: Option<f64>,
+    #[serde(rename = "geodeticDatum")]
+    pub geodetic_datum: Option<&'static str>,
+    #[serde(rename = "scientificName")]
+    pub scientific_name: String,
+    #[serde(rename = "individualCount")]
+    pub individual_count: Option<usize>,
+    #[serde(rename = "identificationVerificationStatus")]
+    pub verification_status: &'static str,
+    #[serde(rename = "associatedMedia")]
+    pub associated_media: String,
+    #[serde(rename = "datasetName")]
+    pub dataset_name: Option<String>,
+}
+
+/// `YYYY-MM-DD hh:mm:ss +zz:zz` as exported to ISO 8601, other values are kept.
+fn event_date(shoot_time: &str) -> String {
+    DateTime::parse_from_str(shoot_time, "%Y-%m-%d %H:%M:%S %:z")
+        .map(|t| t.to_rfc3339())
+        .unwrap_or_else(|_| shoot_time.to_string())
+}
+
+/// Occurrences of `frames`, whose paths are resolved against `folder`. Verified frames
+/// and reviewed boxes win over the detector labels of a file. `positions` holds the
+/// coordinates of files whose frames have none, from results of older versions.
+pub fn occurrences(
+    frames: &[ExportFrame],
+    folder: &Path,
+    review_labels: &ReviewLabels,
+    positions: &HashMap<PathBuf, (f64, f64)>,
+    options: &DarwinCoreOptions,
+) -> Vec<Occurrence> {
+    let mut occurrences = Vec::new();
+    for (file_path, frames) in by_file(frames) {
+        let path = folder.join(file_path);
+        let key = portable_path(&path, None);
+        let reviewed: Vec<Vec<(usize, String)>> = frames
+            .iter()
+            .filter_map(|f| {
+                f.verified_labels()
+                    .or_else(|| review_labels.get(&(key.clone(), f.frame_index)).cloned())
+            })
+            .collect();
+        let verified = !reviewed.is_empty();
+        if options.verified_only && !verified {
+            continue;
+        }
+        // most individuals of a taxon seen in one frame
+        let mut counts: BTreeMap<String, Option<usize>> = BTreeMap::new();
+        if verified {
+            for labels in &reviewed {
+                let mut frame_counts: BTreeMap<&str, usize> = BTreeMap::new();
+                for (_, label) in labels {
+                    let name = options.taxa.get(label).unwrap_or(label);
+                    *frame_counts.entry(name).or_default() += 1;
+                }
+                for (name, count) in frame_counts {
+                    let entry = counts.entry(name.to_string()).or_insert(Some(0));
+                    *entry = (*entry).max(Some(count));
+                }
+            }
+        } else {
+            for frame in frames.iter().filter(|f| is_positive(f)) {
+                let labels = frame.label.as_deref().unwrap_or_default();
+                for label in labels {
+                    let Some(name) = options.taxa.get(label) else {
+                        continue;
+                    };
+                    // boxes can only be told apart when the frame has a single label
+                    let count = (labels.len() == 1).then(|| frame.bboxes.iter().flatten().count());
+                    let entry = counts.entry(name.clone()).or_insert(count);
+                    *entry = (*entry).max(count);
+                }
+            }
+        }
+        if counts.is_empty() {
+            continue;
+        }
+        let media = portable_path(&path, Some(folder));
+        let position = frames
+            .iter()
+            .find_map(|f| Some((f.latitude?, f.longitude?)))
+            .or_else(|| positions.get(&path).copied());
+        let event_date = frames
+            .iter()
+            .find_map(|f| f.shoot_time.as_deref())
+            .filter(|t| !t.is_empty())
+            .map(event_date);
+        for (i, (name, count)) in counts.into_iter().enumerate() {
+            occurrences.push(Occurrence {
+                occurrence_id: format!("{}/{}", hash_path(&media), i + 1),
+                basis_of_record: BASIS_OF_RECORD,
+                event_date: event_date.clone(),
+                decimal_latitude: position.map(|p| p.0),
+                decimal_longitude: position.map(|p| p.1),
+                geodetic_datum: position.map(|_| "WGS84"),
+                scientific_name: name,
+                individual_count: count,
+                verification_status: if verified { "verified" } else { "unverified" },
+                associated_media: media.clone(),
+                dataset_name: options.dataset_name.clone(),
+            });
+        }
+    }
+    occurrences
+}
+
+/// Writes the Darwin Core occurrences of a result file to `output`, `occurrence.csv` next
+/// to the result by default. Returns the number of occurrences.
+pub fn export_darwin_core(
+    result: &Path,
+    output: Option<&Path>,
+    options: &DarwinCoreOptions,
+) -> Result<usize> {
+    let folder = result
+        .parent()
+        .ok_or_else(|| anyhow!("Invalid result path: {}", result.display()))?;
+    let folder = std::fs::canonicalize(folder)?;
+    let frames = load_export(result)?;
+    let db = folder.join(EMBEDDING_DB);
+    let review_labels = if db.is_file() {
+        EmbeddingStore::open(&db)?.review_labels()?
+    } else {
+        ReviewLabels::new()
+    };
+
+    // positions are only read for the files that end up in the export without one
+    let files: Vec<PathBuf> =
+        occurrences(&frames, &folder, &review_labels, &HashMap::new(), options)
+            .into_iter()
+            .filter(|o| o.decimal_latitude.is_none())
+            .map(|o| folder.join(o.associated_media))
+            .collect();
+    let positions: HashMap<PathBuf, (f64, f64)> = files
+        .par_iter()
+        .map_init(MediaParser::new, |parser, path| {
+            let file = FileItem::new(0, 0, path.clone(), None);
+            let position = read_metadata(parser, &file)
+                .gps
+                .as_deref()
+                .and_then(parse_iso6709);
+            (path.clone(), position)
+        })
+        .filter_map(|(path, position)| Some((path, position?)))
+        .collect();
+
+    let rows = occurrences(&frames, &folder, &review_labels, &positions, options);
+    let output = output.map_or_else(|| folder.join(OCCURRENCE_FILE), Path::to_path_buf);
+    let mut wtr = WriterBuilder::new().from_path(&output)?;
+    for row in &rows {
+        wtr.serialize(row)?;
+    }
+    wtr.flush()?;
+    log::info!(
+        "Exported {} occurrences to {}",
+        rows.len(),
+        output.display()
+    );
+    Ok(rows.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::{testing, Bbox, BlankSkip};
+
+    #[test]
+    fn test_occurrences() {
+        let folder = Path::new("/run");
+        let video = ExportFrame {
+            shoot_time: Some("2024-05-01 21:30:00 +08:00".to_string()),
+            total_frames: 2,
+            bboxes: Some(vec![testing::bbox(0, 0.9)]),
+            label: Some(vec!["Animal".to_string()]),
+            ..testing::frame("/run/a/1.mp4")
+        };
+        let frames = vec![
+            video.clone(),
+            ExportFrame {
+                frame_index: 1,
+                bboxes: Some(vec![testing::bbox(0, 0.9); 3]),
+                ..video.clone()
+            },
+            // no taxon for vehicles by default
+            ExportFrame {
+                label: Some(vec!["Vehicle".to_string()]),
+                ..testing::frame("/run/a/2.jpg")
+            },
+            ExportFrame {
+                bboxes: Some(vec![testing::bbox(0, 0.9); 2]),
+                label: Some(vec!["Animal".to_string()]),
+                ..testing::frame("a/3.jpg")
+            },
+        ];
+        let review_labels = HashMap::from([(
+            ("/run/a/3.jpg".to_string(), 0),
+            vec![(0, "Leopard".to_string()), (1, "Leopard".to_string())],
+        )]);
+        let positions = HashMap::from([(PathBuf::from("/run/a/1.mp4"), (22.5, 114.0))]);
+        let mut options = DarwinCoreOptions::default();
+        options
+            .taxa
+            .insert("Leopard".to_string(), "Panthera pardus".to_string());
+
+        let rows = occurrences(&frames, folder, &review_labels, &positions, &options);
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].scientific_name, "Animalia");
+        assert_eq!(rows[0].individual_count, Some(3));
+        assert_eq!(rows[0].decimal_latitude, Some(22.5));
+        assert_eq!(rows[0].geodetic_datum, Some("WGS84"));
+        assert_eq!(
+            rows[0].event_date.as_deref(),
+            Some("2024-05-01T21:30:00+08:00")
+        );
+        assert_eq!(rows[0].verification_status, "unverified");
+        assert_eq!(rows[1].scientific_name, "Panthera pardus");
+        assert_eq!(rows[1].individual_count, Some(2));
+        assert_eq!(rows[1].associated_media, "a/3.jpg");
+        assert_eq!(rows[1].verification_status, "verified");
+        assert_eq!(rows[1].geodetic_datum, None);
+
+        options.verified_only = true;
+        let rows = occurrences(&frames, folder, &review_labels, &positions, &options);
+        assert_eq!(rows.len(), 1);
+    }
+
+    #[test]
+    fn test_occurrence_edges() {
+        let folder = Path::new("/run");
+        let frames = vec![
+            // boxes of two labels can't be counted apart
+            ExportFrame {
+                shoot_time: Some("yesterday".to_string()),
+                latitude: Some(22.6),
+                longitude: Some(114.1),
+                bboxes: Some(vec![testing::bbox(0, 0.9), testing::bbox(1, 0.8)]),
+                label: Some(vec!["Animal".to_string(), "Person".to_string()]),
+                ..testing::frame("a/1.jpg")
+            },
+            // a frame verified in the viewer counts its boxes per label
+            ExportFrame {
+                shoot_time: Some(String::new()),
+                bboxes: Some(vec![
+                    Bbox {
+                        label: Some("Leopard".to_string()),
+                        ..testing::bbox(0, 0.9)
+                    },
+                    Bbox {
+                        label: Some("Muntjac".to_string()),
+                        ..testing::bbox(0, 0.7)
+                    },
+                    Bbox {
+                        label: Some("Muntjac".to_string()),
+                        ..testing::bbox(0, 0.6)
+                    },
+                ]),
+                verified: true,
+                ..testing::frame("a/2.jpg")
+            },
+            // the blank filters skipped it, nothing was seen
+            ExportFrame {
+                bboxes: Some(vec![testing::bbox(0, 0.9)]),
+                label: Some(vec!["Animal".to_string()]),
+                skipped_blank: Some(BlankSkip::Prefilter),
+                ..testing::frame("a/3.jpg")
+            },
+        ];
+        let options = DarwinCoreOptions {
+            taxa: BTreeMap::from([
+                ("Animal".to_string(), "Animalia".to_string()),
+                ("Person".to_string(), "Homo sapiens".to_string()),
+                ("Leopard".to_string(), "Panthera pardus".to_string()),
+            ]),
+            dataset_name: Some("Wutong 2024".to_string()),
+            verified_only: false,
+        };
+        let rows = occurrences(
+            &frames,
+            folder,
+            &ReviewLabels::new(),
+            &HashMap::new(),
+            &options,
+        );
+        let names: Vec<(&str, Option<usize>)> = rows
+            .iter()
+            .map(|r| (r.scientific_name.as_str(), r.individual_count))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                ("Animalia", None),
+                ("Homo sapiens", None),
+                // review labels without a taxon are taken as scientific names
+                ("Muntjac", Some(2)),
+                ("Panthera pardus", Some(1)),
+            ]
+        );
+        // times that don't parse are kept, empty ones left out
+        assert_eq!(rows[0].event_date.as_deref(), Some("yesterday"));
+        assert_eq!(rows[2].event_date, None);
+        assert_eq!(
+            (rows[0].decimal_latitude, rows[0].decimal_longitude),
+            (Some(22.6), Some(114.1))
+        );
+        assert_eq!(rows[0].occurrence_id, format!("{}/1", hash_path("a/1.jpg")));
+        assert_eq!(rows[1].occurrence_id, format!("{}/2", hash_path("a/1.jpg")));
+        assert!(rows
+            .iter()
+            .all(|r| r.dataset_name.as_deref() == Some("Wutong 2024")));
+    }
+}
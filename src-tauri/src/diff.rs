@@ -0,0 +1,356 @@
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+
+use anyhow::Result;
+use csv::Writer;
+use serde::Serialize;
+
+use crate::export::{load_export, Bbox, ExportFrame};
+use crate::utils::portable_path;
+
+/// Minimum IoU for two boxes of the same class to count as the same detection.
+const MATCH_IOU: f32 = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    ScoreShift,
+    LabelChanged,
+    FrameAdded,
+    FrameRemoved,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DiffRow {
+    pub file_path: String,
+    pub frame_index: usize,
+    pub change: ChangeKind,
+    pub class: Option<usize>,
+    pub score_a: Option<f32>,
+    pub score_b: Option<f32>,
+    pub label_a: String,
+    pub label_b: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiffSummary {
+    pub frames_compared: usize,
+    pub frames_only_in_a: usize,
+    pub frames_only_in_b: usize,
+    pub detections_added: usize,
+    pub detections_removed: usize,
+    pub detections_matched: usize,
+    pub label_changes: usize,
+    pub mean_score_shift: f32,
+    pub max_score_shift: f32,
+}
+
+fn iou(a: &Bbox, b: &Bbox) -> f32 {
+    let w = (a.x2.min(b.x2) - a.x1.max(b.x1)).max(0.0);
+    let h = (a.y2.min(b.y2) - a.y1.max(b.y1)).max(0.0);
+    let inter = w * h;
+    let union = (a.x2 - a.x1) * (a.y2 - a.y1) + (b.x2 - b.x1) * (b.y2 - b.y1) - inter;
+    if union <= 0.0 {
+        0.0
+    } else {
+        inter / union
+    }
+}
+
+fn frame_key(frame: &ExportFrame) -> (String, usize) {
+    (
+        portable_path(&frame.file.file_path, None),
+        frame.frame_index,
+    )
+}
+
+fn join_label(frame: &ExportFrame) -> String {
+    frame
+        .label
+        .as_ref()
+        .map(|label| itertools::join(label, ";"))
+        .unwrap_or_default()
+}
+
+/// Compares two exports frame by frame, matching boxes of the same class greedily by IoU.
+pub fn diff_frames(a: &[ExportFrame], b: &[ExportFrame]) -> (Vec<DiffRow>, DiffSummary) {
+    let a: BTreeMap<_, _> = a.iter().map(|f| (frame_key(f), f)).collect();
+    let b: BTreeMap<_, _> = b.iter().map(|f| (frame_key(f), f)).collect();
+    let mut rows = Vec::new();
+    let mut summary = DiffSummary::default();
+    let mut shift_sum = 0.0;
+
+    let keys: HashSet<_> = a.keys().chain(b.keys()).collect();
+    let mut keys: Vec<_> = keys.into_iter().collect();
+    keys.sort();
+
+    for key in keys {
+        let row = |change, class, score_a, score_b, label_a: &str, label_b: &str| DiffRow {
+            file_path: key.0.clone(),
+            frame_index: key.1,
+            change,
+            class,
+            score_a,
+            score_b,
+            label_a: label_a.to_string(),
+            label_b: label_b.to_string(),
+        };
+        let (frame_a, frame_b) = match (a.get(key), b.get(key)) {
+            (Some(frame_a), Some(frame_b)) => (frame_a, frame_b),
+            (Some(frame_a), None) => {
+                summary.frames_only_in_a += 1;
+                rows.push(row(
+                    ChangeKind::FrameRemoved,
+                    None,
+                    None,
+                    None,
+                    &join_label(frame_a),
+                    "",
+                ));
+                continue;
+            }
+            (None, Some(frame_b)) => {
+                summary.frames_only_in_b += 1;
+                rows.push(row(
+                    ChangeKind::FrameAdded,
+                    None,
+                    None,
+                    None,
+                    "",
+                    &join_label(frame_b),
+                ));
+                continue;
+            }
+            (None, None) => unreachable!(),
+        };
+        summary.frames_compared += 1;
+
+        let label_a = join_label(frame_a);
+        let label_b = join_label(frame_b);
+        if label_a != label_b {
+            summary.label_changes += 1;
+            rows.push(row(
+                ChangeKind::LabelChanged,
+                None,
+                None,
+                None,
+                &label_a,
+                &label_b,
+            ));
+        }
+
+        let boxes_a = frame_a.bboxes.as_deref().unwrap_or_default();
+        let boxes_b = frame_b.bboxes.as_deref().unwrap_or_default();
+        let mut matched_b = vec![false; boxes_b.len()];
+        for box_a in boxes_a {
+            let best = boxes_b
+                .iter()
+                .enumerate()
+                .filter(|(i, box_b)| !matched_b[*i] && box_b.class == box_a.class)
+                .map(|(i, box_b)| (i, iou(box_a, box_b)))
+                .filter(|(_, iou)| *iou >= MATCH_IOU)
+                .max_by(|x, y| x.1.total_cmp(&y.1));
+            match best {
+                Some((i, _)) => {
+                    matched_b[i] = true;
+                    let box_b = &boxes_b[i];
+                    let shift = box_b.score - box_a.score;
+                    summary.detections_matched += 1;
+                    shift_sum += shift;
+                    if shift.abs() > summary.max_score_shift.abs() {
+                        summary.max_score_shift = shift;
+                    }
+                    if shift != 0.0 {
+                        rows.push(row(
+                            ChangeKind::ScoreShift,
+                            Some(box_a.class),
+                            Some(box_a.score),
+                            Some(box_b.score),
+                            &label_a,
+                            &label_b,
+                        ));
+                    }
+                }
+                None => {
+                    summary.detections_removed += 1;
+                    rows.push(row(
+                        ChangeKind::Removed,
+                        Some(box_a.class),
+                        Some(box_a.score),
+                        None,
+                        &label_a,
+                        &label_b,
+                    ));
+                }
+            }
+        }
+        for (box_b, _) in boxes_b.iter().zip(matched_b).filter(|(_, m)| !m) {
+            summary.detections_added += 1;
+            rows.push(row(
+                ChangeKind::Added,
+                Some(box_b.class),
+                None,
+                Some(box_b.score),
+                &label_a,
+                &label_b,
+            ));
+        }
+    }
+
+    if summary.detections_matched > 0 {
+        summary.mean_score_shift = shift_sum / summary.detections_matched as f32;
+    }
+    (rows, summary)
+}
+
+/// Diffs the exports at `a` and `b`, writing one row per change to `output` as csv.
+pub fn diff_exports(a: &Path, b: &Path, output: &Path) -> Result<DiffSummary> {
+    let (rows, summary) = diff_frames(&load_export(a)?, &load_export(b)?);
+    let mut wtr = Writer::from_path(output)?;
+    for row in rows {
+        wtr.serialize(row)?;
+    }
+    wtr.flush()?;
+    log::info!("Export diff written to {}: {:?}", output.display(), summary);
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::testing;
+
+    /// A 0.2 wide box at `x1` on the top edge.
+    fn bbox(x1: f32, score: f32, class: usize) -> Bbox {
+        Bbox {
+            x1,
+            y1: 0.0,
+            x2: x1 + 0.2,
+            y2: 0.2,
+            ..testing::bbox(class, score)
+        }
+    }
+
+    #[test]
+    fn test_diff_frames() {
+        let a = vec![ExportFrame {
+            bboxes: Some(vec![bbox(0.0, 0.5, 0), bbox(0.5, 0.3, 1)]),
+            label: Some(vec!["Animal".to_string()]),
+            ..testing::frame("/traps/a.jpg")
+        }];
+        let b = vec![ExportFrame {
+            bboxes: Some(vec![bbox(0.01, 0.7, 0), bbox(0.8, 0.9, 0)]),
+            label: Some(vec!["Person".to_string()]),
+            ..testing::frame("/traps/a.jpg")
+        }];
+        let (rows, summary) = diff_frames(&a, &b);
+        assert_eq!(summary.frames_compared, 1);
+        assert_eq!(summary.label_changes, 1);
+        assert_eq!(summary.detections_matched, 1);
+        assert_eq!(summary.detections_added, 1);
+        assert_eq!(summary.detections_removed, 1);
+        assert!((summary.mean_score_shift - 0.2).abs() < 1e-6);
+        let changes: Vec<ChangeKind> = rows.iter().map(|r| r.change).collect();
+        assert_eq!(
+            changes,
+            [
+                ChangeKind::LabelChanged,
+                ChangeKind::ScoreShift,
+                ChangeKind::Removed,
+                ChangeKind::Added
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_matching() {
+        let a = vec![
+            ExportFrame {
+                // the same box in both runs, and one that lost confidence
+                bboxes: Some(vec![bbox(0.0, 0.5, 0), bbox(0.4, 0.9, 0)]),
+                ..testing::frame("/traps/a.mp4")
+            },
+            ExportFrame {
+                frame_index: 1,
+                ..testing::frame("/traps/a.mp4")
+            },
+        ];
+        let b = vec![
+            ExportFrame {
+                // the closer of two overlapping boxes is the match
+                bboxes: Some(vec![
+                    bbox(0.45, 0.3, 0),
+                    bbox(0.41, 0.6, 0),
+                    bbox(0.0, 0.5, 0),
+                ]),
+                ..testing::frame("/traps/a.mp4")
+            },
+            testing::frame("/traps/b.jpg"),
+        ];
+        let (rows, summary) = diff_frames(&a, &b);
+        assert_eq!(summary.frames_compared, 1);
+        assert_eq!((summary.frames_only_in_a, summary.frames_only_in_b), (1, 1));
+        assert_eq!(summary.detections_matched, 2);
+        assert_eq!(summary.detections_added, 1);
+        assert_eq!(summary.label_changes, 0);
+        // the largest shift keeps its sign
+        assert!((summary.max_score_shift + 0.3).abs() < 1e-6);
+        assert!((summary.mean_score_shift + 0.15).abs() < 1e-6);
+
+        let changes: Vec<(&str, usize, ChangeKind)> = rows
+            .iter()
+            .map(|r| (r.file_path.as_str(), r.frame_index, r.change))
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                ("/traps/a.mp4", 0, ChangeKind::ScoreShift),
+                ("/traps/a.mp4", 0, ChangeKind::Added),
+                ("/traps/a.mp4", 1, ChangeKind::FrameRemoved),
+                ("/traps/b.jpg", 0, ChangeKind::FrameAdded),
+            ]
+        );
+        assert_eq!(rows[1].score_b, Some(0.3));
+
+        // another class at the same place is not the same detection
+        let a = [ExportFrame {
+            bboxes: Some(vec![bbox(0.0, 0.5, 0)]),
+            ..testing::frame("/traps/c.jpg")
+        }];
+        let b = [ExportFrame {
+            bboxes: Some(vec![bbox(0.0, 0.5, 1)]),
+            ..testing::frame("/traps/c.jpg")
+        }];
+        let (_, summary) = diff_frames(&a, &b);
+        assert_eq!(
+            (summary.detections_removed, summary.detections_added),
+            (1, 1)
+        );
+        assert_eq!(summary.mean_score_shift, 0.0);
+        assert_eq!(iou(&bbox(0.0, 0.5, 0), &bbox(0.5, 0.5, 0)), 0.0);
+    }
+
+    #[test]
+    fn test_diff_exports() {
+        let dir = testing::temp_dir();
+        let frames = vec![ExportFrame {
+            bboxes: Some(vec![bbox(0.0, 0.5, 0)]),
+            ..testing::frame("site/a.jpg")
+        }];
+        std::fs::write(dir.join("a.json"), serde_json::to_string(&frames).unwrap()).unwrap();
+        std::fs::write(dir.join("b.json"), "[]").unwrap();
+
+        let output = dir.join("diff.csv");
+        let summary = diff_exports(&dir.join("a.json"), &dir.join("b.json"), &output).unwrap();
+        assert_eq!(summary.frames_only_in_a, 1);
+        let csv = std::fs::read_to_string(&output).unwrap();
+        assert_eq!(csv.lines().count(), 2);
+        assert!(csv.contains("site/a.jpg,0,frame_removed"));
+        assert!(diff_exports(&dir.join("a.txt"), &dir.join("b.json"), &output).is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,652 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use image::{imageops::FilterType, DynamicImage};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+
+use crate::cluster;
+use crate::export::{Bbox, BboxSpace, ExportFrame};
+use crate::utils::portable_path;
+
+pub const EMBEDDING_DB: &str = "embeddings.db";
+
+/// `(bbox_index, review_label)` of the labeled bboxes, keyed by `(file_path, frame_index)`.
+pub type ReviewLabels = HashMap<(String, usize), Vec<(usize, String)>>;
+
+/// Detections of a run, stored per frame and per bbox so similar crops can be searched
+/// and clustered later.
+///
+/// Each row of `crops` is one detection, `bbox_index` is null for the whole frame.
+/// `embedding` is only set when the server returned one, `phash` is filled lazily
+/// for the perceptual hash fallback. `cluster` and `review_label` are written by
+/// clustering and bulk labeling.
+pub struct EmbeddingStore {
+    conn: Connection,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SimilarCrop {
+    pub crop_id: i64,
+    pub file_path: String,
+    pub frame_index: usize,
+    pub bbox_index: Option<usize>,
+    pub class: Option<usize>,
+    pub score: Option<f32>,
+    pub similarity: f32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Cluster {
+    pub cluster_id: usize,
+    pub crop_ids: Vec<i64>,
+}
+
+struct CropRow {
+    id: i64,
+    file_path: String,
+    frame_index: usize,
+    bbox_index: Option<usize>,
+    bbox: Option<Bbox>,
+    embedding: Option<Vec<f32>>,
+    phash: Option<u64>,
+}
+
+const CROP_COLUMNS: &str =
+    "id, file_path, frame_index, bbox_index, x1, y1, x2, y2, class, score, embedding, phash";
+
+fn crop_row(row: &rusqlite::Row) -> rusqlite::Result<CropRow> {
+    let x1: Option<f32> = row.get(4)?;
+    let bbox = match x1 {
+        Some(x1) => Some(Bbox {
+            x1,
+            y1: row.get(5)?,
+            x2: row.get(6)?,
+            y2: row.get(7)?,
+            class: row.get(8)?,
+            score: row.get(9)?,
+            individual: None,
+            label: None,
+            space: BboxSpace::Normalized,
+        }),
+        None => None,
+    };
+    let embedding: Option<Vec<u8>> = row.get(10)?;
+    let phash: Option<i64> = row.get(11)?;
+    Ok(CropRow {
+        id: row.get(0)?,
+        file_path: row.get(1)?,
+        frame_index: row.get(2)?,
+        bbox_index: row.get(3)?,
+        bbox,
+        embedding: embedding.map(|blob| from_blob(&blob)),
+        phash: phash.map(|h| h as u64),
+    })
+}
+
+/// Schema changes of `embeddings.db`, the database's `user_version` counts the ones applied.
+const MIGRATIONS: [&str; 3] = [
+    "CREATE TABLE crops (
+         id INTEGER PRIMARY KEY,
+         file_path TEXT NOT NULL,
+         frame_index INTEGER NOT NULL,
+         bbox_index INTEGER,
+         x1 REAL, y1 REAL, x2 REAL, y2 REAL,
+         class INTEGER,
+         score REAL,
+         embedding BLOB NOT NULL
+     );
+     CREATE INDEX crops_frame ON crops (file_path, frame_index);",
+    // crops without an embedding, hashed for the perceptual fallback
+    "ALTER TABLE crops RENAME TO crops_old;
+     CREATE TABLE crops (
+         id INTEGER PRIMARY KEY,
+         file_path TEXT NOT NULL,
+         frame_index INTEGER NOT NULL,
+         bbox_index INTEGER,
+         x1 REAL, y1 REAL, x2 REAL, y2 REAL,
+         class INTEGER,
+         score REAL,
+         embedding BLOB,
+         phash INTEGER
+     );
+     INSERT INTO crops
+         SELECT id, file_path, frame_index, bbox_index, x1, y1, x2, y2, class, score,
+             embedding, NULL
+         FROM crops_old;
+     DROP TABLE crops_old;
+     CREATE INDEX crops_frame ON crops (file_path, frame_index);",
+    // clustering and bulk labeling
+    "ALTER TABLE crops ADD COLUMN cluster INTEGER;
+     ALTER TABLE crops ADD COLUMN review_label TEXT;",
+];
+
+/// Version of a database written before `user_version` was set, told by its columns.
+fn unversioned_schema(conn: &Connection) -> Result<usize> {
+    let mut stmt = conn.prepare("SELECT name FROM pragma_table_info('crops')")?;
+    let columns = stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let has = |name: &str| columns.iter().any(|c| c == name);
+    Ok(if columns.is_empty() {
+        0
+    } else if has("review_label") {
+        3
+    } else if has("phash") {
+        2
+    } else {
+        1
+    })
+}
+
+fn migrate(conn: &mut Connection) -> Result<()> {
+    let mut version: usize = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    if version == 0 {
+        version = unversioned_schema(conn)?;
+    }
+    if version > MIGRATIONS.len() {
+        return Err(anyhow!(
+            "{} has schema version {}, newer than this version of Megascops",
+            EMBEDDING_DB,
+            version
+        ));
+    }
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", i + 1)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+impl EmbeddingStore {
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut conn = Connection::open(path)?;
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             PRAGMA synchronous = NORMAL;",
+        )?;
+        migrate(&mut conn)?;
+        Ok(Self { conn })
+    }
+
+    /// Stores the frame embedding, if any, and one row per bbox.
+    pub fn insert(
+        &mut self,
+        frame: &ExportFrame,
+        frame_embedding: &[f32],
+        bbox_embeddings: &[Vec<f32>],
+    ) -> Result<()> {
+        let file_path = portable_path(&frame.file.file_path, None);
+        let tx = self.conn.transaction()?;
+        {
+            // rerunning a folder replaces the frame's earlier rows
+            tx.execute(
+                "DELETE FROM crops WHERE file_path = ?1 AND frame_index = ?2",
+                params![file_path, frame.frame_index],
+            )?;
+            let mut stmt = tx.prepare_cached(
+                "INSERT INTO crops
+                 (file_path, frame_index, bbox_index, x1, y1, x2, y2, class, score, embedding)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            )?;
+            if !frame_embedding.is_empty() {
+                stmt.execute(params![
+                    file_path,
+                    frame.frame_index,
+                    None::<usize>,
+                    None::<f32>,
+                    None::<f32>,
+                    None::<f32>,
+                    None::<f32>,
+                    None::<usize>,
+                    None::<f32>,
+                    to_blob(frame_embedding),
+                ])?;
+            }
+            let bboxes = frame.bboxes.as_deref().unwrap_or_default();
+            for (i, bbox) in bboxes.iter().enumerate() {
+                // servers without embedding support return empty vectors
+                let embedding = bbox_embeddings
+                    .get(i)
+                    .filter(|e| !e.is_empty())
+                    .map(|e| to_blob(e));
+                stmt.execute(params![
+                    file_path,
+                    frame.frame_index,
+                    i,
+                    bbox.x1,
+                    bbox.y1,
+                    bbox.x2,
+                    bbox.y2,
+                    bbox.class,
+                    bbox.score,
+                    embedding,
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn crop(&self, crop_id: i64) -> Result<CropRow> {
+        self.conn
+            .query_row(
+                &format!("SELECT {} FROM crops WHERE id = ?1", CROP_COLUMNS),
+                params![crop_id],
+                crop_row,
+            )
+            .optional()?
+            .ok_or_else(|| anyhow!("Crop {} not found", crop_id))
+    }
+
+    fn crops(&self) -> Result<Vec<CropRow>> {
+        let mut stmt = self
+            .conn
+            .prepare(&format!("SELECT {} FROM crops", CROP_COLUMNS))?;
+        let rows = stmt.query_map([], crop_row)?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Returns the `limit` crops most similar to `crop_id`, by cosine similarity of the
+    /// embeddings or, when the crop has none, by perceptual hash distance.
+    pub fn find_similar(&self, crop_id: i64, limit: usize) -> Result<Vec<SimilarCrop>> {
+        let target = self.crop(crop_id)?;
+        let mut similar: Vec<(f32, CropRow)> = match &target.embedding {
+            Some(embedding) => self
+                .crops()?
+                .into_iter()
+                .filter(|c| c.id != crop_id)
+                .filter_map(|c| {
+                    let other = c.embedding.as_ref()?;
+                    if other.len() != embedding.len() {
+                        return None;
+                    }
+                    Some((cosine_similarity(embedding, other), c))
+                })
+                .collect(),
+            None => {
+                let hash = self
+                    .phash(&target)?
+                    .ok_or_else(|| anyhow!("Failed to hash crop {}", crop_id))?;
+                let mut similar = Vec::new();
+                for c in self.crops()?.into_iter().filter(|c| c.id != crop_id) {
+                    if let Some(other) = self.phash(&c)? {
+                        let distance = (hash ^ other).count_ones();
+                        similar.push((1.0 - distance as f32 / 64.0, c));
+                    }
+                }
+                similar
+            }
+        };
+        similar.sort_by(|a, b| b.0.total_cmp(&a.0));
+        Ok(similar
+            .into_iter()
+            .take(limit)
+            .map(|(similarity, c)| SimilarCrop {
+                crop_id: c.id,
+                file_path: c.file_path,
+                frame_index: c.frame_index,
+                bbox_index: c.bbox_index,
+                class: c.bbox.as_ref().map(|b| b.class),
+                score: c.bbox.as_ref().map(|b| b.score),
+                similarity,
+            })
+            .collect())
+    }
+
+    /// Groups the crops by k-means over their embeddings, crops without one are grouped
+    /// by perceptual hash, and stores the cluster of every crop.
+    pub fn cluster(&mut self, k: Option<usize>) -> Result<Vec<Cluster>> {
+        let (embedded, others): (Vec<_>, Vec<_>) = self
+            .crops()?
+            .into_iter()
+            .partition(|c| c.embedding.is_some());
+
+        let mut clusters: Vec<Cluster> = Vec::new();
+        let assign = |clusters: &mut Vec<Cluster>, offset: usize, id: i64, group: usize| {
+            let cluster_id = offset + group;
+            while clusters.len() <= cluster_id {
+                clusters.push(Cluster {
+                    cluster_id: clusters.len(),
+                    crop_ids: Vec::new(),
+                });
+            }
+            clusters[cluster_id].crop_ids.push(id);
+        };
+
+        // embeddings of different models can't be compared, cluster them by dimension
+        let mut by_dim: Vec<(usize, Vec<CropRow>)> = Vec::new();
+        for c in embedded {
+            let dim = c.embedding.as_ref().map_or(0, |e| e.len());
+            match by_dim.iter_mut().find(|(d, _)| *d == dim) {
+                Some((_, crops)) => crops.push(c),
+                None => by_dim.push((dim, vec![c])),
+            }
+        }
+        for (_, crops) in by_dim {
+            let vectors: Vec<Vec<f32>> = crops.iter().filter_map(|c| c.embedding.clone()).collect();
+            let k = k.unwrap_or_else(|| cluster::default_k(vectors.len()));
+            let offset = clusters.len();
+            for (c, group) in crops.iter().zip(cluster::kmeans(&vectors, k)) {
+                assign(&mut clusters, offset, c.id, group);
+            }
+        }
+
+        let mut hashed = Vec::new();
+        for c in others {
+            if let Some(hash) = self.phash(&c)? {
+                hashed.push((c.id, hash));
+            }
+        }
+        let hashes: Vec<u64> = hashed.iter().map(|(_, h)| *h).collect();
+        let offset = clusters.len();
+        for ((id, _), group) in hashed
+            .iter()
+            .zip(cluster::hash_groups(&hashes, cluster::HASH_DISTANCE))
+        {
+            assign(&mut clusters, offset, *id, group);
+        }
+
+        let tx = self.conn.transaction()?;
+        {
+            tx.execute("UPDATE crops SET cluster = NULL", [])?;
+            let mut stmt = tx.prepare("UPDATE crops SET cluster = ?1 WHERE id = ?2")?;
+            for cluster in &clusters {
+                for id in &cluster.crop_ids {
+                    stmt.execute(params![cluster.cluster_id, id])?;
+                }
+            }
+        }
+        tx.commit()?;
+        Ok(clusters)
+    }
+
+    /// Sets the review label of every crop in the cluster, returns the number of crops.
+    pub fn label_cluster(&self, cluster_id: usize, label: &str) -> Result<usize> {
+        Ok(self.conn.execute(
+            "UPDATE crops SET review_label = ?1 WHERE cluster = ?2",
+            params![label, cluster_id],
+        )?)
+    }
+
+    /// `(file_path, frame_index, bbox_index)` of the crops in the cluster.
+    pub fn cluster_boxes(&self, cluster_id: usize) -> Result<Vec<(String, usize, usize)>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT file_path, frame_index, bbox_index FROM crops
+             WHERE cluster = ?1 AND bbox_index IS NOT NULL
+             ORDER BY file_path, frame_index, bbox_index",
+        )?;
+        let rows = stmt.query_map([cluster_id], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Review labels of the bboxes of every labeled frame.
+    pub fn review_labels(&self) -> Result<ReviewLabels> {
+        let mut stmt = self.conn.prepare(
+            "SELECT file_path, frame_index, bbox_index, review_label FROM crops
+             WHERE review_label IS NOT NULL AND bbox_index IS NOT NULL
+             ORDER BY file_path, frame_index, bbox_index",
+        )?;
+        let mut labels = ReviewLabels::new();
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+        })?;
+        for row in rows {
+            let (file_path, frame_index, bbox_index, label) = row?;
+            labels
+                .entry((file_path, frame_index))
+                .or_default()
+                .push((bbox_index, label));
+        }
+        Ok(labels)
+    }
+
+    /// Perceptual hash of the crop, computed from the original image on first use.
+    fn phash(&self, crop: &CropRow) -> Result<Option<u64>> {
+        if crop.phash.is_some() {
+            return Ok(crop.phash);
+        }
+        let img = match megascops_media::decode_image(Path::new(&crop.file_path)) {
+            Ok(img) => img,
+            Err(e) => {
+                // videos and missing files have no hash, they are left out of the results
+                log::debug!("Failed to open {} for hashing: {}", crop.file_path, e);
+                return Ok(None);
+            }
+        };
+        let img = match &crop.bbox {
+            Some(bbox) => {
+                let (x, y, w, h) = bbox.pixel_rect(img.width(), img.height());
+                if w == 0 || h == 0 {
+                    return Ok(None);
+                }
+                img.crop_imm(x, y, w, h)
+            }
+            None => img,
+        };
+        let hash = dhash(&img);
+        self.conn.execute(
+            "UPDATE crops SET phash = ?1 WHERE id = ?2",
+            params![hash as i64, crop.id],
+        )?;
+        Ok(Some(hash))
+    }
+}
+
+/// 64-bit difference hash, robust to scaling and small brightness changes.
+pub fn dhash(img: &DynamicImage) -> u64 {
+    let small = img.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash <<= 1;
+            if small.get_pixel(x, y)[0] > small.get_pixel(x + 1, y)[0] {
+                hash |= 1;
+            }
+        }
+    }
+    hash
+}
+
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+    dot / (norm_a * norm_b)
+}
+
+pub fn to_blob(embedding: &[f32]) -> Vec<u8> {
+    embedding.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+pub fn from_blob(blob: &[u8]) -> Vec<f32> {
+    blob.chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::testing;
+
+    #[test]
+    fn test_find_similar() {
+        let root = testing::temp_dir();
+        let mut store = EmbeddingStore::open(&root.join(EMBEDDING_DB)).unwrap();
+        let bboxes = Some(vec![testing::bbox(0, 0.9)]);
+        for (path, embedding) in [
+            ("/a.jpg", [1.0, 0.0]),
+            ("/b.jpg", [0.9, 0.1]),
+            ("/c.jpg", [0.0, 1.0]),
+            // rerunning replaces the rows of the frame
+            ("/c.jpg", [-1.0, 0.0]),
+        ] {
+            let frame = ExportFrame {
+                bboxes: bboxes.clone(),
+                ..testing::frame(path)
+            };
+            store.insert(&frame, &[], &[embedding.to_vec()]).unwrap();
+        }
+
+        let similar = store.find_similar(1, 10).unwrap();
+        let paths: Vec<_> = similar.iter().map(|s| s.file_path.as_str()).collect();
+        assert_eq!(paths, ["/b.jpg", "/c.jpg"]);
+        assert!(similar[1].similarity < 0.0);
+        assert_eq!(store.find_similar(1, 1).unwrap().len(), 1);
+        assert!(store.find_similar(42, 10).is_err());
+
+        let clusters = store.cluster(Some(2)).unwrap();
+        assert_eq!(clusters.len(), 2);
+        assert_eq!(clusters[0].crop_ids, [1, 2]);
+        assert_eq!(store.label_cluster(0, "Leopard").unwrap(), 2);
+        assert_eq!(
+            store.cluster_boxes(0).unwrap(),
+            [("/a.jpg".to_string(), 0, 0), ("/b.jpg".to_string(), 0, 0)]
+        );
+        let labels = store.review_labels().unwrap();
+        assert_eq!(labels.len(), 2);
+        assert_eq!(
+            labels[&("/a.jpg".to_string(), 0)],
+            [(0, "Leopard".to_string())]
+        );
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_mixed_embeddings() {
+        let root = testing::temp_dir();
+        let mut store = EmbeddingStore::open(&root.join(EMBEDDING_DB)).unwrap();
+        let frame = ExportFrame {
+            bboxes: Some(vec![testing::bbox(0, 0.9), testing::bbox(1, 0.6)]),
+            ..testing::frame("/a.jpg")
+        };
+        // a frame embedding, then a bbox of another model's dimension
+        store
+            .insert(&frame, &[1.0, 0.0], &[vec![1.0, 0.0], vec![1.0, 0.0, 0.0]])
+            .unwrap();
+
+        let similar = store.find_similar(1, 10).unwrap();
+        assert_eq!(similar.len(), 1);
+        assert_eq!(similar[0].bbox_index, Some(0));
+        assert_eq!(similar[0].class, Some(0));
+        let whole = store.find_similar(2, 10).unwrap();
+        assert_eq!(whole[0].bbox_index, None);
+        assert_eq!(whole[0].score, None);
+
+        // embeddings of different dimensions never share a cluster
+        let clusters = store.cluster(Some(1)).unwrap();
+        assert_eq!(clusters.len(), 2);
+        assert_eq!(clusters[0].crop_ids, [1, 2]);
+        assert_eq!(clusters[1].crop_ids, [3]);
+        // the whole frame has no bbox to label
+        assert_eq!(store.label_cluster(0, "Leopard").unwrap(), 2);
+        assert_eq!(store.cluster_boxes(0).unwrap().len(), 1);
+        assert_eq!(store.review_labels().unwrap().len(), 1);
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_phash_fallback() {
+        let root = testing::temp_dir();
+        let gradient = |name: &str, value: fn(u32) -> u8| {
+            let path = root.join(name);
+            let img = image::GrayImage::from_fn(64, 64, |x, _| image::Luma([value(x)]));
+            DynamicImage::ImageLuma8(img).save(&path).unwrap();
+            path
+        };
+        let paths = [
+            gradient("a.png", |x| (x * 3) as u8),
+            gradient("b.png", |x| (x * 3 + 20) as u8),
+            gradient("c.png", |x| (255 - x * 3) as u8),
+            root.join("missing.png"),
+        ];
+        let mut store = EmbeddingStore::open(&root.join(EMBEDDING_DB)).unwrap();
+        for path in &paths {
+            let frame = ExportFrame {
+                bboxes: Some(vec![testing::bbox(0, 0.9)]),
+                ..testing::frame(path)
+            };
+            // servers without embedding support return empty vectors
+            store.insert(&frame, &[], &[Vec::new()]).unwrap();
+        }
+
+        let similar = store.find_similar(1, 10).unwrap();
+        assert_eq!(similar.len(), 2);
+        assert_eq!(similar[0].file_path, portable_path(&paths[1], None));
+        assert_eq!(similar[0].similarity, 1.0);
+        assert_eq!(similar[1].similarity, 0.0);
+        // hashes are kept once computed
+        assert!(store.crops().unwrap()[..3]
+            .iter()
+            .all(|c| c.phash.is_some()));
+        assert!(store.find_similar(4, 10).is_err());
+
+        let clusters = store.cluster(None).unwrap();
+        assert_eq!(clusters.len(), 2);
+        assert_eq!(clusters[0].crop_ids, [1, 2]);
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_similarity_helpers() {
+        let embedding = [0.25, -1.5, 3.0];
+        assert_eq!(from_blob(&to_blob(&embedding)), embedding);
+        assert_eq!(cosine_similarity(&[0.0, 0.0], &[1.0, 0.0]), 0.0);
+        assert_eq!(cosine_similarity(&[2.0, 0.0], &[1.0, 0.0]), 1.0);
+    }
+
+    #[test]
+    fn test_migrate() {
+        let root = testing::temp_dir();
+        let path = root.join(EMBEDDING_DB);
+        // the first schema, written before the database had a version
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(MIGRATIONS[0]).unwrap();
+        conn.execute(
+            "INSERT INTO crops (file_path, frame_index, embedding) VALUES ('/a.jpg', 0, ?1)",
+            params![to_blob(&[1.0, 0.0])],
+        )
+        .unwrap();
+        drop(conn);
+
+        let mut store = EmbeddingStore::open(&path).unwrap();
+        let version: usize = store
+            .conn
+            .pragma_query_value(None, "user_version", |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, MIGRATIONS.len());
+        // crops without an embedding fit the migrated table
+        let frame = ExportFrame {
+            bboxes: Some(vec![testing::bbox(0, 0.9)]),
+            ..testing::frame("/b.jpg")
+        };
+        store.insert(&frame, &[], &[]).unwrap();
+        assert_eq!(store.crops().unwrap().len(), 2);
+        assert_eq!(store.label_cluster(0, "Leopard").unwrap(), 0);
+        drop(store);
+        assert!(EmbeddingStore::open(&path).is_ok());
+
+        // a database of a newer version is left alone
+        let conn = Connection::open(&path).unwrap();
+        conn.pragma_update(None, "user_version", MIGRATIONS.len() + 1)
+            .unwrap();
+        drop(conn);
+        assert!(EmbeddingStore::open(&path).is_err());
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
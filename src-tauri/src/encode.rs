@@ -0,0 +1,117 @@
+use std::thread::{self, JoinHandle};
+
+use crossbeam_channel::{bounded, Sender};
+
+use crate::events::ProgressCounter;
+use crate::priority;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Worker threads that encode frames apart from decoding, so ffmpeg output is drained
+/// while large frames are still being encoded. The queue is bounded, a full queue makes
+/// decoding wait instead of buffering every decoded frame.
+pub struct EncodePool {
+    jobs: Option<Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+    progress: ProgressCounter,
+}
+
+impl EncodePool {
+    pub fn new(
+        workers: usize,
+        capacity: usize,
+        low_priority: bool,
+        progress: ProgressCounter,
+    ) -> Self {
+        let (jobs, queue) = bounded::<Job>(capacity);
+        progress.metrics().set_encode_capacity(capacity);
+        let workers = (0..workers.max(1))
+            .map(|_| {
+                let queue = queue.clone();
+                let progress = progress.clone();
+                thread::spawn(move || {
+                    if low_priority {
+                        priority::lower_current_thread();
+                    }
+                    for job in queue {
+                        progress.metrics().encode_started();
+                        job();
+                        progress.metrics().encode_finished();
+                    }
+                })
+            })
+            .collect();
+        Self {
+            jobs: Some(jobs),
+            workers,
+            progress,
+        }
+    }
+
+    /// One worker per core beside the decoders, queueing two frames each.
+    pub fn with_defaults(low_priority: bool, progress: ProgressCounter) -> Self {
+        let workers = thread::available_parallelism().map_or(2, |n| n.get() / 2);
+        Self::new(workers, workers.max(1) * 2, low_priority, progress)
+    }
+
+    /// Queues `job`, blocking while the queue is full. Runs it on the calling thread once
+    /// the pool is finished.
+    pub fn submit(&self, job: impl FnOnce() + Send + 'static) {
+        let Some(jobs) = &self.jobs else {
+            return job();
+        };
+        self.progress.metrics().encode_queued();
+        if let Err(e) = jobs.send(Box::new(job)) {
+            self.progress.metrics().encode_started();
+            (e.into_inner())();
+            self.progress.metrics().encode_finished();
+        }
+    }
+
+    /// Waits for the queued jobs to be done.
+    pub fn finish(&mut self) {
+        self.jobs.take();
+        for worker in self.workers.drain(..) {
+            if worker.join().is_err() {
+                log::error!("Encode worker panicked");
+            }
+        }
+    }
+}
+
+impl Drop for EncodePool {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_encode_pool() {
+        let progress = ProgressCounter::default();
+        let mut pool = EncodePool::new(2, 1, false, progress.clone());
+        let done = Arc::new(AtomicUsize::new(0));
+        for _ in 0..10 {
+            let done = Arc::clone(&done);
+            pool.submit(move || {
+                done.fetch_add(1, Ordering::Relaxed);
+            });
+        }
+        pool.finish();
+        assert_eq!(done.load(Ordering::Relaxed), 10);
+        let metrics = progress.metrics().snapshot();
+        assert_eq!(
+            (
+                metrics.encode_queue,
+                metrics.encode_capacity,
+                metrics.encoded
+            ),
+            (0, 1, 10)
+        );
+    }
+}
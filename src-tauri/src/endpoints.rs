@@ -0,0 +1,87 @@
+/// Servers a run can send to: the configured one first, then the further ones in order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Endpoints {
+    urls: Vec<String>,
+    current: usize,
+}
+
+impl Endpoints {
+    /// Leaves out blank and repeated URLs, `primary` stays first even when blank.
+    pub fn new(primary: &str, others: &[String]) -> Self {
+        let mut urls = vec![primary.trim().to_string()];
+        for url in others.iter().map(|url| url.trim()) {
+            if !url.is_empty() && !urls.iter().any(|u| u == url) {
+                urls.push(url.to_string());
+            }
+        }
+        Self { urls, current: 0 }
+    }
+
+    pub fn current(&self) -> &str {
+        &self.urls[self.current]
+    }
+
+    /// Every server, starting with the current one.
+    pub fn from_current(&self) -> impl Iterator<Item = &str> {
+        let (before, after) = self.urls.split_at(self.current);
+        after.iter().chain(before).map(String::as_str)
+    }
+
+    /// The servers other than the current one, in order.
+    pub fn others(&self) -> impl Iterator<Item = &str> {
+        self.from_current().skip(1)
+    }
+
+    /// Makes `url` the current server, when it is one of them.
+    pub fn select(&mut self, url: &str) {
+        if let Some(index) = self.urls.iter().position(|u| u == url) {
+            self.current = index;
+        }
+    }
+
+    /// Fails over to the next server, back to the first after the last.
+    pub fn advance(&mut self) -> &str {
+        self.current = (self.current + 1) % self.urls.len();
+        self.current()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_endpoints() {
+        let others = [
+            " https://b.example.org ".to_string(),
+            String::new(),
+            "https://a.example.org".to_string(),
+            "https://c.example.org".to_string(),
+        ];
+        let mut endpoints = Endpoints::new("https://a.example.org", &others);
+        assert_eq!(endpoints.from_current().count(), 3);
+        assert_eq!(endpoints.current(), "https://a.example.org");
+        assert_eq!(
+            endpoints.others().collect::<Vec<_>>(),
+            ["https://b.example.org", "https://c.example.org"]
+        );
+
+        endpoints.select("https://b.example.org");
+        assert_eq!(
+            endpoints.from_current().collect::<Vec<_>>(),
+            [
+                "https://b.example.org",
+                "https://c.example.org",
+                "https://a.example.org"
+            ]
+        );
+        assert_eq!(endpoints.advance(), "https://c.example.org");
+        assert_eq!(endpoints.advance(), "https://a.example.org");
+        endpoints.select("https://unknown.example.org");
+        assert_eq!(endpoints.current(), "https://a.example.org");
+
+        let mut single = Endpoints::new("https://a.example.org", &[]);
+        assert_eq!(single.advance(), "https://a.example.org");
+        assert_eq!(single.others().count(), 0);
+    }
+}
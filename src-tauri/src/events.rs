@@ -0,0 +1,464 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crossbeam_channel::Receiver;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::completion::FileResult;
+use crate::utils::IndexProgress;
+
+/// Where the pipeline reports to. The app forwards to the Tauri frontend, headless
+/// callers can record or print the events instead.
+pub trait EventSink: Send + Sync {
+    fn emit_value(&self, event: &str, payload: Value);
+}
+
+impl dyn EventSink + '_ {
+    pub fn emit<S: Serialize>(&self, event: &str, payload: S) {
+        self.emit_value(event, to_payload(event, payload));
+    }
+}
+
+fn to_payload<S: Serialize>(event: &str, payload: S) -> Value {
+    serde_json::to_value(payload).unwrap_or_else(|e| {
+        log::error!("Failed to serialize {} event: {}", event, e);
+        Value::Null
+    })
+}
+
+/// Receives the progress of a run.
+pub trait ProgressSink: Send + Sync {
+    /// Percent of the files that are done, `None` while indexing as the total isn't
+    /// known yet.
+    fn detect_progress(&self, percent: Option<f32>);
+    fn indexing(&self, progress: &IndexProgress);
+    fn pipeline_metrics(&self, metrics: &MetricsSnapshot);
+    fn file_complete(&self, result: &FileResult);
+}
+
+/// Progress is reported as the events the frontend listens to.
+impl<T: EventSink + ?Sized> ProgressSink for T {
+    fn detect_progress(&self, percent: Option<f32>) {
+        self.emit_value("detect-progress", percent.into());
+    }
+
+    fn indexing(&self, progress: &IndexProgress) {
+        match progress {
+            IndexProgress::Found(count) => self.emit_value("indexing-progress", (*count).into()),
+            IndexProgress::Finished {
+                total,
+                skipped_links,
+            } => {
+                self.emit_value("indexing-progress", (*total).into());
+                self.emit_value("indexing-complete", (*total).into());
+                if !skipped_links.is_empty() {
+                    let links = to_payload("skipped-links", skipped_links);
+                    self.emit_value("skipped-links", links);
+                }
+            }
+            IndexProgress::Failed(e) => self.emit_value("indexing-error", e.as_str().into()),
+        }
+    }
+
+    fn pipeline_metrics(&self, metrics: &MetricsSnapshot) {
+        let metrics = to_payload("pipeline-metrics", metrics);
+        self.emit_value("pipeline-metrics", metrics);
+    }
+
+    fn file_complete(&self, result: &FileResult) {
+        let result = to_payload("file-complete", result);
+        self.emit_value("file-complete", result);
+    }
+}
+
+/// Keeps every event, for tests and headless runs that inspect them afterwards.
+#[derive(Default)]
+pub struct RecordedEvents {
+    pub events: Mutex<Vec<(String, Value)>>,
+}
+
+impl EventSink for RecordedEvents {
+    fn emit_value(&self, event: &str, payload: Value) {
+        self.events
+            .lock()
+            .unwrap()
+            .push((event.to_string(), payload));
+    }
+}
+
+/// Events of a queued job. Its progress is repeated as `job-progress` with the job id,
+/// so the queue can show every job apart.
+pub struct JobEvents {
+    pub id: String,
+    pub sink: Arc<dyn EventSink>,
+}
+
+impl EventSink for JobEvents {
+    fn emit_value(&self, event: &str, payload: Value) {
+        if event == "detect-progress" {
+            let progress = serde_json::json!({ "id": self.id, "percent": payload });
+            self.sink.emit_value("job-progress", progress);
+        }
+        self.sink.emit_value(event, payload);
+    }
+}
+
+/// How often the file count is reported while a run is going.
+pub const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Files done so far, shared by the pipeline stages. Counting is a single atomic add,
+/// so a stalled frontend can never hold up the workers.
+#[derive(Clone, Default)]
+pub struct ProgressCounter {
+    state: Arc<ProgressState>,
+}
+
+#[derive(Default)]
+struct ProgressState {
+    done: AtomicUsize,
+    finished: AtomicBool,
+    metrics: PipelineMetrics,
+    /// Files whose results all came back since the last tick.
+    completed: Mutex<Vec<FileResult>>,
+}
+
+/// Gauges of the pipeline stages, to find the one holding a run up.
+#[derive(Default)]
+pub struct PipelineMetrics {
+    encode_queue: AtomicUsize,
+    encode_capacity: AtomicUsize,
+    encoded: AtomicUsize,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MetricsSnapshot {
+    /// Frames waiting for a WebP encoder.
+    pub encode_queue: usize,
+    /// Frames the encode queue holds before decoding waits.
+    pub encode_capacity: usize,
+    pub encoded: usize,
+}
+
+impl PipelineMetrics {
+    pub fn set_encode_capacity(&self, capacity: usize) {
+        self.encode_capacity.store(capacity, Ordering::Relaxed);
+    }
+
+    pub fn encode_queued(&self) {
+        self.encode_queue.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn encode_started(&self) {
+        self.encode_queue.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn encode_finished(&self) {
+        self.encoded.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            encode_queue: self.encode_queue.load(Ordering::Relaxed),
+            encode_capacity: self.encode_capacity.load(Ordering::Relaxed),
+            encoded: self.encoded.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl ProgressCounter {
+    pub fn add(&self, files: usize) {
+        self.state.done.fetch_add(files, Ordering::Relaxed);
+    }
+
+    pub fn done(&self) -> usize {
+        self.state.done.load(Ordering::Relaxed)
+    }
+
+    pub fn metrics(&self) -> &PipelineMetrics {
+        &self.state.metrics
+    }
+
+    /// Queues the result of a finished file for the next tick.
+    pub fn file_complete(&self, result: FileResult) {
+        self.state.completed.lock().unwrap().push(result);
+    }
+
+    fn take_completed(&self) -> Vec<FileResult> {
+        std::mem::take(&mut *self.state.completed.lock().unwrap())
+    }
+
+    /// Marks the run as over, the forwarder reports the last count and returns.
+    pub fn finish(&self) {
+        self.state.finished.store(true, Ordering::Release);
+    }
+
+    fn is_finished(&self) -> bool {
+        self.state.finished.load(Ordering::Acquire)
+    }
+}
+
+/// Reports the file count, the files completed and the pipeline metrics every `interval`
+/// and passes the indexing messages on, until `progress` is finished. Counts that come in between ticks
+/// are coalesced into one event.
+pub fn forward_progress<P: ProgressSink + ?Sized>(
+    progress: &ProgressCounter,
+    index: Receiver<IndexProgress>,
+    sink: &P,
+    interval: Duration,
+) {
+    let ticker = crossbeam_channel::tick(interval);
+    let mut index = index;
+    let mut found = 0;
+    let mut indexed = false;
+    let mut reported = (0, false);
+    let mut reported_metrics = MetricsSnapshot::default();
+    loop {
+        crossbeam_channel::select! {
+            recv(ticker) -> _ => {
+                // read the flag first so the count can't miss files done before it was set
+                let finished = progress.is_finished();
+                let done = progress.done();
+                for result in progress.take_completed() {
+                    sink.file_complete(&result);
+                }
+                if (done, indexed) != reported {
+                    reported = (done, indexed);
+                    let percent = done as f32 / found.max(done).max(1) as f32 * 100.0;
+                    sink.detect_progress(indexed.then_some(percent));
+                }
+                let metrics = progress.metrics().snapshot();
+                if metrics != reported_metrics {
+                    reported_metrics = metrics;
+                    sink.pipeline_metrics(&metrics);
+                }
+                if finished {
+                    break;
+                }
+            }
+            recv(index) -> msg => match msg {
+                Ok(progress) => {
+                    match &progress {
+                        IndexProgress::Found(total) => found = *total,
+                        IndexProgress::Finished { total, .. } => {
+                            found = *total;
+                            indexed = true;
+                        }
+                        IndexProgress::Failed(_) => indexed = true,
+                    }
+                    sink.indexing(&progress);
+                }
+                Err(_) => index = crossbeam_channel::never(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::testing;
+
+    #[test]
+    fn test_forward_progress() {
+        let (index_s, index_r) = crossbeam_channel::unbounded();
+        index_s.send(IndexProgress::Found(4)).unwrap();
+        index_s
+            .send(IndexProgress::Finished {
+                total: 4,
+                skipped_links: Vec::new(),
+            })
+            .unwrap();
+        drop(index_s);
+
+        // both files are counted before the first tick, they arrive as one event
+        let progress = ProgressCounter::default();
+        progress.add(1);
+        progress.add(1);
+        progress.metrics().set_encode_capacity(8);
+        progress.metrics().encode_queued();
+        progress.finish();
+
+        let sink = RecordedEvents::default();
+        forward_progress(&progress, index_r, &sink, Duration::from_millis(10));
+
+        let events = sink.events.into_inner().unwrap();
+        assert_eq!(
+            events,
+            [
+                ("indexing-progress".to_string(), Value::from(4)),
+                ("indexing-progress".to_string(), Value::from(4)),
+                ("indexing-complete".to_string(), Value::from(4)),
+                ("detect-progress".to_string(), Value::from(50.0)),
+                (
+                    "pipeline-metrics".to_string(),
+                    serde_json::json!({ "encodeQueue": 1, "encodeCapacity": 8, "encoded": 0 })
+                ),
+            ]
+        );
+
+        // the first files are done long before the walk found them all
+        let (index_s, index_r) = crossbeam_channel::unbounded();
+        index_s.send(IndexProgress::Found(1000)).unwrap();
+        let progress = ProgressCounter::default();
+        progress.add(999);
+        progress.finish();
+        let sink = RecordedEvents::default();
+        forward_progress(&progress, index_r, &sink, Duration::from_millis(10));
+        let events = sink.events.into_inner().unwrap();
+        assert_eq!(events[1], ("detect-progress".to_string(), Value::Null));
+    }
+
+    #[test]
+    fn test_job_events() {
+        let recorded = Arc::new(RecordedEvents::default());
+        let sink: &dyn EventSink = &JobEvents {
+            id: "job".to_string(),
+            sink: recorded.clone(),
+        };
+        sink.detect_progress(Some(25.0));
+        sink.emit("detect-complete", ());
+
+        let events = recorded.events.lock().unwrap();
+        let names: Vec<&str> = events.iter().map(|(e, _)| e.as_str()).collect();
+        assert_eq!(
+            names,
+            ["job-progress", "detect-progress", "detect-complete"]
+        );
+        assert_eq!(
+            events[0].1,
+            serde_json::json!({ "id": "job", "percent": 25.0 })
+        );
+    }
+
+    /// Serves `options` on a free port of this machine, returns its url.
+    async fn mock_server(options: md5rs_mock::MockOptions) -> String {
+        use tokio_stream::wrappers::TcpListenerStream;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(md5rs_mock::MockServer::new(options).into_service())
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        format!("http://{}", addr)
+    }
+
+    /// A folder of two small images and the configuration of a run on it against `url`.
+    fn headless_config(url: &str, stream_retries: u32) -> (std::path::PathBuf, crate::Config) {
+        let dir = testing::temp_dir();
+        std::fs::create_dir_all(dir.join("site")).unwrap();
+        for name in ["IMG_0001.JPG", "IMG_0002.JPG"] {
+            image::RgbImage::from_pixel(64, 48, image::Rgb([90, 120, 60]))
+                .save(dir.join("site").join(name))
+                .unwrap();
+        }
+        let config = serde_json::from_value(serde_json::json!({
+            "detectOptions": {
+                "selectedFolder": dir,
+                "grpcUrl": url,
+                "accessToken": "demo",
+                "resumePath": null,
+                "guess": false,
+                "proxy": "direct",
+            },
+            "configOptions": {
+                "confidenceThreshold": 0.2,
+                "iouThreshold": 0.45,
+                "quality": 80.0,
+                "exportFormat": "Json",
+                "maxFrames": null,
+                "iframeOnly": false,
+                "checkPoint": 100,
+                "bufferPath": null,
+                "bufferSize": 4,
+                "streamRetries": stream_retries,
+            },
+        }))
+        .unwrap();
+        (dir, config)
+    }
+
+    /// A whole run against the mock server, with nothing of Tauri involved.
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_headless_run() {
+        use md5rs_mock::{CannedBbox, CannedFrame, Detections, MockOptions};
+
+        let url = mock_server(MockOptions {
+            detections: Detections::Canned(vec![CannedFrame {
+                bboxs: vec![CannedBbox {
+                    x1: 0.1,
+                    y1: 0.2,
+                    x2: 0.4,
+                    y2: 0.6,
+                    class: 0,
+                    score: 0.9,
+                }],
+            }]),
+            ..Default::default()
+        })
+        .await;
+        let (dir, config) = headless_config(&url, 5);
+
+        let sink = Arc::new(RecordedEvents::default());
+        let result = crate::run_detection(
+            config,
+            sink.clone(),
+            Arc::default(),
+            tokio_util::sync::CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(result, dir.join("result.json"));
+
+        let frames = crate::load_export(&result).unwrap();
+        assert_eq!(frames.len(), 2);
+        for frame in &frames {
+            assert_eq!(frame.error, None);
+            let bboxes = frame.bboxes.as_deref().unwrap();
+            assert_eq!(bboxes.len(), 1);
+            assert_eq!(bboxes[0].class_name(), "Animal");
+        }
+        let events = sink.events.lock().unwrap();
+        let count = |name: &str| events.iter().filter(|(e, _)| e == name).count();
+        assert_eq!(count("indexing-complete"), 1);
+        assert_eq!(count("file-complete"), 2);
+        assert_eq!(count("detect-complete"), 1);
+        assert_eq!(count("detect-error"), 0);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// A server that stays down once the retries are spent fails the run, what it
+    /// answered before is still exported.
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_run_gives_up_reconnecting() {
+        let url = mock_server(md5rs_mock::MockOptions {
+            down_after: Some(1),
+            ..Default::default()
+        })
+        .await;
+        let (dir, config) = headless_config(&url, 1);
+
+        let sink = Arc::new(RecordedEvents::default());
+        let result = crate::run_detection(
+            config,
+            sink.clone(),
+            Arc::default(),
+            tokio_util::sync::CancellationToken::new(),
+        )
+        .await;
+        let error = result.unwrap_err().to_string();
+        assert!(error.contains("Gave up on the server"), "{}", error);
+
+        let frames = crate::load_export(dir.join("result.json")).unwrap();
+        assert_eq!(frames.len(), 1);
+        let events = sink.events.lock().unwrap();
+        let count = |name: &str| events.iter().filter(|(e, _)| e == name).count();
+        assert_eq!(count("detect-complete"), 0);
+        assert_eq!(count("detect-error"), 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,15 +1,140 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fs::File;
-use std::io::Write;
+use std::io::{BufReader, Read, Write};
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use csv::WriterBuilder;
+use flate2::{read::GzDecoder, write::GzEncoder};
+use megascops_media::{CameraInfo, EncodeFallback};
 use serde::{Deserialize, Serialize};
 
-use crate::utils::FileItem;
+use crate::annotation::preview_name;
+use crate::anonymize::AnonymizeOptions;
+use crate::media::get_video_dimensions;
+use crate::report::has_error;
+use crate::utils::{is_video, portable_path, FileItem};
 use crate::ExportFormat;
 
+#[derive(Debug, Clone)]
+pub struct ExportOptions {
+    pub format: ExportFormat,
+    /// Write file paths relative to the selected folder instead of absolute.
+    pub relative_paths: bool,
+    pub anonymize: AnonymizeOptions,
+    pub compression: ExportCompression,
+    /// Folder the result files are written to, the media folder when unset.
+    pub destination: Option<PathBuf>,
+}
+
+impl ExportOptions {
+    fn destination<'a>(&'a self, folder_path: &'a Path) -> &'a Path {
+        self.destination.as_deref().unwrap_or(folder_path)
+    }
+}
+
+/// Compression of the json and csv results, marked by an extension after the format's.
+/// MegaDetector and COCO output stays plain for the tools reading it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+pub enum ExportCompression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl ExportCompression {
+    pub fn extension(&self) -> Option<&'static str> {
+        match self {
+            ExportCompression::None => None,
+            ExportCompression::Gzip => Some("gz"),
+            ExportCompression::Zstd => Some("zst"),
+        }
+    }
+
+    fn encode(
+        &self,
+        file: &mut File,
+        write: impl FnOnce(&mut dyn Write) -> Result<()>,
+    ) -> Result<()> {
+        match self {
+            ExportCompression::None => write(file),
+            ExportCompression::Gzip => {
+                let mut encoder = GzEncoder::new(file, flate2::Compression::default());
+                write(&mut encoder)?;
+                encoder.finish()?;
+                Ok(())
+            }
+            ExportCompression::Zstd => {
+                let mut encoder = zstd::Encoder::new(file, 0)?;
+                write(&mut encoder)?;
+                encoder.finish()?;
+                Ok(())
+            }
+        }
+    }
+
+    fn decode(&self, file: File) -> Result<Box<dyn Read>> {
+        let file = BufReader::new(file);
+        Ok(match self {
+            ExportCompression::None => Box::new(file),
+            ExportCompression::Gzip => Box::new(GzDecoder::new(file)),
+            ExportCompression::Zstd => Box::new(zstd::Decoder::with_buffer(file)?),
+        })
+    }
+}
+
+/// Format extension of an export and its compression, `result.csv.gz` is a gzipped csv.
+pub fn export_extension(path: &Path) -> (Option<&str>, ExportCompression) {
+    fn extension(path: &Path) -> Option<&str> {
+        path.extension().and_then(|ext| ext.to_str())
+    }
+    let compression = match extension(path) {
+        Some("gz") => ExportCompression::Gzip,
+        Some("zst") => ExportCompression::Zstd,
+        _ => return (extension(path), ExportCompression::None),
+    };
+    (
+        path.file_stem().map(Path::new).and_then(extension),
+        compression,
+    )
+}
+
+/// Whether `name` is the result file of a run, in any format and compression.
+pub fn is_result_file_name(name: &str) -> bool {
+    let name = [".gz", ".zst"]
+        .iter()
+        .find_map(|ext| name.strip_suffix(ext))
+        .unwrap_or(name);
+    name == "result.csv" || name == "result.json"
+}
+
+/// Detector classes, indexed by `Bbox::class`.
+pub const CLASS_NAMES: [&str; 3] = ["Animal", "Person", "Vehicle"];
+/// MegaDetector batch output written next to the results with `ExportFormat::MegaDetector`.
+pub const MEGADETECTOR_FILE_NAME: &str = "megadetector.json";
+const MEGADETECTOR_FORMAT_VERSION: &str = "1.4";
+/// COCO annotations written next to the results with `ExportFormat::Coco`.
+pub const COCO_FILE_NAME: &str = "coco.json";
+
+/// What the corners of a `Bbox` are measured in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum BboxSpace {
+    /// Fractions of the frame width and height, what the detectors return.
+    #[default]
+    Normalized,
+    Pixel,
+}
+
+impl BboxSpace {
+    fn is_normalized(&self) -> bool {
+        *self == BboxSpace::Normalized
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Bbox {
     pub x1: f32,
@@ -18,6 +143,68 @@ pub struct Bbox {
     pub y2: f32,
     pub score: f32,
     pub class: usize,
+    /// Individual returned by the re-identification endpoint.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub individual: Option<String>,
+    /// Label a person gave the box in review, wins over the detector class.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(default, skip_serializing_if = "BboxSpace::is_normalized")]
+    pub space: BboxSpace,
+}
+
+/// Why a frame was exported as blank without being sent for detection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum BlankSkip {
+    Prefilter,
+    Background,
+}
+
+impl BlankSkip {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BlankSkip::Prefilter => "prefilter",
+            BlankSkip::Background => "background",
+        }
+    }
+}
+
+impl std::str::FromStr for BlankSkip {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "prefilter" => Ok(BlankSkip::Prefilter),
+            "background" => Ok(BlankSkip::Background),
+            _ => Err(anyhow!("Invalid blank skip reason: {}", s)),
+        }
+    }
+}
+
+impl Bbox {
+    pub fn class_name(&self) -> String {
+        if let Some(label) = &self.label {
+            return label.clone();
+        }
+        CLASS_NAMES
+            .get(self.class)
+            .map_or_else(|| format!("Class {}", self.class), |name| name.to_string())
+    }
+
+    /// Pixel rectangle `(x, y, width, height)` of the box inside a `width`x`height`
+    /// image.
+    pub fn pixel_rect(&self, width: u32, height: u32) -> (u32, u32, u32, u32) {
+        let (sx, sy) = match self.space {
+            BboxSpace::Normalized => (width as f32, height as f32),
+            BboxSpace::Pixel => (1.0, 1.0),
+        };
+        let x1 = (self.x1 * sx).clamp(0.0, width as f32) as u32;
+        let y1 = (self.y1 * sy).clamp(0.0, height as f32) as u32;
+        let x2 = (self.x2 * sx).clamp(0.0, width as f32) as u32;
+        let y2 = (self.y2 * sy).clamp(0.0, height as f32) as u32;
+        (x1, y1, x2.saturating_sub(x1), y2.saturating_sub(y1))
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -25,16 +212,55 @@ pub struct ExportFrame {
     #[serde(flatten)]
     pub file: FileItem,
     pub shoot_time: Option<String>,
+    /// Where the image was shot in decimal degrees, from its EXIF GPS tags.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latitude: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub longitude: Option<f64>,
+    /// Make, model and serial number of the camera, to group results per camera.
+    #[serde(flatten)]
+    pub camera: CameraInfo,
     pub frame_index: usize,
     pub total_frames: usize,
     pub bboxes: Option<Vec<Bbox>>,
     pub label: Option<Vec<String>>,
     pub error: Option<String>,
     pub iframe: bool,
+    /// Image of the burst whose result was copied to this frame.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub burst_source: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefilter_score: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub skipped_blank: Option<BlankSkip>,
+    /// How the frame was degraded to be encoded for detection, if it was.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encode_fallback: Option<EncodeFallback>,
+    /// Masked access token whose quota paid for the detection.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    /// Boxes and labels were checked by a person and replace the detections.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub verified: bool,
+}
+
+impl ExportFrame {
+    /// `(bbox_index, label)` of every box of a verified frame.
+    pub fn verified_labels(&self) -> Option<Vec<(usize, String)>> {
+        self.verified.then(|| {
+            self.bboxes
+                .iter()
+                .flatten()
+                .map(|b| b.class_name())
+                .enumerate()
+                .collect()
+        })
+    }
 }
 
 pub fn parse_export_csv<P: AsRef<Path>>(csv: P) -> Result<Vec<ExportFrame>> {
-    let file = File::open(csv)?;
+    let csv = csv.as_ref();
+    let file = export_extension(csv).1.decode(File::open(csv)?)?;
     let mut rdr = csv::Reader::from_reader(file);
     let mut export_data = Vec::new();
     for frame in rdr.records() {
@@ -44,12 +270,30 @@ pub fn parse_export_csv<P: AsRef<Path>>(csv: P) -> Result<Vec<ExportFrame>> {
             file_id: frame[1].parse::<_>()?,
             file_path: frame[2].parse()?,
             tmp_path: frame[2].parse()?,
+            policy: None,
         };
         let bboxes = frame[7].to_string().replace("\"\"", "\"");
         let bboxes = serde_json::from_str(&bboxes)?;
         let frame_item = ExportFrame {
             file: file_item,
             shoot_time: Some(frame[3].to_string()),
+            latitude: match frame.get(15).filter(|s| !s.is_empty()) {
+                Some(latitude) => Some(latitude.parse()?),
+                None => None,
+            },
+            longitude: match frame.get(16).filter(|s| !s.is_empty()) {
+                Some(longitude) => Some(longitude.parse()?),
+                None => None,
+            },
+            camera: CameraInfo {
+                make: frame.get(17).filter(|s| !s.is_empty()).map(str::to_string),
+                model: frame.get(18).filter(|s| !s.is_empty()).map(str::to_string),
+                serial_number: frame.get(19).filter(|s| !s.is_empty()).map(str::to_string),
+            },
+            encode_fallback: match frame.get(20).filter(|s| !s.is_empty()) {
+                Some(fallback) => Some(fallback.parse()?),
+                None => None,
+            },
             frame_index: frame[4].parse::<_>()?,
             total_frames: frame[5].parse::<_>()?,
             bboxes,
@@ -62,53 +306,274 @@ pub fn parse_export_csv<P: AsRef<Path>>(csv: P) -> Result<Vec<ExportFrame>> {
             ),
             iframe: frame[6].parse::<_>()?,
             error: Some(frame[9].to_string()),
+            burst_source: frame.get(10).filter(|s| !s.is_empty()).map(PathBuf::from),
+            prefilter_score: match frame.get(11).filter(|s| !s.is_empty()) {
+                Some(score) => Some(score.parse()?),
+                None => None,
+            },
+            skipped_blank: match frame.get(12).filter(|s| !s.is_empty()) {
+                Some(reason) => Some(reason.parse()?),
+                None => None,
+            },
+            token: frame.get(13).filter(|s| !s.is_empty()).map(str::to_string),
+            verified: frame.get(14).is_some_and(|s| s == "true"),
         };
         export_data.push(frame_item);
     }
     Ok(export_data)
 }
 
+/// Reads a json or csv export back into frames, decompressing it when compressed.
+pub fn load_export<P: AsRef<Path>>(path: P) -> Result<Vec<ExportFrame>> {
+    let path = path.as_ref();
+    match export_extension(path) {
+        (Some("json"), compression) => {
+            let mut json = String::new();
+            compression
+                .decode(File::open(path)?)?
+                .read_to_string(&mut json)?;
+            Ok(serde_json::from_str(&json)?)
+        }
+        (Some("csv"), _) => parse_export_csv(path),
+        _ => Err(anyhow!("Invalid export file extension: {}", path.display())),
+    }
+}
+
+/// Writes `frames` back over the result file at `path`, keeping its format, compression
+/// and paths.
+pub fn save_export<P: AsRef<Path>>(path: P, frames: &Vec<ExportFrame>) -> Result<()> {
+    let path = path.as_ref();
+    let folder_path = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    let file_name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| anyhow!("Invalid export path: {}", path.display()))?;
+    let (format, compression) = match export_extension(path) {
+        (Some("json"), compression) => (ExportFormat::Json, compression),
+        (Some("csv"), compression) => (ExportFormat::Csv, compression),
+        _ => return Err(anyhow!("Invalid export file extension: {}", path.display())),
+    };
+    let options = ExportOptions {
+        format,
+        relative_paths: false,
+        anonymize: AnonymizeOptions::default(),
+        compression,
+        destination: None,
+    };
+    write_result(frames, &folder_path, file_name, &options)
+}
+
+/// When the results of a run in progress are written out, whichever comes first.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CheckpointOptions {
+    /// Frames between two checkpoints.
+    pub frames: usize,
+    /// Longest time between two checkpoints, zero for no limit. Checked as frames come
+    /// in, so runs finding little still checkpoint on time.
+    pub interval: Duration,
+    /// Result bytes written since the last checkpoint that make the next one due, 0 for
+    /// no limit.
+    pub bytes: usize,
+}
+
+/// What came in since the last checkpoint.
+#[derive(Debug)]
+pub struct Checkpointer {
+    options: CheckpointOptions,
+    frames: usize,
+    bytes: usize,
+    last: Instant,
+}
+
+impl Checkpointer {
+    pub fn new(options: CheckpointOptions, now: Instant) -> Self {
+        Self {
+            options,
+            frames: 0,
+            bytes: 0,
+            last: now,
+        }
+    }
+
+    /// Counts a frame of `bytes` and tells whether a checkpoint is due, starting over
+    /// when it is.
+    pub fn record(&mut self, bytes: usize, now: Instant) -> bool {
+        self.frames += 1;
+        self.bytes += bytes;
+        let options = &self.options;
+        let due = self.frames >= options.frames.max(1)
+            || (!options.interval.is_zero() && now.duration_since(self.last) >= options.interval)
+            || (options.bytes > 0 && self.bytes >= options.bytes);
+        if due {
+            self.frames = 0;
+            self.bytes = 0;
+            self.last = now;
+        }
+        due
+    }
+}
+
 pub fn export_worker(
-    checkpoint: usize,
-    checkpoint_counter: &Arc<Mutex<usize>>,
-    format: &ExportFormat,
+    checkpoint: CheckpointOptions,
+    options: &ExportOptions,
     folder_path: &PathBuf,
-    export_q_r: crossbeam_channel::Receiver<ExportFrame>,
+    frames: impl IntoIterator<Item = ExportFrame>,
     export_data: &Arc<Mutex<Vec<ExportFrame>>>,
+    keep: usize,
 ) {
-    loop {
-        match export_q_r.recv() {
-            Ok(export_frame) => {
-                let mut checkpoint_counter = checkpoint_counter.lock().unwrap();
-                if *checkpoint_counter % checkpoint == 0 && *checkpoint_counter != 0 {
-                    let export_data = export_data.lock().unwrap();
-                    log::info!("Exported {} frames", export_data.len());
-                    match format {
-                        ExportFormat::Json => write_json(&export_data, folder_path).unwrap(),
-                        ExportFormat::Csv => write_csv(&export_data, folder_path).unwrap(),
-                    }
-                }
-                export_data.lock().unwrap().push(export_frame);
-                *checkpoint_counter += 1;
-            }
-            Err(_) => break,
+    let mut checkpointer = Checkpointer::new(checkpoint, Instant::now());
+    for export_frame in frames {
+        // sized only when it counts, serializing every frame twice isn't free
+        let bytes = match checkpoint.bytes {
+            0 => 0,
+            _ => serde_json::to_vec(&export_frame).map_or(0, |json| json.len()),
+        };
+        let mut export_data = export_data.lock().unwrap();
+        export_data.push(export_frame);
+        if keep > 0 && export_data.len() > keep {
+            let dropped = export_data.len() - keep;
+            export_data.drain(..dropped);
+        }
+        if checkpointer.record(bytes, Instant::now()) {
+            log::info!("Exported {} frames", export_data.len());
+            let file_name = result_file_name(options.format, options.compression);
+            write_result(&export_data, folder_path, &file_name, options).unwrap();
+        }
+    }
+}
+
+/// Result file the run checkpoints to and resumes from. MegaDetector and COCO output
+/// can't be read back, those runs keep a json result next to it.
+pub fn result_file_name(format: ExportFormat, compression: ExportCompression) -> String {
+    export_file_name("result", format, compression)
+}
+
+/// Anonymized export written next to the results for public data repositories.
+pub fn public_file_name(format: ExportFormat, compression: ExportCompression) -> String {
+    export_file_name("result.public", format, compression)
+}
+
+fn export_file_name(stem: &str, format: ExportFormat, compression: ExportCompression) -> String {
+    let extension = match format {
+        ExportFormat::Json | ExportFormat::MegaDetector | ExportFormat::Coco => "json",
+        ExportFormat::Csv => "csv",
+    };
+    match compression.extension() {
+        Some(compressed) => format!("{}.{}.{}", stem, extension, compressed),
+        None => format!("{}.{}", stem, extension),
+    }
+}
+
+fn write_result(
+    export_data: &Vec<ExportFrame>,
+    folder_path: &PathBuf,
+    file_name: &str,
+    options: &ExportOptions,
+) -> Result<()> {
+    match options.format {
+        ExportFormat::Json | ExportFormat::MegaDetector | ExportFormat::Coco => {
+            write_json(export_data, folder_path, file_name, options)
         }
+        ExportFormat::Csv => write_csv(export_data, folder_path, file_name, options),
     }
 }
 
-fn write_json(export_data: &Vec<ExportFrame>, folder_path: &PathBuf) -> Result<()> {
-    let json = serde_json::to_string_pretty(export_data)?;
-    let json_path = folder_path.join("result.json");
-    let mut file = File::create(json_path)?;
-    file.write_all(json.as_bytes())?;
+/// Whether files can be created in `folder`, tried with a probe file that is removed again.
+/// Network shares mounted read-only only tell by refusing the write.
+pub fn is_writable(folder: &Path) -> bool {
+    let probe = folder.join(format!(".megascops-probe-{}", std::process::id()));
+    match File::create(&probe).and_then(|mut file| file.write_all(b"probe")) {
+        Ok(()) => std::fs::remove_file(&probe).is_ok(),
+        Err(_) => {
+            let _ = std::fs::remove_file(&probe);
+            false
+        }
+    }
+}
+
+/// Writes `path` through a file next to it that is synced and then renamed over `path`.
+/// Until the rename the previous version stays whole, a crash mid-write can't cost the
+/// results of a long run, and readers during a run never see a file cut off.
+pub fn write_atomic(path: &Path, write: impl FnOnce(&mut File) -> Result<()>) -> Result<()> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| anyhow!("Invalid export path: {}", path.display()))?;
+    let mut tmp_name = file_name.to_os_string();
+    tmp_name.push(".tmp");
+    let tmp = path.with_file_name(tmp_name);
+    let written = File::create(&tmp)
+        .map_err(anyhow::Error::from)
+        .and_then(|mut file| {
+            write(&mut file)?;
+            Ok(file.sync_all()?)
+        });
+    if let Err(e) = written {
+        let _ = std::fs::remove_file(&tmp);
+        return Err(e);
+    }
+    std::fs::rename(&tmp, path)?;
+    // the rename is only durable once the folder entry is
+    #[cfg(unix)]
+    if let Some(folder) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        File::open(folder)?.sync_all()?;
+    }
     Ok(())
 }
 
-fn write_csv(export_data: &Vec<ExportFrame>, folder_path: &PathBuf) -> Result<()> {
-    let csv_path = folder_path.join("result.csv");
-    let mut wtr = WriterBuilder::new()
-        .has_headers(false)
-        .from_path(csv_path)?;
+fn export_path(file_path: &Path, folder_path: &Path, options: &ExportOptions) -> String {
+    let root = if options.relative_paths {
+        Some(folder_path)
+    } else {
+        None
+    };
+    portable_path(file_path, root)
+}
+
+fn write_json(
+    export_data: &Vec<ExportFrame>,
+    folder_path: &PathBuf,
+    file_name: &str,
+    options: &ExportOptions,
+) -> Result<()> {
+    let export_data: Vec<ExportFrame> = export_data
+        .iter()
+        .map(|frame| {
+            let mut frame = frame.clone();
+            frame.file.file_path = export_path(&frame.file.file_path, folder_path, options).into();
+            frame.burst_source = frame
+                .burst_source
+                .map(|p| export_path(&p, folder_path, options).into());
+            frame
+        })
+        .collect();
+    let json = serde_json::to_string_pretty(&export_data)?;
+    write_atomic(&options.destination(folder_path).join(file_name), |file| {
+        options
+            .compression
+            .encode(file, |writer| Ok(writer.write_all(json.as_bytes())?))
+    })
+}
+
+fn write_csv(
+    export_data: &Vec<ExportFrame>,
+    folder_path: &PathBuf,
+    file_name: &str,
+    options: &ExportOptions,
+) -> Result<()> {
+    write_atomic(&options.destination(folder_path).join(file_name), |file| {
+        options.compression.encode(file, |writer| {
+            write_csv_records(export_data, folder_path, options, writer)
+        })
+    })
+}
+
+fn write_csv_records(
+    export_data: &Vec<ExportFrame>,
+    folder_path: &PathBuf,
+    options: &ExportOptions,
+    writer: &mut dyn Write,
+) -> Result<()> {
+    let mut wtr = WriterBuilder::new().has_headers(false).from_writer(writer);
     wtr.write_record([
         "folder_id",
         "file_id",
@@ -116,20 +581,27 @@ fn write_csv(export_data: &Vec<ExportFrame>, folder_path: &PathBuf) -> Result<()
         "shoot_time",
         "frame_index",
         "total_frames",
+        "iframe",
         "bboxes",
         "label",
         "error",
+        "burst_source",
+        "prefilter_score",
+        "skipped_blank",
+        "token",
+        "verified",
+        "latitude",
+        "longitude",
+        "make",
+        "model",
+        "serial_number",
+        "encode_fallback",
     ])?;
     for export_frame in export_data {
         wtr.write_record(&[
             export_frame.file.folder_id.to_string().as_str(),
             export_frame.file.file_id.to_string().as_str(),
-            export_frame
-                .file
-                .file_path
-                .to_string_lossy()
-                .into_owned()
-                .as_str(),
+            export_path(&export_frame.file.file_path, folder_path, options).as_str(),
             export_frame
                 .shoot_time
                 .clone()
@@ -137,6 +609,7 @@ fn write_csv(export_data: &Vec<ExportFrame>, folder_path: &PathBuf) -> Result<()
                 .as_str(),
             export_frame.frame_index.to_string().as_str(),
             export_frame.total_frames.to_string().as_str(),
+            export_frame.iframe.to_string().as_str(),
             serde_json::to_string(&export_frame.bboxes)
                 .unwrap_or("".to_string())
                 .as_str(),
@@ -149,30 +622,391 @@ fn write_csv(export_data: &Vec<ExportFrame>, folder_path: &PathBuf) -> Result<()
                 .clone()
                 .unwrap_or("".to_string())
                 .as_str(),
+            export_frame
+                .burst_source
+                .as_ref()
+                .map(|p| export_path(p, folder_path, options))
+                .unwrap_or_default()
+                .as_str(),
+            export_frame
+                .prefilter_score
+                .map(|s| s.to_string())
+                .unwrap_or_default()
+                .as_str(),
+            export_frame
+                .skipped_blank
+                .map(|s| s.as_str())
+                .unwrap_or_default(),
+            export_frame.token.as_deref().unwrap_or_default(),
+            export_frame.verified.to_string().as_str(),
+            export_frame
+                .latitude
+                .map(|l| l.to_string())
+                .unwrap_or_default()
+                .as_str(),
+            export_frame
+                .longitude
+                .map(|l| l.to_string())
+                .unwrap_or_default()
+                .as_str(),
+            export_frame.camera.make.as_deref().unwrap_or_default(),
+            export_frame.camera.model.as_deref().unwrap_or_default(),
+            export_frame
+                .camera
+                .serial_number
+                .as_deref()
+                .unwrap_or_default(),
+            export_frame
+                .encode_fallback
+                .map(|f| f.as_str())
+                .unwrap_or_default(),
         ])?;
     }
     wtr.flush()?;
     Ok(())
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MegaDetectorDetection {
+    /// Key of `detection_categories`.
+    pub category: String,
+    pub conf: f32,
+    /// Normalized `[x_min, y_min, width, height]`.
+    pub bbox: [f32; 4],
+    /// Frame of a video the box was found in.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frame_number: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MegaDetectorImage {
+    /// Path relative to the selected folder.
+    pub file: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failure: Option<String>,
+    /// `None` when the file failed.
+    pub detections: Option<Vec<MegaDetectorDetection>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MegaDetectorInfo {
+    pub format_version: String,
+    pub detector: String,
+    pub detection_completion_time: String,
+}
+
+/// Batch output of MegaDetector, as read by Timelapse and EcoAssist.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MegaDetectorBatch {
+    pub images: Vec<MegaDetectorImage>,
+    pub detection_categories: BTreeMap<String, String>,
+    pub info: MegaDetectorInfo,
+}
+
+fn round(value: f32, decimals: i32) -> f32 {
+    let factor = 10f32.powi(decimals);
+    (value * factor).round() / factor
+}
+
+/// One image per file, the frames of a video are merged with their frame number on
+/// every detection. A file only fails when none of its frames went through.
+pub fn megadetector_batch(export_data: &[ExportFrame], folder_path: &Path) -> MegaDetectorBatch {
+    let mut images: Vec<MegaDetectorImage> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
+    for frame in export_data {
+        let file = portable_path(&frame.file.file_path, Some(folder_path));
+        let i = *index.entry(file.clone()).or_insert_with(|| {
+            images.push(MegaDetectorImage {
+                file,
+                failure: None,
+                detections: None,
+            });
+            images.len() - 1
+        });
+        let image = &mut images[i];
+        if has_error(frame) {
+            image.failure = image.failure.take().or_else(|| frame.error.clone());
+            continue;
+        }
+        let frame_number = (frame.total_frames > 1).then_some(frame.frame_index);
+        image
+            .detections
+            .get_or_insert_with(Vec::new)
+            .extend(
+                frame
+                    .bboxes
+                    .iter()
+                    .flatten()
+                    .map(|b| MegaDetectorDetection {
+                        category: (b.class + 1).to_string(),
+                        conf: round(b.score, 3),
+                        bbox: [
+                            round(b.x1, 4),
+                            round(b.y1, 4),
+                            round(b.x2 - b.x1, 4),
+                            round(b.y2 - b.y1, 4),
+                        ],
+                        frame_number,
+                    }),
+            );
+    }
+    for image in &mut images {
+        if image.detections.is_some() {
+            image.failure = None;
+        }
+    }
+    MegaDetectorBatch {
+        images,
+        detection_categories: CLASS_NAMES
+            .iter()
+            .enumerate()
+            .map(|(i, name)| ((i + 1).to_string(), name.to_lowercase()))
+            .collect(),
+        info: MegaDetectorInfo {
+            format_version: MEGADETECTOR_FORMAT_VERSION.to_string(),
+            detector: format!("Megascops {}", env!("CARGO_PKG_VERSION")),
+            detection_completion_time: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+        },
+    }
+}
+
+fn write_megadetector(
+    export_data: &[ExportFrame],
+    folder_path: &Path,
+    destination: &Path,
+) -> Result<()> {
+    let batch = megadetector_batch(export_data, folder_path);
+    let json = serde_json::to_string_pretty(&batch)?;
+    write_atomic(&destination.join(MEGADETECTOR_FILE_NAME), |file| {
+        Ok(file.write_all(json.as_bytes())?)
+    })?;
+    log::info!(
+        "Exported {} files to {}",
+        batch.images.len(),
+        MEGADETECTOR_FILE_NAME
+    );
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CocoImage {
+    pub id: usize,
+    /// Path relative to the selected folder, video frames are named like the
+    /// annotation previews.
+    pub file_name: String,
+    pub width: u32,
+    pub height: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub video: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frame_index: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CocoAnnotation {
+    pub id: usize,
+    pub image_id: usize,
+    pub category_id: usize,
+    /// `[x, y, width, height]` in pixels.
+    pub bbox: [u32; 4],
+    pub area: u32,
+    pub iscrowd: u8,
+    pub score: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CocoCategory {
+    pub id: usize,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CocoDataset {
+    pub images: Vec<CocoImage>,
+    pub annotations: Vec<CocoAnnotation>,
+    pub categories: Vec<CocoCategory>,
+}
+
+/// COCO annotations of the frames that went through. Categories are the detector
+/// classes followed by labels given in review. `dimensions` gives the size of a file,
+/// frames of files it can't tell are left out.
+pub fn coco_dataset(
+    export_data: &[ExportFrame],
+    folder_path: &Path,
+    mut dimensions: impl FnMut(&Path) -> Option<(u32, u32)>,
+) -> CocoDataset {
+    let mut names: Vec<String> = CLASS_NAMES.iter().map(|n| n.to_string()).collect();
+    let reviewed: BTreeSet<&String> = export_data
+        .iter()
+        .flat_map(|f| f.bboxes.iter().flatten())
+        .filter_map(|b| b.label.as_ref())
+        .filter(|l| !names.contains(l))
+        .collect();
+    names.extend(reviewed.into_iter().cloned());
+
+    let mut dataset = CocoDataset {
+        images: Vec::new(),
+        annotations: Vec::new(),
+        categories: names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| CocoCategory {
+                id: i + 1,
+                name: name.clone(),
+            })
+            .collect(),
+    };
+    for frame in export_data.iter().filter(|f| !has_error(f)) {
+        let path = folder_path.join(&frame.file.file_path);
+        let Some((width, height)) = dimensions(&path) else {
+            log::warn!("Skipping {} in COCO export, no dimensions", path.display());
+            continue;
+        };
+        let relative = portable_path(&path, Some(folder_path));
+        let id = dataset.images.len() + 1;
+        let video = is_video(&path);
+        dataset.images.push(CocoImage {
+            id,
+            file_name: if video {
+                preview_name(&relative, frame.frame_index)
+            } else {
+                relative.clone()
+            },
+            width,
+            height,
+            video: video.then_some(relative),
+            frame_index: video.then_some(frame.frame_index),
+        });
+        for bbox in frame.bboxes.iter().flatten() {
+            let (x, y, w, h) = bbox.pixel_rect(width, height);
+            let name = bbox.class_name();
+            dataset.annotations.push(CocoAnnotation {
+                id: dataset.annotations.len() + 1,
+                image_id: id,
+                category_id: names.iter().position(|n| *n == name).unwrap_or_default() + 1,
+                bbox: [x, y, w, h],
+                area: w * h,
+                iscrowd: 0,
+                score: bbox.score,
+            });
+        }
+    }
+    dataset
+}
+
+fn write_coco(export_data: &[ExportFrame], folder_path: &Path, destination: &Path) -> Result<()> {
+    let mut cache: HashMap<PathBuf, Option<(u32, u32)>> = HashMap::new();
+    let dataset = coco_dataset(export_data, folder_path, |path| {
+        *cache.entry(path.to_path_buf()).or_insert_with(|| {
+            let dimensions = if is_video(path) {
+                get_video_dimensions(&path.to_string_lossy()).map(|(w, h)| (w as u32, h as u32))
+            } else {
+                megascops_media::image_dimensions(path)
+            };
+            dimensions
+                .inspect_err(|e| log::warn!("Failed to read size of {}: {}", path.display(), e))
+                .ok()
+        })
+    });
+    let json = serde_json::to_string_pretty(&dataset)?;
+    write_atomic(&destination.join(COCO_FILE_NAME), |file| {
+        Ok(file.write_all(json.as_bytes())?)
+    })?;
+    log::info!(
+        "Exported {} images with {} boxes to {}",
+        dataset.images.len(),
+        dataset.annotations.len(),
+        COCO_FILE_NAME
+    );
+    Ok(())
+}
+
 pub fn export(
     folder_path: &PathBuf,
     export_data: Arc<Mutex<Vec<ExportFrame>>>,
-    export_format: &ExportFormat,
+    options: &ExportOptions,
 ) -> Result<()> {
     let export_data = export_data.lock().unwrap();
     log::info!("Exported {} frames", export_data.len());
-    match export_format {
-        ExportFormat::Json => {
-            write_json(&export_data, folder_path)?;
+    let file_name = result_file_name(options.format, options.compression);
+    write_result(&export_data, folder_path, &file_name, options)?;
+    match options.format {
+        ExportFormat::MegaDetector => {
+            write_megadetector(&export_data, folder_path, options.destination(folder_path))?
         }
-        ExportFormat::Csv => {
-            write_csv(&export_data, folder_path)?;
+        ExportFormat::Coco => {
+            write_coco(&export_data, folder_path, options.destination(folder_path))?
         }
+        ExportFormat::Json | ExportFormat::Csv => (),
+    }
+    if options.anonymize.applies_to(options.format) {
+        let public = options.anonymize.anonymize(&export_data, folder_path);
+        // paths are relative already, hashed ones would not be touched anyway
+        let public_options = ExportOptions {
+            relative_paths: true,
+            ..options.clone()
+        };
+        let file_name = public_file_name(options.format, options.compression);
+        write_result(&public, folder_path, &file_name, &public_options)?;
+        log::info!("Exported {} frames to {}", public.len(), file_name);
     }
     Ok(())
 }
 
+/// Frames, boxes and folders for the tests of the modules working on exports.
+#[cfg(test)]
+pub(crate) mod testing {
+    use std::path::PathBuf;
+
+    use super::{Bbox, BboxSpace, ExportFrame};
+    use crate::utils::FileItem;
+
+    /// The only frame of `path`, answered without detections.
+    pub fn frame(path: impl Into<PathBuf>) -> ExportFrame {
+        ExportFrame {
+            file: FileItem::new(0, 0, path.into(), None),
+            shoot_time: None,
+            latitude: None,
+            longitude: None,
+            camera: Default::default(),
+            frame_index: 0,
+            total_frames: 1,
+            bboxes: Some(Vec::new()),
+            label: None,
+            error: None,
+            iframe: false,
+            burst_source: None,
+            prefilter_score: None,
+            skipped_blank: None,
+            encode_fallback: None,
+            token: None,
+            verified: false,
+        }
+    }
+
+    /// A box of `class` in the upper left of the frame.
+    pub fn bbox(class: usize, score: f32) -> Bbox {
+        Bbox {
+            x1: 0.1,
+            y1: 0.1,
+            x2: 0.5,
+            y2: 0.5,
+            score,
+            class,
+            individual: None,
+            label: None,
+            space: BboxSpace::Normalized,
+        }
+    }
+
+    /// An empty folder of its own in the system's temporary folder.
+    pub fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("megascops-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -183,4 +1017,225 @@ mod tests {
         let export_data = parse_export_csv("input/result.csv").unwrap();
         assert_eq!(export_data.len(), 11);
     }
+
+    #[test]
+    fn test_pixel_rect() {
+        let bbox = testing::bbox(0, 0.9);
+        assert_eq!(bbox.pixel_rect(200, 100), (20, 10, 80, 40));
+        // a pixel box in the top left corner is not mistaken for a normalized one
+        let corner = Bbox {
+            x2: 1.0,
+            y2: 1.0,
+            space: BboxSpace::Pixel,
+            ..testing::bbox(0, 0.9)
+        };
+        assert_eq!(corner.pixel_rect(200, 100), (0, 0, 1, 1));
+        let json = serde_json::to_string(&bbox).unwrap();
+        assert!(!json.contains("space"));
+        let loaded: Bbox = serde_json::from_str(&serde_json::to_string(&corner).unwrap()).unwrap();
+        assert_eq!(loaded.space, BboxSpace::Pixel);
+    }
+
+    #[test]
+    fn test_megadetector_batch() {
+        let frame =
+            |path: &str, index: usize, total: usize, boxes: Vec<(usize, f32)>| ExportFrame {
+                frame_index: index,
+                total_frames: total,
+                bboxes: Some(
+                    boxes
+                        .into_iter()
+                        .map(|(class, score)| Bbox {
+                            y1: 0.2,
+                            x2: 0.4,
+                            y2: 0.6,
+                            ..testing::bbox(class, score)
+                        })
+                        .collect(),
+                ),
+                ..testing::frame(path)
+            };
+        let mut failed = frame("/run/b/c.jpg", 0, 1, vec![]);
+        failed.error = Some("Failed to decode".to_string());
+        let mut dropped = frame("/run/v.mp4", 5, 10, vec![]);
+        dropped.error = Some("Failed to decode".to_string());
+        let frames = vec![
+            frame("/run/a.jpg", 0, 1, vec![(0, 0.91234)]),
+            frame("/run/v.mp4", 0, 10, vec![]),
+            dropped,
+            frame("/run/v.mp4", 10, 10, vec![(1, 0.5), (0, 0.7)]),
+            failed,
+        ];
+        let batch = megadetector_batch(&frames, Path::new("/run"));
+        assert_eq!(batch.detection_categories["2"], "person");
+        assert_eq!(batch.images.len(), 3);
+
+        let image = &batch.images[0];
+        assert_eq!(image.file, "a.jpg");
+        let detections = image.detections.as_ref().unwrap();
+        assert_eq!(detections[0].category, "1");
+        assert_eq!(detections[0].conf, 0.912);
+        assert_eq!(detections[0].bbox, [0.1, 0.2, 0.3, 0.4]);
+        assert_eq!(detections[0].frame_number, None);
+
+        let video = &batch.images[1];
+        assert_eq!(video.failure, None);
+        let detections = video.detections.as_ref().unwrap();
+        assert_eq!(detections.len(), 2);
+        assert_eq!(detections[0].frame_number, Some(10));
+
+        assert_eq!(batch.images[2].file, "b/c.jpg");
+        assert_eq!(batch.images[2].failure.as_deref(), Some("Failed to decode"));
+        assert_eq!(batch.images[2].detections, None);
+
+        let mut frames = frames;
+        frames[3].bboxes.as_mut().unwrap()[1].label = Some("Deer".to_string());
+        let coco = coco_dataset(&frames, Path::new("/run"), |path| {
+            (!path.ends_with("a.jpg")).then_some((1000, 500))
+        });
+        assert_eq!(coco.categories.len(), 4);
+        assert_eq!(coco.categories[3].name, "Deer");
+        // a.jpg has no size, the failed frames are left out
+        assert_eq!(coco.images.len(), 2);
+        assert_eq!(coco.images[1].file_name, "v_10.jpg");
+        assert_eq!(coco.images[1].video.as_deref(), Some("v.mp4"));
+        assert_eq!(coco.annotations.len(), 2);
+        assert_eq!(coco.annotations[0].image_id, 2);
+        assert_eq!(coco.annotations[0].category_id, 2);
+        assert_eq!(coco.annotations[0].bbox, [100, 100, 300, 200]);
+        assert_eq!(coco.annotations[0].area, 60000);
+        assert_eq!(coco.annotations[1].category_id, 4);
+    }
+
+    #[test]
+    fn test_checkpointer() {
+        let start = Instant::now();
+        let options = CheckpointOptions {
+            frames: 3,
+            interval: Duration::from_secs(300),
+            bytes: 1000,
+        };
+        let mut checkpointer = Checkpointer::new(options, start);
+        let due: Vec<bool> = (0..4).map(|_| checkpointer.record(10, start)).collect();
+        assert_eq!(due, [false, false, true, false]);
+        // a sparse run checkpoints once the interval passed
+        assert!(checkpointer.record(10, start + Duration::from_secs(301)));
+        assert!(!checkpointer.record(10, start + Duration::from_secs(302)));
+        // as does one with large results
+        assert!(checkpointer.record(1000, start + Duration::from_secs(303)));
+
+        let frames_only = CheckpointOptions {
+            interval: Duration::ZERO,
+            bytes: 0,
+            ..options
+        };
+        let mut checkpointer = Checkpointer::new(frames_only, start);
+        assert!(!checkpointer.record(1 << 20, start + Duration::from_secs(3600)));
+    }
+
+    #[test]
+    fn test_write_atomic() {
+        let dir = testing::temp_dir();
+        let path = dir.join("result.json");
+        write_atomic(&path, |file| Ok(file.write_all(b"first")?)).unwrap();
+        // a checkpoint failing halfway keeps the one before
+        let failed = write_atomic(&path, |file| {
+            file.write_all(b"sec")?;
+            Err(anyhow!("disk full"))
+        });
+        assert!(failed.is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "first");
+        assert!(!dir.join("result.json.tmp").exists());
+        assert!(is_writable(&dir));
+        assert!(!is_writable(&dir.join("unmounted")));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_compressed_export() {
+        let dir = testing::temp_dir();
+        let frames: Vec<ExportFrame> = (0..3)
+            .map(|i| ExportFrame {
+                latitude: (i == 1).then_some(22.53113),
+                longitude: (i == 1).then_some(-114.02148),
+                camera: CameraInfo {
+                    make: Some("Browning".to_string()),
+                    model: (i == 2).then(|| "BTC-8E".to_string()),
+                    serial_number: (i == 2).then(|| "E1234".to_string()),
+                },
+                ..testing::frame(dir.join(format!("{}.jpg", i)))
+            })
+            .collect();
+        let name = result_file_name(ExportFormat::Csv, ExportCompression::Gzip);
+        assert_eq!(name, "result.csv.gz");
+        assert!(is_result_file_name(&name));
+        assert!(!is_result_file_name("result.public.json.zst"));
+        assert_eq!(
+            export_extension(Path::new("result.json.zst")),
+            (Some("json"), ExportCompression::Zstd)
+        );
+
+        for format in [ExportFormat::Json, ExportFormat::Csv] {
+            for compression in [ExportCompression::Gzip, ExportCompression::Zstd] {
+                let path = dir.join(result_file_name(format, compression));
+                save_export(&path, &frames).unwrap();
+                let magic = &std::fs::read(&path).unwrap()[..2];
+                match compression {
+                    ExportCompression::Gzip => assert_eq!(magic, [0x1f, 0x8b]),
+                    _ => assert_eq!(magic, [0x28, 0xb5]),
+                }
+                let loaded = load_export(&path).unwrap();
+                let paths: Vec<&PathBuf> = loaded.iter().map(|f| &f.file.file_path).collect();
+                assert_eq!(
+                    paths,
+                    frames.iter().map(|f| &f.file.file_path).collect::<Vec<_>>()
+                );
+                assert_eq!(loaded[0].latitude, None);
+                assert_eq!(
+                    (loaded[1].latitude, loaded[1].longitude),
+                    (Some(22.53113), Some(-114.02148))
+                );
+                assert_eq!(loaded[0].camera.model, None);
+                assert_eq!(loaded[2].camera, frames[2].camera);
+            }
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_csv_round_trip() {
+        // annotation import saves a csv result over itself, so every column the
+        // parser reads has to be written back
+        let dir = testing::temp_dir();
+        let frames = vec![
+            ExportFrame {
+                frame_index: 4,
+                total_frames: 10,
+                iframe: true,
+                bboxes: Some(vec![testing::bbox(0, 0.9)]),
+                label: Some(vec!["Leopard".to_string()]),
+                verified: true,
+                encode_fallback: Some(EncodeFallback::Rgb8),
+                ..testing::frame(dir.join("a.mp4"))
+            },
+            testing::frame(dir.join("b.jpg")),
+        ];
+        let path = dir.join("result.csv");
+        save_export(&path, &frames).unwrap();
+        let loaded = load_export(&path).unwrap();
+        assert_eq!(
+            loaded
+                .iter()
+                .map(|f| (f.frame_index, f.total_frames, f.iframe, f.verified))
+                .collect::<Vec<_>>(),
+            [(4, 10, true, true), (0, 1, false, false)]
+        );
+        assert_eq!(loaded[0].label, frames[0].label);
+        assert_eq!(loaded[0].bboxes.as_ref().unwrap()[0].score, 0.9);
+        assert_eq!(
+            loaded.iter().map(|f| f.encode_fallback).collect::<Vec<_>>(),
+            [Some(EncodeFallback::Rgb8), None]
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
 }
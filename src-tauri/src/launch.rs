@@ -0,0 +1,116 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use url::Url;
+
+pub const DEEP_LINK_SCHEME: &str = "megascops";
+/// Command line flag naming a folder to process, may be repeated.
+pub const PROCESS_ARG: &str = "--process";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum LaunchSource {
+    DeepLink,
+    CommandLine,
+}
+
+/// Folders another tool asked to process. Nothing starts until the user confirms, the
+/// frontend does so by queueing the folders.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LaunchRequest {
+    pub source: LaunchSource,
+    pub folders: Vec<PathBuf>,
+}
+
+/// Parses `megascops://process?folder=...`, the folder parameter may be repeated.
+pub fn parse_deep_link(url: &Url) -> Result<LaunchRequest> {
+    if url.scheme() != DEEP_LINK_SCHEME {
+        return Err(anyhow!("Unsupported link: {}", url));
+    }
+    // `megascops://process` parses with `process` as the host, `megascops:process` as the path
+    let action = url
+        .host_str()
+        .unwrap_or_else(|| url.path())
+        .trim_matches('/');
+    if action != "process" {
+        return Err(anyhow!("Unsupported link action: {}", action));
+    }
+    let folders: Vec<PathBuf> = url
+        .query_pairs()
+        .filter(|(key, _)| key == "folder")
+        .map(|(_, folder)| PathBuf::from(folder.as_ref()))
+        .collect();
+    if folders.is_empty() {
+        return Err(anyhow!("Link has no folder to process: {}", url));
+    }
+    Ok(LaunchRequest {
+        source: LaunchSource::DeepLink,
+        folders,
+    })
+}
+
+/// Reads `--process <folder>` from the arguments, without the program name. Relative
+/// folders resolve against `cwd`, the directory the app was launched from. Deep links
+/// passed as arguments are left to the deep link handler.
+pub fn parse_args<I, S>(args: I, cwd: &Path) -> Option<LaunchRequest>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    let mut folders = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let arg = arg.as_ref();
+        if let Some(folder) = arg.strip_prefix("--process=") {
+            folders.push(cwd.join(folder));
+        } else if arg == PROCESS_ARG {
+            if let Some(folder) = args.next() {
+                folders.push(cwd.join(folder.as_ref()));
+            }
+        }
+    }
+    if folders.is_empty() {
+        None
+    } else {
+        Some(LaunchRequest {
+            source: LaunchSource::CommandLine,
+            folders,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_launch() {
+        let url =
+            Url::parse("megascops://process?folder=%2Fdata%2Fsite%201&folder=/data/site2").unwrap();
+        let request = parse_deep_link(&url).unwrap();
+        assert_eq!(
+            request.folders,
+            [PathBuf::from("/data/site 1"), PathBuf::from("/data/site2")]
+        );
+        assert!(parse_deep_link(&Url::parse("megascops://delete?folder=/data").unwrap()).is_err());
+        assert!(parse_deep_link(&Url::parse("megascops://process").unwrap()).is_err());
+
+        let cwd = Path::new("/home/user");
+        let request = parse_args(
+            ["--process", "cards", "--process=/data/site2", "--verbose"],
+            cwd,
+        )
+        .unwrap();
+        assert_eq!(request.source, LaunchSource::CommandLine);
+        assert_eq!(
+            request.folders,
+            [
+                PathBuf::from("/home/user/cards"),
+                PathBuf::from("/data/site2")
+            ]
+        );
+        assert!(parse_args(["megascops://process?folder=/data"], cwd).is_none());
+    }
+}
@@ -8,39 +8,161 @@ use anyhow::{Context, Result};
 use crossbeam_channel::{bounded, unbounded};
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
-use tauri::{AppHandle, Emitter};
+use tauri::{AppHandle, Emitter, Manager, WebviewUrl, WebviewWindowBuilder};
+use tauri_plugin_deep_link::DeepLinkExt;
 use tauri_plugin_store::StoreExt;
-use tonic::{
-    transport::{Certificate, Channel, ClientTlsConfig},
-    Request,
-};
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+use tokio_stream::{StreamExt, StreamMap};
+use tokio_util::sync::CancellationToken;
 use url::Url;
 use uuid::Uuid;
 
-use md5rs::md5rs_client::Md5rsClient;
-use md5rs::{AuthRequest, AuthResponse, DetectRequest, HealthRequest};
+use md5rs::{AuthResponse, DetectRequest, DetectResponse};
+use md5rs_client::Client;
 
-pub mod md5rs {
-    tonic::include_proto!("md5rs");
-}
+pub use md5rs_client::{md5rs, ConnectOptions, StreamCompression};
 
+pub mod agreement;
+pub mod annotate;
+pub mod annotation;
+pub mod announcement;
+pub mod anonymize;
+pub mod background;
+pub mod burst;
+pub mod chips;
+pub mod cluster;
+pub mod completion;
+pub mod contact_sheet;
+pub mod context_menu;
+pub mod daemon;
+pub mod darwin_core;
+pub mod diff;
+pub mod embedding;
+pub mod encode;
+pub mod endpoints;
+pub mod events;
 pub mod export;
 pub mod io;
+pub mod launch;
+pub mod live;
+pub mod local;
+pub mod manifest;
 pub mod media;
+pub mod metadata;
+pub mod organize;
+pub mod overlay;
+pub mod partial;
+pub mod policy;
+pub mod post_run;
+pub mod prefilter;
+pub mod priority;
+pub mod protocol;
+pub mod queue;
+pub mod quota;
+pub mod reid;
+pub mod remote;
+pub mod report;
+pub mod review;
+pub mod share;
+pub mod shrink;
+pub mod storage;
+pub mod template;
+pub mod throttle;
+pub mod timestamps;
+pub mod tokens;
+pub mod triage;
+pub mod unacked;
+pub mod usage;
 pub mod utils;
+pub mod viewer;
+pub mod watch;
+pub mod yolo;
 
-pub use export::{export_worker, parse_export_csv, Bbox, ExportFrame};
-pub use media::{media_worker, WebpItem};
-pub use utils::FileItem;
+pub use burst::BurstMode;
+pub use events::{EventSink, ProgressCounter, ProgressSink};
+pub use export::{
+    export_worker, load_export, parse_export_csv, Bbox, BboxSpace, BlankSkip, ExportFrame,
+    ExportOptions,
+};
+pub use media::{media_worker, BlankFilters, WebpItem};
+pub use post_run::PostRunAction;
+pub use template::OutputTemplates;
+pub use utils::{FileItem, IndexOptions, IndexProgress};
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct DetectOptions {
     pub selected_folder: String,
     pub grpc_url: String,
+    /// Further servers, failed over to in order when the one in use goes down.
+    #[serde(default)]
+    pub grpc_urls: Vec<String>,
+    /// Send to all healthy servers at once instead of only failing over.
+    #[serde(default)]
+    pub load_balance: bool,
     pub access_token: String,
     pub resume_path: Option<String>,
     pub guess: bool,
+    /// Files and folders below the selected folder to process, all of it when empty.
+    #[serde(default)]
+    pub paths: Vec<String>,
+    /// Further tokens, each used once the quota of the one before is exhausted.
+    #[serde(default)]
+    pub access_tokens: Vec<String>,
+    #[serde(default)]
+    pub backend: InferenceBackend,
+    /// ONNX detector model run by the local backend.
+    #[serde(default)]
+    pub local_model: Option<String>,
+    #[serde(flatten)]
+    pub connect: ConnectOptions,
+}
+
+/// Where frames are detected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum InferenceBackend {
+    #[default]
+    Server,
+    /// A detector model run with ONNX Runtime on this machine, for working offline.
+    Local,
+}
+
+/// The server session or the local detector frames are sent to.
+enum Inference {
+    Server(Client),
+    Local(Arc<local::LocalDetector>),
+}
+
+/// A further server frames are spread over when load balancing, with its own session.
+struct Peer {
+    url: String,
+    client: Client,
+    token: String,
+}
+
+/// Responses of the server streams or of the local detector.
+enum Inbound {
+    /// Stream 0 is the server in use, the others those of the peers after it.
+    Server(StreamMap<usize, tonic::Streaming<DetectResponse>>),
+    Local(mpsc::Receiver<DetectResponse>),
+}
+
+impl Inbound {
+    /// The next response, or the error with the stream it ended.
+    async fn message(
+        &mut self,
+    ) -> std::result::Result<Option<DetectResponse>, (usize, tonic::Status)> {
+        match self {
+            Inbound::Server(streams) => match streams.next().await {
+                Some((_, Ok(response))) => Ok(Some(response)),
+                Some((stream, Err(status))) => Err((stream, status)),
+                None => Ok(None),
+            },
+            Inbound::Local(responses) => Ok(responses.recv().await),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -52,9 +174,216 @@ pub struct ConfigOptions {
     pub export_format: ExportFormat,
     pub max_frames: Option<usize>,
     pub iframe_only: bool,
+    /// How frames are sampled from videos, as `iframe_only` and `max_frames` say when
+    /// unset.
+    #[serde(default)]
+    pub frame_sampling: Option<media::FrameSampling>,
     pub check_point: usize,
     pub buffer_path: Option<String>,
     pub buffer_size: usize,
+    #[serde(default)]
+    pub follow_links: bool,
+    #[serde(default = "default_true")]
+    pub skip_hidden: bool,
+    #[serde(default)]
+    pub relative_paths: bool,
+    #[serde(default)]
+    pub post_run_action: PostRunAction,
+    #[serde(default)]
+    pub output_templates: OutputTemplates,
+    #[serde(default)]
+    pub burst_mode: BurstMode,
+    #[serde(default = "default_burst_gap")]
+    pub burst_gap: f32,
+    #[serde(default = "default_burst_size")]
+    pub burst_size: usize,
+    /// ONNX model used to skip near-certain blanks before upload.
+    #[serde(default)]
+    pub prefilter_model: Option<String>,
+    #[serde(default = "default_prefilter_threshold")]
+    pub prefilter_threshold: f32,
+    /// Skip frames that barely differ from a running median background of their folder.
+    #[serde(default)]
+    pub background_model: bool,
+    #[serde(default = "default_background_threshold")]
+    pub background_threshold: f32,
+    /// Ask the server for feature embeddings and store them in `embeddings.db`.
+    #[serde(default)]
+    pub export_embeddings: bool,
+    #[serde(default)]
+    pub reid: Option<reid::ReidOptions>,
+    /// JSON feed of server announcements, also used to refuse runs of outdated clients.
+    #[serde(default)]
+    pub announcement_url: Option<String>,
+    /// Public exports with people and identifying details removed, per format.
+    #[serde(default)]
+    pub anonymize: anonymize::AnonymizeOptions,
+    /// Fewer media workers on battery, a pause when the CPU runs hot.
+    #[serde(default)]
+    pub throttle: throttle::ThrottleOptions,
+    /// Decode and copy at a low CPU and I/O priority so the machine stays usable.
+    #[serde(default)]
+    pub low_priority: bool,
+    /// Write copies of the positives with their boxes drawn into `annotated/`.
+    #[serde(default)]
+    pub annotate_images: bool,
+    /// Send images already at or below the model size as they are when the server takes
+    /// their codec, skipping decode and re-encode. Only applies without blank filters.
+    #[serde(default)]
+    pub passthrough: bool,
+    /// Cut the detections out of the originals into `crops/` after the run.
+    #[serde(default)]
+    pub export_crops: Option<chips::ChipOptions>,
+    /// Whether the organize post-run action moves the files or copies them.
+    #[serde(default)]
+    pub organize_mode: organize::OrganizeMode,
+    /// WebP effort from 0, fastest, to 6, smallest. Unset, video frames get a faster one
+    /// than images.
+    #[serde(default)]
+    pub webp_method: Option<u8>,
+    /// Bytes a WebP frame aims for instead of the quality, 0 to use the quality.
+    #[serde(default)]
+    pub webp_target_size: usize,
+    #[serde(default)]
+    pub webp_sharp_yuv: bool,
+    /// Send frames as AVIF instead of WebP when the server decodes it, less upload for
+    /// more encoding time.
+    #[serde(default)]
+    pub avif: bool,
+    /// Small frames packed into one message when the server takes batches, 0 or 1 sends
+    /// every frame alone.
+    #[serde(default)]
+    pub batch_frames: usize,
+    /// Reconnects after a dropped stream before the run gives up and exports what came
+    /// back, 0 stops at the first error.
+    #[serde(default = "default_stream_retries")]
+    pub stream_retries: u32,
+    /// Seconds after which results are checkpointed however few frames came in, 0 only
+    /// checkpoints every `check_point` frames.
+    #[serde(default = "default_checkpoint_interval")]
+    pub checkpoint_interval: u64,
+    /// Result bytes that make a checkpoint due early, 0 for no limit.
+    #[serde(default)]
+    pub checkpoint_bytes: usize,
+    /// Compression of the json and csv results, which can grow to gigabytes.
+    #[serde(default)]
+    pub export_compression: export::ExportCompression,
+    /// Compression of the messages on the detect stream.
+    #[serde(default)]
+    pub stream_compression: StreamCompression,
+    /// What of the generated files, spools and logs is kept when cleaning up.
+    #[serde(default)]
+    pub retention: storage::RetentionPolicy,
+    /// Folder the results are written to, the selected folder when unset.
+    #[serde(default)]
+    pub export_folder: Option<String>,
+    /// Local folder the results go to when the export folder can't be written, such as a
+    /// read-only network share. Each selected folder gets a folder of its name in it.
+    #[serde(default)]
+    pub export_fallback: Option<String>,
+    /// Seconds after which the server session is renewed during a run, 0 only renews it
+    /// once the server refuses it.
+    #[serde(default)]
+    pub session_refresh: u64,
+    /// Stop sending once the quota of the access tokens is spent and end the run as
+    /// exhausted, instead of having the server refuse the frames.
+    #[serde(default)]
+    pub stop_at_quota: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_burst_gap() -> f32 {
+    2.0
+}
+
+fn default_burst_size() -> usize {
+    10
+}
+
+fn default_prefilter_threshold() -> f32 {
+    0.05
+}
+
+fn default_background_threshold() -> f32 {
+    0.005
+}
+
+fn default_stream_retries() -> u32 {
+    5
+}
+
+fn default_checkpoint_interval() -> u64 {
+    300
+}
+
+impl ConfigOptions {
+    pub fn index_options(&self) -> IndexOptions {
+        IndexOptions {
+            follow_links: self.follow_links,
+            skip_hidden: self.skip_hidden,
+            ..Default::default()
+        }
+    }
+
+    pub fn export_options(&self) -> ExportOptions {
+        ExportOptions {
+            format: self.export_format,
+            relative_paths: self.relative_paths,
+            anonymize: self.anonymize.clone(),
+            compression: self.export_compression,
+            destination: non_empty(&self.export_folder).map(PathBuf::from),
+        }
+    }
+
+    pub fn checkpoint_options(&self) -> export::CheckpointOptions {
+        export::CheckpointOptions {
+            frames: self.check_point,
+            interval: Duration::from_secs(self.checkpoint_interval),
+            bytes: self.checkpoint_bytes,
+        }
+    }
+
+    pub fn frame_sampling(&self) -> media::FrameSampling {
+        self.frame_sampling.unwrap_or(media::FrameSampling::legacy(
+            self.iframe_only,
+            self.max_frames,
+        ))
+    }
+
+    pub fn webp_options(&self) -> media::WebpOptions {
+        media::WebpOptions {
+            avif: false,
+            quality: self.quality,
+            method: self.webp_method,
+            target_size: self.webp_target_size,
+            sharp_yuv: self.webp_sharp_yuv,
+            jpeg_fallback: false,
+        }
+    }
+
+    pub fn organize_options(&self) -> organize::OrganizeOptions {
+        organize::OrganizeOptions {
+            mode: self.organize_mode,
+            template: self.output_templates.organize.clone(),
+        }
+    }
+
+    /// Crop options of a run, laid out by the crops output template.
+    pub fn chip_options(&self) -> Option<chips::ChipOptions> {
+        self.export_crops.clone().map(|options| chips::ChipOptions {
+            template: self.output_templates.crops.clone(),
+            ..options
+        })
+    }
+
+    /// Previews template of the annotated copies of a run, `None` when they aren't written.
+    pub fn annotate_template(&self) -> Option<String> {
+        self.annotate_images
+            .then(|| self.output_templates.previews.clone())
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -64,57 +393,184 @@ pub struct Config {
     pub config_options: ConfigOptions,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
-pub enum ExportFormat {
-    Json,
-    Csv,
+impl DetectOptions {
+    /// Whether the run appends a batch of files to an existing result, as the batches of
+    /// a watched folder do.
+    pub fn is_incremental(&self) -> bool {
+        self.resume_path
+            .as_deref()
+            .is_some_and(|p| !p.trim().is_empty())
+            && !self.paths.is_empty()
+    }
 }
 
-async fn create_grpc_client(grpc_url: &str) -> Result<Channel> {
-    let url = Url::parse(grpc_url)?;
-
-    // 创建 channel builder
-    let mut channel_builder = Channel::from_shared(url.to_string()).context("Invalid URL")?;
-
-    // 仅在 HTTPS 时应用 TLS 配置
-    if url.scheme() == "https" {
-        let host = url
-            .host_str()
-            .ok_or_else(|| anyhow::anyhow!("Missing host in URL"))?;
-
-        // 检查主机是否为 IP 地址
-        let is_ip_addr = host.parse::<std::net::IpAddr>().is_ok();
-
-        // 获取 TLS 证书并配置 TLS
-        let pem = utils::get_tls_certificate(grpc_url)?;
-        let ca = Certificate::from_pem(pem);
+impl Config {
+    /// Folder the result files of the run go to.
+    pub fn result_folder(&self) -> PathBuf {
+        match non_empty(&self.config_options.export_folder) {
+            Some(folder) => PathBuf::from(folder),
+            None => PathBuf::from(&self.detect_options.selected_folder),
+        }
+    }
+}
 
-        // 对 IP 地址可能需要特殊处理域名验证
-        let tls = if is_ip_addr {
-            ClientTlsConfig::new().ca_certificate(ca).domain_name(host) // 仍然需要 SNI
-        } else {
-            ClientTlsConfig::new().ca_certificate(ca).domain_name(host)
-        };
+fn non_empty(path: &Option<String>) -> Option<&str> {
+    path.as_deref().map(str::trim).filter(|p| !p.is_empty())
+}
 
-        channel_builder = channel_builder
-            .tls_config(tls)
-            .context("Failed to configure TLS")?;
+/// Makes sure the results can be written before hours of detection. When the export
+/// folder can't be, the run exports to the fallback folder and says so with an
+/// "export-fallback" event.
+fn preflight_export(config: &mut Config, sink: &dyn EventSink) -> Result<()> {
+    let folder = config.result_folder();
+    if export::is_writable(&folder) {
+        return Ok(());
     }
+    let Some(fallback) = non_empty(&config.config_options.export_fallback) else {
+        return Err(anyhow::anyhow!(
+            "Results can't be written to {}",
+            folder.display()
+        ));
+    };
+    let name = folder
+        .file_name()
+        .map_or_else(|| "results".into(), |name| name.to_os_string());
+    let fallback = Path::new(fallback).join(name);
+    std::fs::create_dir_all(&fallback)
+        .with_context(|| format!("Failed to create {}", fallback.display()))?;
+    if !export::is_writable(&fallback) {
+        return Err(anyhow::anyhow!(
+            "Results can't be written to {} nor to {}",
+            folder.display(),
+            fallback.display()
+        ));
+    }
+    log::warn!(
+        "{} is not writable, exporting to {}",
+        folder.display(),
+        fallback.display()
+    );
+    sink.emit(
+        "export-fallback",
+        serde_json::json!({ "folder": folder, "fallback": fallback }),
+    );
+    config.config_options.export_folder = Some(fallback.to_string_lossy().into_owned());
+    Ok(())
+}
 
-    // 连接到服务器
-    channel_builder
-        .connect()
-        .await
-        .context("Failed to connect to server")
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ExportFormat {
+    Json,
+    Csv,
+    /// MegaDetector batch JSON, for Timelapse and EcoAssist.
+    MegaDetector,
+    /// COCO annotations, for annotation tools and fine-tuning.
+    Coco,
 }
 
-async fn process(config: Config, progress_sender: crossbeam_channel::Sender<usize>) -> Result<()> {
-    let channel = create_grpc_client(&config.detect_options.grpc_url).await?;
+/// How often a run following new files checks whether it was cancelled.
+const FOLLOW_POLL: Duration = Duration::from_millis(500);
+
+/// New files a run keeps taking after its walk, one session for a stream of snapshots.
+#[derive(Clone)]
+pub(crate) struct Follow {
+    /// Files to detect on, the run ends once every sender is gone.
+    pub files: crossbeam_channel::Receiver<PathBuf>,
+    /// Every exported frame as it comes back.
+    pub frames: crossbeam_channel::Sender<ExportFrame>,
+    /// Frames the result keeps, older ones are dropped from it. 0 for no limit.
+    pub keep: usize,
+}
 
-    let mut client = Md5rsClient::new(channel);
-    let auth_response = auth(&mut client, &config.detect_options.access_token).await?;
+async fn process(
+    config: Config,
+    progress: ProgressCounter,
+    index_sender: crossbeam_channel::Sender<IndexProgress>,
+    sink: Arc<dyn EventSink>,
+    gate: Arc<throttle::Gate>,
+    cancel: CancellationToken,
+    follow: Option<Follow>,
+) -> Result<()> {
+    if let Some(url) = &config.config_options.announcement_url {
+        let url = url.trim().to_string();
+        tokio::task::spawn_blocking(move || check_client_version(&url)).await??;
+    }
 
-    let session_token = auth_response.token;
+    let mut token_pool = tokens::TokenPool::new(
+        &config.detect_options.access_token,
+        &config.detect_options.access_tokens,
+    );
+    let connect = config.detect_options.connect.clone();
+    let stream_compression = config.config_options.stream_compression;
+    let mut servers = endpoints::Endpoints::new(
+        &config.detect_options.grpc_url,
+        &config.detect_options.grpc_urls,
+    );
+    let mut peers = Vec::new();
+    let mut quota = None;
+    // quota of every access token, to set the frames of the run against once indexed
+    let mut total_quota = None;
+    let (mut inference, mut session_token, image_limit, image_codecs, batch_size) =
+        match config.detect_options.backend {
+            InferenceBackend::Server => {
+                let (mut client, server) =
+                    connect_healthy(&mut servers, &connect, stream_compression).await?;
+                let token = token_pool.current().unwrap_or_default().to_string();
+                let auth_response = client.auth(&token).await?;
+                quota = Some(auth_response.quota);
+                if !config.detect_options.is_incremental() {
+                    total_quota =
+                        Some(token_quota(&mut client, &token_pool, auth_response.quota).await);
+                }
+                let mut image_limit = server.image_limit();
+                let mut image_codecs = server.image_codecs();
+                let mut batch_size = server.batch_size(config.config_options.batch_frames);
+                if config.detect_options.load_balance {
+                    let others: Vec<String> = servers.others().map(str::to_string).collect();
+                    for url in others {
+                        match connect_peer(&url, &connect, stream_compression, &token).await {
+                            Ok((peer, server)) => {
+                                // frames have to suit every server they may go to
+                                image_limit = image_limit.min(server.image_limit());
+                                let codecs = server.image_codecs();
+                                image_codecs.retain(|c| codecs.contains(c));
+                                batch_size = batch_size
+                                    .min(server.batch_size(config.config_options.batch_frames));
+                                log::info!("Spreading frames over {} as well", url);
+                                peers.push(peer);
+                            }
+                            Err(e) => log::warn!("Leaving out server {}: {}", url, e),
+                        }
+                    }
+                }
+                (
+                    Inference::Server(client),
+                    auth_response.token,
+                    image_limit,
+                    image_codecs,
+                    batch_size,
+                )
+            }
+            InferenceBackend::Local => {
+                let model = config
+                    .detect_options
+                    .local_model
+                    .as_deref()
+                    .map(str::trim)
+                    .filter(|m| !m.is_empty())
+                    .ok_or_else(|| anyhow::anyhow!("No local detector model selected"))?;
+                let detector = local::LocalDetector::load(Path::new(model))
+                    .context("Failed to load local detector model")?;
+                // frames never leave the machine, so no message limit applies
+                (
+                    Inference::Local(Arc::new(detector)),
+                    String::new(),
+                    usize::MAX,
+                    protocol::ImageCodec::ALL.to_vec(),
+                    1,
+                )
+            }
+        };
 
     cleanup_buffer(&config.config_options.buffer_path)?;
 
@@ -123,252 +579,1096 @@ async fn process(config: Config, progress_sender: crossbeam_channel::Sender<usiz
         return Ok(());
     }
 
+    config.config_options.output_templates.validate()?;
+
     let folder_path = std::path::PathBuf::from(&config.detect_options.selected_folder);
     let folder_path = std::fs::canonicalize(folder_path)?;
+    let result_folder = match non_empty(&config.config_options.export_folder) {
+        Some(folder) => PathBuf::from(folder),
+        None => folder_path.clone(),
+    };
 
     let imgsz = 1280;
     let start = Instant::now();
 
-    let mut file_paths = utils::index_files_and_folders(&folder_path)?;
+    let prefilter = match &config.config_options.prefilter_model {
+        Some(model) if !model.trim().is_empty() => Some(Arc::new(
+            prefilter::PreFilter::load(Path::new(model.trim()))
+                .context("Failed to load pre-filter model")?,
+        )),
+        _ => None,
+    };
+    let background = if config.config_options.background_model {
+        Some(Arc::new(background::BackgroundModels::default()))
+    } else {
+        None
+    };
 
     let export_data = Arc::new(Mutex::new(Vec::new()));
     let frames = Arc::new(Mutex::new(HashMap::<String, ExportFrame>::new()));
 
-    let file_paths = match config.detect_options.resume_path {
-        Some(checkpoint_path) => {
-            let resume_path = &checkpoint_path.trim().to_string();
-            if resume_path != "" {
-                let all_files =
-                    resume_from_checkpoint(&resume_path, &mut file_paths, &export_data)?;
-                all_files.to_owned()
-            } else {
-                file_paths
-            }
+    let resume_path = config
+        .detect_options
+        .resume_path
+        .as_deref()
+        .map(str::trim)
+        .filter(|p| !p.is_empty());
+    let mut unacked = match resume_path {
+        Some(_) => unacked::load_unacked(&result_folder)?,
+        None => Vec::new(),
+    };
+    let finished_files = match resume_path {
+        Some(resume_path) => {
+            resume_from_checkpoint(resume_path, &folder_path, &export_data, &mut unacked)?
         }
-        None => file_paths,
+        None => HashSet::new(),
     };
 
-    let (media_q_s, media_q_r) = bounded(8);
-    let (io_q_s, io_q_r) = bounded(config.config_options.buffer_size);
-    let (export_q_s, export_q_r) = unbounded();
-    let checkpoint_counter = Arc::new(Mutex::new(0 as usize));
-    let progress_sender_clone = progress_sender.clone();
+    let mut embeddings = if config.config_options.export_embeddings {
+        Some(embedding::EmbeddingStore::open(
+            &result_folder.join(embedding::EMBEDDING_DB),
+        )?)
+    } else {
+        None
+    };
+
+    // every stage is a task of this set and ends once its input closes, `stop` makes the
+    // producers wind down early when the stream ends or the run is cancelled
+    let mut tasks: JoinSet<Result<()>> = JoinSet::new();
+    let stop = cancel.child_token();
+
+    // the walk feeds the pipeline directly so the first files are processed while indexing continues
+    let (file_q_s, file_q_r) = unbounded();
+    let mut index_options = config.config_options.index_options();
+    for path in &config.detect_options.paths {
+        index_options.include.push(std::fs::canonicalize(path)?);
+    }
+    let remaining_options = index_options.clone();
+    let index_folder = folder_path.clone();
+    let bursts = burst::BurstMap::default();
+    let index_bursts = Arc::clone(&bursts);
+    let index_stop = stop.clone();
+    let sampling = config.config_options.frame_sampling();
+    let follow_files = follow.as_ref().map(|f| f.files.clone());
+    // a followed run has no end to estimate the frames of
+    let total_quota = total_quota.filter(|_| follow_files.is_none());
+    tasks.spawn_blocking(move || {
+        let mut collapser = burst::BurstCollapser::new(
+            config.config_options.burst_mode,
+            config.config_options.burst_gap,
+            config.config_options.burst_size,
+            index_bursts,
+        );
+        let mut found = 0;
+        let (mut images, mut videos) = (0, quota::VideoFrames::default());
+        let mut send = |file: FileItem| {
+            found += 1;
+            if found % utils::INDEX_PROGRESS_BATCH == 0 {
+                let _ = index_sender.send(IndexProgress::Found(found));
+            }
+            if total_quota.is_some() {
+                if utils::is_video(&file.file_path) {
+                    videos.add(|| {
+                        let path = file.file_path.to_string_lossy();
+                        let info = megascops_media::probe_video(&path).ok()?;
+                        Some(sampling.expected_frames(&info))
+                    });
+                } else {
+                    images += 1;
+                }
+            }
+            let _ = file_q_s.send(file);
+        };
+        let mut walked = 0;
+        let result = utils::walk_files(&index_folder, &index_options, |file| {
+            walked += 1;
+            if index_stop.is_cancelled()
+                || finished_files.contains(&utils::portable_path(&file.file_path, None))
+            {
+                return;
+            }
+            collapser.push(file, &mut send);
+        });
+        collapser.finish(&mut send);
+        let result = result.inspect(|_| {
+            let Some(files) = follow_files else {
+                return;
+            };
+            while !index_stop.is_cancelled() {
+                match files.recv_timeout(FOLLOW_POLL) {
+                    Ok(path) => {
+                        send(FileItem::new(0, walked, path, None));
+                        walked += 1;
+                    }
+                    Err(crossbeam_channel::RecvTimeoutError::Timeout) => (),
+                    Err(crossbeam_channel::RecvTimeoutError::Disconnected) => break,
+                }
+            }
+        });
+        match result {
+            Ok(skipped_links) => {
+                let _ = index_sender.send(IndexProgress::Finished {
+                    total: found,
+                    skipped_links,
+                });
+                if let Some(quota) = total_quota {
+                    report_estimate(
+                        sink.as_ref(),
+                        quota::QuotaEstimate::new(images, &videos, quota),
+                    );
+                }
+                Ok(())
+            }
+            // files left out of the walk would be missing from a result that looks complete
+            Err(e) => {
+                log::error!("Indexing failed: {}", e);
+                let _ = index_sender.send(IndexProgress::Failed(e.to_string()));
+                index_stop.cancel();
+                Err(e.context("Indexing failed"))
+            }
+        }
+    });
+
+    let (media_q_s, media_q_r) = mpsc::channel::<WebpItem>(8);
+    let (export_q_s, mut export_q_r) = mpsc::unbounded_channel::<ExportFrame>();
+    let checkpoint = config.config_options.checkpoint_options();
 
-    let buffer_path = config.config_options.buffer_path.clone();
     let folder_path_clone = folder_path.clone();
     let export_data_clone = Arc::clone(&export_data);
-    let finish = Arc::new(Mutex::new(false));
-    let finish_clone = Arc::clone(&finish);
+    let export_options = config.config_options.export_options();
+    let export_options_clone = export_options.clone();
+    let export_progress = progress.clone();
+    let (followed_frames, keep) = match follow {
+        Some(follow) => (Some(follow.frames), follow.keep),
+        None => (None, 0),
+    };
 
-    thread::spawn(move || {
-        let export_data = Arc::clone(&export_data);
-        let folder_path = folder_path.clone();
-        let checkpoint_counter = Arc::clone(&checkpoint_counter);
+    tasks.spawn_blocking(move || {
+        let mut completion = completion::FileCompletion::default();
+        let frames = std::iter::from_fn(|| export_q_r.blocking_recv()).inspect(|frame| {
+            if let Some(result) = completion.add(frame) {
+                export_progress.file_complete(result);
+            }
+            if let Some(frames) = &followed_frames {
+                let _ = frames.send(frame.clone());
+            }
+        });
         export_worker(
-            config.config_options.check_point,
-            &checkpoint_counter,
-            &config.config_options.export_format,
+            checkpoint,
+            &export_options,
             &folder_path,
-            export_q_r,
+            frames,
             &export_data,
+            keep,
         );
-        let mut finish_lock = finish.lock().unwrap();
-        *finish_lock = true;
+        Ok(())
     });
 
-    if let Some(buffer_path) = buffer_path {
-        rayon::spawn(move || {
-            std::fs::create_dir_all(&buffer_path).unwrap();
-            let buffer_path = std::fs::canonicalize(buffer_path).unwrap();
-
-            let io_handle = thread::spawn(move || {
-                for file in file_paths.iter() {
-                    io::io_worker(&buffer_path, file, io_q_s.clone()).unwrap();
+    let low_priority = config.config_options.low_priority;
+    let media_files = match config.config_options.buffer_path.clone() {
+        Some(buffer_path) => {
+            let (io_q_s, io_q_r) = bounded(config.config_options.buffer_size);
+            let io_media_q_s = media_q_s.clone();
+            let io_progress = progress.clone();
+            let io_stop = stop.clone();
+            tasks.spawn_blocking(move || {
+                std::fs::create_dir_all(&buffer_path)?;
+                let buffer_path = std::fs::canonicalize(buffer_path)?;
+                let copy = || {
+                    for file in file_q_r.iter() {
+                        if io_stop.is_cancelled() {
+                            break;
+                        }
+                        // a file that can't be buffered is exported with its error, the rest go on
+                        if let Err(error) = io::io_worker(&buffer_path, &file, io_q_s.clone()) {
+                            log::error!("Failed to buffer {}: {}", file.file_path.display(), error);
+                            let err_file = WebpItem::ErrFile(media::ErrFile { file, error });
+                            if io_media_q_s.blocking_send(err_file).is_err() {
+                                break;
+                            }
+                            io_progress.add(1);
+                        }
+                    }
+                };
+                if low_priority {
+                    priority::run_lowered(copy);
+                } else {
+                    copy();
                 }
-                drop(io_q_s);
+                Ok(())
             });
+            io_q_r
+        }
+        None => file_q_r,
+    };
 
-            io_q_r.iter().par_bridge().for_each(|file| {
-                media_worker(
-                    file,
-                    imgsz,
-                    config.config_options.quality,
-                    config.config_options.iframe_only,
-                    config.config_options.max_frames,
-                    media_q_s.clone(),
-                    progress_sender_clone.clone(),
-                );
-            });
-            io_handle.join().unwrap();
-        });
+    let media_stop = stop.clone();
+    let outbound_gate = Arc::clone(&gate);
+    let mut webp = config.config_options.webp_options();
+    if config.config_options.avif {
+        webp.avif = image_codecs.contains(&protocol::ImageCodec::Avif);
+        if !webp.avif {
+            log::warn!("The server does not decode AVIF, sending WebP");
+        }
+    }
+    webp.jpeg_fallback = image_codecs.contains(&protocol::ImageCodec::Jpeg);
+    let passthrough = if config.config_options.passthrough {
+        image_codecs
+    } else {
+        Vec::new()
+    };
+    // the pool's threads are lowered for this run only and end with it
+    let low_pool = if low_priority {
+        Some(priority::low_priority_pool()?)
     } else {
-        rayon::spawn(move || {
-            file_paths.par_iter().for_each(|file| {
+        None
+    };
+    let encode_progress = progress.clone();
+    tasks.spawn_blocking(move || {
+        // encoding gets threads of its own, a slow encode must not stall decoding
+        let mut encoder = encode::EncodePool::with_defaults(low_priority, encode_progress);
+        // decoding is CPU bound and stays on the rayon pool
+        let decode = || {
+            media_files.iter().par_bridge().for_each(|file| {
+                let Some(_permit) = gate.acquire(&media_stop) else {
+                    return;
+                };
+                if media_stop.is_cancelled() {
+                    return;
+                }
                 media_worker(
-                    file.clone(),
+                    file,
                     imgsz,
-                    config.config_options.quality,
-                    config.config_options.iframe_only,
-                    config.config_options.max_frames,
+                    webp,
+                    config.config_options.frame_sampling(),
+                    BlankFilters {
+                        prefilter: prefilter.as_deref(),
+                        background: background.as_deref(),
+                    },
+                    &passthrough,
+                    &encoder,
                     media_q_s.clone(),
-                    progress_sender_clone.clone(),
+                    &progress,
                 );
-            });
-            drop(media_q_s);
-        });
-    }
+            })
+        };
+        match low_pool {
+            Some(pool) => pool.install(decode),
+            None => decode(),
+        }
+        encoder.finish();
+        Ok(())
+    });
 
-    let frames_clone = Arc::clone(&frames);
-    let export_q_s_clone = export_q_s.clone();
-    let outbound = async_stream::stream! {
-        while let Ok(item) = media_q_r.recv() {
-            match item {
-                WebpItem::Frame(frame) => {
-                    let uuid = Uuid::new_v4().to_string();
-                    let export_frame = ExportFrame {
-                        file: frame.file.clone(),
-                        frame_index: frame.frame_index,
-                        shoot_time: frame.shoot_time.map(|t| t.to_string()),
-                        total_frames: frame.total_frames,
-                        iframe: frame.iframe,
-                        bboxes: None,
-                        label: None,
-                        error: None,
-                    };
-                    frames_clone.lock().unwrap().insert(uuid.clone(), export_frame);
-                    yield DetectRequest { uuid, image: frame.webp, width: frame.width as i32, height: frame.height as i32, iou: config.config_options.iou_threshold, score: config.config_options.confidence_threshold, iframe:frame.iframe };
+    let payload = Arc::new(Mutex::new(protocol::PayloadStats::default()));
+    // requests sent but not answered yet, sent again when rolling over to the next token or
+    // reconnecting and kept for the next run when this one ends
+    let in_flight = Arc::new(Mutex::new(HashMap::<String, DetectRequest>::new()));
+    let media_q_r = Arc::new(tokio::sync::Mutex::new(media_q_r));
+    let prefilter_threshold = config.config_options.prefilter_threshold;
+    let background_threshold = config.config_options.background_threshold;
+    let quality = config.config_options.quality;
+    let iou_threshold = config.config_options.iou_threshold;
+    let confidence_threshold = config.config_options.confidence_threshold;
+    let export_embeddings = config.config_options.export_embeddings;
+    // what the last run sent without an answer goes out first, its files aren't decoded
+    for frame in unacked {
+        let uuid = Uuid::new_v4().to_string();
+        let request = frame.request(uuid.clone(), export_embeddings)?;
+        in_flight.lock().unwrap().insert(uuid.clone(), request);
+        frames.lock().unwrap().insert(uuid, frame.frame);
+    }
+    // requests sent again count against the quota of the token they go out with
+    let stop_at_quota = config.config_options.stop_at_quota;
+    let budget_of = move |quota: Option<i32>, resent: usize| {
+        quota
+            .filter(|_| stop_at_quota)
+            .map(|quota| quota as i64 - resent as i64)
+    };
+    let budget = Arc::new(quota::QuotaBudget::new(budget_of(
+        quota,
+        in_flight.lock().unwrap().len(),
+    )));
+    let outbound_budget = Arc::clone(&budget);
+    let outbound_frames = Arc::clone(&frames);
+    let outbound_export_q_s = export_q_s.clone();
+    let outbound_bursts = Arc::clone(&bursts);
+    let outbound_payload = Arc::clone(&payload);
+    let outbound_in_flight = Arc::clone(&in_flight);
+    // one stream per access token and server, each ends once its `attempt` is cancelled.
+    // Only the one of the server in use sends the unanswered requests again.
+    let outbound = move |attempt: CancellationToken, resend: bool| {
+        let media_q_r = Arc::clone(&media_q_r);
+        let payload_clone = Arc::clone(&outbound_payload);
+        let frames_clone = Arc::clone(&outbound_frames);
+        let export_q_s_clone = outbound_export_q_s.clone();
+        let bursts_clone = Arc::clone(&outbound_bursts);
+        let in_flight = Arc::clone(&outbound_in_flight);
+        let gate = Arc::clone(&outbound_gate);
+        let budget = Arc::clone(&outbound_budget);
+        async_stream::stream! {
+            let pending: Vec<DetectRequest> = if resend {
+                in_flight.lock().unwrap().values().cloned().collect()
+            } else {
+                Vec::new()
+            };
+            for request in pending {
+                yield request;
+            }
+            let mut packer = protocol::FramePacker::new(batch_size, image_limit);
+            loop {
+                // with the quota spent only what was sent is still answered
+                if budget.check() {
+                    if let Some(batch) = packer.flush() {
+                        yield batch;
+                    }
+                    break;
+                }
+                // a paused run keeps its session but sends nothing new
+                if gate.is_paused() {
+                    tokio::select! {
+                        _ = attempt.cancelled() => break,
+                        _ = gate.resumed() => (),
+                    }
                 }
-                WebpItem::ErrFile(file) => {
-                    export_q_s_clone.send(ExportFrame {
-                        file: file.file.clone(),
-                        frame_index: 0,
-                        shoot_time: None,
-                        total_frames: 0,
-                        iframe: false,
-                        bboxes: None,
-                        label: None,
-                        error: Some(file.error.to_string()),
-                    }).unwrap();
+                let item = if packer.is_empty() {
+                    tokio::select! {
+                        _ = attempt.cancelled() => break,
+                        item = async { media_q_r.lock().await.recv().await } => match item {
+                            Some(item) => item,
+                            None => break,
+                        },
+                    }
+                } else {
+                    // packed frames go out as soon as no further frame is ready
+                    match media_q_r.lock().await.try_recv() {
+                        Ok(item) => item,
+                        Err(_) => {
+                            if let Some(batch) = packer.flush() {
+                                yield batch;
+                            }
+                            continue;
+                        }
+                    }
+                };
+                match item {
+                    WebpItem::Frame(frame) => {
+                        let uuid = Uuid::new_v4().to_string();
+                        let mut export_frame = ExportFrame {
+                            file: frame.file.clone(),
+                            frame_index: frame.frame_index,
+                            shoot_time: frame.shoot_time.map(|t| t.to_string()),
+                            latitude: frame.position.map(|p| p.0),
+                            longitude: frame.position.map(|p| p.1),
+                            camera: frame.camera.clone(),
+                            total_frames: frame.total_frames,
+                            iframe: frame.iframe,
+                            bboxes: None,
+                            label: None,
+                            error: None,
+                            burst_source: None,
+                            prefilter_score: frame.prefilter_score,
+                            skipped_blank: None,
+                            encode_fallback: frame.fallback,
+                            token: None,
+                            verified: false,
+                        };
+                        if frame.prefilter_score.is_some_and(|s| s < prefilter_threshold) {
+                            export_frame.skipped_blank = Some(BlankSkip::Prefilter);
+                        } else if frame.foreground.is_some_and(|f| f < background_threshold) {
+                            export_frame.skipped_blank = Some(BlankSkip::Background);
+                        }
+                        if export_frame.skipped_blank.is_some() {
+                            // near-certain blank, keep it out of the upload but record why
+                            export_frame.bboxes = Some(Vec::new());
+                            export_frame.label = Some(vec!["Blank".to_string()]);
+                            if send_export(&export_q_s_clone, export_frame, &bursts_clone).is_err() {
+                                break;
+                            }
+                            continue;
+                        }
+                        let mut webp = frame.webp;
+                        let mut codec = frame.codec;
+                        if webp.len() > image_limit {
+                            // a message over the server limit would end the whole stream
+                            match media::shrink_webp(&webp, codec, image_limit, quality) {
+                                Ok(smaller) => {
+                                    log::warn!("Re-encoded {} from {} to {} bytes to fit the server message limit", frame.file.file_path.display(), webp.len(), smaller.len());
+                                    payload_clone.lock().unwrap().reencoded += 1;
+                                    webp = smaller;
+                                    codec = protocol::ImageCodec::Webp;
+                                }
+                                Err(e) => {
+                                    log::error!("Skipping frame of {}: {}", frame.file.file_path.display(), e);
+                                    payload_clone.lock().unwrap().oversized += 1;
+                                    export_frame.error = Some(e.to_string());
+                                    if send_export(&export_q_s_clone, export_frame, &bursts_clone).is_err() {
+                                        break;
+                                    }
+                                    continue;
+                                }
+                            }
+                        }
+                        payload_clone.lock().unwrap().record(webp.len());
+                        frames_clone.lock().unwrap().insert(uuid.clone(), export_frame);
+                        let policy = frame.file.policy.as_deref();
+                        let iou = policy.and_then(|p| p.iou_threshold).unwrap_or(iou_threshold);
+                        let score = policy.and_then(|p| p.confidence_threshold).unwrap_or(confidence_threshold);
+                        let request = DetectRequest { uuid: uuid.clone(), image: webp, width: frame.width as i32, height: frame.height as i32, iou, score, iframe:frame.iframe, embeddings: export_embeddings, codec: codec.request_codec(), batch: Vec::new() };
+                        in_flight.lock().unwrap().insert(uuid, request.clone());
+                        budget.spend();
+                        if let Some(message) = packer.push(request) {
+                            yield message;
+                        }
+                    }
+                    WebpItem::ErrFile(file) => {
+                        let frame = ExportFrame {
+                            file: file.file.clone(),
+                            frame_index: 0,
+                            shoot_time: None,
+                            latitude: None,
+                            longitude: None,
+                            camera: Default::default(),
+                            total_frames: 0,
+                            iframe: false,
+                            bboxes: None,
+                            label: None,
+                            error: Some(file.error.to_string()),
+                            burst_source: None,
+                            prefilter_score: None,
+                            skipped_blank: None,
+                            encode_fallback: None,
+                            token: None,
+                            verified: false,
+                        };
+                        if send_export(&export_q_s_clone, frame, &bursts_clone).is_err() {
+                            break;
+                        }
+                    }
                 }
             }
         }
     };
 
-    let mut request = Request::new(outbound);
-    request
-        .metadata_mut()
-        .insert("authorization", session_token.parse().unwrap());
-
-    let response = client.detect(request).await;
-    let mut inbound = match response {
-        Ok(response) => response.into_inner(),
-        Err(status) => {
-            log::error!("{}", status.message());
-            cleanup_buffer(&config.config_options.buffer_path)?;
-            return Ok(());
+    // detected frames go through the re-identification workers before export
+    let export_q_s = match config.config_options.reid.clone() {
+        Some(options) => {
+            let (reid_q_s, mut reid_q_r) = mpsc::unbounded_channel::<ExportFrame>();
+            let reidentifier = reid::HttpReidentifier::new(options.clone());
+            let pool = reid::reid_pool()?;
+            tasks.spawn_blocking(move || {
+                pool.install(|| {
+                    std::iter::from_fn(|| reid_q_r.blocking_recv())
+                        .par_bridge()
+                        .try_for_each(|mut frame| {
+                            reid::identify_frame(&mut frame, &reidentifier, &options);
+                            export_q_s.send(frame).map_err(|_| export_stopped())
+                        })
+                })
+            });
+            reid_q_s
         }
+        None => export_q_s,
     };
 
+    let mut backoff = md5rs_client::Backoff::new(
+        config.config_options.stream_retries,
+        md5rs_client::INITIAL_BACKOFF,
+        md5rs_client::MAX_BACKOFF,
+    );
+    // sessions expire on multi-day runs, they are renewed on a timer or once refused
+    let session_refresh = config.config_options.session_refresh;
+    let renew_after =
+        || (session_refresh > 0).then(|| Instant::now() + Duration::from_secs(session_refresh));
+    let mut renew_at = renew_after().filter(|_| matches!(inference, Inference::Server(_)));
+    let mut renewed = false;
+    let mut quota_exhausted;
+    // ends the run as failed once the results are exported
+    let mut failure = None;
     loop {
-        match inbound.message().await {
-            Ok(Some(response)) => {
-                let uuid = response.uuid.clone();
-                let mut frames = frames.lock().unwrap();
-                if let Some(mut frame) = frames.remove(&uuid) {
-                    frame.bboxes = Some(
-                        response
+        let attempt = stop.child_token();
+        quota_exhausted = false;
+        let mut renew = false;
+        let mut stream_error = None;
+        let mut inbound = match &mut inference {
+            Inference::Server(client) => {
+                let response = client
+                    .detect(outbound(attempt.clone(), true), &session_token)
+                    .await;
+                // the server answers RESOURCE_EXHAUSTED once the quota is used up
+                match response {
+                    Ok(stream) => {
+                        let mut streams = StreamMap::new();
+                        streams.insert(0, stream);
+                        // peers draw from the same queue, whichever is ready takes the next frame
+                        for (index, peer) in peers.iter_mut().enumerate() {
+                            let requests = outbound(attempt.clone(), false);
+                            match peer.client.detect(requests, &peer.token).await {
+                                Ok(stream) => {
+                                    streams.insert(index + 1, stream);
+                                }
+                                Err(status) => {
+                                    log::warn!("Failed to stream to {}: {}", peer.url, status)
+                                }
+                            }
+                        }
+                        Some(Inbound::Server(streams))
+                    }
+                    Err(status) if status.code() == tonic::Code::ResourceExhausted => {
+                        log::warn!("Quota exhausted: {}", status.message());
+                        quota_exhausted = true;
+                        None
+                    }
+                    Err(status) if status.code() == tonic::Code::Unauthenticated => {
+                        log::warn!("Session refused: {}", status.message());
+                        renew = true;
+                        None
+                    }
+                    Err(status) if md5rs_client::is_transient(&status) => {
+                        stream_error = Some((0, status));
+                        None
+                    }
+                    Err(status) => {
+                        log::error!("{}", status.message());
+                        // servers refuse outdated clients before streaming anything
+                        failure = Some(if status.code() == tonic::Code::FailedPrecondition {
+                            anyhow::anyhow!("{}", status.message())
+                        } else {
+                            anyhow::anyhow!("Detection failed: {}", status.message())
+                        });
+                        None
+                    }
+                }
+            }
+            Inference::Local(detector) => {
+                let (response_s, response_r) = mpsc::channel(8);
+                let requests = outbound(attempt.clone(), true);
+                let detector = Arc::clone(detector);
+                let local_frames = Arc::clone(&frames);
+                let local_in_flight = Arc::clone(&in_flight);
+                let local_bursts = Arc::clone(&bursts);
+                let local_export_q_s = export_q_s.clone();
+                tasks.spawn(async move {
+                    let mut requests = std::pin::pin!(requests);
+                    while let Some(request) = requests.next().await {
+                        let detector = Arc::clone(&detector);
+                        let uuid = request.uuid.clone();
+                        let detected =
+                            tokio::task::spawn_blocking(move || detector.detect(&request)).await?;
+                        match detected {
+                            Ok(response) => {
+                                if response_s.send(response).await.is_err() {
+                                    break;
+                                }
+                            }
+                            // a frame the detector fails on is exported with the error
+                            Err(e) => {
+                                local_in_flight.lock().unwrap().remove(&uuid);
+                                let frame = local_frames.lock().unwrap().remove(&uuid);
+                                if let Some(mut frame) = frame {
+                                    log::error!(
+                                        "Local detection of {} failed: {}",
+                                        frame.file.file_path.display(),
+                                        e
+                                    );
+                                    frame.error = Some(e.to_string());
+                                    send_export(&local_export_q_s, frame, &local_bursts)?;
+                                }
+                            }
+                        }
+                    }
+                    Ok(())
+                });
+                Some(Inbound::Local(response_r))
+            }
+        };
+
+        while let Some(stream) = inbound.as_mut() {
+            let message = tokio::select! {
+                _ = cancel.cancelled() => break,
+                // frames already sent are still answered before the streams close
+                _ = session_due(renew_at) => {
+                    log::info!("Renewing the session");
+                    renew = true;
+                    renew_at = None;
+                    attempt.cancel();
+                    continue;
+                }
+                message = stream.message() => message,
+            };
+            match message {
+                Ok(Some(response)) => {
+                    backoff.reset();
+                    renewed = false;
+                    let uuid = response.uuid.clone();
+                    in_flight.lock().unwrap().remove(&uuid);
+                    let mut frames = frames.lock().unwrap();
+                    if let Some(mut frame) = frames.remove(&uuid) {
+                        if matches!(inference, Inference::Server(_)) {
+                            token_pool.record();
+                            frame.token = Some(token_pool.label());
+                        }
+                        let (bboxes, bbox_embeddings): (Vec<_>, Vec<_>) = response
                             .bboxs
                             .into_iter()
-                            .map(|bbox| Bbox {
-                                x1: bbox.x1,
-                                y1: bbox.y1,
-                                x2: bbox.x2,
-                                y2: bbox.y2,
-                                class: bbox.class as usize,
-                                score: bbox.score,
+                            .map(|bbox| {
+                                let b = Bbox {
+                                    x1: bbox.x1,
+                                    y1: bbox.y1,
+                                    x2: bbox.x2,
+                                    y2: bbox.y2,
+                                    class: bbox.class as usize,
+                                    score: bbox.score,
+                                    individual: None,
+                                    label: None,
+                                    space: BboxSpace::Normalized,
+                                };
+                                (b, bbox.embedding)
                             })
-                            .collect(),
-                    );
-                    frame.label = Some(response.label);
-                    export_q_s.send(frame).unwrap();
+                            .unzip();
+                        frame.bboxes = Some(bboxes);
+                        frame.label = Some(response.label);
+                        if let Some(store) = embeddings.as_mut() {
+                            if let Err(e) =
+                                store.insert(&frame, &response.embedding, &bbox_embeddings)
+                            {
+                                log::error!("Failed to store embeddings: {}", e);
+                            }
+                        }
+                        if let Err(e) = send_export(&export_q_s, frame, &bursts) {
+                            failure = Some(e);
+                            break;
+                        }
+                    }
                 }
-            }
-            Ok(None) => {
-                drop(export_q_s);
-                while !*finish_clone.lock().unwrap() {
-                    thread::sleep(Duration::from_millis(100));
+                Ok(None) => break,
+                Err((_, status)) if status.code() == tonic::Code::ResourceExhausted => {
+                    log::warn!("Quota exhausted: {}", status.message());
+                    quota_exhausted = true;
+                    break;
+                }
+                Err((_, status)) if status.code() == tonic::Code::Unauthenticated => {
+                    log::warn!("Session refused: {}", status.message());
+                    renew = true;
+                    break;
                 }
-                export::export(
-                    &folder_path_clone,
-                    export_data_clone,
-                    &config.config_options.export_format,
-                )?;
-                cleanup_buffer(&config.config_options.buffer_path)?;
+                // a failing peer is left out, the others go on without it
+                Err((stream, status)) if stream > 0 || md5rs_client::is_transient(&status) => {
+                    stream_error = Some((stream, status));
+                    break;
+                }
+                Err((_, e)) => {
+                    log::error!("Error receiving detection: {}", e);
+                    failure = Some(anyhow::anyhow!("Error receiving detection: {}", e));
+                    break;
+                }
+            }
+        }
+
+        // ends the outbound stream of this token, unanswered requests go out again with the next
+        attempt.cancel();
+        drop(inbound);
+        if failure.is_some() {
+            break;
+        }
+        if budget.is_spent() && stream_error.is_none() && !renew {
+            log::info!(
+                "Stopping at the quota of access token {}",
+                token_pool.label()
+            );
+            quota_exhausted = true;
+        }
+        if renew && !cancel.is_cancelled() {
+            let Inference::Server(client) = &mut inference else {
+                break;
+            };
+            // a session refused again before anything came back won't get any further
+            if renewed {
+                log::error!("Server refused the renewed session");
+                failure = Some(anyhow::anyhow!("Server refused the renewed session"));
                 break;
             }
-            Err(e) => {
-                log::error!("Error receiving detection: {}", e);
-                drop(export_q_s);
-                while !*finish_clone.lock().unwrap() {
-                    thread::sleep(Duration::from_millis(100));
+            let token = token_pool.current().unwrap_or_default().to_string();
+            match client.auth(&token).await {
+                Ok(response) => session_token = response.token,
+                Err(e) => {
+                    log::error!("Failed to renew the session: {}", e);
+                    failure = Some(anyhow::anyhow!("Failed to renew the session: {}", e));
+                    break;
                 }
-                export::export(
-                    &folder_path_clone,
-                    export_data_clone,
-                    &config.config_options.export_format,
-                )?;
-                cleanup_buffer(&config.config_options.buffer_path)?;
+            }
+            reauth_peers(&mut peers, &token).await;
+            renewed = true;
+            renew_at = renew_after();
+            continue;
+        }
+        if let Some((stream, status)) = stream_error.filter(|_| !cancel.is_cancelled()) {
+            if stream > 0 {
+                let peer = peers.remove(stream - 1);
+                log::warn!("Leaving out server {}: {}", peer.url, status);
+                continue;
+            }
+            log::warn!("Detection stream failed: {}", status);
+            let Inference::Server(client) = &mut inference else {
                 break;
+            };
+            let token = token_pool.current().unwrap_or_default().to_string();
+            match reconnect(
+                &mut servers,
+                &connect,
+                stream_compression,
+                &token,
+                &mut backoff,
+                &cancel,
+            )
+            .await
+            {
+                Some((reconnected, token)) => {
+                    *client = reconnected;
+                    session_token = token;
+                    renew_at = renew_after();
+                    continue;
+                }
+                // what came back is still exported, the run ends as incomplete
+                None => {
+                    let e = if cancel.is_cancelled() {
+                        anyhow::anyhow!("Detection was cancelled while reconnecting")
+                    } else {
+                        anyhow::anyhow!("Gave up on the server after {} retries", backoff.retries())
+                    };
+                    log::error!("{}", e);
+                    failure = Some(e);
+                    break;
+                }
+            }
+        }
+        if !quota_exhausted || cancel.is_cancelled() {
+            break;
+        }
+        log::warn!("Quota of access token {} exhausted", token_pool.label());
+        let Inference::Server(client) = &mut inference else {
+            break;
+        };
+        match next_session(client, &mut token_pool).await {
+            Some(response) => {
+                session_token = response.token;
+                budget.reset(budget_of(
+                    Some(response.quota),
+                    in_flight.lock().unwrap().len(),
+                ));
             }
+            None => break,
         }
+        renew_at = renew_after();
+        // the peers go on with the same access token
+        let token = token_pool.current().unwrap_or_default().to_string();
+        reauth_peers(&mut peers, &token).await;
     }
+    for usage in token_pool.usage() {
+        log::info!(
+            "Access token {} processed {} frames",
+            usage.token,
+            usage.frames
+        );
+    }
+
+    // the export task ends once every sender is gone, the others once `stop` is seen
+    drop(export_q_s);
+    drop(outbound);
+    stop.cancel();
+    // a stage that failed ends the run as failed after the export, one that panicked may
+    // have left the results half written
+    let mut panicked = None;
+    while let Some(task) = tasks.join_next().await {
+        match task {
+            Ok(Ok(())) => (),
+            Ok(Err(e)) => {
+                failure.get_or_insert(e);
+            }
+            Err(e) => {
+                panicked.get_or_insert(e);
+            }
+        }
+    }
+    if let Some(e) = panicked {
+        return Err(e.into());
+    }
+    let unanswered: Vec<unacked::UnackedFrame> = {
+        let in_flight = in_flight.lock().unwrap();
+        let mut frames = frames.lock().unwrap();
+        in_flight
+            .iter()
+            .filter_map(|(uuid, request)| {
+                let frame = frames.remove(uuid)?;
+                Some(unacked::UnackedFrame::new(frame, request))
+            })
+            .collect()
+    };
+    if let Err(e) = unacked::save_unacked(&result_folder, &unanswered) {
+        log::error!("Failed to keep the unacknowledged frames: {}", e);
+    }
+    export::export(&folder_path_clone, export_data_clone, &export_options_clone)?;
+    cleanup_buffer(&config.config_options.buffer_path)?;
 
+    let payload = payload.lock().unwrap();
+    log::info!(
+        "Sent {} frames, {} bytes, average {} bytes, largest {} bytes, {} re-encoded, {} over the limit",
+        payload.frames,
+        payload.bytes,
+        payload.average(),
+        payload.largest,
+        payload.reencoded,
+        payload.oversized
+    );
     log::info!("Elapsed time: {:?}", start.elapsed());
+    if let Some(e) = failure {
+        return Err(e);
+    }
+    if quota_exhausted {
+        // the export above is the checkpoint, frames still in flight are sent again on resume
+        let frames = export_data_clone.lock().unwrap().clone();
+        let folder = folder_path_clone.clone();
+        let remaining = tokio::task::spawn_blocking(move || {
+            remaining_files(&folder, &remaining_options, &frames)
+        })
+        .await??;
+        return Err(protocol::QuotaExhausted {
+            remaining,
+            resume_path: result_folder.join(export::result_file_name(
+                export_options_clone.format,
+                export_options_clone.compression,
+            )),
+        }
+        .into());
+    }
+    if cancel.is_cancelled() {
+        return Err(anyhow::anyhow!("Detection cancelled"));
+    }
     Ok(())
 }
 
-async fn auth(client: &mut Md5rsClient<Channel>, token: &str) -> Result<AuthResponse> {
-    let response = client
-        .auth(Request::new(AuthRequest {
-            token: token.to_string(),
-        }))
-        .await?;
-    let auth_response = response.into_inner();
-    if auth_response.success {
-        Ok(auth_response)
-    } else {
-        Err(anyhow::anyhow!("Auth failed"))
-    }
+fn export_stopped() -> anyhow::Error {
+    anyhow::anyhow!("The export of the results stopped")
 }
 
-async fn get_auth(grpc_url: String, token: String) -> Result<i32> {
-    let channel = create_grpc_client(&grpc_url).await?;
-    let mut client = Md5rsClient::new(channel);
-
-    match auth(&mut client, &token).await {
-        Ok(response) => Ok(response.quota),
-        Err(_) => Err(anyhow::anyhow!("Auth failed")),
+/// Hands `frame` and the frames of its burst to the export task, failing once it ended.
+fn send_export(
+    export_q_s: &mpsc::UnboundedSender<ExportFrame>,
+    frame: ExportFrame,
+    bursts: &burst::BurstMap,
+) -> Result<()> {
+    for sibling in burst::sibling_frames(&frame, bursts) {
+        export_q_s.send(sibling).map_err(|_| export_stopped())?;
     }
+    export_q_s.send(frame).map_err(|_| export_stopped())
 }
 
-async fn health(client: &mut Md5rsClient<Channel>) -> Result<()> {
-    let response = client.health(Request::new(HealthRequest {})).await?;
-    let health_response = response.into_inner();
-    if health_response.status {
-        Ok(())
-    } else {
-        log::error!("Health check failed");
-        Err(anyhow::anyhow!("Check failed"))
+/// Quota left on every token of `pool`, `quota` being that of the current one. The
+/// others are authenticated on `client` as well.
+async fn token_quota(client: &mut Client, pool: &tokens::TokenPool, quota: i32) -> i64 {
+    let mut total = quota as i64;
+    for token in pool.remaining() {
+        match client.auth(token).await {
+            Ok(response) => total += response.quota as i64,
+            Err(e) => log::warn!(
+                "No quota for access token {}: {}",
+                tokens::mask_token(token),
+                e
+            ),
+        }
     }
+    total
 }
 
-async fn get_health(grpc_url: String) -> Result<bool> {
-    let channel = create_grpc_client(&grpc_url).await?;
-    let mut client = Md5rsClient::new(channel);
+/// Reports the frames the run is expected to send against its quota as `quota-estimate`.
+fn report_estimate(sink: &dyn EventSink, estimate: quota::QuotaEstimate) {
+    if !estimate.is_sufficient() {
+        log::warn!(
+            "About {} frames to send with a quota of {} left",
+            estimate.frames,
+            estimate.quota
+        );
+    }
+    sink.emit("quota-estimate", &estimate);
+}
 
-    match health(&mut client).await {
-        Ok(_) => Ok(true),
-        Err(_) => Ok(false),
+/// Authenticates the next usable token of `pool`, `None` once none is left.
+async fn next_session(client: &mut Client, pool: &mut tokens::TokenPool) -> Option<AuthResponse> {
+    while let Some(token) = pool.advance() {
+        match client.auth(&token).await {
+            Ok(response) => {
+                log::info!("Continuing with access token {}", pool.label());
+                return Some(response);
+            }
+            Err(e) => log::warn!("Skipping access token {}: {}", pool.label(), e),
+        }
+    }
+    None
+}
+
+/// Authenticates `token` on every peer again, leaving out those refusing it.
+async fn reauth_peers(peers: &mut Vec<Peer>, token: &str) {
+    let mut authenticated = Vec::new();
+    for mut peer in peers.drain(..) {
+        match peer.client.auth(token).await {
+            Ok(response) => {
+                peer.token = response.token;
+                authenticated.push(peer);
+            }
+            Err(e) => log::warn!("Leaving out server {}: {}", peer.url, e),
+        }
+    }
+    *peers = authenticated;
+}
+
+/// Resolves once `at` has passed, never without a deadline.
+async fn session_due(at: Option<Instant>) {
+    match at {
+        Some(at) => tokio::time::sleep_until(at.into()).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Connects to the first of `servers` passing its health check, starting from the one in
+/// use, and keeps using it.
+async fn connect_healthy(
+    servers: &mut endpoints::Endpoints,
+    connect: &ConnectOptions,
+    compression: StreamCompression,
+) -> Result<(Client, protocol::ServerVersion)> {
+    let urls: Vec<String> = servers.from_current().map(str::to_string).collect();
+    let mut last_error = None;
+    for url in urls {
+        let connected = async {
+            let mut client = connect_client(&url, connect, compression).await?;
+            let server = negotiate(&mut client).await?;
+            anyhow::Ok((client, server))
+        };
+        match connected.await {
+            Ok(connected) => {
+                servers.select(&url);
+                return Ok(connected);
+            }
+            Err(e) => {
+                log::warn!("Server {} is unavailable: {}", url, e);
+                last_error = Some(e);
+            }
+        }
+    }
+    Err(last_error.unwrap_or_else(|| anyhow::anyhow!("No server configured")))
+}
+
+/// Connects a further server to spread frames over and authenticates `token` on it.
+async fn connect_peer(
+    url: &str,
+    connect: &ConnectOptions,
+    compression: StreamCompression,
+    token: &str,
+) -> Result<(Peer, protocol::ServerVersion)> {
+    let mut client = connect_client(url, connect, compression).await?;
+    let server = negotiate(&mut client).await?;
+    let response = client.auth(token).await?;
+    let peer = Peer {
+        url: url.to_string(),
+        client,
+        token: response.token,
+    };
+    Ok((peer, server))
+}
+
+/// Connects and authenticates `token` again, waiting as `backoff` says before every try
+/// and failing over to the next of `servers` each time. `None` once the retries are used
+/// up or the run is cancelled.
+async fn reconnect(
+    servers: &mut endpoints::Endpoints,
+    connect: &ConnectOptions,
+    compression: StreamCompression,
+    token: &str,
+    backoff: &mut md5rs_client::Backoff,
+    cancel: &CancellationToken,
+) -> Option<(Client, String)> {
+    while let Some(delay) = backoff.next_delay() {
+        log::info!("Reconnecting in {:?}", delay);
+        tokio::select! {
+            _ = cancel.cancelled() => return None,
+            _ = tokio::time::sleep(delay) => (),
+        }
+        let url = servers.advance().to_string();
+        let connected = async {
+            let mut client = connect_client(&url, connect, compression).await?;
+            let response = client.auth(token).await?;
+            anyhow::Ok((client, response.token))
+        };
+        match connected.await {
+            Ok(connected) => {
+                log::info!("Reconnected to {}", url);
+                return Some(connected);
+            }
+            Err(e) => log::warn!("Failed to reconnect to {}: {}", url, e),
+        }
+    }
+    None
+}
+
+/// Connects to the server at `url`, announcing the app version with every call.
+async fn connect_client(
+    url: &str,
+    connect: &ConnectOptions,
+    compression: StreamCompression,
+) -> Result<Client> {
+    let client = Client::connect(url, connect, compression).await?;
+    Ok(client.with_metadata(
+        protocol::CLIENT_VERSION_HEADER,
+        announcement::CLIENT_VERSION,
+    ))
+}
+
+async fn get_auth(grpc_url: String, token: String, connect: ConnectOptions) -> Result<i32> {
+    let mut client = connect_client(&grpc_url, &connect, StreamCompression::None).await?;
+
+    match client.auth(&token).await {
+        Ok(response) => Ok(response.quota),
+        Err(_) => Err(anyhow::anyhow!("Auth failed")),
+    }
+}
+
+/// Checks the versions advertised by the server before any frame is sent, so an
+/// incompatible server fails the run up front instead of with decode errors mid-stream.
+async fn negotiate(client: &mut Client) -> Result<protocol::ServerVersion> {
+    let response = client.health().await?;
+    let server = protocol::ServerVersion {
+        proto_version: response.proto_version,
+        min_proto_version: response.min_proto_version,
+        server_version: response.server_version,
+        max_message_size: response.max_message_size,
+        image_codecs: response.image_codecs,
+        max_batch: response.max_batch,
+    };
+    server.check()?;
+    Ok(server)
+}
+
+async fn get_health(grpc_url: String, connect: ConnectOptions) -> Result<bool> {
+    let mut client = connect_client(&grpc_url, &connect, StreamCompression::None).await?;
+
+    match client.health().await {
+        Ok(_) => Ok(true),
+        Err(_) => Ok(false),
+    }
+}
+
+/// Refuses the run when the server requires a newer client. An unreachable feed doesn't
+/// block the run.
+fn check_client_version(url: &str) -> Result<()> {
+    if url.is_empty() {
+        return Ok(());
+    }
+    match announcement::fetch_announcements(url) {
+        Ok(feed) => feed.check_client(announcement::CLIENT_VERSION),
+        Err(e) => {
+            log::warn!("Failed to fetch announcements: {}", e);
+            Ok(())
+        }
     }
 }
 
@@ -382,11 +1682,46 @@ fn cleanup_buffer(buffer_path: &Option<String>) -> Result<()> {
     Ok(())
 }
 
-fn resume_from_checkpoint<'a>(
+/// Files whose frames all have a result, in portable form. Checkpoints may hold relative
+/// or portable paths, so they are compared that way.
+fn finished_files<'a>(frames: impl IntoIterator<Item = &'a ExportFrame>) -> HashSet<String> {
+    let mut file_frame_count = HashMap::new();
+    let mut finished = HashSet::new();
+    for f in frames {
+        let file = utils::portable_path(&f.file.file_path, None);
+        let count = file_frame_count.entry(file.clone()).or_insert(0);
+        *count += 1;
+        if *count == f.total_frames {
+            finished.insert(file);
+        }
+    }
+    finished
+}
+
+/// Media files of the folder without a complete result in `frames`.
+fn remaining_files(
+    folder: &PathBuf,
+    options: &IndexOptions,
+    frames: &[ExportFrame],
+) -> Result<usize> {
+    let finished = finished_files(frames);
+    let mut remaining = 0;
+    utils::walk_files(folder, options, |file| {
+        if !finished.contains(&utils::portable_path(&file.file_path, None)) {
+            remaining += 1;
+        }
+    })?;
+    Ok(remaining)
+}
+
+/// Loads the checkpoint into `export_data` and returns the portable paths of the files
+/// that are already complete.
+fn resume_from_checkpoint(
     checkpoint_path: &str,
-    all_files: &'a mut HashSet<FileItem>,
+    folder_path: &Path,
     export_data: &Arc<Mutex<Vec<ExportFrame>>>,
-) -> Result<&'a mut HashSet<FileItem>> {
+    unacked: &mut Vec<unacked::UnackedFrame>,
+) -> Result<HashSet<String>> {
     let checkpoint = Path::new(checkpoint_path);
     if !checkpoint.exists() {
         log::error!("Checkpoint file does not exist");
@@ -396,9 +1731,8 @@ fn resume_from_checkpoint<'a>(
         log::error!("Checkpoint path is not a file");
         return Err(anyhow::anyhow!("Checkpoint path is not a file"));
     }
-    match checkpoint.extension() {
+    match export::export_extension(checkpoint).0 {
         Some(ext) => {
-            let ext = ext.to_str().unwrap();
             if ext != "json" && ext != "csv" {
                 log::error!("Invalid checkpoint file extension: {}", ext);
                 return Err(anyhow::anyhow!(
@@ -406,33 +1740,27 @@ fn resume_from_checkpoint<'a>(
                     ext
                 ));
             } else {
-                let frames;
-                if ext == "json" {
-                    let json = std::fs::read_to_string(checkpoint)?;
-                    frames = serde_json::from_str(&json)?;
-                } else {
-                    frames = parse_export_csv(checkpoint)?;
-                }
-                let mut file_frame_count = HashMap::new();
-                let mut file_total_frames = HashMap::new();
-                for f in &frames {
-                    let file = &f.file;
-                    let count = file_frame_count.entry(file.clone()).or_insert(0);
-                    *count += 1;
-                    file_total_frames
-                        .entry(file.clone())
-                        .or_insert(f.total_frames);
-
-                    if let Some(total_frames) = file_total_frames.get(&file) {
-                        if let Some(frame_count) = file_frame_count.get(&file) {
-                            if total_frames == frame_count {
-                                all_files.remove(&file);
-                            }
-                        }
+                let mut frames = export::load_export(checkpoint)?;
+                for f in frames.iter_mut() {
+                    if f.file.file_path.is_relative() {
+                        f.file.file_path = folder_path.join(&f.file.file_path);
+                        f.file.tmp_path = f.file.file_path.clone();
                     }
                 }
+                for u in unacked.iter_mut() {
+                    u.frame.file.tmp_path = u.frame.file.file_path.clone();
+                }
+                // frames waiting for an answer complete their file, it is sent instead of decoded
+                let finished =
+                    finished_files(frames.iter().chain(unacked.iter().map(|u| &u.frame)));
+                let is_finished = |f: &ExportFrame| {
+                    finished.contains(&utils::portable_path(&f.file.file_path, None))
+                };
+                // unfinished files are processed again, their partial frames would be duplicates
+                frames.retain(is_finished);
+                unacked.retain(|u| is_finished(&u.frame));
                 export_data.lock().unwrap().extend_from_slice(&frames);
-                Ok(all_files)
+                Ok(finished)
             }
         }
         None => {
@@ -442,82 +1770,1390 @@ fn resume_from_checkpoint<'a>(
     }
 }
 
-#[tauri::command]
-async fn check_health(app: AppHandle, grpc_url: String) {
-    match get_health(grpc_url).await {
+pub async fn report_health(sink: &dyn EventSink, grpc_url: String, connect: ConnectOptions) {
+    match get_health(grpc_url, connect).await {
         Ok(health) => {
-            app.emit("health-status", health).unwrap();
+            sink.emit("health-status", health);
         }
         Err(err) => {
             // Log the error
             log::error!("Health check failed: {}", err);
 
-            app.emit("health-status", false).unwrap();
+            sink.emit("health-status", false);
         }
     }
 }
 
-#[tauri::command]
-async fn check_quota(app: AppHandle, grpc_url: String, token: String) {
-    if let Ok(quota) = get_auth(grpc_url, token).await {
-        app.emit("quota", quota).unwrap();
+pub async fn report_quota(
+    sink: &dyn EventSink,
+    grpc_url: String,
+    token: String,
+    connect: ConnectOptions,
+) {
+    if let Ok(quota) = get_auth(grpc_url, token, connect).await {
+        sink.emit("quota", quota);
     } else {
-        app.emit("quota", None::<i32>).unwrap();
+        sink.emit("quota", None::<i32>);
+    }
+}
+
+pub async fn report_announcements(sink: &dyn EventSink, url: &str) {
+    let url = url.trim().to_string();
+    let feed = tokio::task::spawn_blocking(move || announcement::fetch_announcements(&url))
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|feed| feed);
+    match feed {
+        Ok(feed) => {
+            let active = feed.active(chrono::Utc::now(), announcement::CLIENT_VERSION);
+            sink.emit("announcements", active);
+            if let Err(e) = feed.check_client(announcement::CLIENT_VERSION) {
+                sink.emit("client-incompatible", e.to_string());
+            }
+        }
+        Err(e) => {
+            log::warn!("Failed to fetch announcements: {}", e);
+            sink.emit("announcements", Vec::<announcement::Announcement>::new());
+        }
     }
 }
 
+#[tauri::command]
+async fn check_health(app: AppHandle, grpc_url: String, connect: Option<ConnectOptions>) {
+    report_health(&app, grpc_url, connect.unwrap_or_default()).await
+}
+
+#[tauri::command]
+async fn check_quota(
+    app: AppHandle,
+    grpc_url: String,
+    token: String,
+    connect: Option<ConnectOptions>,
+) {
+    report_quota(&app, grpc_url, token, connect.unwrap_or_default()).await
+}
+
+#[tauri::command]
+async fn check_announcements(app: AppHandle, url: String) {
+    report_announcements(&app, &url).await
+}
+
 #[tauri::command]
 async fn check_path_exists(path_str: String) -> Result<bool, String> {
     let path = std::path::PathBuf::from(path_str);
     Ok(path.exists())
 }
 
+#[tauri::command]
+async fn diff_exports(
+    a: String,
+    b: String,
+    output: Option<String>,
+) -> Result<diff::DiffSummary, String> {
+    let b = PathBuf::from(b);
+    let output = match output {
+        Some(output) => PathBuf::from(output),
+        None => b.with_file_name("diff.csv"),
+    };
+    diff::diff_exports(Path::new(&a), &b, &output).map_err(|e| {
+        log::error!("Failed to diff exports: {}", e);
+        e.to_string()
+    })
+}
+
+#[tauri::command]
+async fn find_similar(
+    folder: String,
+    crop_id: i64,
+    limit: Option<usize>,
+) -> Result<Vec<embedding::SimilarCrop>, String> {
+    open_embeddings(&folder)?
+        .find_similar(crop_id, limit.unwrap_or(50))
+        .map_err(|e| {
+            log::error!("Failed to find similar crops: {}", e);
+            e.to_string()
+        })
+}
+
+/// How folders are walked by the saved configuration, the defaults before one was saved.
+fn stored_index_options(app: &AppHandle) -> IndexOptions {
+    stored_config(app)
+        .map(|config| config.config_options.index_options())
+        .unwrap_or_default()
+}
+
+#[tauri::command]
+async fn export_metadata(
+    app: AppHandle,
+    folder: String,
+    output: Option<String>,
+) -> Result<usize, String> {
+    let folder = PathBuf::from(folder);
+    let output = match output {
+        Some(output) => PathBuf::from(output),
+        None => folder.join(metadata::METADATA_FILE),
+    };
+    let options = stored_index_options(&app);
+    metadata::export_metadata(&folder, &options, &output).map_err(|e| {
+        log::error!("Failed to export metadata: {}", e);
+        e.to_string()
+    })
+}
+
+#[tauri::command]
+async fn repair_timestamps(
+    app: AppHandle,
+    folder: String,
+    output: String,
+    repair: timestamps::TimestampRepair,
+) -> Result<timestamps::RepairSummary, String> {
+    timestamps::repair_timestamps(
+        &PathBuf::from(folder),
+        Path::new(&output),
+        &stored_index_options(&app),
+        &repair,
+    )
+    .map_err(|e| {
+        log::error!("Failed to repair timestamps: {}", e);
+        e.to_string()
+    })
+}
+
+#[tauri::command]
+async fn shrink_archive(
+    result: String,
+    options: shrink::ShrinkOptions,
+) -> Result<shrink::ShrinkSummary, String> {
+    shrink::shrink_archive(Path::new(&result), &options).map_err(|e| {
+        log::error!("Failed to shrink archive: {}", e);
+        e.to_string()
+    })
+}
+
+/// Copies of the positives of `result` with their boxes drawn, laid out by the previews
+/// `template`, `annotated/` by default.
+#[tauri::command]
+async fn annotate_images(
+    result: String,
+    template: Option<String>,
+) -> Result<annotate::AnnotateSummary, String> {
+    let template = template.unwrap_or_else(|| OutputTemplates::default().previews);
+    annotate::annotate_result(Path::new(&result), &template).map_err(|e| {
+        log::error!("Failed to annotate images: {}", e);
+        e.to_string()
+    })
+}
+
+/// Crops of the detections of `result`, into `crops/`.
+#[tauri::command]
+async fn export_crops(
+    result: String,
+    options: Option<chips::ChipOptions>,
+) -> Result<chips::ChipSummary, String> {
+    chips::export_result_chips(Path::new(&result), &options.unwrap_or_default()).map_err(|e| {
+        log::error!("Failed to export crops: {}", e);
+        e.to_string()
+    })
+}
+
+/// Where `organize_files` would put the files of `result`, without touching them.
+#[tauri::command]
+async fn preview_organize(
+    result: String,
+    options: Option<organize::OrganizeOptions>,
+) -> Result<organize::OrganizePlan, String> {
+    organize::preview_result(Path::new(&result), &options.unwrap_or_default()).map_err(|e| {
+        log::error!("Failed to preview organize: {}", e);
+        e.to_string()
+    })
+}
+
+#[tauri::command]
+async fn organize_files(
+    result: String,
+    options: Option<organize::OrganizeOptions>,
+) -> Result<organize::OrganizeSummary, String> {
+    organize::organize_result(Path::new(&result), &options.unwrap_or_default()).map_err(|e| {
+        log::error!("Failed to organize files: {}", e);
+        e.to_string()
+    })
+}
+
+/// Files kept in the result `folders`, the buffer folder and the log folder.
+fn stored_files(
+    app: &AppHandle,
+    folders: &[String],
+    buffer_path: Option<&str>,
+) -> (Vec<storage::StoredFile>, Vec<PathBuf>) {
+    let folders: Vec<PathBuf> = folders.iter().map(PathBuf::from).collect();
+    let buffer = buffer_path.map(PathBuf::from);
+    let logs = app.path().app_log_dir().ok();
+    let files = storage::stored_files(&folders, buffer.as_deref(), logs.as_deref());
+    let locations = folders.into_iter().chain(buffer).chain(logs).collect();
+    (files, locations)
+}
+
+/// What Megascops stores where, by kind.
+#[tauri::command]
+async fn storage_usage(
+    app: AppHandle,
+    folders: Vec<String>,
+    buffer_path: Option<String>,
+) -> Result<storage::StorageUsage, String> {
+    let (files, locations) = stored_files(&app, &folders, buffer_path.as_deref());
+    Ok(storage::storage_usage(&files, &locations))
+}
+
+/// Removes what `policy` doesn't keep, or only counts it with `dry_run`. Refused during a
+/// run, which may still be using its spools.
+#[tauri::command]
+async fn prune_storage(
+    app: AppHandle,
+    run: tauri::State<'_, SharedRun>,
+    folders: Vec<String>,
+    buffer_path: Option<String>,
+    policy: storage::RetentionPolicy,
+    dry_run: bool,
+) -> Result<storage::PruneSummary, String> {
+    if !dry_run && run.lock().unwrap().is_some() {
+        return Err("Detection in progress".to_string());
+    }
+    let (files, _) = stored_files(&app, &folders, buffer_path.as_deref());
+    let pruned = storage::plan_prune(files, &policy, std::time::SystemTime::now());
+    Ok(storage::prune(&pruned, dry_run))
+}
+
+/// Puts the files organized next to `result` back from the undo manifest.
+#[tauri::command]
+async fn undo_organize(result: String) -> Result<organize::OrganizeSummary, String> {
+    let result = PathBuf::from(result);
+    let folder = result.parent().unwrap_or(Path::new(""));
+    organize::undo_organize(folder).map_err(|e| {
+        log::error!("Failed to undo organize: {}", e);
+        e.to_string()
+    })
+}
+
+#[tauri::command]
+async fn render_overlays(
+    result: String,
+    options: Option<overlay::OverlayOptions>,
+) -> Result<overlay::OverlaySummary, String> {
+    overlay::render_overlays(Path::new(&result), &options.unwrap_or_default()).map_err(|e| {
+        log::error!("Failed to render overlays: {}", e);
+        e.to_string()
+    })
+}
+
+#[tauri::command]
+async fn generate_contact_sheets(
+    result: String,
+    options: Option<contact_sheet::ContactSheetOptions>,
+) -> Result<Vec<PathBuf>, String> {
+    contact_sheet::generate_contact_sheets(Path::new(&result), &options.unwrap_or_default())
+        .map_err(|e| {
+            log::error!("Failed to generate contact sheets: {}", e);
+            e.to_string()
+        })
+}
+
+#[tauri::command]
+async fn export_darwin_core(
+    result: String,
+    output: Option<String>,
+    options: Option<darwin_core::DarwinCoreOptions>,
+) -> Result<usize, String> {
+    darwin_core::export_darwin_core(
+        Path::new(&result),
+        output.as_deref().map(Path::new),
+        &options.unwrap_or_default(),
+    )
+    .map_err(|e| {
+        log::error!("Failed to export Darwin Core occurrences: {}", e);
+        e.to_string()
+    })
+}
+
+#[tauri::command]
+async fn export_annotations(
+    result: String,
+    options: Option<annotation::AnnotationOptions>,
+) -> Result<annotation::AnnotationSummary, String> {
+    annotation::export_annotations(Path::new(&result), &options.unwrap_or_default()).map_err(|e| {
+        log::error!("Failed to export annotations: {}", e);
+        e.to_string()
+    })
+}
+
+/// Merges corrected Label Studio or CVAT annotations into `result`, marking the
+/// annotated frames verified.
+#[tauri::command]
+async fn import_annotations(
+    app: AppHandle,
+    result: String,
+    annotations: String,
+) -> Result<annotation::ImportSummary, String> {
+    let result = PathBuf::from(result);
+    let summary =
+        annotation::import_annotations(&result, Path::new(&annotations)).map_err(|e| {
+            log::error!("Failed to import annotations: {}", e);
+            e.to_string()
+        })?;
+    if summary.frames > 0 {
+        notify_viewer(&app, &result);
+    }
+    Ok(summary)
+}
+
+#[tauri::command]
+async fn export_yolo(
+    result: String,
+    options: Option<yolo::YoloOptions>,
+) -> Result<yolo::YoloSummary, String> {
+    yolo::export_yolo(Path::new(&result), &options.unwrap_or_default()).map_err(|e| {
+        log::error!("Failed to export YOLO dataset: {}", e);
+        e.to_string()
+    })
+}
+
+#[tauri::command]
+async fn generate_pdf_report(result: String, output: Option<String>) -> Result<PathBuf, String> {
+    report::generate_pdf_report(Path::new(&result), output.as_deref().map(Path::new)).map_err(|e| {
+        log::error!("Failed to generate report: {}", e);
+        e.to_string()
+    })
+}
+
+fn open_embeddings(folder: &str) -> Result<embedding::EmbeddingStore, String> {
+    let db = Path::new(folder).join(embedding::EMBEDDING_DB);
+    if !db.is_file() {
+        return Err(format!("No embeddings found in {}", folder));
+    }
+    embedding::EmbeddingStore::open(&db).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn cluster_crops(
+    folder: String,
+    k: Option<usize>,
+) -> Result<Vec<embedding::Cluster>, String> {
+    open_embeddings(&folder)?.cluster(k).map_err(|e| {
+        log::error!("Failed to cluster crops: {}", e);
+        e.to_string()
+    })
+}
+
+#[tauri::command]
+async fn label_cluster(folder: String, cluster_id: usize, label: String) -> Result<usize, String> {
+    open_embeddings(&folder)?
+        .label_cluster(cluster_id, &label)
+        .map_err(|e| {
+            log::error!("Failed to label cluster: {}", e);
+            e.to_string()
+        })
+}
+
+/// Runs one detection, reporting progress and the outcome to `sink`, and returns the
+/// result file once it succeeded. Nothing in here depends on Tauri, so the pipeline can
+/// also be driven headless.
+pub async fn run_detection(
+    config: Config,
+    sink: Arc<dyn EventSink>,
+    gate: Arc<throttle::Gate>,
+    cancel: CancellationToken,
+) -> Result<PathBuf> {
+    follow_detection(config, sink, gate, cancel, None).await
+}
+
+/// [`run_detection`] that keeps taking the files of `follow` after its walk.
+pub(crate) async fn follow_detection(
+    mut config: Config,
+    sink: Arc<dyn EventSink>,
+    gate: Arc<throttle::Gate>,
+    cancel: CancellationToken,
+    follow: Option<Follow>,
+) -> Result<PathBuf> {
+    if let Err(e) = preflight_export(&mut config, sink.as_ref()) {
+        sink.emit("detect-error", e.to_string());
+        log::error!("Error processing: {}", e);
+        return Err(e);
+    }
+    let progress = ProgressCounter::default();
+    let (index_sender, index_receiver) = unbounded();
+
+    let progress_clone = progress.clone();
+    let progress_sink = Arc::clone(&sink);
+    let progress_thread = thread::spawn(move || {
+        events::forward_progress(
+            &progress_clone,
+            index_receiver,
+            progress_sink.as_ref(),
+            events::PROGRESS_INTERVAL,
+        )
+    });
+
+    let (throttle_stop, throttle_stop_r) = bounded(1);
+    let throttle_options = config.config_options.throttle.clone();
+    let throttle_gate = Arc::clone(&gate);
+    let throttle_sink = Arc::clone(&sink);
+    let throttle_thread = thread::spawn(move || {
+        throttle::monitor(
+            &throttle_options,
+            &throttle_gate,
+            throttle_sink.as_ref(),
+            throttle_stop_r,
+            throttle::CHECK_INTERVAL,
+        )
+    });
+
+    let folder = config.result_folder();
+    let annotate_images = config.config_options.annotate_template();
+    let export_crops = config.config_options.chip_options();
+    let result_file = folder.join(export::result_file_name(
+        config.config_options.export_format,
+        config.config_options.export_compression,
+    ));
+    let started_at = chrono::Local::now();
+    let sampler = usage::UsageSampler::start(usage::SAMPLE_INTERVAL);
+    let result = process(
+        config,
+        progress.clone(),
+        index_sender,
+        Arc::clone(&sink),
+        gate,
+        cancel,
+        follow,
+    )
+    .await;
+    progress.finish();
+    drop(throttle_stop);
+    let _ = throttle_thread.join();
+    let usage = sampler.finish();
+    log::info!("Resource usage: {:?}", usage);
+    if folder.is_dir() {
+        let manifest = manifest::RunManifest::new(&folder, started_at, usage);
+        let manifest = match &result {
+            Ok(_) => manifest,
+            Err(e) => manifest.failed(e.to_string()),
+        };
+        if let Err(e) = manifest.write() {
+            log::warn!("Failed to write the run manifest: {}", e);
+        }
+    }
+    if let (Ok(_), Some(template)) = (&result, annotate_images) {
+        let result_file = result_file.clone();
+        match tokio::task::spawn_blocking(move || {
+            annotate::annotate_result(&result_file, &template)
+        })
+        .await
+        {
+            Ok(Ok(summary)) => sink.emit("annotate-complete", &summary),
+            Ok(Err(e)) => log::error!("Failed to annotate images: {}", e),
+            Err(e) => log::error!("Annotation task failed: {}", e),
+        }
+    }
+    if let (Ok(_), Some(options)) = (&result, export_crops) {
+        let result_file = result_file.clone();
+        match tokio::task::spawn_blocking(move || {
+            chips::export_result_chips(&result_file, &options)
+        })
+        .await
+        {
+            Ok(Ok(summary)) => sink.emit("crops-complete", &summary),
+            Ok(Err(e)) => log::error!("Failed to export crops: {}", e),
+            Err(e) => log::error!("Crop task failed: {}", e),
+        }
+    }
+    match &result {
+        Ok(_) => sink.emit("detect-complete", 1),
+        Err(e) => {
+            if let Some(update) = e.downcast_ref::<protocol::UpdateRequired>() {
+                sink.emit("update-required", update);
+            }
+            if let Some(quota) = e.downcast_ref::<protocol::QuotaExhausted>() {
+                sink.emit("quota-exhausted", quota);
+            }
+            sink.emit("detect-error", e.to_string());
+            log::error!("Error processing: {}", e);
+        }
+    }
+    progress_thread.join().unwrap();
+    result.map(|_| result_file)
+}
+
+/// The app's sink, events go to the frontend.
+impl EventSink for AppHandle {
+    fn emit_value(&self, event: &str, payload: serde_json::Value) {
+        if let Err(e) = Emitter::emit(self, event, payload) {
+            log::error!("Failed to emit {}: {}", event, e);
+        }
+    }
+}
+
+/// Events for one results viewer window, in its own namespace.
+struct ViewerSink {
+    app: AppHandle,
+    label: String,
+}
+
+impl EventSink for ViewerSink {
+    fn emit_value(&self, event: &str, payload: serde_json::Value) {
+        let event = format!("{}{}", viewer::VIEWER_EVENT_PREFIX, event);
+        if let Err(e) = self.app.emit_to(self.label.as_str(), &event, payload) {
+            log::error!("Failed to emit {}: {}", event, e);
+        }
+    }
+}
+
+/// Tells an open viewer of `result` that the file was rewritten.
+fn notify_viewer(app: &AppHandle, result: &Path) {
+    let label = viewer::viewer_label(result);
+    if app.get_webview_window(&label).is_some() {
+        let sink: &dyn EventSink = &ViewerSink {
+            app: app.clone(),
+            label,
+        };
+        sink.emit("results-changed", result);
+    }
+}
+
+/// Opens a window to review `result`, or focuses it when already open. Returns the
+/// window label.
+#[tauri::command]
+async fn open_results_viewer(app: AppHandle, result: String) -> Result<String, String> {
+    let result = PathBuf::from(result);
+    if !result.is_file() {
+        return Err(format!("Result file not found: {}", result.display()));
+    }
+    let label = viewer::viewer_label(&result);
+    if let Some(window) = app.get_webview_window(&label) {
+        window.set_focus().map_err(|e| e.to_string())?;
+        return Ok(label);
+    }
+    let query: String =
+        url::form_urlencoded::byte_serialize(result.to_string_lossy().as_bytes()).collect();
+    let title = result
+        .parent()
+        .and_then(|p| p.file_name())
+        .map(|name| format!("Megascops - {}", name.to_string_lossy()))
+        .unwrap_or_else(|| "Megascops".to_string());
+    WebviewWindowBuilder::new(
+        &app,
+        &label,
+        WebviewUrl::App(format!("viewer?result={}", query).into()),
+    )
+    .title(title)
+    .inner_size(1024.0, 720.0)
+    .build()
+    .map_err(|e| {
+        log::error!("Failed to open results viewer: {}", e);
+        e.to_string()
+    })?;
+    Ok(label)
+}
+
+#[tauri::command]
+async fn query_results(
+    result: String,
+    query: Option<viewer::ResultQuery>,
+) -> Result<viewer::ResultPage, String> {
+    let result = PathBuf::from(result);
+    let folder = result.parent().unwrap_or(Path::new(""));
+    export::load_export(&result)
+        .map(|frames| viewer::query_results(&frames, folder, &query.unwrap_or_default()))
+        .map_err(|e| {
+            log::error!("Failed to query results: {}", e);
+            e.to_string()
+        })
+}
+
+/// Frames of `result` between the thresholds, for verification.
+#[tauri::command]
+async fn get_review_queue(
+    result: String,
+    options: Option<triage::TriageOptions>,
+    part: Option<triage::ReviewPart>,
+    offset: Option<usize>,
+    limit: Option<usize>,
+) -> Result<triage::ReviewQueue, String> {
+    let result = PathBuf::from(result);
+    let folder = result.parent().unwrap_or(Path::new(""));
+    export::load_export(&result)
+        .map(|frames| {
+            triage::review_queue(
+                &frames,
+                folder,
+                &options.unwrap_or_default(),
+                part.as_ref(),
+                offset.unwrap_or_default(),
+                limit,
+            )
+        })
+        .map_err(|e| {
+            log::error!("Failed to build the review queue: {}", e);
+            e.to_string()
+        })
+}
+
+/// Page of the frames of `result` matching `filters`, also while the run writing it is
+/// still going.
+#[tauri::command]
+async fn get_partial_results(
+    result: String,
+    filters: Option<partial::ResultFilters>,
+    page: Option<usize>,
+) -> Result<partial::ResultPage, String> {
+    partial::load_partial(
+        Path::new(&result),
+        &filters.unwrap_or_default(),
+        page.unwrap_or_default(),
+    )
+    .map_err(|e| {
+        log::error!("Failed to read partial results: {}", e);
+        e.to_string()
+    })
+}
+
+/// Accepts, rejects or relabels the selected frames of `result` at once.
+#[tauri::command]
+async fn review_batch(
+    app: AppHandle,
+    result: String,
+    selection: review::ReviewSelection,
+    action: review::ReviewAction,
+    reviewer: Option<String>,
+) -> Result<review::BatchSummary, String> {
+    let result = PathBuf::from(result);
+    let summary =
+        review::review_batch(&result, &selection, &action, reviewer.as_deref()).map_err(|e| {
+            log::error!("Failed to review frames: {}", e);
+            e.to_string()
+        })?;
+    notify_viewer(&app, &result);
+    Ok(summary)
+}
+
+#[tauri::command]
+async fn undo_review(app: AppHandle, result: String) -> Result<review::BatchSummary, String> {
+    let result = PathBuf::from(result);
+    let summary = review::undo_review(&result).map_err(|e| {
+        log::error!("Failed to undo review: {}", e);
+        e.to_string()
+    })?;
+    notify_viewer(&app, &result);
+    Ok(summary)
+}
+
+/// Agreement between the reviewers of `result` and the frames they disagree on.
+#[tauri::command]
+async fn get_agreement_report(result: String) -> Result<agreement::AgreementReport, String> {
+    agreement::agreement_report(Path::new(&result)).map_err(|e| {
+        log::error!("Failed to compare reviewers: {}", e);
+        e.to_string()
+    })
+}
+
+/// Saves where `session.reviewer` is in the review of `result`.
+#[tauri::command]
+async fn save_review_session(
+    result: String,
+    session: review::ReviewSession,
+) -> Result<review::ReviewSession, String> {
+    review::session_store(Path::new(&result))
+        .and_then(|store| store.save(&session))
+        .map_err(|e| {
+            log::error!("Failed to save review session: {}", e);
+            e.to_string()
+        })
+}
+
+/// The saved session of `reviewer`, or every reviewer's session without one.
+#[tauri::command]
+async fn resume_review_session(
+    result: String,
+    reviewer: Option<String>,
+) -> Result<Vec<review::ReviewSession>, String> {
+    review::session_store(Path::new(&result))
+        .and_then(|store| match reviewer {
+            Some(reviewer) => Ok(store.load(&reviewer)?.into_iter().collect()),
+            None => store.sessions(),
+        })
+        .map_err(|e| {
+            log::error!("Failed to resume review session: {}", e);
+            e.to_string()
+        })
+}
+
+#[tauri::command]
+async fn result_summary(result: String) -> Result<report::RunStats, String> {
+    let result = PathBuf::from(result);
+    let folder = result.parent().unwrap_or(Path::new(""));
+    export::load_export(&result)
+        .map(|frames| report::RunStats::from_frames(&frames, folder))
+        .map_err(|e| {
+            log::error!("Failed to summarize results: {}", e);
+            e.to_string()
+        })
+}
+
 #[tauri::command]
 async fn process_media(app: AppHandle, config: Config) {
-    let (progress_sender, progress_receiver) = crossbeam_channel::bounded(5);
+    let post_run_action = config.config_options.post_run_action;
+    let organize = config.config_options.organize_options();
+
+    let run = match start_run(app.runs()) {
+        Ok(run) => run,
+        Err(e) => {
+            log::error!("Error processing: {}", e);
+            let sink: &dyn EventSink = &app;
+            sink.emit("detect-error", e.to_string());
+            return;
+        }
+    };
+    let result = run_detection(
+        config,
+        Arc::new(app.clone()),
+        Arc::clone(&run.gate),
+        run.cancel.clone(),
+    )
+    .await;
+    finish_run(app.runs(), run);
+    if let Ok(result_file) = result {
+        notify_viewer(&app, &result_file);
+        if let Err(e) =
+            post_run::run_post_action(&app, post_run_action, &result_file, &organize).await
+        {
+            log::error!("Post-run action failed: {}", e);
+            let sink: &dyn EventSink = &app;
+            sink.emit("post-run-error", e.to_string());
+        }
+    }
+}
+
+/// Handle of the detection in progress. `done` is cancelled once the run wound down,
+/// after its last checkpoint and the buffer cleanup.
+#[derive(Clone)]
+struct ActiveRun {
+    id: Uuid,
+    /// Pauses the media workers and the upload.
+    gate: Arc<throttle::Gate>,
+    cancel: CancellationToken,
+    done: CancellationToken,
+    /// Queued job the run is for.
+    job: Option<String>,
+}
 
-    let total_files;
+pub(crate) type SharedRun = Mutex<Option<ActiveRun>>;
 
-    match crate::utils::index_files_and_folders(&PathBuf::from(
-        &config.detect_options.selected_folder,
-    )) {
-        Ok(files) => {
-            total_files = files.len();
+/// What watches and queued jobs run in: the app, or the daemon without a window.
+pub(crate) trait Host: Clone + Send + Sync + 'static {
+    fn sink(&self) -> Arc<dyn EventSink>;
+    fn runs(&self) -> &SharedRun;
+    fn queue(&self) -> &SharedQueue;
+    /// Configuration of the jobs queued without one.
+    fn default_config(&self) -> Result<Config>;
+    /// A run wrote its results to `result`.
+    fn result_written(&self, _result: &Path) {}
+}
+
+impl Host for AppHandle {
+    fn sink(&self) -> Arc<dyn EventSink> {
+        Arc::new(self.clone())
+    }
+
+    fn runs(&self) -> &SharedRun {
+        self.state::<SharedRun>().inner()
+    }
+
+    fn queue(&self) -> &SharedQueue {
+        self.state::<SharedQueue>().inner()
+    }
+
+    fn default_config(&self) -> Result<Config> {
+        stored_config(self)
+    }
+
+    fn result_written(&self, result: &Path) {
+        notify_viewer(self, result);
+    }
+}
+
+/// Registers a new run, or hands back the token cancelled once the one in progress is
+/// done.
+fn try_start_run(
+    runs: &SharedRun,
+    job: Option<&str>,
+) -> std::result::Result<ActiveRun, CancellationToken> {
+    let mut active = runs.lock().unwrap();
+    if let Some(run) = active.as_ref() {
+        return Err(run.done.clone());
+    }
+    let run = ActiveRun {
+        id: Uuid::new_v4(),
+        gate: Arc::new(throttle::Gate::default()),
+        cancel: CancellationToken::new(),
+        done: CancellationToken::new(),
+        job: job.map(str::to_string),
+    };
+    *active = Some(run.clone());
+    Ok(run)
+}
+
+/// Registers a new run, failing while an other one is in progress. Runs share the buffer
+/// folder, and only the registered one can be paused or cancelled.
+fn start_run(runs: &SharedRun) -> Result<ActiveRun> {
+    try_start_run(runs, None).map_err(|_| anyhow::anyhow!("A detection is already running"))
+}
+
+/// Registers a new run once the one in progress is done.
+async fn queue_run(runs: &SharedRun, job: Option<&str>) -> ActiveRun {
+    loop {
+        match try_start_run(runs, job) {
+            Ok(run) => return run,
+            Err(done) => done.cancelled().await,
         }
+    }
+}
+
+fn finish_run(runs: &SharedRun, run: ActiveRun) {
+    let mut active = runs.lock().unwrap();
+    if active.as_ref().is_some_and(|a| a.id == run.id) {
+        *active = None;
+    }
+    run.done.cancel();
+}
+
+/// Stops the detection in progress and returns once it flushed its checkpoint and
+/// cleaned the buffer. `false` when nothing was running.
+#[tauri::command]
+async fn cancel_detection(
+    app: AppHandle,
+    run: tauri::State<'_, SharedRun>,
+) -> Result<bool, String> {
+    Ok(cancel_run(&app, &run).await)
+}
+
+pub(crate) async fn cancel_run(sink: &dyn EventSink, run: &SharedRun) -> bool {
+    let active = run.lock().unwrap().clone();
+    stop_run(sink, active).await
+}
+
+/// Stops the run of the queued job `id`, `false` when an other one is in progress.
+pub(crate) async fn cancel_job_run(sink: &dyn EventSink, run: &SharedRun, id: &str) -> bool {
+    let active = run
+        .lock()
+        .unwrap()
+        .clone()
+        .filter(|active| active.job.as_deref() == Some(id));
+    stop_run(sink, active).await
+}
+
+async fn stop_run(sink: &dyn EventSink, active: Option<ActiveRun>) -> bool {
+    let Some(active) = active else {
+        return false;
+    };
+    log::info!("Cancelling detection");
+    active.cancel.cancel();
+    active.done.cancelled().await;
+    sink.emit("detect-cancelled", ());
+    true
+}
+
+pub(crate) fn pause_run(sink: &dyn EventSink, run: &SharedRun, paused: bool) -> bool {
+    let Some(active) = run.lock().unwrap().clone() else {
+        return false;
+    };
+    if active.gate.set_paused(paused) {
+        log::info!("Detection {}", if paused { "paused" } else { "resumed" });
+        sink.emit("detect-paused", paused);
+    }
+    true
+}
+
+/// Suspends decoding and uploading of the detection in progress, the session stays open.
+/// `false` when nothing was running.
+#[tauri::command]
+async fn pause_detection(app: AppHandle, run: tauri::State<'_, SharedRun>) -> Result<bool, String> {
+    Ok(pause_run(&app, &run, true))
+}
+
+#[tauri::command]
+async fn resume_detection(
+    app: AppHandle,
+    run: tauri::State<'_, SharedRun>,
+) -> Result<bool, String> {
+    Ok(pause_run(&app, &run, false))
+}
+
+pub(crate) type SharedWatch = Mutex<Option<CancellationToken>>;
+
+/// Detects on the folder of `config`, then keeps watching it and detects on the files
+/// that arrive once their copy settled, appending to the result file. Watching an
+/// other folder stops the current watch.
+#[tauri::command]
+async fn start_watch(
+    app: AppHandle,
+    watch: tauri::State<'_, SharedWatch>,
+    config: Config,
+) -> Result<(), String> {
+    let stop = CancellationToken::new();
+    if let Some(previous) = watch.lock().unwrap().replace(stop.clone()) {
+        previous.cancel();
+    }
+    tauri::async_runtime::spawn(watch_folder(app, config, stop));
+    Ok(())
+}
+
+/// Stops watching, a detection in progress still finishes. `false` when nothing was
+/// watched.
+#[tauri::command]
+async fn stop_watch(watch: tauri::State<'_, SharedWatch>) -> Result<bool, String> {
+    let Some(stop) = watch.lock().unwrap().take() else {
+        return Ok(false);
+    };
+    stop.cancel();
+    Ok(true)
+}
+
+pub(crate) async fn watch_folder<H: Host>(host: H, config: Config, stop: CancellationToken) {
+    let events = host.sink();
+    let sink: &dyn EventSink = events.as_ref();
+    let folder = PathBuf::from(&config.detect_options.selected_folder);
+    let mut result_file = config.result_folder().join(export::result_file_name(
+        config.config_options.export_format,
+        config.config_options.export_compression,
+    ));
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let _watcher = match watch::watch(&folder, sender) {
+        Ok(watcher) => watcher,
         Err(e) => {
-            log::error!("{}", e);
-            app.emit("detect-error", e.to_string()).unwrap();
+            log::error!("Failed to watch {}: {}", folder.display(), e);
+            sink.emit("watch-error", e.to_string());
             return;
         }
+    };
+    sink.emit("watch-started", &folder);
+    let mut pending = watch::PendingFiles::default();
+    let mut tick = tokio::time::interval(watch::WATCH_TICK);
+    // the first run catches up on what is already there, later ones take the new files
+    let mut batch = None;
+    loop {
+        let paths: Vec<String> = batch
+            .iter()
+            .flatten()
+            .filter(|p| p.is_file())
+            .map(|p| p.to_string_lossy().into_owned())
+            .collect();
+        if batch.is_none() || !paths.is_empty() {
+            log::info!("Detecting on {} new files", paths.len());
+            sink.emit("watch-batch", &paths);
+            let mut config = config.clone();
+            config.detect_options.paths = paths;
+            config.detect_options.resume_path = result_file
+                .is_file()
+                .then(|| result_file.to_string_lossy().into_owned());
+            // files arriving meanwhile wait in the channel
+            let run = tokio::select! {
+                _ = stop.cancelled() => break,
+                run = queue_run(host.runs(), None) => run,
+            };
+            let result = run_detection(
+                config,
+                host.sink(),
+                Arc::clone(&run.gate),
+                run.cancel.clone(),
+            )
+            .await;
+            finish_run(host.runs(), run);
+            // a run that fell back to the local folder resumes from there
+            if let Ok(written) = result {
+                host.result_written(&written);
+                result_file = written;
+            }
+        }
+        batch = Some(Vec::new());
+        tokio::select! {
+            _ = stop.cancelled() => break,
+            Some(path) = receiver.recv() => pending.touch(path, Instant::now()),
+            _ = tick.tick() => {
+                if !pending.is_empty() {
+                    batch = Some(pending.take_settled(Instant::now(), watch::SETTLE_TIME));
+                }
+            }
+        }
     }
+    log::info!("Stopped watching {}", folder.display());
+    sink.emit("watch-stopped", &folder);
+}
+
+pub(crate) type SharedQueue = Mutex<queue::JobQueue>;
+
+type SharedRemote = Mutex<Option<CancellationToken>>;
+
+/// Starts the remote control API, replacing the one running. Returns where it listens
+/// and the token requests must carry.
+#[tauri::command]
+async fn start_remote_api(
+    app: AppHandle,
+    remote: tauri::State<'_, SharedRemote>,
+    options: remote::RemoteOptions,
+) -> Result<remote::RemoteInfo, String> {
+    let options = options.with_token().map_err(|e| {
+        log::error!("Failed to start the remote API: {}", e);
+        e.to_string()
+    })?;
+    if let Some(previous) = remote.lock().unwrap().take() {
+        previous.cancel();
+    }
+    let listener = remote::bind(&options).await.map_err(|e| {
+        log::error!("Failed to start the remote API: {}", e);
+        e.to_string()
+    })?;
+    let stop = CancellationToken::new();
+    *remote.lock().unwrap() = Some(stop.clone());
+    let token = options.token.clone();
+    tauri::async_runtime::spawn(async move {
+        if let Err(e) = remote::serve(app, listener, token, stop).await {
+            log::error!("Remote API failed: {}", e);
+        }
+    });
+    Ok(remote::RemoteInfo {
+        address: options.address,
+        token: options.token,
+    })
+}
+
+/// `false` when the remote API wasn't running.
+#[tauri::command]
+async fn stop_remote_api(remote: tauri::State<'_, SharedRemote>) -> Result<bool, String> {
+    let Some(stop) = remote.lock().unwrap().take() else {
+        return Ok(false);
+    };
+    stop.cancel();
+    Ok(true)
+}
 
-    let app_clone = app.clone();
+type SharedShare = Mutex<Option<CancellationToken>>;
 
-    let progress_thread = std::thread::spawn(move || {
-        let mut progress = 0.0;
-        for _ in progress_receiver.iter() {
-            progress += 1.0 / total_files as f32 * 100.0;
-            app_clone
-                .emit("detect-progress", progress)
-                .unwrap();
+/// Splits the folder of `config` into batches run by the peers of `options`, and merges
+/// their results into the folder's result file. Reports "share-progress", then
+/// "share-complete" with the summary or "share-error".
+#[tauri::command]
+async fn start_share(
+    app: AppHandle,
+    share: tauri::State<'_, SharedShare>,
+    config: Config,
+    options: share::ShareOptions,
+) -> Result<(), String> {
+    let stop = CancellationToken::new();
+    if let Some(previous) = share.lock().unwrap().replace(stop.clone()) {
+        previous.cancel();
+    }
+    let sink = app.sink();
+    tauri::async_runtime::spawn_blocking(move || {
+        let events: &dyn EventSink = sink.as_ref();
+        match share::run(config, options, Arc::clone(&sink), stop) {
+            Ok(summary) => events.emit("share-complete", summary),
+            Err(e) => {
+                log::error!("Sharing failed: {}", e);
+                events.emit("share-error", e.to_string());
+            }
         }
     });
+    Ok(())
+}
+
+/// Stops handing out batches and stops the ones running. `false` when nothing was shared.
+#[tauri::command]
+async fn cancel_share(share: tauri::State<'_, SharedShare>) -> Result<bool, String> {
+    let Some(stop) = share.lock().unwrap().take() else {
+        return Ok(false);
+    };
+    stop.cancel();
+    Ok(true)
+}
+
+type SharedLive = Mutex<Option<CancellationToken>>;
+
+/// Experimental: takes snapshots of the stream of `options` into the folder of `config`
+/// and detects on them continuously, appending to its result file. Reports "live-alert"
+/// for the snapshots with a detection of an alert class. Monitoring an other stream
+/// stops the current one.
+#[tauri::command]
+async fn start_live(
+    app: AppHandle,
+    live: tauri::State<'_, SharedLive>,
+    config: Config,
+    options: live::LiveOptions,
+) -> Result<(), String> {
+    if options.url.trim().is_empty() {
+        return Err("No stream address given".to_string());
+    }
+    let stop = CancellationToken::new();
+    if let Some(previous) = live.lock().unwrap().replace(stop.clone()) {
+        previous.cancel();
+    }
+    tauri::async_runtime::spawn(live::run(app, config, options, stop));
+    Ok(())
+}
+
+/// Stops taking snapshots and watching them, a detection in progress still finishes.
+/// `false` when no stream was monitored.
+#[tauri::command]
+async fn stop_live(live: tauri::State<'_, SharedLive>) -> Result<bool, String> {
+    let Some(stop) = live.lock().unwrap().take() else {
+        return Ok(false);
+    };
+    stop.cancel();
+    Ok(true)
+}
+
+/// Configuration the frontend saved last, used for runs started from the backend.
+fn stored_config(app: &AppHandle) -> Result<Config> {
+    let config = app
+        .store("store.json")?
+        .get("config")
+        .ok_or_else(|| anyhow::anyhow!("No configuration saved yet"))?;
+    Ok(serde_json::from_value(config)?)
+}
+
+/// Groups dropped files and folders into a job and starts it when nothing is running.
+#[tauri::command]
+async fn queue_paths(
+    app: AppHandle,
+    queue: tauri::State<'_, SharedQueue>,
+    paths: Vec<String>,
+) -> Result<queue::Job, String> {
+    let paths: Vec<PathBuf> = paths.into_iter().map(PathBuf::from).collect();
+    let job = queue.lock().unwrap().add_paths(&paths, None).map_err(|e| {
+        log::error!("Failed to queue paths: {}", e);
+        e.to_string()
+    })?;
+    let sink: &dyn EventSink = &app;
+    sink.emit("job-queued", &job);
+    start_next_job(app);
+    Ok(job)
+}
+
+/// Queues the selected folder of `config` as a job run with that configuration, after
+/// the jobs queued before it.
+#[tauri::command]
+async fn enqueue_folder(
+    app: AppHandle,
+    queue: tauri::State<'_, SharedQueue>,
+    config: Config,
+) -> Result<queue::Job, String> {
+    let folder = PathBuf::from(&config.detect_options.selected_folder);
+    let job = queue
+        .lock()
+        .unwrap()
+        .add_folder(&folder, Some(config))
+        .map_err(|e| {
+            log::error!("Failed to queue folder: {}", e);
+            e.to_string()
+        })?;
+    let sink: &dyn EventSink = &app;
+    sink.emit("job-queued", &job);
+    start_next_job(app);
+    Ok(job)
+}
+
+/// Every job queued since the app started, with its status.
+#[tauri::command]
+async fn get_jobs(queue: tauri::State<'_, SharedQueue>) -> Result<Vec<queue::Job>, String> {
+    Ok(queue.lock().unwrap().jobs().to_vec())
+}
+
+/// Takes a job that hasn't started yet out of the queue.
+#[tauri::command]
+async fn remove_job(
+    app: AppHandle,
+    queue: tauri::State<'_, SharedQueue>,
+    id: String,
+) -> Result<(), String> {
+    let job = queue.lock().unwrap().remove(&id).map_err(|e| {
+        log::error!("Failed to remove job: {}", e);
+        e.to_string()
+    })?;
+    let sink: &dyn EventSink = &app;
+    sink.emit("job-removed", &job.id);
+    Ok(())
+}
+
+pub(crate) fn start_next_job<H: Host>(host: H) {
+    let job = {
+        let mut queue = host.queue().lock().unwrap();
+        if queue.is_running() {
+            return;
+        }
+        queue.start_next()
+    };
+    if let Some(job) = job {
+        tauri::async_runtime::spawn(run_job(host, job));
+    }
+}
 
-    match process(config, progress_sender).await {
-        Ok(_) => {
-            app.emit("detect-complete", 1).unwrap();
+async fn run_job<H: Host>(host: H, job: queue::Job) {
+    let events = host.sink();
+    let sink: &dyn EventSink = events.as_ref();
+    sink.emit("job-started", &job);
+    let config = match job.config.clone() {
+        Some(config) => Ok(config),
+        None => host.default_config(),
+    };
+    let result = match config {
+        Ok(mut config) => {
+            config.detect_options.selected_folder = job.root.to_string_lossy().into_owned();
+            config.detect_options.paths = job
+                .paths
+                .iter()
+                .map(|p| p.to_string_lossy().into_owned())
+                .collect();
+            config.detect_options.resume_path = None;
+            let run = queue_run(host.runs(), Some(&job.id)).await;
+            let sink = events::JobEvents {
+                id: job.id.clone(),
+                sink: host.sink(),
+            };
+            let result = run_detection(
+                config,
+                Arc::new(sink),
+                Arc::clone(&run.gate),
+                run.cancel.clone(),
+            )
+            .await;
+            finish_run(host.runs(), run);
+            result
         }
+        Err(e) => Err(e),
+    };
+    host.queue()
+        .lock()
+        .unwrap()
+        .finish(&job.id, result.as_ref().ok().cloned());
+    match result {
+        Ok(_) => sink.emit("job-complete", &job.id),
         Err(e) => {
-            app.emit("detect-error", e.to_string()).unwrap();
-            log::error!("Error processing: {}", e);
+            log::error!("Job {} failed: {}", job.id, e);
+            sink.emit(
+                "job-failed",
+                serde_json::json!({ "id": job.id, "error": e.to_string() }),
+            );
         }
     }
-    progress_thread.join().unwrap();
+    start_next_job(host);
+}
+
+type PendingLaunches = Mutex<Vec<launch::LaunchRequest>>;
+
+/// Keeps a launch request until the frontend takes it, and asks the user to confirm it.
+fn request_launch(app: &AppHandle, request: launch::LaunchRequest) {
+    log::info!("Launch requested for {:?}", request.folders);
+    app.state::<PendingLaunches>()
+        .lock()
+        .unwrap()
+        .push(request.clone());
+    let sink: &dyn EventSink = app;
+    sink.emit("launch-request", &request);
+    if let Some(window) = app.get_webview_window("main") {
+        let _ = window.set_focus();
+    }
+}
+
+fn handle_deep_link(app: &AppHandle, url: &Url) {
+    match launch::parse_deep_link(url) {
+        Ok(request) => request_launch(app, request),
+        Err(e) => {
+            log::warn!("Ignored deep link: {}", e);
+            let sink: &dyn EventSink = app;
+            sink.emit("launch-error", e.to_string());
+        }
+    }
+}
+
+/// Launch requests that arrived before the frontend was listening.
+#[tauri::command]
+async fn take_launch_requests(
+    pending: tauri::State<'_, PendingLaunches>,
+) -> Result<Vec<launch::LaunchRequest>, String> {
+    Ok(std::mem::take(&mut *pending.lock().unwrap()))
+}
+
+/// Adds or removes the "Send to Megascops" entry of the file manager, returns whether it
+/// is installed afterwards.
+#[tauri::command]
+async fn set_context_menu(enabled: bool) -> Result<bool, String> {
+    let result = if enabled {
+        context_menu::launcher_path().and_then(|exe| context_menu::install(&exe))
+    } else {
+        context_menu::uninstall()
+    };
+    result
+        .and_then(|_| context_menu::is_installed())
+        .map_err(|e| {
+            log::error!("Failed to update the context menu: {}", e);
+            e.to_string()
+        })
+}
+
+#[tauri::command]
+async fn context_menu_installed() -> Result<bool, String> {
+    context_menu::is_installed().map_err(|e| e.to_string())
+}
+
+/// Installs or removes the ingest daemon, returns whether it is installed afterwards.
+#[tauri::command]
+async fn set_daemon(enabled: bool) -> Result<bool, String> {
+    let result = if enabled {
+        context_menu::launcher_path().and_then(|exe| daemon::install(&exe))
+    } else {
+        daemon::uninstall()
+    };
+    result.and_then(|_| daemon::is_installed()).map_err(|e| {
+        log::error!("Failed to update the daemon: {}", e);
+        e.to_string()
+    })
+}
+
+#[tauri::command]
+async fn daemon_installed() -> Result<bool, String> {
+    daemon::is_installed().map_err(|e| e.to_string())
+}
+
+/// Sends a command to the daemon, the app acting as its control panel.
+#[tauri::command]
+async fn daemon_request(request: daemon::DaemonRequest) -> Result<serde_json::Value, String> {
+    daemon::request(request).await.map_err(|e| {
+        log::error!("Daemon request failed: {}", e);
+        e.to_string()
+    })
+}
+
+/// Emits the events of the daemon as the app's own, so its watches and jobs show like
+/// local ones. Returns once the daemon stops or the connection breaks.
+#[tauri::command]
+async fn connect_daemon(app: AppHandle) -> Result<(), String> {
+    daemon::forward_events(Arc::new(app)).await.map_err(|e| {
+        log::warn!("Lost the daemon connection: {}", e);
+        e.to_string()
+    })
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     tauri::Builder::default()
+        // a second launch hands its arguments to the running app, deep links included
+        .plugin(tauri_plugin_single_instance::init(
+            |app, argv, cwd| match launch::parse_args(argv.iter().skip(1), Path::new(&cwd)) {
+                Some(request) => request_launch(app, request),
+                None => {
+                    if let Some(window) = app.get_webview_window("main") {
+                        let _ = window.set_focus();
+                    }
+                }
+            },
+        ))
+        .plugin(tauri_plugin_deep_link::init())
         .plugin(tauri_plugin_store::Builder::default().build())
         .plugin(
             tauri_plugin_log::Builder::new()
@@ -529,14 +3165,94 @@ pub fn run() {
         .plugin(tauri_plugin_fs::init())
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_opener::init())
+        .manage(SharedRun::default())
+        .manage(SharedQueue::default())
+        .manage(SharedWatch::default())
+        .manage(SharedRemote::default())
+        .manage(SharedShare::default())
+        .manage(SharedLive::default())
+        .manage(PendingLaunches::default())
         .invoke_handler(tauri::generate_handler![
             process_media,
+            cancel_detection,
+            pause_detection,
+            resume_detection,
+            start_watch,
+            stop_watch,
             check_health,
             check_quota,
+            check_announcements,
             check_path_exists,
+            diff_exports,
+            export_metadata,
+            repair_timestamps,
+            shrink_archive,
+            render_overlays,
+            annotate_images,
+            export_crops,
+            preview_organize,
+            organize_files,
+            undo_organize,
+            storage_usage,
+            prune_storage,
+            generate_contact_sheets,
+            generate_pdf_report,
+            export_darwin_core,
+            export_annotations,
+            import_annotations,
+            export_yolo,
+            find_similar,
+            cluster_crops,
+            label_cluster,
+            open_results_viewer,
+            query_results,
+            get_review_queue,
+            get_partial_results,
+            review_batch,
+            undo_review,
+            save_review_session,
+            resume_review_session,
+            get_agreement_report,
+            result_summary,
+            queue_paths,
+            enqueue_folder,
+            get_jobs,
+            remove_job,
+            start_remote_api,
+            stop_remote_api,
+            start_share,
+            cancel_share,
+            start_live,
+            stop_live,
+            take_launch_requests,
+            set_context_menu,
+            context_menu_installed,
+            set_daemon,
+            daemon_installed,
+            daemon_request,
+            connect_daemon,
         ])
         .setup(|app| {
             let _ = app.store("store.json")?;
+
+            // installed builds register the scheme on install, this covers portable and dev builds
+            #[cfg(any(windows, target_os = "linux"))]
+            app.deep_link().register_all()?;
+            let handle = app.handle().clone();
+            app.deep_link().on_open_url(move |event| {
+                for url in event.urls() {
+                    handle_deep_link(&handle, &url);
+                }
+            });
+            if let Some(urls) = app.deep_link().get_current()? {
+                for url in urls {
+                    handle_deep_link(app.handle(), &url);
+                }
+            }
+            let cwd = std::env::current_dir().unwrap_or_default();
+            if let Some(request) = launch::parse_args(std::env::args().skip(1), &cwd) {
+                request_launch(app.handle(), request);
+            }
             Ok(())
         })
         .run(tauri::generate_context!())
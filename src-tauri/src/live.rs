@@ -0,0 +1,505 @@
+use std::collections::VecDeque;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use ffmpeg_sidecar::command::FfmpegCommand;
+use ffmpeg_sidecar::event::{FfmpegEvent, LogLevel};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+use crate::events::EventSink;
+use crate::export::{self, ExportFrame};
+use crate::watch::{self, PendingFiles};
+use crate::{finish_run, follow_detection, queue_run, Config, Follow, Host};
+
+/// Shortest time between two snapshots, their names are stamped to the second.
+const MIN_INTERVAL: f64 = 1.0;
+/// Longest wait before connecting again to a stream that keeps failing.
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+/// A stream that ran this long before it broke is reconnected to at once.
+const STABLE_TIME: Duration = Duration::from_secs(60);
+/// How long a snapshot stays untouched before it is detected on, ffmpeg writes it at once.
+const SNAPSHOT_SETTLE: Duration = Duration::from_secs(1);
+/// Prefix of the snapshot file names.
+const SNAPSHOT_PREFIX: &str = "live-";
+
+/// Settings of a live stream monitored like a watched folder.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct LiveOptions {
+    /// Any stream FFmpeg reads, such as the `rtsp://` address an ONVIF camera gives.
+    pub url: String,
+    /// Seconds between two snapshots.
+    pub interval: f64,
+    /// Classes whose detection raises an alert.
+    pub alert_classes: Vec<String>,
+    /// Lowest score of a detection raising an alert.
+    pub alert_score: f32,
+    /// Address the alerts are posted to as JSON, besides the "live-alert" event.
+    pub webhook: Option<String>,
+    /// Snapshots kept, older ones are deleted and dropped from the result. 0 keeps all.
+    pub keep_snapshots: usize,
+}
+
+impl Default for LiveOptions {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            interval: 5.0,
+            alert_classes: vec!["Animal".to_string()],
+            alert_score: 0.5,
+            webhook: None,
+            keep_snapshots: 2000,
+        }
+    }
+}
+
+/// A snapshot of the stream with a detection of an alert class.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LiveAlert {
+    pub file: PathBuf,
+    pub shoot_time: Option<String>,
+    pub label: String,
+    pub score: f32,
+}
+
+/// FFmpeg arguments writing a JPEG of the stream every `interval` seconds into `folder`,
+/// named after the time it was taken.
+fn capture_args(options: &LiveOptions, folder: &Path) -> Vec<String> {
+    let mut args = Vec::new();
+    if options.url.starts_with("rtsp") {
+        // UDP drops packets on busy networks and smears the frames
+        args.extend(["-rtsp_transport", "tcp"].map(String::from));
+    }
+    let pattern = folder.join(format!("{}%Y%m%d-%H%M%S.jpg", SNAPSHOT_PREFIX));
+    args.extend([
+        "-i".to_string(),
+        options.url.clone(),
+        "-vf".to_string(),
+        format!("fps=1/{}", options.interval.max(MIN_INTERVAL)),
+        "-q:v".to_string(),
+        "2".to_string(),
+        "-strftime".to_string(),
+        "1".to_string(),
+        "-y".to_string(),
+        pattern.to_string_lossy().into_owned(),
+    ]);
+    args
+}
+
+/// How long to wait before connecting again after `failures` attempts in a row broke.
+fn reconnect_delay(failures: u32) -> Duration {
+    Duration::from_secs(1 << failures.min(6)).min(MAX_RECONNECT_DELAY)
+}
+
+/// Takes snapshots until the stream ends or `stop` is cancelled.
+async fn capture_once(
+    options: &LiveOptions,
+    folder: &Path,
+    stop: &CancellationToken,
+) -> Result<()> {
+    let mut child = FfmpegCommand::new()
+        .hide_banner()
+        .args(capture_args(options, folder))
+        .spawn()?;
+    let stdin = child.take_stdin();
+    let mut reader = tokio::task::spawn_blocking(move || -> Result<()> {
+        let errors: Vec<String> = child
+            .iter()?
+            .filter_map(|event| match event {
+                FfmpegEvent::Error(e) | FfmpegEvent::Log(LogLevel::Error, e) => Some(e),
+                _ => None,
+            })
+            .collect();
+        let status = child.wait()?;
+        if !status.success() {
+            return Err(anyhow!("ffmpeg failed: {}", errors.join("; ")));
+        }
+        Ok(())
+    });
+    tokio::select! {
+        result = &mut reader => result?,
+        _ = stop.cancelled() => {
+            // ffmpeg finishes the snapshot it is writing before it quits
+            if let Some(mut stdin) = stdin {
+                let _ = stdin.write_all(b"q");
+            }
+            let _ = reader.await;
+            Ok(())
+        }
+    }
+}
+
+/// Takes snapshots of the stream into `folder`, connecting again whenever it breaks,
+/// until `stop` is cancelled.
+async fn capture(
+    options: &LiveOptions,
+    folder: &Path,
+    sink: &dyn EventSink,
+    stop: &CancellationToken,
+) {
+    let mut failures = 0;
+    while !stop.is_cancelled() {
+        let started = Instant::now();
+        sink.emit("live-connected", &options.url);
+        match capture_once(options, folder, stop).await {
+            Ok(()) if stop.is_cancelled() => break,
+            Ok(()) => log::warn!("Stream {} ended", options.url),
+            Err(e) => {
+                log::warn!("Stream {} broke: {}", options.url, e);
+                sink.emit("live-error", e.to_string());
+            }
+        }
+        if started.elapsed() >= STABLE_TIME {
+            failures = 0;
+        }
+        let delay = reconnect_delay(failures);
+        failures += 1;
+        sink.emit("live-reconnecting", delay.as_secs());
+        tokio::select! {
+            _ = stop.cancelled() => break,
+            _ = tokio::time::sleep(delay) => (),
+        }
+    }
+}
+
+/// Alert for `frame` when its best detection of an alert class scores high enough.
+fn frame_alert(frame: &ExportFrame, options: &LiveOptions) -> Option<LiveAlert> {
+    let (label, score) = frame
+        .bboxes
+        .iter()
+        .flatten()
+        .filter(|bbox| bbox.score >= options.alert_score)
+        .map(|bbox| (bbox.class_name(), bbox.score))
+        .filter(|(label, _)| {
+            options
+                .alert_classes
+                .iter()
+                .any(|class| class.eq_ignore_ascii_case(label))
+        })
+        .max_by(|a, b| a.1.total_cmp(&b.1))?;
+    Some(LiveAlert {
+        file: frame.file.file_path.clone(),
+        shoot_time: frame.shoot_time.clone(),
+        label,
+        score,
+    })
+}
+
+fn post_alerts(webhook: &str, alerts: &[LiveAlert]) -> Result<()> {
+    ureq::AgentBuilder::new()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .post(webhook)
+        .send_json(alerts)?;
+    Ok(())
+}
+
+/// Raises the alerts of the frames as they come back, until every sender is gone.
+fn raise_alerts(
+    frames: crossbeam_channel::Receiver<ExportFrame>,
+    options: &LiveOptions,
+    sink: &dyn EventSink,
+) {
+    for frame in frames {
+        let Some(alert) = frame_alert(&frame, options) else {
+            continue;
+        };
+        log::info!("Live alert for {}", alert.file.display());
+        sink.emit("live-alert", &alert);
+        if let Some(webhook) = options.webhook.clone() {
+            std::thread::spawn(move || {
+                if let Err(e) = post_alerts(&webhook, &[alert]) {
+                    log::warn!("Failed to post alerts to {}: {}", webhook, e);
+                }
+            });
+        }
+    }
+}
+
+fn is_snapshot(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.starts_with(SNAPSHOT_PREFIX) && name.ends_with(".jpg"))
+}
+
+/// Snapshots on disk, oldest first, deleted once more than `keep` were taken.
+#[derive(Debug)]
+struct Snapshots {
+    stored: VecDeque<PathBuf>,
+    keep: usize,
+}
+
+impl Snapshots {
+    /// The snapshots a previous session left in `folder`.
+    fn load(folder: &Path, keep: usize) -> Self {
+        let mut stored: Vec<PathBuf> = std::fs::read_dir(folder)
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok().map(|e| e.path()))
+                    .filter(|path| is_snapshot(path))
+                    .collect()
+            })
+            .unwrap_or_default();
+        // the names are stamped, so they sort by time
+        stored.sort();
+        Self {
+            stored: stored.into(),
+            keep,
+        }
+    }
+
+    /// Adds `path`, returning the snapshots it pushed out.
+    fn push(&mut self, path: PathBuf) -> Vec<PathBuf> {
+        self.stored.push_back(path);
+        if self.keep == 0 || self.stored.len() <= self.keep {
+            return Vec::new();
+        }
+        let excess = self.stored.len() - self.keep;
+        self.stored.drain(..excess).collect()
+    }
+}
+
+/// Hands the snapshots written into `folder` to the run once they settled, deleting the
+/// oldest beyond what `options` keeps, until `stop` is cancelled.
+async fn follow_snapshots(
+    options: &LiveOptions,
+    folder: &Path,
+    files: crossbeam_channel::Sender<PathBuf>,
+    sink: &dyn EventSink,
+    stop: &CancellationToken,
+) {
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let _watcher = match watch::watch(folder, sender) {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            log::error!("Failed to watch {}: {}", folder.display(), e);
+            sink.emit("live-error", e.to_string());
+            return;
+        }
+    };
+    let mut snapshots = Snapshots::load(folder, options.keep_snapshots);
+    let mut pending = PendingFiles::default();
+    let mut tick = tokio::time::interval(watch::WATCH_TICK);
+    loop {
+        tokio::select! {
+            _ = stop.cancelled() => break,
+            Some(path) = receiver.recv() => {
+                if is_snapshot(&path) {
+                    pending.touch(path, Instant::now());
+                }
+            }
+            _ = tick.tick() => {
+                for path in pending.take_settled(Instant::now(), SNAPSHOT_SETTLE) {
+                    if files.send(path.clone()).is_err() {
+                        return;
+                    }
+                    for old in snapshots.push(path) {
+                        if let Err(e) = std::fs::remove_file(&old) {
+                            log::warn!("Failed to delete {}: {}", old.display(), e);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Detects on the snapshots in one run that follows them, started again after it failed,
+/// until `stop` is cancelled and the last snapshots are done.
+async fn detect<H: Host>(host: &H, mut config: Config, follow: Follow, stop: &CancellationToken) {
+    let mut result_file = config.result_folder().join(export::result_file_name(
+        config.config_options.export_format,
+        config.config_options.export_compression,
+    ));
+    let mut failures = 0;
+    loop {
+        config.detect_options.resume_path = result_file
+            .is_file()
+            .then(|| result_file.to_string_lossy().into_owned());
+        let run = tokio::select! {
+            _ = stop.cancelled() => break,
+            run = queue_run(host.runs(), None) => run,
+        };
+        let started = Instant::now();
+        let result = follow_detection(
+            config.clone(),
+            host.sink(),
+            Arc::clone(&run.gate),
+            run.cancel.clone(),
+            Some(follow.clone()),
+        )
+        .await;
+        finish_run(host.runs(), run);
+        if let Ok(written) = result {
+            host.result_written(&written);
+            result_file = written;
+        }
+        if stop.is_cancelled() {
+            break;
+        }
+        // the run ended by itself, the server went away or it was cancelled
+        if started.elapsed() >= STABLE_TIME {
+            failures = 0;
+        }
+        let delay = reconnect_delay(failures);
+        failures += 1;
+        tokio::select! {
+            _ = stop.cancelled() => break,
+            _ = tokio::time::sleep(delay) => (),
+        }
+    }
+}
+
+/// Takes snapshots of the stream of `options` into the folder of `config` and detects on
+/// them as they arrive in one run, appending to its result file, until `stop` is
+/// cancelled.
+pub(crate) async fn run<H: Host>(
+    host: H,
+    config: Config,
+    options: LiveOptions,
+    stop: CancellationToken,
+) {
+    let events = host.sink();
+    let sink: &dyn EventSink = events.as_ref();
+    let folder = PathBuf::from(&config.detect_options.selected_folder);
+    if let Err(e) = std::fs::create_dir_all(&folder) {
+        log::error!("Failed to create {}: {}", folder.display(), e);
+        sink.emit("live-error", e.to_string());
+        return;
+    }
+    let (files, followed) = crossbeam_channel::unbounded();
+    let (frames, detected) = crossbeam_channel::unbounded();
+    let follow = Follow {
+        files: followed,
+        frames,
+        keep: options.keep_snapshots,
+    };
+    let alert_options = options.clone();
+    let alert_sink = host.sink();
+    let alerts = tokio::task::spawn_blocking(move || {
+        raise_alerts(detected, &alert_options, alert_sink.as_ref())
+    });
+    log::info!("Monitoring {} into {}", options.url, folder.display());
+    tokio::join!(
+        capture(&options, &folder, sink, &stop),
+        follow_snapshots(&options, &folder, files, sink, &stop),
+        detect(&host, config, follow, &stop),
+    );
+    let _ = alerts.await;
+    log::info!("Stopped monitoring {}", options.url);
+    sink.emit("live-stopped", &options.url);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::testing;
+
+    #[test]
+    fn test_live_options() {
+        let options: LiveOptions =
+            serde_json::from_str(r#"{"url": "rtsp://10.0.0.5:554/stream1", "interval": 0.2}"#)
+                .unwrap();
+        assert_eq!(options.alert_classes, ["Animal"]);
+        let args = capture_args(&options, Path::new("live"));
+        assert_eq!(
+            args[..4],
+            [
+                "-rtsp_transport",
+                "tcp",
+                "-i",
+                "rtsp://10.0.0.5:554/stream1"
+            ]
+        );
+        assert!(args.contains(&"fps=1/1".to_string()));
+        assert!(args.last().unwrap().ends_with("live-%Y%m%d-%H%M%S.jpg"));
+
+        let options = LiveOptions {
+            url: "http://10.0.0.5/mjpeg".to_string(),
+            ..Default::default()
+        };
+        let args = capture_args(&options, Path::new("live"));
+        assert_eq!(args[0], "-i");
+        assert!(args.contains(&"fps=1/5".to_string()));
+
+        assert_eq!(reconnect_delay(0), Duration::from_secs(1));
+        assert_eq!(reconnect_delay(3), Duration::from_secs(8));
+        assert_eq!(reconnect_delay(20), MAX_RECONNECT_DELAY);
+    }
+
+    #[test]
+    fn test_frame_alert() {
+        let frame = |path: &str, boxes: &[(usize, f32)]| ExportFrame {
+            bboxes: Some(
+                boxes
+                    .iter()
+                    .map(|&(class, score)| testing::bbox(class, score))
+                    .collect(),
+            ),
+            ..testing::frame(path)
+        };
+        let frames = vec![
+            frame("live-1.jpg", &[]),
+            frame("live-2.jpg", &[(0, 0.3), (0, 0.8), (1, 0.9)]),
+            frame("live-3.jpg", &[(2, 0.9)]),
+        ];
+        let options = LiveOptions::default();
+        let alerts: Vec<LiveAlert> = frames
+            .iter()
+            .filter_map(|f| frame_alert(f, &options))
+            .collect();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].file, PathBuf::from("live-2.jpg"));
+        assert_eq!((alerts[0].label.as_str(), alerts[0].score), ("Animal", 0.8));
+
+        let options = LiveOptions {
+            alert_classes: vec!["person".to_string(), "Vehicle".to_string()],
+            ..Default::default()
+        };
+        let alerts: Vec<LiveAlert> = frames
+            .iter()
+            .filter_map(|f| frame_alert(f, &options))
+            .collect();
+        let labels: Vec<&str> = alerts.iter().map(|a| a.label.as_str()).collect();
+        assert_eq!(labels, ["Person", "Vehicle"]);
+    }
+
+    #[test]
+    fn test_snapshots() {
+        let dir = testing::temp_dir();
+        for name in [
+            "live-20240501-063002.jpg",
+            "live-20240501-063001.jpg",
+            "notes.txt",
+        ] {
+            std::fs::write(dir.join(name), b"").unwrap();
+        }
+        let mut snapshots = Snapshots::load(&dir, 3);
+        assert_eq!(
+            snapshots.stored,
+            [
+                dir.join("live-20240501-063001.jpg"),
+                dir.join("live-20240501-063002.jpg")
+            ]
+        );
+        assert!(snapshots
+            .push(dir.join("live-20240501-063003.jpg"))
+            .is_empty());
+        assert_eq!(
+            snapshots.push(dir.join("live-20240501-063004.jpg")),
+            [dir.join("live-20240501-063001.jpg")]
+        );
+        assert_eq!(snapshots.stored.len(), 3);
+
+        let mut all = Snapshots::load(&dir, 0);
+        assert!(all.push(dir.join("live-20240501-063003.jpg")).is_empty());
+        assert!(!is_snapshot(Path::new("live/notes.txt")));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,273 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use image::{imageops::FilterType, DynamicImage, ImageFormat};
+use ndarray::{Array4, ArrayViewD, Axis, Ix2};
+use ort::session::{builder::GraphOptimizationLevel, Session};
+
+use crate::export::CLASS_NAMES;
+use crate::md5rs::{Bbox, DetectRequest, DetectResponse};
+use crate::protocol::ImageCodec;
+
+/// Input size of MegaDetector v5, used when the model doesn't fix its own.
+const DEFAULT_INPUT_SIZE: u32 = 1280;
+/// Gray of the letterbox padding, as in training.
+const PAD_VALUE: f32 = 114.0 / 255.0;
+
+/// Where the frame ended up inside the square model input.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Letterbox {
+    pub scale: f32,
+    pub pad_x: f32,
+    pub pad_y: f32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Letterbox {
+    pub fn new(width: u32, height: u32, size: u32) -> Self {
+        let scale = size as f32 / width.max(height) as f32;
+        Self {
+            scale,
+            pad_x: (size as f32 - width as f32 * scale) / 2.0,
+            pad_y: (size as f32 - height as f32 * scale) / 2.0,
+            width,
+            height,
+        }
+    }
+
+    /// Box in model input pixels, centered, to normalized frame coordinates.
+    fn to_frame(self, cx: f32, cy: f32, w: f32, h: f32) -> [f32; 4] {
+        let x = |v: f32| ((v - self.pad_x) / self.scale / self.width as f32).clamp(0.0, 1.0);
+        let y = |v: f32| ((v - self.pad_y) / self.scale / self.height as f32).clamp(0.0, 1.0);
+        [
+            x(cx - w / 2.0),
+            y(cy - h / 2.0),
+            x(cx + w / 2.0),
+            y(cy + h / 2.0),
+        ]
+    }
+}
+
+fn iou(a: &Bbox, b: &Bbox) -> f32 {
+    let w = (a.x2.min(b.x2) - a.x1.max(b.x1)).max(0.0);
+    let h = (a.y2.min(b.y2) - a.y1.max(b.y1)).max(0.0);
+    let intersection = w * h;
+    let union = (a.x2 - a.x1) * (a.y2 - a.y1) + (b.x2 - b.x1) * (b.y2 - b.y1) - intersection;
+    if union <= 0.0 {
+        0.0
+    } else {
+        intersection / union
+    }
+}
+
+/// Greedy non-maximum suppression within each class, most confident first.
+pub fn nms(mut boxes: Vec<Bbox>, iou_threshold: f32) -> Vec<Bbox> {
+    boxes.sort_by(|a, b| b.score.total_cmp(&a.score));
+    let mut kept: Vec<Bbox> = Vec::new();
+    for bbox in boxes {
+        if kept
+            .iter()
+            .all(|k| k.class != bbox.class || iou(k, &bbox) <= iou_threshold)
+        {
+            kept.push(bbox);
+        }
+    }
+    kept
+}
+
+/// Boxes of a YOLO output above `score_threshold`. Takes both the YOLOv5 layout
+/// `[1, boxes, 5 + classes]` with objectness and the transposed later one
+/// `[1, 4 + classes, boxes]` without.
+pub fn decode(
+    output: ArrayViewD<f32>,
+    letterbox: Letterbox,
+    score_threshold: f32,
+    iou_threshold: f32,
+) -> Result<Vec<Bbox>> {
+    let shape = output.shape().to_vec();
+    if shape.len() != 3 {
+        return Err(anyhow!("Unexpected detector output shape {:?}", shape));
+    }
+    let output = output.remove_axis(Axis(0)).into_dimensionality::<Ix2>()?;
+    // there are far more candidate boxes than values per box
+    let (predictions, objectness) = if shape[1] < shape[2] {
+        (output.reversed_axes(), false)
+    } else {
+        (output, true)
+    };
+    let first_class = if objectness { 5 } else { 4 };
+    if predictions.shape()[1] <= first_class {
+        return Err(anyhow!("Unexpected detector output shape {:?}", shape));
+    }
+
+    let mut boxes = Vec::new();
+    for row in predictions.outer_iter() {
+        let (class, class_score) = row.iter().skip(first_class).copied().enumerate().fold(
+            (0, f32::MIN),
+            |best, (i, s)| if s > best.1 { (i, s) } else { best },
+        );
+        let score = if objectness {
+            row[4] * class_score
+        } else {
+            class_score
+        };
+        if score < score_threshold {
+            continue;
+        }
+        let [x1, y1, x2, y2] = letterbox.to_frame(row[0], row[1], row[2], row[3]);
+        boxes.push(Bbox {
+            x1,
+            y1,
+            x2,
+            y2,
+            class: class as i32,
+            score,
+            embedding: Vec::new(),
+        });
+    }
+    Ok(nms(boxes, iou_threshold))
+}
+
+/// Labels of a frame the way the server reports them.
+pub fn frame_labels(boxes: &[Bbox]) -> Vec<String> {
+    let mut labels: Vec<String> = Vec::new();
+    for bbox in boxes {
+        let name = CLASS_NAMES
+            .get(bbox.class as usize)
+            .map_or_else(|| format!("Class {}", bbox.class), |n| n.to_string());
+        if !labels.contains(&name) {
+            labels.push(name);
+        }
+    }
+    if labels.is_empty() {
+        labels.push("Blank".to_string());
+    }
+    labels
+}
+
+/// Detector run with ONNX Runtime on this machine, answering the same requests as the
+/// server so the pipeline works offline. Embeddings are not computed.
+pub struct LocalDetector {
+    session: Session,
+    size: u32,
+}
+
+impl LocalDetector {
+    pub fn load(model_path: &Path) -> Result<Self> {
+        let session = Session::builder()?
+            .with_optimization_level(GraphOptimizationLevel::Level3)?
+            .commit_from_file(model_path)?;
+        // NCHW, dynamic axes are negative
+        let size = session
+            .inputs
+            .first()
+            .and_then(|input| input.input_type.tensor_dimensions())
+            .and_then(|dims| dims.get(3).copied())
+            .filter(|size| *size > 0)
+            .map_or(DEFAULT_INPUT_SIZE, |size| size as u32);
+        log::info!(
+            "Loaded local detector {} with input size {}",
+            model_path.display(),
+            size
+        );
+        Ok(Self { session, size })
+    }
+
+    fn input(&self, img: &DynamicImage) -> (Array4<f32>, Letterbox) {
+        let letterbox = Letterbox::new(img.width(), img.height(), self.size);
+        let resized = img
+            .resize_exact(
+                (img.width() as f32 * letterbox.scale).round() as u32,
+                (img.height() as f32 * letterbox.scale).round() as u32,
+                FilterType::Triangle,
+            )
+            .to_rgb8();
+        let size = self.size as usize;
+        let mut input = Array4::<f32>::from_elem((1, 3, size, size), PAD_VALUE);
+        let (left, top) = (letterbox.pad_x as usize, letterbox.pad_y as usize);
+        for (x, y, pixel) in resized.enumerate_pixels() {
+            let (x, y) = (left + x as usize, top + y as usize);
+            if x < size && y < size {
+                for c in 0..3 {
+                    input[[0, c, y, x]] = pixel[c] as f32 / 255.0;
+                }
+            }
+        }
+        (input, letterbox)
+    }
+
+    pub fn detect(&self, request: &DetectRequest) -> Result<DetectResponse> {
+        let format = match ImageCodec::from_request(&request.codec) {
+            Some(ImageCodec::Webp) => ImageFormat::WebP,
+            Some(ImageCodec::Jpeg) => ImageFormat::Jpeg,
+            Some(ImageCodec::Png) => ImageFormat::Png,
+            Some(ImageCodec::Avif) => ImageFormat::Avif,
+            None => return Err(anyhow!("Unsupported image codec {}", request.codec)),
+        };
+        let img = image::load_from_memory_with_format(&request.image, format)?;
+        let (input, letterbox) = self.input(&img);
+        let outputs = self.session.run(ort::inputs![input]?)?;
+        let output = outputs[0].try_extract_tensor::<f32>()?;
+        let bboxs = decode(output, letterbox, request.score, request.iou)?;
+        Ok(DetectResponse {
+            uuid: request.uuid.clone(),
+            label: frame_labels(&bboxs),
+            bboxs,
+            iframe: request.iframe,
+            embedding: Vec::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::Array3;
+
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        // a 200x100 frame in a 400x400 input, scaled by 2 and padded by 100 rows
+        let letterbox = Letterbox::new(200, 100, 400);
+        assert_eq!(
+            (letterbox.scale, letterbox.pad_x, letterbox.pad_y),
+            (2.0, 0.0, 100.0)
+        );
+
+        // unused candidates are zero, there are always more candidates than values
+        let mut output = Array3::<f32>::zeros((1, 10, 8));
+        // two overlapping animals and a weak person, YOLOv5 layout
+        let rows = [
+            [200.0, 200.0, 100.0, 100.0, 0.9, 0.9, 0.1, 0.0],
+            [205.0, 200.0, 100.0, 100.0, 0.8, 0.9, 0.1, 0.0],
+            [100.0, 150.0, 40.0, 40.0, 0.5, 0.1, 0.3, 0.0],
+        ];
+        for (i, row) in rows.iter().enumerate() {
+            for (j, v) in row.iter().enumerate() {
+                output[[0, i, j]] = *v;
+            }
+        }
+        let boxes = decode(output.view().into_dyn(), letterbox, 0.1, 0.45).unwrap();
+        assert_eq!(boxes.len(), 2);
+        assert_eq!(boxes[0].class, 0);
+        assert!((boxes[0].score - 0.81).abs() < 1e-6);
+        assert_eq!(
+            [boxes[0].x1, boxes[0].y1, boxes[0].x2, boxes[0].y2],
+            [0.375, 0.25, 0.625, 0.75]
+        );
+        assert_eq!(boxes[1].class, 1);
+        assert_eq!(frame_labels(&boxes), ["Animal", "Person"]);
+
+        // the same predictions transposed, without objectness
+        let transposed = Array3::<f32>::from_shape_fn((1, 6, 10), |(_, j, i)| match (i, j) {
+            (3.., _) => 0.0,
+            (_, 0..4) => rows[i][j],
+            _ => rows[i][j + 1] * rows[i][4],
+        });
+        let again = decode(transposed.view().into_dyn(), letterbox, 0.1, 0.45).unwrap();
+        assert_eq!(again.len(), 2);
+        assert!((again[0].score - boxes[0].score).abs() < 1e-6);
+        assert!(frame_labels(&[]) == ["Blank"]);
+    }
+}
@@ -2,5 +2,12 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 fn main() {
+    if std::env::args().any(|arg| arg == megascops_lib::daemon::DAEMON_ARG) {
+        if let Err(e) = megascops_lib::daemon::main() {
+            eprintln!("Megascops daemon failed: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
     megascops_lib::run()
 }
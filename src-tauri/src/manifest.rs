@@ -0,0 +1,58 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use serde::Serialize;
+
+use crate::announcement::CLIENT_VERSION;
+use crate::usage::ResourceUsage;
+
+/// Written next to the result file at the end of every run.
+pub const RUN_MANIFEST: &str = "run-manifest.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RunStatus {
+    Complete,
+    Failed,
+}
+
+/// What ran, when, and what it cost.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RunManifest {
+    pub client_version: String,
+    pub folder: PathBuf,
+    pub started_at: DateTime<Local>,
+    pub finished_at: DateTime<Local>,
+    pub status: RunStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub usage: ResourceUsage,
+}
+
+impl RunManifest {
+    pub fn new(folder: &Path, started_at: DateTime<Local>, usage: ResourceUsage) -> Self {
+        Self {
+            client_version: CLIENT_VERSION.to_string(),
+            folder: folder.to_path_buf(),
+            started_at,
+            finished_at: Local::now(),
+            status: RunStatus::Complete,
+            error: None,
+            usage,
+        }
+    }
+
+    pub fn failed(mut self, error: String) -> Self {
+        self.status = RunStatus::Failed;
+        self.error = Some(error);
+        self
+    }
+
+    pub fn write(&self) -> Result<PathBuf> {
+        let path = self.folder.join(RUN_MANIFEST);
+        std::fs::write(&path, serde_json::to_string_pretty(self)?)?;
+        Ok(path)
+    }
+}
@@ -1,55 +1,74 @@
-use std::fs::{metadata, File};
-use std::io::BufReader;
-use std::path::{Path, PathBuf};
-use std::process::{Command, Stdio};
-use std::str;
+use std::path::PathBuf;
 use std::thread;
 use std::time::Duration;
 
-use anyhow::{anyhow, Context, Result};
-use chrono::{DateTime, Local, TimeZone};
-use crossbeam_channel::Sender;
-use fast_image_resize::{ResizeAlg, ResizeOptions, Resizer};
-use ffmpeg_sidecar::command::FfmpegCommand;
-use ffmpeg_sidecar::event::{FfmpegEvent, LogLevel};
-use ffmpeg_sidecar::ffprobe::ffprobe_path;
-use ffmpeg_sidecar::iter::FfmpegIterator;
-use image::{DynamicImage, GenericImageView, ImageReader};
-use jpeg_decoder::Decoder;
-use nom_exif::{EntryValue, Exif, ExifIter, ExifTag, MediaParser, MediaSource};
-use thiserror::Error;
-use webp::Encoder;
-
-use crate::utils::{sample_evenly, FileItem};
-
-//define meadia error
-#[derive(Error, Debug)]
-pub enum MediaError {
-    #[error("Failed to open file: {0}")]
-    IoError(#[from] std::io::Error),
-
-    #[error("Failed to decode: {0}")]
-    ImageDecodeError(#[from] jpeg_decoder::Error),
-
-    #[error("Failed to decode: {0}")]
-    VideoDecodeError(String),
-
-    #[error("Failed to encode: {0}")]
-    WebpEncodeError(String),
-
-    #[error("Ffmpeg error when decoding {1}: {0}")]
-    FfmpegError(String, String),
-}
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Local};
+use image::DynamicImage;
+use megascops_media::{
+    decode_animation, decode_image, decode_samples, decode_tiff_page, get_image_metadata,
+    is_animation, is_bmp, is_heif, is_tiff, probe_image, read_heif, resize_encode,
+    sample_animation, sample_evenly, spawn_heif_decoder, CameraInfo, DecodedVideo, ImageMetadata,
+    MediaKind, Resizer,
+};
+use nom_exif::MediaParser;
+use tokio::sync::mpsc;
+
+pub use megascops_media::{
+    extract_frame, get_image_date, get_video_date, get_video_dimensions, shrink_webp,
+    EncodeFallback, FrameSampling, MediaError, WebpOptions,
+};
+
+use crate::background::BackgroundModels;
+use crate::encode::EncodePool;
+use crate::events::ProgressCounter;
+use crate::prefilter::PreFilter;
+use crate::protocol::ImageCodec;
+use crate::utils::FileItem;
 
 pub struct Frame {
     pub file: FileItem,
     pub webp: Vec<u8>,
+    /// Encoding of `webp`, other than WebP for images sent as read from disk.
+    pub codec: ImageCodec,
+    /// How the encoder degraded the frame, if it had to.
+    pub fallback: Option<EncodeFallback>,
     pub width: usize,
     pub height: usize,
     pub frame_index: usize,
     pub total_frames: usize,
     pub shoot_time: Option<DateTime<Local>>,
+    /// Latitude and longitude of an image with GPS tags.
+    pub position: Option<(f64, f64)>,
+    pub camera: CameraInfo,
     pub iframe: bool,
+    /// Non-blank probability from the local pre-filter, if one is loaded.
+    pub prefilter_score: Option<f32>,
+    /// Fraction of the frame that differs from its folder's background model.
+    pub foreground: Option<f32>,
+}
+
+/// Local checks that can mark a frame as blank before it is uploaded.
+#[derive(Clone, Copy, Default)]
+pub struct BlankFilters<'a> {
+    pub prefilter: Option<&'a PreFilter>,
+    pub background: Option<&'a BackgroundModels>,
+}
+
+impl BlankFilters<'_> {
+    fn is_empty(&self) -> bool {
+        self.prefilter.is_none() && self.background.is_none()
+    }
+
+    /// Returns the pre-filter score and the foreground mass of `img`.
+    fn score(&self, file: &FileItem, img: &DynamicImage) -> (Option<f32>, Option<f32>) {
+        let prefilter_score = self.prefilter.and_then(|p| p.try_score(img));
+        let foreground = self.background.and_then(|b| {
+            let folder = file.file_path.parent()?.to_path_buf();
+            b.foreground(folder, img)
+        });
+        (prefilter_score, foreground)
+    }
 }
 
 pub struct ErrFile {
@@ -65,30 +84,79 @@ pub enum WebpItem {
 pub fn media_worker(
     file: FileItem,
     imgsz: usize,
-    quality: f32,
-    iframe: bool,
-    max_frames: Option<usize>,
-    array_q_s: Sender<WebpItem>,
-    progress_sender: Sender<usize>,
+    webp: WebpOptions,
+    sampling: FrameSampling,
+    filters: BlankFilters,
+    passthrough: &[ImageCodec],
+    encoder: &EncodePool,
+    array_q_s: mpsc::Sender<WebpItem>,
+    progress: &ProgressCounter,
 ) {
+    // a broken file must never take a worker down, whatever fails is exported as its error
+    if let Err(error) = process_file(
+        &file,
+        imgsz,
+        webp,
+        sampling,
+        filters,
+        passthrough,
+        encoder,
+        array_q_s.clone(),
+    ) {
+        log::error!("Failed to process {}: {}", file.file_path.display(), error);
+        let err_file = WebpItem::ErrFile(ErrFile {
+            file: file.clone(),
+            error,
+        });
+        if array_q_s.blocking_send(err_file).is_err() {
+            log::warn!(
+                "Pipeline closed, dropping error of {}",
+                file.file_path.display()
+            );
+        }
+    }
+    if &file.file_path != &file.tmp_path {
+        if let Err(e) = remove_file_with_retries(&file.tmp_path, 3, Duration::from_secs(1)) {
+            log::warn!("Failed to remove {}: {}", file.tmp_path.display(), e);
+        }
+    }
+    progress.add(1);
+}
+
+fn process_file(
+    file: &FileItem,
+    imgsz: usize,
+    webp: WebpOptions,
+    sampling: FrameSampling,
+    filters: BlankFilters,
+    passthrough: &[ImageCodec],
+    encoder: &EncodePool,
+    array_q_s: mpsc::Sender<WebpItem>,
+) -> Result<()> {
     let mut parser = MediaParser::new();
     let mut resizer = Resizer::new();
-    if let Some(extension) = file.file_path.extension() {
-        let array_q_s = array_q_s.clone();
-        match extension.to_str().unwrap().to_lowercase().as_str() {
-            "jpg" | "jpeg" | "png" => {
-                process_image(&file, imgsz, quality, &mut parser, &mut resizer, array_q_s).unwrap();
-            }
-            "mp4" | "avi" | "mkv" | "mov" => {
-                process_video(&file, imgsz, quality, iframe, max_frames, array_q_s).unwrap();
-            }
-            _ => (),
-        }
-        if &file.file_path != &file.tmp_path {
-            remove_file_with_retries(&file.tmp_path, 3, Duration::from_secs(1))
-                .expect("Failed to remove file");
+    // folder policies take precedence over the global options
+    let policy = file.policy.clone();
+    let sampling = match &policy {
+        Some(p) => sampling.with_policy(p.iframe_only, p.max_frames),
+        None => sampling,
+    };
+    match MediaKind::of(&file.file_path) {
+        Some(MediaKind::Image) => process_image(
+            file,
+            imgsz,
+            webp,
+            &mut parser,
+            &mut resizer,
+            sampling,
+            filters,
+            passthrough,
+            array_q_s,
+        ),
+        Some(MediaKind::Video) => {
+            process_video(file, imgsz, webp, sampling, filters, encoder, array_q_s)
         }
-        progress_sender.send(1).expect("Send progress failed");
+        None => Ok(()),
     }
 }
 
@@ -120,144 +188,164 @@ fn remove_file_with_retries(file_path: &PathBuf, max_retries: u32, delay: Durati
     Ok(())
 }
 
-fn decode_image(file: &FileItem) -> Result<DynamicImage> {
-    let img = match ImageReader::open(file.tmp_path.as_path())
-        .map_err(MediaError::IoError)?
-        .decode()
-    {
-        Ok(img) => DynamicImage::ImageRgb8(img.to_rgb8()),
-        Err(_e) => {
-            log::warn!(
-                "Failed to decode image with ImageReader. Trying jpeg_decoder. {:?}",
-                _e
-            );
-            let img_reader = File::open(file.tmp_path.as_path()).map_err(MediaError::IoError)?;
-            let mut decoder = Decoder::new(BufReader::new(img_reader));
-            let pixels = decoder.decode().map_err(MediaError::ImageDecodeError)?;
-            let img = DynamicImage::ImageRgb8(
-                image::ImageBuffer::from_raw(
-                    decoder.info().unwrap().width as u32,
-                    decoder.info().unwrap().height as u32,
-                    pixels,
-                )
-                .unwrap(),
-            );
-            img
+/// Decodes an image of the run as its frames by index: the first page of a multi-page
+/// TIFF, the frames `sampling` keeps of an animated GIF or PNG. HEIF images are decoded by
+/// ffmpeg, which runs at the priority of the worker like the video decodes.
+fn decode_file(file: &FileItem, sampling: FrameSampling) -> Result<Vec<(usize, DynamicImage)>> {
+    let path = file.tmp_path.as_path();
+    if is_tiff(path) {
+        return Ok(vec![(0, decode_tiff_page(path)?)]);
+    }
+    if is_animation(path) {
+        if let Some(frames) = decode_animation(path)? {
+            let starts: Vec<Duration> = frames.iter().map(|(_, start)| *start).collect();
+            let kept = sample_animation(&starts, sampling);
+            return Ok(frames
+                .into_iter()
+                .enumerate()
+                .filter(|(index, _)| kept.contains(index))
+                .map(|(index, (img, _))| (index, img))
+                .collect());
+        }
+    }
+    if !is_heif(path) {
+        return Ok(vec![(0, decode_image(path)?)]);
+    }
+    let mut child = spawn_heif_decoder(path)?;
+    crate::priority::inherit(child.as_inner());
+    Ok(vec![(0, read_heif(&mut child, path)?)])
+}
+
+fn image_metadata(parser: &mut MediaParser, file: &FileItem) -> ImageMetadata {
+    // TIFF keeps EXIF like JPEG
+    if is_bmp(&file.file_path) {
+        return ImageMetadata::default();
+    }
+    let metadata = match get_image_metadata(parser, file.tmp_path.as_path()) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            log::error!("Failed to read {} EXIF: {}", file.file_path.display(), e);
+            return ImageMetadata::default();
         }
     };
-    Ok(img)
+    if metadata.shoot_time.is_none() {
+        log::error!("Failed to get {} shoot time", file.file_path.display());
+    }
+    metadata
+}
+
+/// The file as it is when it is no larger than `imgsz` and in one of `codecs`, read from
+/// its header without decoding.
+fn passthrough_frame(
+    file: &FileItem,
+    imgsz: usize,
+    codecs: &[ImageCodec],
+    parser: &mut MediaParser,
+) -> Option<Frame> {
+    let (codec, width, height) = probe_image(file.tmp_path.as_path())?;
+    if !codecs.contains(&codec) {
+        return None;
+    }
+    if width.max(height) as usize > imgsz {
+        return None;
+    }
+    let data = std::fs::read(file.tmp_path.as_path()).ok()?;
+    let metadata = image_metadata(parser, file);
+    Some(Frame {
+        webp: data,
+        codec,
+        fallback: None,
+        file: file.clone(),
+        width: width as usize,
+        height: height as usize,
+        frame_index: 0,
+        total_frames: 1,
+        shoot_time: metadata.shoot_time,
+        position: metadata.position,
+        camera: metadata.camera,
+        iframe: false,
+        prefilter_score: None,
+        foreground: None,
+    })
 }
 
 pub fn process_image(
     file: &FileItem,
     imgsz: usize,
-    quality: f32,
+    webp: WebpOptions,
     parser: &mut MediaParser,
     resizer: &mut Resizer,
-    array_q_s: Sender<WebpItem>,
+    sampling: FrameSampling,
+    filters: BlankFilters,
+    passthrough: &[ImageCodec],
+    array_q_s: mpsc::Sender<WebpItem>,
 ) -> Result<()> {
-    let frame_data = match decode_image(file) {
-        Ok(img) => {
-            let webp: Option<Vec<u8>> = match resize_encode(&img, imgsz as u32, quality, resizer) {
-                Ok(webp) => Some(webp),
-                Err(_e) => None,
-            };
-            let shoot_time: Option<DateTime<Local>> =
-                match get_image_date(parser, file.tmp_path.as_path()) {
-                    Ok(shoot_time) => Some(shoot_time),
-                    Err(_e) => {
-                        log::error!(
-                            "Failed to get {} shoot time error: {}",
-                            file.file_path.display(),
-                            _e
-                        );
-                        None
-                    }
-                };
-            if webp.is_none() {
-                WebpItem::ErrFile(ErrFile {
-                    file: file.clone(),
-                    error: MediaError::WebpEncodeError("Failed to encode image".to_string()).into(),
-                })
-            } else {
-                let webp = webp.unwrap();
-                let frame_data = Frame {
-                    webp,
+    // the blank filters need the pixels, without them small images can skip decoding
+    if filters.is_empty() && !passthrough.is_empty() {
+        if let Some(frame) = passthrough_frame(file, imgsz, passthrough, parser) {
+            array_q_s
+                .blocking_send(WebpItem::Frame(frame))
+                .map_err(|_| MediaError::ChannelClosed)?;
+            return Ok(());
+        }
+    }
+    let pages = match decode_file(file, sampling) {
+        Ok(pages) => pages,
+        Err(error) => {
+            let err_file = WebpItem::ErrFile(ErrFile {
+                file: file.clone(),
+                error,
+            });
+            array_q_s
+                .blocking_send(err_file)
+                .map_err(|_| MediaError::ChannelClosed)?;
+            return Ok(());
+        }
+    };
+    let metadata = image_metadata(parser, file);
+    // the frames of an animation are sent as the frames of one file, like a video's
+    let total_frames = pages.len();
+    for &(frame_index, ref img) in &pages {
+        let frame_data = match resize_encode(img, imgsz as u32, webp, resizer) {
+            Err(_e) => WebpItem::ErrFile(ErrFile {
+                file: file.clone(),
+                error: MediaError::WebpEncodeError("Failed to encode image".to_string()).into(),
+            }),
+            Ok(encoded) => {
+                let (prefilter_score, foreground) = filters.score(file, img);
+                WebpItem::Frame(Frame {
+                    webp: encoded.data,
+                    codec: encoded.codec,
+                    fallback: encoded.fallback,
                     file: file.clone(),
                     width: img.width() as usize,
                     height: img.height() as usize,
-                    frame_index: 0,
-                    total_frames: 1,
-                    shoot_time,
+                    frame_index,
+                    total_frames,
+                    shoot_time: metadata.shoot_time,
+                    position: metadata.position,
+                    camera: metadata.camera.clone(),
                     iframe: false,
-                };
-                WebpItem::Frame(frame_data)
+                    prefilter_score,
+                    foreground,
+                })
             }
-        }
-        Err(error) => WebpItem::ErrFile(ErrFile {
-            file: file.clone(),
-            error,
-        }),
-    };
-    match array_q_s.send(frame_data) {
-        Ok(_) => (),
-        Err(_e) => log::error!("Failed to send frame data, channel disconnected"),
+        };
+        array_q_s
+            .blocking_send(frame_data)
+            .map_err(|_| MediaError::ChannelClosed)?;
     }
     Ok(())
 }
 
-fn resize_encode(
-    img: &DynamicImage,
-    imgsz: u32,
-    quality: f32,
-    resizer: &mut Resizer,
-) -> Result<Vec<u8>> {
-    // Get the dimensions of the original image
-    let (width, height) = img.dimensions();
-    let mut resized_width = imgsz;
-    let mut resized_height = imgsz;
-    let ratio: f32;
-
-    if width > height {
-        ratio = width as f32 / imgsz as f32;
-        resized_height = (height as f32 / ratio) as u32;
-        resized_height = resized_height % 2 + resized_height;
-    } else {
-        ratio = height as f32 / imgsz as f32;
-        resized_width = (width as f32 / ratio) as u32;
-        resized_width = resized_width % 2 + resized_width;
-    }
-
-    let mut resized_img = DynamicImage::new(resized_width, resized_height, img.color());
-
-    let resize_option = ResizeOptions::new().resize_alg(ResizeAlg::Nearest);
-
-    resizer
-        .resize(img, &mut resized_img, &resize_option)
-        .unwrap();
-
-    let encoder = Encoder::from_image(&resized_img);
-
-    match encoder {
-        Ok(encoder) => {
-            let webp = encoder.encode(quality);
-            let data = (&*webp).to_vec();
-            Ok(data)
-        }
-        Err(e) => {
-            log::error!("Failed to encode image: {:?}", e);
-            Err(MediaError::WebpEncodeError(e.to_string()).into())
-        }
-    }
-}
-
 pub fn process_video(
     file: &FileItem,
     imgsz: usize,
-    quality: f32,
-    iframe: bool,
-    max_frames: Option<usize>,
-    array_q_s: Sender<WebpItem>,
+    webp: WebpOptions,
+    sampling: FrameSampling,
+    filters: BlankFilters,
+    encoder: &EncodePool,
+    array_q_s: mpsc::Sender<WebpItem>,
 ) -> Result<()> {
     let video_path = file.tmp_path.to_string_lossy();
     let (orig_w, orig_h) = match get_video_dimensions(&video_path) {
@@ -270,117 +358,40 @@ pub fn process_video(
                 error,
             });
             array_q_s
-                .send(err_file)
-                .context("Failed to send dimension error")?;
+                .blocking_send(err_file)
+                .map_err(|_| MediaError::ChannelClosed)?;
             return Ok(());
         }
     };
-    let input = create_ffmpeg_iter(&video_path, imgsz, iframe)?;
+    let iframe = sampling.iframe();
+    let max_frames = sampling.max_frames();
+    // a decode started from a low priority worker runs at a low priority too
+    let decoded = decode_samples(&video_path, imgsz, sampling, &crate::priority::inherit)?;
 
     handle_ffmpeg_output(
-        input, array_q_s, file, quality, max_frames, orig_w, orig_h, iframe,
+        decoded, array_q_s, file, webp, max_frames, orig_w, orig_h, iframe, filters, encoder,
     )?;
 
     Ok(())
 }
 
-fn get_video_dimensions(video_path: &str) -> Result<(usize, usize)> {
-    let mut command = Command::new(ffprobe_path());
-
-    command.args([
-        "-v",
-        "error",
-        "-select_streams",
-        "v:0",
-        "-show_entries",
-        "stream=width,height",
-        "-of",
-        "csv=s=x:p=0",
-        video_path,
-    ]);
-
-    #[cfg(windows)]
-    {
-        use std::os::windows::process::CommandExt;
-        const CREATE_NO_WINDOW: u32 = 0x08000000;
-        command.creation_flags(CREATE_NO_WINDOW);
-    }
-
-    let output = command
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .output()?;
-
-    let dimensions = str::from_utf8(&output.stdout)?;
-    let parts: Vec<&str> = dimensions.trim().split('x').collect();
-
-    if parts.len() == 2 {
-        let width = parts[0].parse::<usize>()?;
-        let height = parts[1].parse::<usize>()?;
-        Ok((width, height))
-    } else {
-        Err(anyhow!(
-            "Invalid video dimensions: {}, video path: {}",
-            dimensions,
-            video_path
-        ))
-    }
-}
-
-fn create_ffmpeg_iter(video_path: &str, imgsz: usize, iframe: bool) -> Result<FfmpegIterator> {
-    let mut ffmpeg_command = FfmpegCommand::new();
-    if iframe {
-        ffmpeg_command.args(["-skip_frame", "nokey"]);
-    }
-    let iter = ffmpeg_command
-        .input(video_path)
-        .args(&[
-            "-an",
-            "-vf",
-            &format!(
-                "scale=w={}:h={}:force_original_aspect_ratio=decrease",
-                imgsz, imgsz
-            ),
-            "-f",
-            "rawvideo",
-            "-pix_fmt",
-            "rgb24",
-            "-vsync",
-            "vfr",
-        ])
-        .output("-")
-        .spawn()?
-        .iter()?;
-    Ok(iter)
-}
-
 fn handle_ffmpeg_output(
-    input: FfmpegIterator,
-    s: Sender<WebpItem>,
+    decoded: DecodedVideo,
+    s: mpsc::Sender<WebpItem>,
     file: &FileItem,
-    quality: f32,
+    webp: WebpOptions,
     max_frames: Option<usize>,
     orig_w: usize,
     orig_h: usize,
     iframe: bool,
+    filters: BlankFilters,
+    encoder: &EncodePool,
 ) -> Result<()> {
     let file_path = file.file_path.to_string_lossy().into_owned();
 
-    let mut frames = Vec::new();
-    let mut ffmpeg_error = Vec::new();
-    for event in input {
-        match event {
-            FfmpegEvent::Error(e) | FfmpegEvent::Log(LogLevel::Error, e) => {
-                ffmpeg_error.push(e);
-            }
-            FfmpegEvent::OutputFrame(frame) => {
-                frames.push(frame);
-            }
-            _ => (),
-        }
-    }
+    let DecodedVideo { frames, errors } = decoded;
 
-    for e in ffmpeg_error {
+    for e in errors {
         let error = MediaError::FfmpegError(e, file_path.clone());
         log::warn!("{:?}", error);
     }
@@ -392,9 +403,12 @@ fn handle_ffmpeg_output(
             file: file.clone(),
             error,
         });
-        s.send(frame_data).expect("Send video frame failed");
+        s.blocking_send(frame_data)
+            .map_err(|_| MediaError::ChannelClosed)?;
     } else {
-        let sampled_frames = sample_evenly(&frames, max_frames.unwrap_or(frames.len()));
+        // seeked or scene samples, or a video shorter than the sample, are all kept once
+        let sample_size = max_frames.map_or(frames.len(), |n| n.min(frames.len()));
+        let sampled_frames = sample_evenly(&frames, sample_size);
 
         let shoot_time: Option<DateTime<Local>> = match get_video_date(&file.tmp_path.as_path()) {
             Ok(shoot_time) => Some(shoot_time),
@@ -406,90 +420,96 @@ fn handle_ffmpeg_output(
         let frames_length = sampled_frames.len();
 
         for f in sampled_frames.into_iter() {
-            let encoder = Encoder::from_rgb(&f.data, f.width, f.height);
-
-            let webp = encoder.encode(quality);
-
-            let webp = (&*webp).to_vec();
+            // the filters score on the decoding thread, only the encode is handed off
+            let (prefilter_score, foreground) = if filters.is_empty() {
+                (None, None)
+            } else {
+                match image::RgbImage::from_raw(f.width, f.height, f.data.clone()) {
+                    Some(img) => filters.score(file, &DynamicImage::ImageRgb8(img)),
+                    None => (None, None),
+                }
+            };
 
-            let frame_data = WebpItem::Frame(Frame {
-                webp,
-                file: file.clone(),
-                width: orig_w,
-                height: orig_h,
-                frame_index: f.frame_num as usize,
-                total_frames: frames_length,
-                shoot_time,
-                iframe,
+            let s = s.clone();
+            let file = file.clone();
+            encoder.submit(move || {
+                let frame_num = f.frame_num;
+                let encoded = image::RgbImage::from_raw(f.width, f.height, f.data)
+                    .ok_or_else(|| anyhow!("Frame {} has an unexpected size", frame_num))
+                    .and_then(|img| webp.encode(&DynamicImage::ImageRgb8(img), true));
+                let frame_data = match encoded {
+                    Ok(encoded) => WebpItem::Frame(Frame {
+                        webp: encoded.data,
+                        codec: encoded.codec,
+                        fallback: encoded.fallback,
+                        width: orig_w,
+                        height: orig_h,
+                        frame_index: frame_num as usize,
+                        total_frames: frames_length,
+                        shoot_time,
+                        position: None,
+                        camera: CameraInfo::default(),
+                        iframe,
+                        prefilter_score,
+                        foreground,
+                        file,
+                    }),
+                    Err(error) => WebpItem::ErrFile(ErrFile { file, error }),
+                };
+                if s.blocking_send(frame_data).is_err() {
+                    log::warn!("Pipeline closed, dropping frame {}", frame_num);
+                }
             });
-            s.send(frame_data).expect("Send video frame failed");
         }
     }
     Ok(())
 }
 
-fn get_image_date(parser: &mut MediaParser, image: &Path) -> Result<DateTime<Local>> {
-    let ms = MediaSource::file_path(image)?;
-    let iter: ExifIter = parser.parse(ms)?;
-    let exif: Exif = iter.into();
-    let shoot_time_tag = exif
-        .get(ExifTag::DateTimeOriginal)
-        .or_else(|| exif.get(ExifTag::ModifyDate))
-        .context("Neither DateTimeOriginal nor ModifyDate found")?;
-
-    let shoot_time = match shoot_time_tag {
-        EntryValue::Time(time) => time.with_timezone(&Local),
-        EntryValue::NaiveDateTime(time) => {
-            Local.from_local_datetime(&time).single().ok_or_else(|| {
-                anyhow::anyhow!("Ambiguous local time for image: {}", image.display())
-            })?
-        }
-        _ => {
-            return Err(anyhow::anyhow!(
-                "Unexpected EXIF time data format for image: {}",
-                image.display()
-            ))
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::testing;
+
+    #[test]
+    fn test_broken_media() {
+        let dir = testing::temp_dir();
+        let broken = [
+            ("broken.jpg", b"not a jpeg".as_slice()),
+            ("empty.png", b"".as_slice()),
+            ("broken.tif", b"not a tiff".as_slice()),
+            ("broken.mp4", b"not a video".as_slice()),
+        ];
+        for (i, (name, content)) in broken.into_iter().enumerate() {
+            let path = dir.join(name);
+            std::fs::write(&path, content).unwrap();
+            let (array_q_s, mut array_q_r) = mpsc::channel(8);
+            let progress = ProgressCounter::default();
+            let encoder = EncodePool::new(1, 1, false, progress.clone());
+            media_worker(
+                FileItem::new(0, i, path, None),
+                640,
+                WebpOptions {
+                    avif: false,
+                    quality: 80.0,
+                    method: None,
+                    target_size: 0,
+                    sharp_yuv: false,
+                    jpeg_fallback: false,
+                },
+                FrameSampling::legacy(false, None),
+                None,
+                BlankFilters::default(),
+                &[],
+                &encoder,
+                array_q_s,
+                &progress,
+            );
+            match array_q_r.try_recv() {
+                Ok(WebpItem::ErrFile(err_file)) => assert!(err_file.file.file_path.ends_with(name)),
+                _ => panic!("{} was not reported as an error", name),
+            }
+            assert_eq!(progress.done(), 1);
         }
-    };
-    Ok(shoot_time)
-}
-
-fn get_video_date(video: &Path) -> Result<DateTime<Local>> {
-    let metadata = metadata(video)?;
-    #[cfg(target_os = "windows")]
-    {
-        let m_time = metadata.modified()?;
-        let shoot_time: DateTime<Local> = m_time.clone().into();
-
-        Ok(shoot_time)
-    }
-
-    #[cfg(target_os = "linux")]
-    #[allow(deprecated)]
-    {
-        use chrono::NaiveDateTime;
-        use std::os::linux::fs::MetadataExt;
-        let m_time: i64 = metadata.st_mtime();
-        let c_time: i64 = metadata.st_ctime();
-        let shoot_time = m_time.min(c_time);
-        let offset = Local::now().offset().to_owned();
-        let shoot_time = NaiveDateTime::from_timestamp(shoot_time, 0);
-        let shoot_time = DateTime::<Local>::from_naive_utc_and_offset(shoot_time, offset);
-
-        Ok(shoot_time)
-    }
-
-    #[cfg(target_os = "macos")]
-    {
-        use chrono::NaiveDateTime;
-        use std::os::unix::fs::MetadataExt;
-        let m_time: i64 = metadata.mtime();
-        let c_time: i64 = metadata.ctime();
-        let shoot_time = m_time.min(c_time);
-        let offset = Local::now().offset().to_owned();
-        let shoot_time = NaiveDateTime::from_timestamp(shoot_time, 0);
-        let shoot_time = DateTime::<Local>::from_naive_utc_and_offset(shoot_time, offset);
-
-        Ok(shoot_time)
+        std::fs::remove_dir_all(dir).unwrap();
     }
 }
@@ -0,0 +1,117 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use csv::WriterBuilder;
+use megascops_media::{image_dimensions, MediaKind};
+use nom_exif::{EntryValue, ExifIter, ExifTag, MediaParser, MediaSource};
+use rayon::prelude::*;
+use serde::Serialize;
+
+use crate::media::{get_image_date, get_video_date, get_video_dimensions};
+use crate::utils::{self, portable_path, FileItem, IndexOptions};
+
+pub const METADATA_FILE: &str = "metadata.csv";
+
+/// EXIF `Temperature` tag, written by some camera traps instead of a maker note.
+const TEMPERATURE_TAG: u16 = 0x9400;
+
+/// Metadata of one file, read without decoding it.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MediaMetadata {
+    pub file_path: String,
+    pub shoot_time: Option<String>,
+    pub make: Option<String>,
+    pub model: Option<String>,
+    /// ISO 6709 position, e.g. `+22.53113+114.02148/`.
+    pub gps: Option<String>,
+    /// Ambient temperature in degrees Celsius.
+    pub temperature: Option<f64>,
+    pub width: Option<usize>,
+    pub height: Option<usize>,
+    pub error: Option<String>,
+}
+
+fn entry_string(value: &EntryValue) -> String {
+    value.to_string().trim().to_string()
+}
+
+fn read_exif(parser: &mut MediaParser, path: &Path, metadata: &mut MediaMetadata) -> Result<()> {
+    let ms = MediaSource::file_path(path)?;
+    let mut iter: ExifIter = parser.parse(ms)?;
+    metadata.gps = iter
+        .parse_gps_info()
+        .ok()
+        .flatten()
+        .map(|gps| gps.format_iso6709());
+    for entry in iter {
+        let Some(value) = entry.get_value() else {
+            continue;
+        };
+        match entry.tag() {
+            Some(ExifTag::Make) => metadata.make = Some(entry_string(value)),
+            Some(ExifTag::Model) => metadata.model = Some(entry_string(value)),
+            _ if entry.tag_code() == TEMPERATURE_TAG => {
+                metadata.temperature = match value {
+                    EntryValue::SRational(v) if v.1 != 0 => Some(v.0 as f64 / v.1 as f64),
+                    EntryValue::URational(v) if v.1 != 0 => Some(v.0 as f64 / v.1 as f64),
+                    _ => None,
+                }
+            }
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+/// Reads the metadata of an image or video, errors are recorded instead of returned so
+/// one broken file doesn't stop the report.
+pub fn read_metadata(parser: &mut MediaParser, file: &FileItem) -> MediaMetadata {
+    let path = file.tmp_path.as_path();
+    let mut metadata = MediaMetadata {
+        file_path: portable_path(&file.file_path, None),
+        ..Default::default()
+    };
+    let result = match MediaKind::of(path) {
+        Some(MediaKind::Image) => {
+            metadata.shoot_time = get_image_date(parser, path).ok().map(|t| t.to_string());
+            if let Ok((width, height)) = image_dimensions(path) {
+                metadata.width = Some(width as usize);
+                metadata.height = Some(height as usize);
+            }
+            read_exif(parser, path, &mut metadata)
+        }
+        Some(MediaKind::Video) => {
+            metadata.shoot_time = get_video_date(path).ok().map(|t| t.to_string());
+            get_video_dimensions(&path.to_string_lossy()).map(|(width, height)| {
+                metadata.width = Some(width);
+                metadata.height = Some(height);
+            })
+        }
+        None => Ok(()),
+    };
+    if let Err(e) = result {
+        log::debug!("Failed to read metadata of {}: {}", path.display(), e);
+        metadata.error = Some(e.to_string());
+    }
+    metadata
+}
+
+/// Writes the metadata of every indexed file under `folder` to `output` as csv,
+/// returns the number of files.
+pub fn export_metadata(folder: &PathBuf, options: &IndexOptions, output: &Path) -> Result<usize> {
+    let index = utils::index_files_and_folders(folder, options, |_| {})?;
+    let mut files: Vec<FileItem> = index.files.into_iter().collect();
+    files.sort_by(|a, b| a.file_path.cmp(&b.file_path));
+    let rows: Vec<MediaMetadata> = files
+        .par_iter()
+        .map_init(MediaParser::new, read_metadata)
+        .collect();
+
+    let mut wtr = WriterBuilder::new().from_path(output)?;
+    for row in &rows {
+        wtr.serialize(row)?;
+    }
+    wtr.flush()?;
+    log::info!("Exported metadata of {} files", rows.len());
+    Ok(rows.len())
+}
@@ -0,0 +1,396 @@
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::export::{load_export, ExportFrame, CLASS_NAMES};
+use crate::report::has_error;
+use crate::template::{render, OutputTemplates, TemplateContext};
+use crate::utils::portable_path;
+
+/// Undo manifest of the files organized below a folder.
+pub const ORGANIZE_MANIFEST: &str = "organize.json";
+/// Label of files without any detection.
+pub const EMPTY_LABEL: &str = "Empty";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+pub enum OrganizeMode {
+    #[default]
+    Move,
+    Copy,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct OrganizeOptions {
+    pub mode: OrganizeMode,
+    /// Target of every file relative to the folder, an output template.
+    pub template: String,
+}
+
+impl Default for OrganizeOptions {
+    fn default() -> Self {
+        Self {
+            mode: OrganizeMode::default(),
+            template: OutputTemplates::default().organize,
+        }
+    }
+}
+
+/// A file moved or copied, paths relative to the folder.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrganizeEntry {
+    pub label: String,
+    pub mode: OrganizeMode,
+    pub from: String,
+    pub to: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrganizePlan {
+    pub entries: Vec<OrganizeEntry>,
+    /// Files left where they are, with the reason.
+    pub skipped: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrganizeSummary {
+    pub manifest: PathBuf,
+    pub organized: usize,
+    pub failed: Vec<(String, String)>,
+}
+
+/// Label of a file from the boxes of all its frames. Animal wins over Person and Person
+/// over Vehicle, so no animal ends up in another folder.
+pub fn file_label<'a>(frames: impl IntoIterator<Item = &'a ExportFrame>) -> &'static str {
+    let classes: HashSet<usize> = frames
+        .into_iter()
+        .flat_map(|f| f.bboxes.iter().flatten())
+        .map(|b| b.class)
+        .collect();
+    CLASS_NAMES
+        .iter()
+        .enumerate()
+        .find(|(i, _)| classes.contains(i))
+        .map_or(EMPTY_LABEL, |(_, name)| name)
+}
+
+/// Where every file of `frames` goes below `folder`, without touching anything. Failed
+/// files, missing ones and those whose target is taken stay where they are.
+pub fn plan_organize(
+    frames: &[ExportFrame],
+    folder: &Path,
+    options: &OrganizeOptions,
+) -> Result<OrganizePlan> {
+    let mut files: BTreeMap<String, Vec<&ExportFrame>> = BTreeMap::new();
+    for frame in frames {
+        let relative = portable_path(&folder.join(&frame.file.file_path), Some(folder));
+        files.entry(relative).or_default().push(frame);
+    }
+    let mut plan = OrganizePlan::default();
+    let mut targets = HashSet::new();
+    for (relative, frames) in files {
+        let path = folder.join(&relative);
+        if let Some(failed) = frames.iter().find(|f| has_error(f)) {
+            let error = failed.error.clone().unwrap_or_default();
+            plan.skipped.push((relative, error));
+            continue;
+        }
+        let label = file_label(frames.iter().copied());
+        let shoot_time = frames[0].shoot_time.as_deref();
+        let context = TemplateContext::new(&path, folder, shoot_time, label);
+        let target = portable_path(&render(&options.template, &context)?, None);
+        let skipped = if target == relative {
+            Some("Already in place")
+        } else if !path.is_file() {
+            Some("File not found")
+        } else if folder.join(&target).exists() || !targets.insert(target.clone()) {
+            Some("Target already taken")
+        } else {
+            None
+        };
+        match skipped {
+            Some(reason) => plan.skipped.push((relative, reason.to_string())),
+            None => plan.entries.push(OrganizeEntry {
+                label: label.to_string(),
+                mode: options.mode,
+                from: relative,
+                to: target,
+            }),
+        }
+    }
+    Ok(plan)
+}
+
+/// Moves `from` to `to`, copying across file systems where a rename fails.
+fn move_file(from: &Path, to: &Path) -> Result<()> {
+    if std::fs::rename(from, to).is_err() {
+        std::fs::copy(from, to)?;
+        std::fs::remove_file(from)?;
+    }
+    Ok(())
+}
+
+fn apply(entry: &OrganizeEntry, folder: &Path) -> Result<()> {
+    let (from, to) = (folder.join(&entry.from), folder.join(&entry.to));
+    if let Some(parent) = to.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    match entry.mode {
+        OrganizeMode::Move => move_file(&from, &to),
+        OrganizeMode::Copy => Ok(std::fs::copy(&from, &to).map(|_| ())?),
+    }
+}
+
+fn revert(entry: &OrganizeEntry, folder: &Path) -> Result<()> {
+    let (from, to) = (folder.join(&entry.from), folder.join(&entry.to));
+    match entry.mode {
+        OrganizeMode::Move => {
+            if from.exists() {
+                return Err(anyhow!("{} exists again", entry.from));
+            }
+            if let Some(parent) = from.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            move_file(&to, &from)?;
+        }
+        OrganizeMode::Copy => std::fs::remove_file(&to)?,
+    }
+    // drop the label folders emptied, up to the folder itself
+    let mut dir = to.parent();
+    while let Some(parent) = dir.filter(|d| *d != folder) {
+        if std::fs::remove_dir(parent).is_err() {
+            break;
+        }
+        dir = parent.parent();
+    }
+    Ok(())
+}
+
+fn load_manifest(path: &Path) -> Result<Vec<OrganizeEntry>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+}
+
+fn save_manifest(path: &Path, entries: &[OrganizeEntry]) -> Result<()> {
+    if entries.is_empty() {
+        if path.exists() {
+            std::fs::remove_file(path)?;
+        }
+        return Ok(());
+    }
+    Ok(std::fs::write(
+        path,
+        serde_json::to_string_pretty(entries)?,
+    )?)
+}
+
+/// Moves or copies the files of `frames` into a folder per label below `folder`, adding
+/// what was done to the undo manifest. The result file keeps the original paths.
+pub fn organize_frames(
+    frames: &[ExportFrame],
+    folder: &Path,
+    options: &OrganizeOptions,
+) -> Result<OrganizeSummary> {
+    let plan = plan_organize(frames, folder, options)?;
+    let manifest = folder.join(ORGANIZE_MANIFEST);
+    let mut entries = load_manifest(&manifest)?;
+    let mut summary = OrganizeSummary {
+        manifest: manifest.clone(),
+        ..Default::default()
+    };
+    for entry in plan.entries {
+        match apply(&entry, folder) {
+            Ok(()) => {
+                summary.organized += 1;
+                entries.push(entry);
+            }
+            Err(e) => {
+                log::warn!("Failed to organize {}: {}", entry.from, e);
+                summary.failed.push((entry.from, e.to_string()));
+            }
+        }
+    }
+    save_manifest(&manifest, &entries)?;
+    log::info!(
+        "Organized {} files below {}",
+        summary.organized,
+        folder.display()
+    );
+    Ok(summary)
+}
+
+/// Puts back everything the undo manifest of `folder` lists, newest first. Entries that
+/// can't be undone stay in the manifest.
+pub fn undo_organize(folder: &Path) -> Result<OrganizeSummary> {
+    let manifest = folder.join(ORGANIZE_MANIFEST);
+    let entries = load_manifest(&manifest)?;
+    if entries.is_empty() {
+        return Err(anyhow!("Nothing to undo in {}", folder.display()));
+    }
+    let mut summary = OrganizeSummary {
+        manifest: manifest.clone(),
+        ..Default::default()
+    };
+    let mut left = Vec::new();
+    for entry in entries.into_iter().rev() {
+        match revert(&entry, folder) {
+            Ok(()) => summary.organized += 1,
+            Err(e) => {
+                log::warn!("Failed to undo {}: {}", entry.to, e);
+                summary.failed.push((entry.to.clone(), e.to_string()));
+                left.push(entry);
+            }
+        }
+    }
+    left.reverse();
+    save_manifest(&manifest, &left)?;
+    log::info!(
+        "Put back {} files below {}",
+        summary.organized,
+        folder.display()
+    );
+    Ok(summary)
+}
+
+fn result_folder(result: &Path) -> Result<&Path> {
+    result
+        .parent()
+        .ok_or_else(|| anyhow!("Invalid result path: {}", result.display()))
+}
+
+/// Dry run of organizing the files of a result file.
+pub fn preview_result(result: &Path, options: &OrganizeOptions) -> Result<OrganizePlan> {
+    plan_organize(&load_export(result)?, result_folder(result)?, options)
+}
+
+/// Organizes the files of a result file below its folder.
+pub fn organize_result(result: &Path, options: &OrganizeOptions) -> Result<OrganizeSummary> {
+    organize_frames(&load_export(result)?, result_folder(result)?, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::testing;
+
+    #[test]
+    fn test_organize() {
+        let dir = testing::temp_dir();
+        std::fs::create_dir_all(dir.join("site")).unwrap();
+        for name in ["site/a.jpg", "site/b.jpg", "site/c.mp4", "site/d.jpg"] {
+            std::fs::write(dir.join(name), name).unwrap();
+        }
+        let frames: Vec<ExportFrame> = [
+            ("site/a.jpg", vec![1, 0]),
+            ("site/b.jpg", vec![]),
+            // a video counts as one file, whichever frame the animal is in
+            ("site/c.mp4", vec![2]),
+            ("site/c.mp4", vec![0]),
+            ("site/missing.jpg", vec![0]),
+        ]
+        .into_iter()
+        .map(|(path, classes)| ExportFrame {
+            bboxes: Some(classes.into_iter().map(|c| testing::bbox(c, 0.9)).collect()),
+            ..testing::frame(path)
+        })
+        .collect();
+        assert_eq!(file_label([&frames[0]]), "Animal");
+        assert_eq!(file_label([&frames[2]]), "Vehicle");
+
+        let options = OrganizeOptions::default();
+        let plan = plan_organize(&frames, &dir, &options).unwrap();
+        let targets: Vec<&str> = plan.entries.iter().map(|e| e.to.as_str()).collect();
+        assert_eq!(
+            targets,
+            ["site/Animal/a.jpg", "site/Empty/b.jpg", "site/Animal/c.mp4"]
+        );
+        assert_eq!(plan.skipped[0].0, "site/missing.jpg");
+        // the dry run left everything in place
+        assert!(dir.join("site/a.jpg").exists());
+
+        let summary = organize_frames(&frames, &dir, &options).unwrap();
+        assert_eq!(summary.organized, 3);
+        assert!(dir.join("site/Empty/b.jpg").exists());
+        assert!(!dir.join("site/b.jpg").exists());
+
+        let summary = undo_organize(&dir).unwrap();
+        assert_eq!(summary.organized, 3);
+        assert_eq!(
+            std::fs::read_to_string(dir.join("site/b.jpg")).unwrap(),
+            "site/b.jpg"
+        );
+        assert!(!dir.join("site/Empty").exists());
+        assert!(!dir.join(ORGANIZE_MANIFEST).exists());
+        assert!(undo_organize(&dir).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_plan_skips() {
+        let dir = testing::temp_dir();
+        for name in [
+            "Animal/b.jpg",
+            "Animal/c.jpg",
+            "other/a.jpg",
+            "site/a.jpg",
+            "site/b.jpg",
+        ] {
+            std::fs::create_dir_all(dir.join(name).parent().unwrap()).unwrap();
+            std::fs::write(dir.join(name), name).unwrap();
+        }
+        // site/a.jpg and other/a.jpg both land on Animal/a.jpg without their folder in
+        // the template
+        let paths = [
+            "site/gone.jpg",
+            "site/a.jpg",
+            "other/a.jpg",
+            "site/b.jpg",
+            "Animal/c.jpg",
+        ];
+        let mut frames: Vec<ExportFrame> = paths
+            .into_iter()
+            .map(|path| ExportFrame {
+                bboxes: Some(vec![testing::bbox(0, 0.9)]),
+                ..testing::frame(path)
+            })
+            .collect();
+        frames.push(ExportFrame {
+            error: Some("Invalid data".to_string()),
+            ..testing::frame("site/failed.jpg")
+        });
+        let options = OrganizeOptions {
+            template: "{label}/{filename}".to_string(),
+            ..Default::default()
+        };
+        let plan = plan_organize(&frames, &dir, &options).unwrap();
+        let targets: Vec<(&str, &str)> = plan
+            .entries
+            .iter()
+            .map(|e| (e.from.as_str(), e.to.as_str()))
+            .collect();
+        assert_eq!(targets, [("other/a.jpg", "Animal/a.jpg")]);
+        let skipped: Vec<(&str, &str)> = plan
+            .skipped
+            .iter()
+            .map(|(file, reason)| (file.as_str(), reason.as_str()))
+            .collect();
+        assert_eq!(
+            skipped,
+            [
+                ("Animal/c.jpg", "Already in place"),
+                ("site/a.jpg", "Target already taken"),
+                ("site/b.jpg", "Target already taken"),
+                ("site/failed.jpg", "Invalid data"),
+                ("site/gone.jpg", "File not found"),
+            ]
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
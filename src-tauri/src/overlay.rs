@@ -0,0 +1,440 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use anyhow::{anyhow, Result};
+use ffmpeg_sidecar::command::FfmpegCommand;
+use ffmpeg_sidecar::event::{FfmpegEvent, LogLevel};
+use ffmpeg_sidecar::ffprobe::ffprobe_path;
+use serde::{Deserialize, Serialize};
+
+use crate::export::{load_export, Bbox, ExportFrame};
+use crate::media::get_video_dimensions;
+use crate::organize::file_label;
+use crate::template::{self, OutputTemplates, TemplateContext};
+
+/// Folder of the overlays with the default template.
+pub const OVERLAY_DIR: &str = "overlays";
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OverlayOptions {
+    /// Seconds kept before the first and after the last detection.
+    #[serde(default = "default_padding")]
+    pub padding: f64,
+    /// Detections below this score are not drawn.
+    #[serde(default)]
+    pub min_score: f32,
+    /// Output template of an overlay, relative to the result's folder.
+    #[serde(default = "default_template")]
+    pub template: String,
+}
+
+impl Default for OverlayOptions {
+    fn default() -> Self {
+        Self {
+            padding: default_padding(),
+            min_score: 0.0,
+            template: default_template(),
+        }
+    }
+}
+
+fn default_padding() -> f64 {
+    2.0
+}
+
+fn default_template() -> String {
+    OutputTemplates::default().proxies
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OverlaySummary {
+    pub rendered: Vec<PathBuf>,
+    pub failed: Vec<(String, String)>,
+}
+
+/// Cut and boxes of one overlay video, times in seconds of the source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OverlayPlan {
+    pub start: f64,
+    pub end: f64,
+    /// `drawbox` filters, timed relative to `start`.
+    pub filters: Vec<String>,
+}
+
+/// Plans the highlight of one video from its frames, `None` when nothing was detected.
+///
+/// Frame indices are decoded frame numbers, so the time of a frame is `index / fps`.
+/// Boxes of a sampled frame stay on screen until the next sampled frame.
+pub fn plan_overlay(
+    frames: &[&ExportFrame],
+    fps: f64,
+    width: u32,
+    height: u32,
+    options: &OverlayOptions,
+) -> Option<OverlayPlan> {
+    let mut by_index: BTreeMap<usize, Vec<&Bbox>> = BTreeMap::new();
+    for frame in frames {
+        let bboxes = by_index.entry(frame.frame_index).or_default();
+        for bbox in frame.bboxes.iter().flatten() {
+            if bbox.score >= options.min_score {
+                bboxes.push(bbox);
+            }
+        }
+    }
+    let times: Vec<(f64, &Vec<&Bbox>)> = by_index
+        .iter()
+        .map(|(index, bboxes)| (*index as f64 / fps, bboxes))
+        .collect();
+    let first = times.iter().find(|(_, b)| !b.is_empty())?.0;
+    let last = times.iter().rev().find(|(_, b)| !b.is_empty())?.0;
+    let start = (first - options.padding).max(0.0);
+    let end = last + options.padding;
+
+    let mut filters = Vec::new();
+    for (i, (time, bboxes)) in times.iter().enumerate() {
+        let until = times.get(i + 1).map_or(end, |(next, _)| *next);
+        for bbox in bboxes.iter() {
+            let (x, y, w, h) = bbox.pixel_rect(width, height);
+            filters.push(format!(
+                "drawbox=x={}:y={}:w={}:h={}:color=red@0.8:t=4:enable='between(t,{:.3},{:.3})'",
+                x,
+                y,
+                w,
+                h,
+                time - start,
+                until - start
+            ));
+        }
+    }
+    Some(OverlayPlan {
+        start,
+        end,
+        filters,
+    })
+}
+
+fn get_video_fps(video_path: &str) -> Result<f64> {
+    let mut command = Command::new(ffprobe_path());
+    command.args([
+        "-v",
+        "error",
+        "-select_streams",
+        "v:0",
+        "-show_entries",
+        "stream=r_frame_rate",
+        "-of",
+        "csv=p=0",
+        video_path,
+    ]);
+
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        const CREATE_NO_WINDOW: u32 = 0x08000000;
+        command.creation_flags(CREATE_NO_WINDOW);
+    }
+
+    let output = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output()?;
+    let rate = std::str::from_utf8(&output.stdout)?.trim().to_string();
+    let fps = match rate.split_once('/') {
+        Some((num, den)) => num.parse::<f64>()? / den.parse::<f64>()?,
+        None => rate.parse::<f64>()?,
+    };
+    if !fps.is_finite() || fps <= 0.0 {
+        return Err(anyhow!("Invalid frame rate {} of {}", rate, video_path));
+    }
+    Ok(fps)
+}
+
+fn render(video: &Path, target: &Path, plan: &OverlayPlan) -> Result<()> {
+    let start = format!("{:.3}", plan.start);
+    let duration = format!("{:.3}", plan.end - plan.start);
+    let filter = if plan.filters.is_empty() {
+        "null".to_string()
+    } else {
+        plan.filters.join(",")
+    };
+    let mut child = FfmpegCommand::new()
+        .args(["-ss", start.as_str()])
+        .input(video.to_string_lossy())
+        .args([
+            "-t",
+            duration.as_str(),
+            "-vf",
+            filter.as_str(),
+            "-an",
+            "-c:v",
+            "libx264",
+            "-preset",
+            "fast",
+            "-crf",
+            "23",
+            "-pix_fmt",
+            "yuv420p",
+        ])
+        .overwrite()
+        .output(target.to_string_lossy())
+        .spawn()?;
+    let errors: Vec<String> = child
+        .iter()?
+        .filter_map(|event| match event {
+            FfmpegEvent::Error(e) | FfmpegEvent::Log(LogLevel::Error, e) => Some(e),
+            _ => None,
+        })
+        .collect();
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(anyhow!("ffmpeg failed: {}", errors.join("; ")));
+    }
+    Ok(())
+}
+
+fn render_video(
+    video: &Path,
+    frames: &[&ExportFrame],
+    target: &Path,
+    options: &OverlayOptions,
+) -> Result<bool> {
+    let video_path = video.to_string_lossy();
+    let (width, height) = get_video_dimensions(&video_path)?;
+    let fps = get_video_fps(&video_path)?;
+    let Some(plan) = plan_overlay(frames, fps, width as u32, height as u32, options) else {
+        return Ok(false);
+    };
+    if let Some(parent) = target.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    render(video, target, &plan)?;
+    Ok(true)
+}
+
+/// Where the overlay of `video` goes below `folder`, following the proxies template.
+fn overlay_path(
+    folder: &Path,
+    video: &Path,
+    frames: &[&ExportFrame],
+    template: &str,
+) -> Result<PathBuf> {
+    let label = file_label(frames.iter().copied());
+    let shoot_time = frames[0].shoot_time.as_deref();
+    let mut context = TemplateContext::new(video, folder, shoot_time, label);
+    let stem = video
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    context.filename = format!("{}_overlay.mp4", stem);
+    Ok(folder.join(template::render(template, &context)?))
+}
+
+/// Renders a boxed highlight clip for every video with detections in a result file,
+/// laid out next to the result by the proxies template.
+pub fn render_overlays(result: &Path, options: &OverlayOptions) -> Result<OverlaySummary> {
+    let folder = result
+        .parent()
+        .ok_or_else(|| anyhow!("Invalid result path: {}", result.display()))?;
+    template::validate(&options.template)?;
+    let frames = load_export(result)?;
+    let mut videos: BTreeMap<PathBuf, Vec<&ExportFrame>> = BTreeMap::new();
+    for frame in &frames {
+        // iframe indices count key frames only and can't be placed in time
+        if frame.total_frames > 1 && !frame.iframe {
+            videos
+                .entry(folder.join(&frame.file.file_path))
+                .or_default()
+                .push(frame);
+        }
+    }
+
+    let mut summary = OverlaySummary::default();
+    for (video, frames) in videos {
+        let rendered =
+            overlay_path(folder, &video, &frames, &options.template).and_then(|target| {
+                Ok(render_video(&video, &frames, &target, options)?.then_some(target))
+            });
+        match rendered {
+            Ok(Some(target)) => summary.rendered.push(target),
+            Ok(None) => (),
+            Err(e) => {
+                log::error!("Failed to render overlay of {}: {}", video.display(), e);
+                summary
+                    .failed
+                    .push((video.display().to_string(), e.to_string()));
+            }
+        }
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::testing;
+
+    #[test]
+    fn test_plan_overlay() {
+        let bbox = Bbox {
+            x1: 0.5,
+            y1: 0.5,
+            x2: 1.0,
+            y2: 1.0,
+            ..testing::bbox(0, 0.9)
+        };
+        let frames: Vec<ExportFrame> = [(0, vec![]), (100, vec![bbox]), (200, vec![])]
+            .into_iter()
+            .map(|(frame_index, bboxes)| ExportFrame {
+                frame_index,
+                total_frames: 3,
+                bboxes: Some(bboxes),
+                ..testing::frame("a.mp4")
+            })
+            .collect();
+        let frames: Vec<&ExportFrame> = frames.iter().collect();
+        let plan = plan_overlay(&frames, 25.0, 200, 100, &OverlayOptions::default()).unwrap();
+        assert_eq!(plan.start, 2.0);
+        assert_eq!(plan.end, 6.0);
+        assert_eq!(
+            plan.filters,
+            ["drawbox=x=100:y=50:w=100:h=50:color=red@0.8:t=4:enable='between(t,2.000,6.000)'"]
+        );
+
+        let blank: Vec<&ExportFrame> = frames[..1].to_vec();
+        assert_eq!(
+            plan_overlay(&blank, 25.0, 200, 100, &OverlayOptions::default()),
+            None
+        );
+    }
+
+    #[test]
+    fn test_plan_overlay_edges() {
+        // detections from the first frame to the last, one of them too weak to draw
+        let first = ExportFrame {
+            total_frames: 2,
+            bboxes: Some(vec![testing::bbox(0, 0.9), testing::bbox(1, 0.2)]),
+            ..testing::frame("a.mp4")
+        };
+        let last = ExportFrame {
+            frame_index: 50,
+            bboxes: Some(vec![testing::bbox(0, 0.8)]),
+            ..first.clone()
+        };
+        let options = OverlayOptions {
+            padding: 1.0,
+            min_score: 0.5,
+            ..Default::default()
+        };
+        let plan = plan_overlay(&[&first, &last], 25.0, 100, 100, &options).unwrap();
+        // the cut can't start before the video does
+        assert_eq!(plan.start, 0.0);
+        assert_eq!(plan.end, 3.0);
+        assert_eq!(plan.filters.len(), 2);
+        assert!(plan.filters[0].ends_with("enable='between(t,0.000,2.000)'"));
+        // the last boxes stay on screen until the end of the cut
+        assert!(plan.filters[1].ends_with("enable='between(t,2.000,3.000)'"));
+
+        // nothing is drawn when every detection is below the score
+        let options = OverlayOptions {
+            min_score: 0.95,
+            ..options
+        };
+        assert_eq!(
+            plan_overlay(&[&first, &last], 25.0, 100, 100, &options),
+            None
+        );
+        // a frame that failed has no bboxes at all
+        let failed = ExportFrame {
+            bboxes: None,
+            error: Some("Failed to decode".to_string()),
+            ..first
+        };
+        assert_eq!(
+            plan_overlay(&[&failed], 25.0, 100, 100, &OverlayOptions::default()),
+            None
+        );
+    }
+
+    #[test]
+    fn test_render_overlays_skips() {
+        let dir = testing::temp_dir();
+        let result = dir.join("result.json");
+        let frames = vec![
+            // images and key frames can't be placed in a clip
+            ExportFrame {
+                bboxes: Some(vec![testing::bbox(0, 0.9)]),
+                ..testing::frame("a.jpg")
+            },
+            ExportFrame {
+                total_frames: 4,
+                iframe: true,
+                bboxes: Some(vec![testing::bbox(0, 0.9)]),
+                ..testing::frame("b.mp4")
+            },
+            // a video the result names but that is gone
+            ExportFrame {
+                total_frames: 4,
+                bboxes: Some(vec![testing::bbox(0, 0.9)]),
+                ..testing::frame("site/c.mp4")
+            },
+        ];
+        std::fs::write(&result, serde_json::to_string(&frames).unwrap()).unwrap();
+
+        let summary = render_overlays(&result, &OverlayOptions::default()).unwrap();
+        assert!(summary.rendered.is_empty());
+        assert_eq!(summary.failed.len(), 1);
+        assert!(summary.failed[0].0.ends_with("c.mp4"));
+        assert!(!dir.join(OVERLAY_DIR).exists());
+
+        // a template that can't place the video fails it without rendering
+        let options = OverlayOptions {
+            template: "{dir}/{filename}".to_string(),
+            ..Default::default()
+        };
+        let outside = ExportFrame {
+            total_frames: 4,
+            bboxes: Some(vec![testing::bbox(0, 0.9)]),
+            ..testing::frame("/elsewhere/d.mp4")
+        };
+        std::fs::write(&result, serde_json::to_string(&[outside]).unwrap()).unwrap();
+        let summary = render_overlays(&result, &options).unwrap();
+        assert_eq!(summary.failed.len(), 1);
+        assert!(summary.failed[0].1.contains("outside the output folder"));
+        let options = OverlayOptions {
+            template: "{species}/{filename}".to_string(),
+            ..Default::default()
+        };
+        assert!(render_overlays(&result, &options).is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_overlay_path() {
+        let folder = Path::new("/traps");
+        let video = folder.join("site1/cam2/clip.MP4");
+        let frame = ExportFrame {
+            shoot_time: Some("2024-05-01 06:30:00 +08:00".to_string()),
+            total_frames: 4,
+            ..testing::frame(&video)
+        };
+        let person = ExportFrame {
+            frame_index: 2,
+            bboxes: Some(vec![testing::bbox(1, 0.9)]),
+            ..frame.clone()
+        };
+        let frames = [&frame, &person];
+        let template = OverlayOptions::default().template;
+        assert_eq!(
+            overlay_path(folder, &video, &frames, &template).unwrap(),
+            folder.join("overlays/site1/cam2/clip_overlay.mp4")
+        );
+        assert_eq!(
+            overlay_path(folder, &video, &frames, "{label}/{date}/{filename}").unwrap(),
+            folder.join("Person/2024-05-01/clip_overlay.mp4")
+        );
+    }
+}
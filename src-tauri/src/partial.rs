@@ -0,0 +1,204 @@
+use std::path::Path;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::export::{load_export, ExportFrame};
+use crate::organize::EMPTY_LABEL;
+use crate::report::has_error;
+use crate::utils::portable_path;
+
+pub const PAGE_SIZE: usize = 100;
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ResultFilters {
+    /// Class or review label of a box, `Empty` for frames without any.
+    pub label: Option<String>,
+    /// Lowest score of the matching box.
+    pub min_score: f32,
+    /// Only the failed frames, or only the others.
+    pub failed: Option<bool>,
+    /// Part of the file path, in any case.
+    pub path: Option<String>,
+}
+
+impl ResultFilters {
+    pub fn matches(&self, frame: &ExportFrame) -> bool {
+        if self.failed.is_some_and(|failed| failed != has_error(frame)) {
+            return false;
+        }
+        if let Some(path) = &self.path {
+            let file = portable_path(&frame.file.file_path, None).to_lowercase();
+            if !file.contains(&path.to_lowercase()) {
+                return false;
+            }
+        }
+        let mut bboxes = frame.bboxes.iter().flatten().peekable();
+        match self.label.as_deref() {
+            Some(EMPTY_LABEL) => bboxes.peek().is_none(),
+            Some(label) => bboxes.any(|b| b.class_name() == label && b.score >= self.min_score),
+            None if self.min_score > 0.0 => bboxes.any(|b| b.score >= self.min_score),
+            None => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResultPage {
+    /// Frames with a result so far, a run in progress adds more.
+    pub frames_done: usize,
+    /// Frames matching the filters, of which `frames` is the requested page.
+    pub total: usize,
+    pub page: usize,
+    pub frames: Vec<ExportFrame>,
+}
+
+/// Page `page`, counted from 0, of the frames matching `filters` in result order.
+pub fn filter_page(frames: Vec<ExportFrame>, filters: &ResultFilters, page: usize) -> ResultPage {
+    let frames_done = frames.len();
+    let matching: Vec<ExportFrame> = frames.into_iter().filter(|f| filters.matches(f)).collect();
+    ResultPage {
+        frames_done,
+        total: matching.len(),
+        page,
+        frames: matching
+            .into_iter()
+            .skip(page * PAGE_SIZE)
+            .take(PAGE_SIZE)
+            .collect(),
+    }
+}
+
+/// Reads the result file a run checkpoints to. Checkpoints replace the file at once, so
+/// it can be read at any time; before the first one there is nothing to show yet.
+pub fn load_partial(result: &Path, filters: &ResultFilters, page: usize) -> Result<ResultPage> {
+    if !result.exists() {
+        return Ok(ResultPage {
+            page,
+            ..Default::default()
+        });
+    }
+    Ok(filter_page(load_export(result)?, filters, page))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::{testing, Bbox};
+
+    fn paths(frames: &[ExportFrame], filters: ResultFilters) -> Vec<String> {
+        filter_page(frames.to_vec(), &filters, 0)
+            .frames
+            .iter()
+            .map(|f| portable_path(&f.file.file_path, None))
+            .collect()
+    }
+
+    #[test]
+    fn test_filter_page() {
+        let frames = vec![
+            ExportFrame {
+                bboxes: Some(vec![testing::bbox(0, 0.9), testing::bbox(1, 0.3)]),
+                ..testing::frame("site1/a.jpg")
+            },
+            testing::frame("site1/b.jpg"),
+            ExportFrame {
+                error: Some("Invalid data".to_string()),
+                ..testing::frame("site2/c.jpg")
+            },
+            ExportFrame {
+                bboxes: Some(vec![testing::bbox(1, 0.8)]),
+                ..testing::frame("site2/d.jpg")
+            },
+        ];
+        let person = ResultFilters {
+            label: Some("Person".to_string()),
+            min_score: 0.5,
+            ..Default::default()
+        };
+        assert_eq!(paths(&frames, person), ["site2/d.jpg"]);
+        let empty = ResultFilters {
+            label: Some(EMPTY_LABEL.to_string()),
+            failed: Some(false),
+            ..Default::default()
+        };
+        assert_eq!(paths(&frames, empty), ["site1/b.jpg"]);
+        let site = ResultFilters {
+            path: Some("SITE1".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(paths(&frames, site), ["site1/a.jpg", "site1/b.jpg"]);
+        // a score alone matches a box of any class
+        let confident = ResultFilters {
+            min_score: 0.85,
+            ..Default::default()
+        };
+        assert_eq!(paths(&frames, confident), ["site1/a.jpg"]);
+        let failed = ResultFilters {
+            failed: Some(true),
+            ..Default::default()
+        };
+        assert_eq!(paths(&frames, failed), ["site2/c.jpg"]);
+    }
+
+    #[test]
+    fn test_filter_labels() {
+        let reviewed = Bbox {
+            label: Some("Leopard".to_string()),
+            ..testing::bbox(0, 0.4)
+        };
+        let frames = vec![
+            ExportFrame {
+                bboxes: Some(vec![reviewed]),
+                ..testing::frame("a.jpg")
+            },
+            // a frame that was never answered has no boxes either
+            ExportFrame {
+                bboxes: None,
+                ..testing::frame("b.jpg")
+            },
+        ];
+        let label = |label: &str| ResultFilters {
+            label: Some(label.to_string()),
+            ..Default::default()
+        };
+        // the review label replaces the class
+        assert_eq!(paths(&frames, label("Leopard")), ["a.jpg"]);
+        assert!(paths(&frames, label("Animal")).is_empty());
+        assert_eq!(paths(&frames, label(EMPTY_LABEL)), ["b.jpg"]);
+        let weak = ResultFilters {
+            min_score: 0.5,
+            ..label("Leopard")
+        };
+        assert!(paths(&frames, weak).is_empty());
+    }
+
+    #[test]
+    fn test_pages() {
+        let frames: Vec<ExportFrame> = (0..PAGE_SIZE + 5)
+            .map(|i| testing::frame(format!("{}.jpg", i)))
+            .collect();
+        let page = filter_page(frames.clone(), &ResultFilters::default(), 1);
+        assert_eq!(
+            (page.frames_done, page.total),
+            (PAGE_SIZE + 5, PAGE_SIZE + 5)
+        );
+        assert_eq!(page.frames.len(), 5);
+        assert_eq!(
+            page.frames[0].file.file_path,
+            frames[PAGE_SIZE].file.file_path
+        );
+        // past the last page
+        let page = filter_page(frames, &ResultFilters::default(), 2);
+        assert_eq!((page.page, page.total), (2, PAGE_SIZE + 5));
+        assert!(page.frames.is_empty());
+
+        // before the first checkpoint there is no result file yet
+        let dir = testing::temp_dir();
+        let page = load_partial(&dir.join("result.json"), &ResultFilters::default(), 3).unwrap();
+        assert_eq!((page.frames_done, page.total, page.page), (0, 0, 3));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,65 @@
+use std::path::Path;
+
+use anyhow::Result;
+use serde::Deserialize;
+
+/// File name of the per-folder policy override.
+pub const POLICY_FILE: &str = "megascops.toml";
+
+/// Options a `megascops.toml` can override for its folder and everything below it.
+/// Unset fields fall back to the parent folder and finally to the global config.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FolderPolicy {
+    pub max_frames: Option<usize>,
+    pub iframe_only: Option<bool>,
+    pub confidence_threshold: Option<f32>,
+    pub iou_threshold: Option<f32>,
+    /// Skip the whole subtree.
+    pub skip: Option<bool>,
+}
+
+impl FolderPolicy {
+    /// Layers `child` on top of `self`, child values win.
+    pub fn merge(&self, child: &FolderPolicy) -> FolderPolicy {
+        FolderPolicy {
+            max_frames: child.max_frames.or(self.max_frames),
+            iframe_only: child.iframe_only.or(self.iframe_only),
+            confidence_threshold: child.confidence_threshold.or(self.confidence_threshold),
+            iou_threshold: child.iou_threshold.or(self.iou_threshold),
+            skip: child.skip.or(self.skip),
+        }
+    }
+
+    pub fn is_skip(&self) -> bool {
+        self.skip.unwrap_or(false)
+    }
+}
+
+/// Reads `megascops.toml` from `dir`, if there is one.
+pub fn load_policy(dir: &Path) -> Result<Option<FolderPolicy>> {
+    let path = dir.join(POLICY_FILE);
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let content = std::fs::read_to_string(&path)?;
+    let policy = toml::from_str(&content)?;
+    log::info!("Loaded folder policy {}: {:?}", path.display(), policy);
+    Ok(Some(policy))
+}
+
+/// Like [`load_policy`] but logs and ignores malformed files so one typo doesn't stop indexing.
+pub fn load_policy_or_warn(dir: &Path) -> Option<FolderPolicy> {
+    match load_policy(dir) {
+        Ok(policy) => policy,
+        Err(e) => {
+            log::error!(
+                "Ignoring invalid {} in {}: {}",
+                POLICY_FILE,
+                dir.display(),
+                e
+            );
+            None
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use std::path::Path;
+use std::process::Command;
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter};
+use tauri_plugin_opener::OpenerExt;
+
+use crate::organize::{organize_result, OrganizeOptions};
+
+/// What to do once a run has completed successfully.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+pub enum PostRunAction {
+    #[default]
+    None,
+    OpenFolder,
+    Organize,
+    Shutdown,
+    NextJob,
+}
+
+pub async fn run_post_action(
+    app: &AppHandle,
+    action: PostRunAction,
+    result: &Path,
+    organize: &OrganizeOptions,
+) -> Result<()> {
+    if action == PostRunAction::None {
+        return Ok(());
+    }
+    log::info!("Running post-run action {:?}", action);
+    app.emit("post-run-action", action)?;
+    match action {
+        PostRunAction::None => (),
+        PostRunAction::OpenFolder => {
+            let folder = result.parent().unwrap_or(Path::new(""));
+            app.opener()
+                .open_path(folder.to_string_lossy(), None::<&str>)?;
+        }
+        PostRunAction::Organize => {
+            let result = result.to_path_buf();
+            let options = organize.clone();
+            let summary =
+                tokio::task::spawn_blocking(move || organize_result(&result, &options)).await??;
+            app.emit("organize-complete", summary)?;
+        }
+        PostRunAction::Shutdown => shutdown()?,
+        // the queue lives in the frontend, it starts the next job on this event
+        PostRunAction::NextJob => app.emit("next-job", ())?,
+    }
+    Ok(())
+}
+
+fn shutdown() -> Result<()> {
+    let status = shutdown_command()?.status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!("Shutdown command failed: {}", status))
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn shutdown_command() -> Result<Command> {
+    use std::os::windows::process::CommandExt;
+    const CREATE_NO_WINDOW: u32 = 0x08000000;
+    // give the user a minute to abort with `shutdown /a`
+    let mut command = Command::new("shutdown");
+    command.args(["/s", "/t", "60"]);
+    command.creation_flags(CREATE_NO_WINDOW);
+    Ok(command)
+}
+
+#[cfg(target_os = "macos")]
+fn shutdown_command() -> Result<Command> {
+    let mut command = Command::new("osascript");
+    command.args(["-e", "tell application \"System Events\" to shut down"]);
+    Ok(command)
+}
+
+#[cfg(target_os = "linux")]
+fn shutdown_command() -> Result<Command> {
+    let mut command = Command::new("systemctl");
+    command.arg("poweroff");
+    Ok(command)
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "macos", target_os = "linux")))]
+fn shutdown_command() -> Result<Command> {
+    Err(anyhow!("Shutting down is not supported on this platform"))
+}
@@ -0,0 +1,75 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use image::{imageops::FilterType, DynamicImage};
+use ndarray::Array4;
+use ort::session::{builder::GraphOptimizationLevel, Session};
+
+/// Side length of the square input the pre-filter model expects.
+const INPUT_SIZE: u32 = 224;
+const MEAN: [f32; 3] = [0.485, 0.456, 0.406];
+const STD: [f32; 3] = [0.229, 0.224, 0.225];
+
+/// Small local classifier that scores how likely a frame contains anything at all,
+/// so near-certain blanks can be kept from using server quota.
+///
+/// The model takes a `1x3x224x224` ImageNet-normalized RGB tensor and returns either a
+/// single "non-blank" probability or `[blank, non-blank]` logits.
+pub struct PreFilter {
+    session: Session,
+}
+
+impl PreFilter {
+    pub fn load(model_path: &Path) -> Result<Self> {
+        let session = Session::builder()?
+            .with_optimization_level(GraphOptimizationLevel::Level3)?
+            // media workers already run in parallel, one thread per inference is enough
+            .with_intra_threads(1)?
+            .commit_from_file(model_path)?;
+        log::info!("Loaded pre-filter model {}", model_path.display());
+        Ok(Self { session })
+    }
+
+    /// Probability that `img` is not blank.
+    pub fn score(&self, img: &DynamicImage) -> Result<f32> {
+        let img = img
+            .resize_exact(INPUT_SIZE, INPUT_SIZE, FilterType::Triangle)
+            .to_rgb8();
+        let size = INPUT_SIZE as usize;
+        let mut input = Array4::<f32>::zeros((1, 3, size, size));
+        for (x, y, pixel) in img.enumerate_pixels() {
+            for c in 0..3 {
+                input[[0, c, y as usize, x as usize]] =
+                    (pixel[c] as f32 / 255.0 - MEAN[c]) / STD[c];
+            }
+        }
+        let outputs = self.session.run(ort::inputs![input]?)?;
+        let output = outputs[0].try_extract_tensor::<f32>()?;
+        let values: Vec<f32> = output.iter().copied().collect();
+        match values.as_slice() {
+            [p] => Ok(*p),
+            [blank, non_blank] => {
+                // softmax over the two logits
+                let max = blank.max(*non_blank);
+                let (b, n) = ((blank - max).exp(), (non_blank - max).exp());
+                Ok(n / (b + n))
+            }
+            _ => Err(anyhow!(
+                "Unexpected pre-filter output of {} values",
+                values.len()
+            )),
+        }
+    }
+
+    /// Scores `img`, logging failures instead of returning them so a broken model
+    /// never drops a frame.
+    pub fn try_score(&self, img: &DynamicImage) -> Option<f32> {
+        match self.score(img) {
+            Ok(score) => Some(score),
+            Err(e) => {
+                log::warn!("Pre-filter failed: {}", e);
+                None
+            }
+        }
+    }
+}
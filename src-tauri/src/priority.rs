@@ -0,0 +1,106 @@
+use std::cell::Cell;
+use std::process::Child;
+
+use anyhow::Result;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+
+/// Nice value of lowered threads and child processes on unix.
+#[cfg(unix)]
+const NICE: libc::c_int = 10;
+
+thread_local! {
+    static LOWERED: Cell<bool> = const { Cell::new(false) };
+}
+
+#[cfg(target_os = "windows")]
+mod win32 {
+    pub const THREAD_MODE_BACKGROUND_BEGIN: i32 = 0x0001_0000;
+    pub const BELOW_NORMAL_PRIORITY_CLASS: u32 = 0x0000_4000;
+    extern "system" {
+        pub fn GetCurrentThread() -> *mut std::ffi::c_void;
+        pub fn SetThreadPriority(thread: *mut std::ffi::c_void, priority: i32) -> i32;
+        pub fn SetPriorityClass(process: *mut std::ffi::c_void, class: u32) -> i32;
+    }
+}
+
+/// Lowers the CPU and I/O priority of the calling thread. A lowered thread can't be raised
+/// again without privileges on Linux, so only call this on threads owned by a run.
+pub fn lower_current_thread() {
+    #[cfg(target_os = "linux")]
+    // SAFETY: plain syscalls on the calling thread
+    let ok = unsafe {
+        const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+        const IOPRIO_CLASS_IDLE: libc::c_int = 3;
+        // with who 0 both calls apply to the calling thread only
+        libc::setpriority(libc::PRIO_PROCESS, 0, NICE) == 0
+            && libc::syscall(
+                libc::SYS_ioprio_set,
+                IOPRIO_WHO_PROCESS,
+                0,
+                IOPRIO_CLASS_IDLE << 13,
+            ) == 0
+    };
+    #[cfg(target_os = "macos")]
+    // SAFETY: plain syscall on the calling thread, the background band also throttles I/O
+    let ok = unsafe { libc::setpriority(libc::PRIO_DARWIN_THREAD, 0, libc::PRIO_DARWIN_BG) == 0 };
+    #[cfg(target_os = "windows")]
+    // SAFETY: the pseudo handle of the calling thread is always valid, background mode
+    // lowers its CPU, I/O and memory priority
+    let ok = unsafe {
+        win32::SetThreadPriority(
+            win32::GetCurrentThread(),
+            win32::THREAD_MODE_BACKGROUND_BEGIN,
+        ) != 0
+    };
+    #[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+    let ok = false;
+
+    if ok {
+        LOWERED.with(|lowered| lowered.set(true));
+    } else {
+        log::debug!("Failed to lower the priority of the current thread");
+    }
+}
+
+/// Gives a child process started from a lowered thread a low priority as well.
+pub fn inherit(child: &Child) {
+    if !LOWERED.with(|lowered| lowered.get()) {
+        return;
+    }
+    #[cfg(unix)]
+    // SAFETY: plain syscall on our own child
+    let ok = unsafe { libc::setpriority(libc::PRIO_PROCESS, child.id() as libc::id_t, NICE) == 0 };
+    #[cfg(target_os = "windows")]
+    // SAFETY: the handle belongs to `child` and stays open while it is borrowed
+    let ok = unsafe {
+        use std::os::windows::io::AsRawHandle;
+        win32::SetPriorityClass(child.as_raw_handle(), win32::BELOW_NORMAL_PRIORITY_CLASS) != 0
+    };
+    #[cfg(not(any(unix, target_os = "windows")))]
+    let ok = false;
+
+    if !ok {
+        log::debug!("Failed to lower the priority of process {}", child.id());
+    }
+}
+
+/// Runs `f` on a new low priority thread and waits for it.
+pub fn run_lowered<T: Send>(f: impl FnOnce() -> T + Send) -> T {
+    std::thread::scope(|scope| {
+        scope
+            .spawn(|| {
+                lower_current_thread();
+                f()
+            })
+            .join()
+            .unwrap_or_else(|e| std::panic::resume_unwind(e))
+    })
+}
+
+/// Thread pool whose threads run at a low priority, they end with the pool.
+pub fn low_priority_pool() -> Result<ThreadPool> {
+    Ok(ThreadPoolBuilder::new()
+        .thread_name(|i| format!("megascops-low-{}", i))
+        .start_handler(|_| lower_current_thread())
+        .build()?)
+}
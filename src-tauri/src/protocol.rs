@@ -0,0 +1,291 @@
+use std::path::PathBuf;
+
+use md5rs_client::PROTO_VERSION;
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::announcement::CLIENT_VERSION;
+use crate::md5rs::DetectRequest;
+
+pub use megascops_media::ImageCodec;
+
+/// Oldest server protocol this client still talks to.
+pub const MIN_SERVER_PROTO_VERSION: u32 = 1;
+
+/// Request metadata with the app version, sent with every call.
+pub const CLIENT_VERSION_HEADER: &str = "x-megascops-version";
+
+/// gRPC's default receive limit, assumed for servers that don't advertise theirs.
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 4 * 1024 * 1024;
+/// Room left for the other fields of a `DetectRequest` next to the image.
+const REQUEST_OVERHEAD: usize = 1024;
+/// Frames up to this size are packed into batches, larger ones gain nothing from it.
+pub const SMALL_FRAME_SIZE: usize = 256 * 1024;
+
+/// Versions advertised by the server in its health response. Servers from before the
+/// negotiation advertise nothing, which reads as 0.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerVersion {
+    pub proto_version: u32,
+    pub min_proto_version: u32,
+    pub server_version: String,
+    /// Largest message the server accepts, 0 when not advertised.
+    pub max_message_size: u64,
+    /// Image codecs the server decodes besides WebP.
+    pub image_codecs: Vec<String>,
+    /// Frames one request may pack, 0 when batches aren't taken.
+    pub max_batch: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum UpdateTarget {
+    /// The app is too old for the server.
+    Client,
+    /// The server is too old for the app.
+    Server,
+}
+
+/// Emitted as `update-required` so the frontend can show what has to be updated.
+#[derive(Error, Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+#[error("{message}")]
+pub struct UpdateRequired {
+    pub target: UpdateTarget,
+    pub client_version: String,
+    pub client_proto_version: u32,
+    pub server_version: String,
+    pub server_proto_version: u32,
+    pub message: String,
+}
+
+/// Emitted as `quota-exhausted` when the server stops accepting frames mid-run. The
+/// results so far are exported, resuming from `resume_path` after a top-up continues
+/// with the remaining files.
+#[derive(Error, Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+#[error("Quota exhausted with {remaining} files left, top up and resume from {}", .resume_path.display())]
+pub struct QuotaExhausted {
+    pub remaining: usize,
+    pub resume_path: PathBuf,
+}
+
+impl ServerVersion {
+    /// Largest image that fits in one `DetectRequest`.
+    pub fn image_limit(&self) -> usize {
+        let max = match self.max_message_size {
+            0 => DEFAULT_MAX_MESSAGE_SIZE,
+            max => max as usize,
+        };
+        max.saturating_sub(REQUEST_OVERHEAD)
+    }
+
+    /// Codecs images can be sent in without re-encoding.
+    pub fn image_codecs(&self) -> Vec<ImageCodec> {
+        let mut codecs = vec![ImageCodec::Webp];
+        for codec in self
+            .image_codecs
+            .iter()
+            .filter_map(|c| ImageCodec::from_request(c))
+        {
+            if !codecs.contains(&codec) {
+                codecs.push(codec);
+            }
+        }
+        codecs
+    }
+
+    /// Frames to pack into one request, `wanted` at most.
+    pub fn batch_size(&self, wanted: usize) -> usize {
+        wanted.min(self.max_batch as usize).max(1)
+    }
+
+    pub fn check(&self) -> Result<(), UpdateRequired> {
+        let update = |target: UpdateTarget, message: String| UpdateRequired {
+            target,
+            client_version: CLIENT_VERSION.to_string(),
+            client_proto_version: PROTO_VERSION,
+            server_version: self.server_version.clone(),
+            server_proto_version: self.proto_version,
+            message,
+        };
+        if self.proto_version == 0 {
+            log::warn!("Server does not advertise a protocol version, assuming compatible");
+            return Ok(());
+        }
+        if PROTO_VERSION < self.min_proto_version {
+            return Err(update(
+                UpdateTarget::Client,
+                format!(
+                    "The server requires protocol version {} but Megascops {} speaks version {}. Please update the app.",
+                    self.min_proto_version, CLIENT_VERSION, PROTO_VERSION
+                ),
+            ));
+        }
+        if self.proto_version < MIN_SERVER_PROTO_VERSION {
+            return Err(update(
+                UpdateTarget::Server,
+                format!(
+                    "The server speaks protocol version {} but Megascops {} requires at least version {}. Please ask the server operator to update.",
+                    self.proto_version, CLIENT_VERSION, MIN_SERVER_PROTO_VERSION
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Packs small frames into batch requests, saving the per-message round trips on a
+/// high-latency link.
+#[derive(Debug, Default)]
+pub struct FramePacker {
+    max_frames: usize,
+    max_bytes: usize,
+    frames: Vec<DetectRequest>,
+    bytes: usize,
+}
+
+impl FramePacker {
+    pub fn new(max_frames: usize, max_bytes: usize) -> Self {
+        Self {
+            max_frames,
+            max_bytes,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Adds `request` and returns a message ready to send: the request itself when it
+    /// isn't packed, or the batch once it is full.
+    pub fn push(&mut self, request: DetectRequest) -> Option<DetectRequest> {
+        if self.max_frames <= 1 || request.image.len() > SMALL_FRAME_SIZE {
+            return Some(request);
+        }
+        let size = request.image.len() + REQUEST_OVERHEAD;
+        let full = if self.bytes + size > self.max_bytes {
+            self.flush()
+        } else {
+            None
+        };
+        self.bytes += size;
+        self.frames.push(request);
+        if full.is_none() && self.frames.len() >= self.max_frames {
+            return self.flush();
+        }
+        full
+    }
+
+    /// The frames packed so far as one message, a single frame as itself.
+    pub fn flush(&mut self) -> Option<DetectRequest> {
+        self.bytes = 0;
+        let mut frames = std::mem::take(&mut self.frames);
+        match frames.len() {
+            0 => None,
+            1 => frames.pop(),
+            _ => Some(DetectRequest {
+                batch: frames,
+                ..Default::default()
+            }),
+        }
+    }
+}
+
+/// Sizes of the images sent during a run.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PayloadStats {
+    pub frames: usize,
+    pub bytes: u64,
+    pub largest: usize,
+    /// Frames re-encoded at a lower quality to fit the message limit.
+    pub reencoded: usize,
+    /// Frames that didn't fit even at the lowest quality.
+    pub oversized: usize,
+}
+
+impl PayloadStats {
+    pub fn record(&mut self, size: usize) {
+        self.frames += 1;
+        self.bytes += size as u64;
+        self.largest = self.largest.max(size);
+    }
+
+    pub fn average(&self) -> usize {
+        match self.frames {
+            0 => 0,
+            n => (self.bytes / n as u64) as usize,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_version_check() {
+        let legacy = ServerVersion::default();
+        assert!(legacy.check().is_ok());
+        assert_eq!(
+            legacy.image_limit(),
+            DEFAULT_MAX_MESSAGE_SIZE - REQUEST_OVERHEAD
+        );
+
+        let current = ServerVersion {
+            proto_version: PROTO_VERSION,
+            min_proto_version: 1,
+            server_version: "1.0.0".to_string(),
+            max_message_size: 2048,
+            image_codecs: vec!["jpeg".to_string(), "avif".to_string(), "heic".to_string()],
+            max_batch: 8,
+        };
+        assert_eq!(current.image_limit(), 1024);
+        assert_eq!(legacy.image_codecs(), [ImageCodec::Webp]);
+        assert_eq!(
+            current.image_codecs(),
+            [ImageCodec::Webp, ImageCodec::Jpeg, ImageCodec::Avif]
+        );
+        assert_eq!(ImageCodec::Webp.request_codec(), "");
+        assert_eq!(ImageCodec::from_request(""), Some(ImageCodec::Webp));
+        assert!(current.check().is_ok());
+        assert_eq!(legacy.batch_size(16), 1);
+        assert_eq!(current.batch_size(16), 8);
+
+        let newer = ServerVersion {
+            proto_version: PROTO_VERSION + 1,
+            min_proto_version: PROTO_VERSION + 1,
+            ..current.clone()
+        };
+        assert_eq!(newer.check().unwrap_err().target, UpdateTarget::Client);
+    }
+
+    #[test]
+    fn test_frame_packer() {
+        let frame = |uuid: &str, size: usize| DetectRequest {
+            uuid: uuid.to_string(),
+            image: vec![0; size],
+            ..Default::default()
+        };
+        let mut packer = FramePacker::new(3, 4 * REQUEST_OVERHEAD);
+        assert!(packer.push(frame("a", 10)).is_none());
+        assert!(packer.push(frame("b", 10)).is_none());
+        let batch = packer.push(frame("c", 10)).unwrap();
+        let uuids: Vec<&str> = batch.batch.iter().map(|r| r.uuid.as_str()).collect();
+        assert_eq!(uuids, ["a", "b", "c"]);
+        assert!(packer.is_empty());
+
+        // large frames go alone, the small ones keep waiting
+        assert!(packer.push(frame("d", 10)).is_none());
+        let large = packer.push(frame("e", SMALL_FRAME_SIZE + 1)).unwrap();
+        assert_eq!(large.uuid, "e");
+        // a frame that would overflow the message sends the batch before it
+        let full = packer.push(frame("f", 3 * REQUEST_OVERHEAD)).unwrap();
+        assert_eq!(full.uuid, "d");
+        assert_eq!(packer.flush().unwrap().uuid, "f");
+        assert!(packer.flush().is_none());
+    }
+}
@@ -0,0 +1,248 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::Config;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Done,
+    Failed,
+}
+
+/// Files and folders processed together as one run.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Job {
+    pub id: String,
+    /// Folder the run is started on, the result is written here.
+    pub root: PathBuf,
+    /// Paths below `root` to process, the whole of it when empty.
+    pub paths: Vec<PathBuf>,
+    pub status: JobStatus,
+    /// Result file the finished run wrote.
+    pub result: Option<PathBuf>,
+    /// Configuration of the run, the one saved last when none was given.
+    #[serde(skip)]
+    pub config: Option<Config>,
+}
+
+impl Job {
+    fn covers(&self, path: &Path) -> bool {
+        if self.paths.is_empty() {
+            path.starts_with(&self.root)
+        } else {
+            self.paths.iter().any(|p| path.starts_with(p))
+        }
+    }
+}
+
+/// Jobs run one after the other, in the order they were queued.
+#[derive(Debug, Default)]
+pub struct JobQueue {
+    jobs: Vec<Job>,
+}
+
+/// Closest folder containing all of `paths`.
+pub fn common_root(paths: &[PathBuf]) -> Option<PathBuf> {
+    let (first, rest) = paths.split_first()?;
+    let mut root = if first.is_dir() {
+        first.as_path()
+    } else {
+        first.parent()?
+    };
+    for path in rest {
+        while !path.starts_with(root) {
+            root = root.parent()?;
+        }
+    }
+    Some(root.to_path_buf())
+}
+
+impl JobQueue {
+    pub fn jobs(&self) -> &[Job] {
+        &self.jobs
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.jobs.iter().any(|j| j.status == JobStatus::Running)
+    }
+
+    /// Whether a queued or running job already processes `path`.
+    fn is_queued(&self, path: &Path) -> bool {
+        self.jobs
+            .iter()
+            .filter(|j| matches!(j.status, JobStatus::Queued | JobStatus::Running))
+            .any(|j| j.covers(path))
+    }
+
+    fn push(&mut self, root: PathBuf, paths: Vec<PathBuf>, config: Option<Config>) -> Job {
+        let job = Job {
+            id: Uuid::new_v4().to_string(),
+            root,
+            paths,
+            status: JobStatus::Queued,
+            result: None,
+            config,
+        };
+        self.jobs.push(job.clone());
+        job
+    }
+
+    /// Groups dropped `paths` into one job, with its own `config` when given. Missing
+    /// paths, paths inside another dropped path and paths a queued or running job
+    /// already covers are left out.
+    pub fn add_paths(&mut self, paths: &[PathBuf], config: Option<Config>) -> Result<Job> {
+        let mut paths: Vec<PathBuf> = paths
+            .iter()
+            .filter_map(|p| match std::fs::canonicalize(p) {
+                Ok(p) => Some(p),
+                Err(e) => {
+                    log::warn!("Skipped dropped path {}: {}", p.display(), e);
+                    None
+                }
+            })
+            .collect();
+        paths.sort();
+        paths.dedup();
+        let dropped = paths.clone();
+        paths.retain(|p| {
+            !dropped
+                .iter()
+                .any(|other| other != p && p.starts_with(other))
+        });
+        paths.retain(|p| {
+            let queued = self.is_queued(p);
+            if queued {
+                log::info!("Skipped {}, it is already queued", p.display());
+            }
+            !queued
+        });
+        if paths.is_empty() {
+            return Err(anyhow!("Nothing new to process in the dropped paths"));
+        }
+
+        let root = common_root(&paths)
+            .ok_or_else(|| anyhow!("The dropped paths don't share a common folder"))?;
+        // a single dropped folder is processed as a whole
+        if paths == [root.clone()] {
+            paths.clear();
+        }
+        Ok(self.push(root, paths, config))
+    }
+
+    /// Queues the whole of `folder`, with its own `config` when given.
+    pub fn add_folder(&mut self, folder: &Path, config: Option<Config>) -> Result<Job> {
+        let root = std::fs::canonicalize(folder)
+            .map_err(|e| anyhow!("Failed to open {}: {}", folder.display(), e))?;
+        if !root.is_dir() {
+            return Err(anyhow!("{} is not a folder", root.display()));
+        }
+        if self.is_queued(&root) {
+            return Err(anyhow!("{} is already queued", root.display()));
+        }
+        Ok(self.push(root, Vec::new(), config))
+    }
+
+    /// Takes a job out of the queue before it started.
+    pub fn remove(&mut self, id: &str) -> Result<Job> {
+        let index = self
+            .jobs
+            .iter()
+            .position(|j| j.id == id && j.status == JobStatus::Queued)
+            .ok_or_else(|| anyhow!("No queued job {}", id))?;
+        Ok(self.jobs.remove(index))
+    }
+
+    /// Marks the oldest queued job as running and returns it.
+    pub fn start_next(&mut self) -> Option<Job> {
+        let job = self
+            .jobs
+            .iter_mut()
+            .find(|j| j.status == JobStatus::Queued)?;
+        job.status = JobStatus::Running;
+        Some(job.clone())
+    }
+
+    /// Marks a job as done with the result file it wrote, or as failed without one.
+    pub fn finish(&mut self, id: &str, result: Option<PathBuf>) {
+        if let Some(job) = self.jobs.iter_mut().find(|j| j.id == id) {
+            job.status = if result.is_some() {
+                JobStatus::Done
+            } else {
+                JobStatus::Failed
+            };
+            job.result = result;
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Job> {
+        self.jobs.iter().find(|j| j.id == id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::testing;
+
+    #[test]
+    fn test_add_paths() {
+        let root = testing::temp_dir();
+        let site1 = root.join("site1");
+        let site2 = root.join("site2");
+        std::fs::create_dir_all(&site1).unwrap();
+        std::fs::create_dir_all(&site2).unwrap();
+        std::fs::write(site1.join("a.jpg"), b"").unwrap();
+        std::fs::write(site2.join("b.jpg"), b"").unwrap();
+        let root = std::fs::canonicalize(&root).unwrap();
+
+        let mut queue = JobQueue::default();
+        let job = queue
+            .add_paths(&[site1.clone(), site1.join("a.jpg")], None)
+            .unwrap();
+        assert_eq!(job.root, root.join("site1"));
+        assert!(job.paths.is_empty());
+
+        // the file in site1 is already queued, only the one in site2 is new
+        let job = queue
+            .add_paths(
+                &[
+                    site1.join("a.jpg"),
+                    site2.join("b.jpg"),
+                    root.join("missing"),
+                ],
+                None,
+            )
+            .unwrap();
+        assert_eq!(job.root, root.join("site2"));
+        assert_eq!(job.paths, [root.join("site2").join("b.jpg")]);
+        assert!(queue.add_paths(std::slice::from_ref(&site1), None).is_err());
+
+        let first = queue.start_next().unwrap();
+        assert!(queue.is_running());
+        queue.finish(&first.id, Some(root.join("site1/result.json")));
+        assert!(!queue.is_running());
+        assert_eq!(queue.get(&first.id).unwrap().status, JobStatus::Done);
+        // finished jobs don't block the same folder from being queued again
+        assert!(queue.add_paths(std::slice::from_ref(&site1), None).is_ok());
+        assert_eq!(queue.jobs().len(), 3);
+
+        assert!(queue.add_folder(&site1, None).is_err());
+        assert!(queue.add_folder(&site1.join("a.jpg"), None).is_err());
+        let job = queue.add_folder(&site2, None).unwrap();
+        assert_eq!(job.root, root.join("site2"));
+        // the second job was started in between and can't be taken out anymore
+        queue.start_next();
+        assert!(queue.remove(&first.id).is_err());
+        assert_eq!(queue.remove(&job.id).unwrap().id, job.id);
+        assert_eq!(queue.jobs().len(), 3);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
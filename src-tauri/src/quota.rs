@@ -0,0 +1,157 @@
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+
+use serde::Serialize;
+
+/// Frames a run is expected to send, set against the quota of its access tokens.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuotaEstimate {
+    pub files: usize,
+    pub frames: usize,
+    pub quota: i64,
+    /// Frames the quota falls short by, 0 when it suffices.
+    pub shortfall: usize,
+}
+
+impl QuotaEstimate {
+    /// Images send one frame, `videos` send what `VideoFrames` expects of them.
+    pub fn new(images: usize, videos: &VideoFrames, quota: i64) -> Self {
+        let frames = images + videos.estimate();
+        Self {
+            files: images + videos.videos,
+            frames,
+            quota,
+            shortfall: (frames as i64 - quota.max(0)).max(0) as usize,
+        }
+    }
+
+    pub fn is_sufficient(&self) -> bool {
+        self.shortfall == 0
+    }
+}
+
+/// Videos probed for their length, the others are taken to be as long on average.
+pub const PROBED_VIDEOS: usize = 16;
+
+/// Frames the videos of a run are expected to send, from the first few of them.
+#[derive(Debug, Default)]
+pub struct VideoFrames {
+    videos: usize,
+    probed: usize,
+    probed_frames: usize,
+}
+
+impl VideoFrames {
+    /// Counts a video. `expected` gives its frames, it is only asked for the first
+    /// `PROBED_VIDEOS` and `None` leaves the video out of the average.
+    pub fn add(&mut self, expected: impl FnOnce() -> Option<usize>) {
+        self.videos += 1;
+        if self.probed < PROBED_VIDEOS {
+            if let Some(frames) = expected() {
+                self.probed += 1;
+                self.probed_frames += frames;
+            }
+        }
+    }
+
+    /// Without a video probed each counts once, the least it sends.
+    pub fn estimate(&self) -> usize {
+        match self.probed {
+            0 => self.videos,
+            probed => (self.videos * self.probed_frames).div_ceil(probed),
+        }
+    }
+}
+
+/// Frames the access token in use may still send. A run stopping at the quota sends no
+/// further frames once it is spent, rather than having the server refuse them.
+#[derive(Debug)]
+pub struct QuotaBudget {
+    remaining: AtomicI64,
+    spent: AtomicBool,
+}
+
+impl QuotaBudget {
+    /// `None` never runs out.
+    pub fn new(quota: Option<i64>) -> Self {
+        Self {
+            remaining: AtomicI64::new(quota.unwrap_or(i64::MAX)),
+            spent: AtomicBool::new(false),
+        }
+    }
+
+    /// Starts over with the quota of the next access token.
+    pub fn reset(&self, quota: Option<i64>) {
+        self.remaining
+            .store(quota.unwrap_or(i64::MAX), Ordering::Release);
+        self.spent.store(false, Ordering::Release);
+    }
+
+    /// Counts a frame sent.
+    pub fn spend(&self) {
+        self.remaining.fetch_sub(1, Ordering::AcqRel);
+    }
+
+    /// Whether no frame is left to send. Once seen the budget stays spent until reset, so
+    /// the run can tell stopping at the quota from running out of frames.
+    pub fn check(&self) -> bool {
+        if self.remaining.load(Ordering::Acquire) <= 0 {
+            self.spent.store(true, Ordering::Release);
+        }
+        self.is_spent()
+    }
+
+    pub fn is_spent(&self) -> bool {
+        self.spent.load(Ordering::Acquire)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quota() {
+        let mut videos = VideoFrames::default();
+        for _ in 0..10 {
+            videos.add(|| Some(5));
+        }
+        let estimate = QuotaEstimate::new(100, &videos, 120);
+        assert_eq!((estimate.files, estimate.frames), (110, 150));
+        assert_eq!(estimate.shortfall, 30);
+        assert!(!estimate.is_sufficient());
+        let mut unprobed = VideoFrames::default();
+        for _ in 0..10 {
+            unprobed.add(|| None);
+        }
+        assert!(QuotaEstimate::new(100, &unprobed, 110).is_sufficient());
+        assert_eq!(
+            QuotaEstimate::new(2, &VideoFrames::default(), -1).shortfall,
+            2
+        );
+
+        // only the first videos are probed, the rest count as their average
+        let mut videos = VideoFrames::default();
+        let mut probes = 0;
+        for i in 0..100 {
+            videos.add(|| {
+                probes += 1;
+                Some(if i % 2 == 0 { 10 } else { 20 })
+            });
+        }
+        assert_eq!(probes, PROBED_VIDEOS);
+        assert_eq!(videos.estimate(), 1500);
+
+        let budget = QuotaBudget::new(Some(2));
+        budget.spend();
+        assert!(!budget.check());
+        budget.spend();
+        assert!(!budget.is_spent());
+        assert!(budget.check());
+        budget.reset(Some(1));
+        assert!(!budget.is_spent());
+        let unlimited = QuotaBudget::new(None);
+        unlimited.spend();
+        assert!(!unlimited.check());
+    }
+}
@@ -0,0 +1,141 @@
+use std::io::Cursor;
+use std::time::Duration;
+
+use anyhow::Result;
+use image::{DynamicImage, ImageFormat};
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use serde::{Deserialize, Serialize};
+
+use crate::export::ExportFrame;
+
+/// Re-identification endpoint for individually marked species.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReidOptions {
+    pub endpoint: String,
+    #[serde(default)]
+    pub token: Option<String>,
+    /// Bbox classes whose crops are sent, by default only animals.
+    #[serde(default = "default_classes")]
+    pub classes: Vec<usize>,
+    /// Labels the frame must have for its crops to be sent, empty sends all.
+    #[serde(default)]
+    pub labels: Vec<String>,
+}
+
+fn default_classes() -> Vec<usize> {
+    vec![0]
+}
+
+/// Requests to the endpoint in flight at once.
+pub const REID_THREADS: usize = 4;
+
+/// Threads the requests wait on, apart from the rayon pool decoding the media so a slow
+/// endpoint doesn't stall it. They end with the pool.
+pub fn reid_pool() -> Result<ThreadPool> {
+    Ok(ThreadPoolBuilder::new()
+        .num_threads(REID_THREADS)
+        .thread_name(|i| format!("megascops-reid-{}", i))
+        .build()?)
+}
+
+/// Assigns individual IDs to animal crops.
+pub trait Reidentifier: Send + Sync {
+    /// Returns the individual on the crop, `None` if it isn't recognized.
+    fn identify(
+        &self,
+        crop: &DynamicImage,
+        class: usize,
+        label: &[String],
+    ) -> Result<Option<String>>;
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct IdentifyResponse {
+    individual_id: Option<String>,
+}
+
+/// Posts every crop as JPEG to the configured endpoint, which answers with
+/// `{"individualId": "..."}` or `{"individualId": null}`.
+pub struct HttpReidentifier {
+    agent: ureq::Agent,
+    options: ReidOptions,
+}
+
+impl HttpReidentifier {
+    pub fn new(options: ReidOptions) -> Self {
+        let agent = ureq::AgentBuilder::new()
+            .timeout(Duration::from_secs(30))
+            .build();
+        Self { agent, options }
+    }
+}
+
+impl Reidentifier for HttpReidentifier {
+    fn identify(
+        &self,
+        crop: &DynamicImage,
+        class: usize,
+        label: &[String],
+    ) -> Result<Option<String>> {
+        let mut jpeg = Vec::new();
+        crop.to_rgb8()
+            .write_to(&mut Cursor::new(&mut jpeg), ImageFormat::Jpeg)?;
+        let mut request = self
+            .agent
+            .post(&self.options.endpoint)
+            .set("Content-Type", "image/jpeg")
+            .query("class", &class.to_string())
+            .query("label", &label.join(";"));
+        if let Some(token) = &self.options.token {
+            request = request.set("Authorization", &format!("Bearer {}", token));
+        }
+        let response: IdentifyResponse = request.send_bytes(&jpeg)?.into_json()?;
+        Ok(response.individual_id)
+    }
+}
+
+/// Sends the matching crops of `frame` to `reid` and stores the returned IDs on the
+/// bboxes. Failures are logged and leave the bbox unidentified.
+pub fn identify_frame(frame: &mut ExportFrame, reid: &dyn Reidentifier, options: &ReidOptions) {
+    let label = frame.label.clone().unwrap_or_default();
+    if !options.labels.is_empty() && !label.iter().any(|l| options.labels.contains(l)) {
+        return;
+    }
+    let Some(bboxes) = frame.bboxes.as_mut() else {
+        return;
+    };
+    if !bboxes.iter().any(|b| options.classes.contains(&b.class)) {
+        return;
+    }
+    // crops come from the original image, video frames aren't kept after upload
+    let img = match megascops_media::decode_image(&frame.file.file_path) {
+        Ok(img) => img,
+        Err(e) => {
+            log::warn!(
+                "Skipping re-identification of {}: {}",
+                frame.file.file_path.display(),
+                e
+            );
+            return;
+        }
+    };
+    for bbox in bboxes
+        .iter_mut()
+        .filter(|b| options.classes.contains(&b.class))
+    {
+        let (x, y, w, h) = bbox.pixel_rect(img.width(), img.height());
+        if w == 0 || h == 0 {
+            continue;
+        }
+        match reid.identify(&img.crop_imm(x, y, w, h), bbox.class, &label) {
+            Ok(individual) => bbox.individual = individual,
+            Err(e) => log::error!(
+                "Re-identification failed for {}: {}",
+                frame.file.file_path.display(),
+                e
+            ),
+        }
+    }
+}
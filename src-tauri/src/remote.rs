@@ -0,0 +1,386 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use axum::extract::{Path as UrlPath, Query, Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::queue::{Job, JobStatus};
+use crate::{
+    cancel_job_run, cancel_run, export, pause_run, report, start_next_job, viewer, Config, Host,
+};
+
+/// Port of the API unless set otherwise.
+pub const DEFAULT_PORT: u16 = 47292;
+/// Characters a token set in the options needs at least, the random ones have 32.
+pub const MIN_TOKEN_LENGTH: usize = 16;
+
+/// Settings of the remote control API.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RemoteOptions {
+    /// Address to listen on, only this machine can connect on a loopback address.
+    pub address: String,
+    /// Bearer token every request must carry, a random one is made when empty.
+    pub token: String,
+}
+
+impl Default for RemoteOptions {
+    fn default() -> Self {
+        Self {
+            address: format!("127.0.0.1:{}", DEFAULT_PORT),
+            token: String::new(),
+        }
+    }
+}
+
+impl RemoteOptions {
+    /// The options with a random token when none was set, failing on one too short to
+    /// withstand guessing.
+    pub fn with_token(mut self) -> Result<Self> {
+        let token = self.token.trim();
+        if token.is_empty() {
+            self.token = Uuid::new_v4().simple().to_string();
+        } else if token.chars().count() < MIN_TOKEN_LENGTH {
+            return Err(anyhow!(
+                "The remote API token needs at least {} characters",
+                MIN_TOKEN_LENGTH
+            ));
+        }
+        Ok(self)
+    }
+}
+
+/// Where the API listens, with the token to reach it.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RemoteInfo {
+    pub address: String,
+    pub token: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RemoteStatus {
+    pub version: String,
+    pub running: bool,
+    pub paused: bool,
+    pub jobs: Vec<Job>,
+}
+
+/// A job to queue: a folder, or files and folders grouped into one job, with its own
+/// configuration or the default one.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct JobSubmission {
+    pub folder: Option<PathBuf>,
+    pub config: Option<Box<Config>>,
+    pub paths: Vec<PathBuf>,
+}
+
+/// An error answered as `{"error": "..."}`.
+struct ApiError(StatusCode, String);
+
+impl ApiError {
+    fn bad_request(e: anyhow::Error) -> Self {
+        Self(StatusCode::BAD_REQUEST, e.to_string())
+    }
+
+    fn not_found(message: String) -> Self {
+        Self(StatusCode::NOT_FOUND, message)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(serde_json::json!({ "error": self.1 }))).into_response()
+    }
+}
+
+type ApiResult<T> = Result<Json<T>, ApiError>;
+
+#[derive(Clone)]
+struct ApiState<H> {
+    host: H,
+    token: String,
+}
+
+/// Compares the digests of the tokens in constant time, so neither the content nor the
+/// length of the expected one can be told from timing.
+pub(crate) fn tokens_match(given: &str, expected: &str) -> bool {
+    Sha256::digest(given)
+        .iter()
+        .zip(Sha256::digest(expected).iter())
+        .fold(0, |diff, (a, b)| diff | (a ^ b))
+        == 0
+}
+
+async fn authorize<H: Host>(
+    State(state): State<ApiState<H>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let given = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .unwrap_or_default();
+    if !tokens_match(given.trim(), &state.token) {
+        return ApiError(StatusCode::UNAUTHORIZED, "Invalid token".to_string()).into_response();
+    }
+    next.run(request).await
+}
+
+async fn status<H: Host>(State(state): State<ApiState<H>>) -> Json<RemoteStatus> {
+    let paused = state
+        .host
+        .runs()
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(|run| run.gate.is_paused());
+    Json(RemoteStatus {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        running: paused.is_some(),
+        paused: paused.unwrap_or(false),
+        jobs: state.host.queue().lock().unwrap().jobs().to_vec(),
+    })
+}
+
+async fn list_jobs<H: Host>(State(state): State<ApiState<H>>) -> Json<Vec<Job>> {
+    Json(state.host.queue().lock().unwrap().jobs().to_vec())
+}
+
+async fn submit_job<H: Host>(
+    State(state): State<ApiState<H>>,
+    Json(submission): Json<JobSubmission>,
+) -> Result<(StatusCode, Json<Job>), ApiError> {
+    // the buffer folder is removed after the run, only this machine's configuration names it
+    let config = submission.config.map(|mut config| {
+        config.config_options.buffer_path = state
+            .host
+            .default_config()
+            .ok()
+            .and_then(|local| local.config_options.buffer_path);
+        config
+    });
+    let job = {
+        let mut queue = state.host.queue().lock().unwrap();
+        match (submission.folder, config) {
+            (None, config) if !submission.paths.is_empty() => {
+                queue.add_paths(&submission.paths, config.map(|config| *config))
+            }
+            (folder, Some(config)) => {
+                let folder =
+                    folder.unwrap_or_else(|| PathBuf::from(&config.detect_options.selected_folder));
+                queue.add_folder(&folder, Some(*config))
+            }
+            (Some(folder), None) => queue.add_folder(&folder, None),
+            (None, None) => queue.add_paths(&submission.paths, None),
+        }
+    }
+    .map_err(ApiError::bad_request)?;
+    log::info!(
+        "Job {} submitted remotely for {}",
+        job.id,
+        job.root.display()
+    );
+    state.host.sink().emit("job-queued", &job);
+    start_next_job(state.host.clone());
+    Ok((StatusCode::CREATED, Json(job)))
+}
+
+fn find_job<H: Host>(host: &H, id: &str) -> Result<Job, ApiError> {
+    host.queue()
+        .lock()
+        .unwrap()
+        .get(id)
+        .cloned()
+        .ok_or_else(|| ApiError::not_found(format!("No job {}", id)))
+}
+
+async fn get_job<H: Host>(
+    State(state): State<ApiState<H>>,
+    UrlPath(id): UrlPath<String>,
+) -> ApiResult<Job> {
+    find_job(&state.host, &id).map(Json)
+}
+
+async fn remove_job<H: Host>(
+    State(state): State<ApiState<H>>,
+    UrlPath(id): UrlPath<String>,
+) -> Result<StatusCode, ApiError> {
+    let job = state
+        .host
+        .queue()
+        .lock()
+        .unwrap()
+        .remove(&id)
+        .map_err(|e| ApiError(StatusCode::CONFLICT, e.to_string()))?;
+    state.host.sink().emit("job-removed", &job.id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Stops a job, taking it out of the queue before it started. `false` when it already
+/// finished.
+async fn cancel_job<H: Host>(
+    State(state): State<ApiState<H>>,
+    UrlPath(id): UrlPath<String>,
+) -> ApiResult<bool> {
+    let job = find_job(&state.host, &id)?;
+    let cancelled = match job.status {
+        JobStatus::Queued => {
+            let removed = state.host.queue().lock().unwrap().remove(&id).is_ok();
+            if removed {
+                state.host.sink().emit("job-removed", &id);
+            }
+            removed
+        }
+        JobStatus::Running => {
+            cancel_job_run(state.host.sink().as_ref(), state.host.runs(), &id).await
+        }
+        JobStatus::Done | JobStatus::Failed => false,
+    };
+    Ok(Json(cancelled))
+}
+
+/// Result file of a finished job, with the folder its paths are relative to.
+fn job_result<H: Host>(host: &H, id: &str) -> Result<(PathBuf, PathBuf), ApiError> {
+    let job = find_job(host, id)?;
+    let result = job
+        .result
+        .ok_or_else(|| ApiError(StatusCode::CONFLICT, format!("Job {} has no result", id)))?;
+    let folder = result.parent().unwrap_or(Path::new("")).to_path_buf();
+    Ok((result, folder))
+}
+
+async fn job_summary<H: Host>(
+    State(state): State<ApiState<H>>,
+    UrlPath(id): UrlPath<String>,
+) -> ApiResult<report::RunStats> {
+    let (result, folder) = job_result(&state.host, &id)?;
+    let frames = export::load_export(&result).map_err(ApiError::bad_request)?;
+    Ok(Json(report::RunStats::from_frames(&frames, &folder)))
+}
+
+async fn job_results<H: Host>(
+    State(state): State<ApiState<H>>,
+    UrlPath(id): UrlPath<String>,
+    Query(query): Query<viewer::ResultQuery>,
+) -> ApiResult<viewer::ResultPage> {
+    let (result, folder) = job_result(&state.host, &id)?;
+    let frames = export::load_export(&result).map_err(ApiError::bad_request)?;
+    Ok(Json(viewer::query_results(&frames, &folder, &query)))
+}
+
+/// Frames of a finished job, for a coordinator merging the batches of its peers.
+async fn job_export<H: Host>(
+    State(state): State<ApiState<H>>,
+    UrlPath(id): UrlPath<String>,
+) -> ApiResult<Vec<export::ExportFrame>> {
+    let (result, _) = job_result(&state.host, &id)?;
+    let frames = export::load_export(&result).map_err(ApiError::bad_request)?;
+    Ok(Json(frames))
+}
+
+async fn pause<H: Host>(State(state): State<ApiState<H>>) -> Json<bool> {
+    Json(pause_run(
+        state.host.sink().as_ref(),
+        state.host.runs(),
+        true,
+    ))
+}
+
+async fn resume<H: Host>(State(state): State<ApiState<H>>) -> Json<bool> {
+    Json(pause_run(
+        state.host.sink().as_ref(),
+        state.host.runs(),
+        false,
+    ))
+}
+
+async fn cancel<H: Host>(State(state): State<ApiState<H>>) -> Json<bool> {
+    Json(cancel_run(state.host.sink().as_ref(), state.host.runs()).await)
+}
+
+/// Routes of the API, all behind the bearer `token`.
+pub(crate) fn router<H: Host>(host: H, token: String) -> Router {
+    let state = ApiState { host, token };
+    Router::new()
+        .route("/api/v1/status", get(status::<H>))
+        .route("/api/v1/jobs", get(list_jobs::<H>).post(submit_job::<H>))
+        .route(
+            "/api/v1/jobs/{id}",
+            get(get_job::<H>).delete(remove_job::<H>),
+        )
+        .route("/api/v1/jobs/{id}/cancel", post(cancel_job::<H>))
+        .route("/api/v1/jobs/{id}/summary", get(job_summary::<H>))
+        .route("/api/v1/jobs/{id}/results", get(job_results::<H>))
+        .route("/api/v1/jobs/{id}/export", get(job_export::<H>))
+        .route("/api/v1/run/pause", post(pause::<H>))
+        .route("/api/v1/run/resume", post(resume::<H>))
+        .route("/api/v1/run/cancel", post(cancel::<H>))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            authorize::<H>,
+        ))
+        .with_state(state)
+}
+
+/// Binds the address of `options`, so a taken port fails before anything is spawned.
+pub async fn bind(options: &RemoteOptions) -> Result<TcpListener> {
+    TcpListener::bind(&options.address)
+        .await
+        .map_err(|e| anyhow!("Failed to listen on {}: {}", options.address, e))
+}
+
+/// Serves the API on `listener` until `stop` is cancelled.
+pub(crate) async fn serve<H: Host>(
+    host: H,
+    listener: TcpListener,
+    token: String,
+    stop: CancellationToken,
+) -> Result<()> {
+    log::info!("Remote API listening on {}", listener.local_addr()?);
+    axum::serve(listener, router(host, token))
+        .with_graceful_shutdown(stop.cancelled_owned())
+        .await?;
+    log::info!("Remote API stopped");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remote_options() {
+        assert!(tokens_match("secret", "secret"));
+        assert!(!tokens_match("secreT", "secret"));
+        assert!(!tokens_match("", "secret"));
+        assert!(!tokens_match("secret", "secret "));
+
+        let options = RemoteOptions::default().with_token().unwrap();
+        assert_eq!(options.address, "127.0.0.1:47292");
+        assert_eq!(options.token.len(), 32);
+        let options: RemoteOptions =
+            serde_json::from_str(r#"{"token": "correct-horse-battery"}"#).unwrap();
+        assert_eq!(options.with_token().unwrap().token, "correct-horse-battery");
+        let options: RemoteOptions = serde_json::from_str(r#"{"token": "abc"}"#).unwrap();
+        assert!(options.with_token().is_err());
+
+        let submission: JobSubmission =
+            serde_json::from_str(r#"{"paths": ["/data/a", "/data/b"]}"#).unwrap();
+        assert!(submission.folder.is_none() && submission.config.is_none());
+        assert_eq!(submission.paths.len(), 2);
+    }
+}
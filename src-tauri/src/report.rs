@@ -0,0 +1,560 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use image::RgbImage;
+use printpdf::path::PaintMode;
+use printpdf::{
+    BuiltinFont, Color, ColorBits, ColorSpace, Image, ImageTransform, ImageXObject,
+    IndirectFontRef, Mm, PdfDocument, PdfDocumentReference, PdfLayerReference, Px, Rect, Rgb,
+};
+use serde::Serialize;
+
+use crate::contact_sheet::{is_positive, load_frame, tile};
+use crate::export::{load_export, ExportFrame};
+use crate::template::TemplateContext;
+
+pub const REPORT_FILE: &str = "report.pdf";
+
+const PAGE_WIDTH: f32 = 210.0;
+const PAGE_HEIGHT: f32 = 297.0;
+const MARGIN: f32 = 15.0;
+const SAMPLES: usize = 6;
+const MAX_SITES: usize = 15;
+const MAX_ERRORS: usize = 10;
+
+/// Fonts covering Chinese as well as Latin, looked up on the machine and embedded in the
+/// report. Collections (`.ttc`) can't be embedded in a PDF, only single fonts are listed.
+#[cfg(windows)]
+const CJK_FONTS: &[&str] = &[
+    r"C:\Windows\Fonts\Deng.ttf",
+    r"C:\Windows\Fonts\simhei.ttf",
+    r"C:\Windows\Fonts\simkai.ttf",
+];
+#[cfg(target_os = "macos")]
+const CJK_FONTS: &[&str] = &[
+    "/System/Library/Fonts/Supplemental/Arial Unicode.ttf",
+    "/Library/Fonts/Arial Unicode.ttf",
+];
+#[cfg(not(any(windows, target_os = "macos")))]
+const CJK_FONTS: &[&str] = &[
+    "/usr/share/fonts/truetype/droid/DroidSansFallbackFull.ttf",
+    "/usr/share/fonts/google-droid-sans-fonts/DroidSansFallbackFull.ttf",
+    "/usr/share/fonts/truetype/arphic-gbsn00lp/gbsn00lp.ttf",
+];
+
+/// Summary of one run, counted per file. A file is positive when any of its frames has a
+/// detection.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RunStats {
+    pub files: usize,
+    pub frames: usize,
+    pub positives: usize,
+    pub blanks: usize,
+    pub errors: usize,
+    /// Positive files per label.
+    pub labels: BTreeMap<String, usize>,
+    /// Positive files per hour of the shoot time.
+    pub hours: [usize; 24],
+    /// Positive files per site.
+    pub sites: BTreeMap<String, usize>,
+    /// Distinct error messages with the number of files, most frequent first.
+    pub error_messages: Vec<(String, usize)>,
+}
+
+pub(crate) fn has_error(frame: &ExportFrame) -> bool {
+    !frame.error.as_deref().unwrap_or_default().is_empty()
+}
+
+pub(crate) fn by_file(frames: &[ExportFrame]) -> BTreeMap<&PathBuf, Vec<&ExportFrame>> {
+    let mut files: BTreeMap<&PathBuf, Vec<&ExportFrame>> = BTreeMap::new();
+    for frame in frames {
+        files.entry(&frame.file.file_path).or_default().push(frame);
+    }
+    files
+}
+
+impl RunStats {
+    pub fn from_frames(frames: &[ExportFrame], folder: &Path) -> Self {
+        let mut stats = RunStats {
+            frames: frames.len(),
+            ..Default::default()
+        };
+        let mut errors: HashMap<&str, usize> = HashMap::new();
+        for frames in by_file(frames).values() {
+            stats.files += 1;
+            let mut file_errors: BTreeSet<&str> = BTreeSet::new();
+            for frame in frames.iter().filter(|f| has_error(f)) {
+                file_errors.insert(frame.error.as_deref().unwrap_or_default());
+            }
+            if !file_errors.is_empty() {
+                stats.errors += 1;
+                for error in file_errors {
+                    *errors.entry(error).or_default() += 1;
+                }
+            }
+            let Some(first) = frames.iter().find(|f| is_positive(f)) else {
+                if !frames.iter().any(|f| has_error(f)) {
+                    stats.blanks += 1;
+                }
+                continue;
+            };
+            stats.positives += 1;
+            let labels: BTreeSet<&String> = frames
+                .iter()
+                .filter(|f| is_positive(f))
+                .flat_map(|f| f.label.iter().flatten())
+                .collect();
+            for label in labels {
+                *stats.labels.entry(label.clone()).or_default() += 1;
+            }
+            // shoot_time is exported as `YYYY-MM-DD hh:mm:ss +zz:zz`
+            if let Some(hour) = first
+                .shoot_time
+                .as_deref()
+                .and_then(|t| t.get(11..13))
+                .and_then(|h| h.parse::<usize>().ok())
+                .filter(|h| *h < 24)
+            {
+                stats.hours[hour] += 1;
+            }
+            let ctx = TemplateContext::from_frame(first, folder, "");
+            *stats.sites.entry(ctx.site).or_default() += 1;
+        }
+        let mut errors: Vec<(String, usize)> = errors
+            .into_iter()
+            .map(|(e, n)| (e.to_string(), n))
+            .collect();
+        errors.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        stats.error_messages = errors;
+        stats
+    }
+}
+
+/// The first positive frame of the files with the most confident detections.
+fn pick_samples(frames: &[ExportFrame], count: usize) -> Vec<&ExportFrame> {
+    let mut samples: Vec<(&ExportFrame, f32)> = by_file(frames)
+        .into_values()
+        .filter_map(|frames| {
+            let frame = *frames.iter().find(|f| is_positive(f))?;
+            let score = frame
+                .bboxes
+                .iter()
+                .flatten()
+                .map(|b| b.score)
+                .fold(0.0, f32::max);
+            Some((frame, score))
+        })
+        .collect();
+    samples.sort_by(|a, b| b.1.total_cmp(&a.1));
+    samples.into_iter().take(count).map(|(f, _)| f).collect()
+}
+
+/// The builtin PDF fonts only cover Latin-1, anything else is replaced when no font of
+/// `CJK_FONTS` is found.
+fn latin1_text(text: &str) -> String {
+    text.chars()
+        .map(|c| if (c as u32) < 0x100 { c } else { '?' })
+        .collect()
+}
+
+/// Embeds the first font of `CJK_FONTS` found on this machine.
+fn add_cjk_font(doc: &PdfDocumentReference) -> Option<IndirectFontRef> {
+    CJK_FONTS.iter().find_map(|path| {
+        let file = File::open(path).ok()?;
+        doc.add_external_font(BufReader::new(file))
+            .inspect_err(|e| log::warn!("Failed to embed font {}: {}", path, e))
+            .ok()
+    })
+}
+
+fn gray(level: f32) -> Color {
+    Color::Rgb(Rgb::new(level, level, level, None))
+}
+
+/// Lays out the report top to bottom, starting a new page when one is full.
+struct Writer {
+    doc: PdfDocumentReference,
+    layer: PdfLayerReference,
+    font: IndirectFontRef,
+    bold: IndirectFontRef,
+    /// Only the builtin fonts are available.
+    latin1: bool,
+    y: f32,
+}
+
+impl Writer {
+    fn new(title: &str) -> Result<Self> {
+        let (doc, page, layer) =
+            PdfDocument::new(title, Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Layer 1");
+        // the embedded font has no bold face, headings stand out by their size
+        let (font, bold, latin1) = match add_cjk_font(&doc) {
+            Some(font) => (font.clone(), font, false),
+            None => (
+                doc.add_builtin_font(BuiltinFont::Helvetica)?,
+                doc.add_builtin_font(BuiltinFont::HelveticaBold)?,
+                true,
+            ),
+        };
+        let layer = doc.get_page(page).get_layer(layer);
+        Ok(Self {
+            doc,
+            layer,
+            font,
+            bold,
+            latin1,
+            y: PAGE_HEIGHT - MARGIN,
+        })
+    }
+
+    /// Makes sure `height` fits below the cursor.
+    fn reserve(&mut self, height: f32) {
+        if self.y - height >= MARGIN {
+            return;
+        }
+        let (page, layer) = self
+            .doc
+            .add_page(Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Layer 1");
+        self.layer = self.doc.get_page(page).get_layer(layer);
+        self.y = PAGE_HEIGHT - MARGIN;
+    }
+
+    fn text_at(&self, text: &str, size: f32, x: f32, y: f32, bold: bool) {
+        self.layer.set_fill_color(gray(0.0));
+        let font = if bold { &self.bold } else { &self.font };
+        if self.latin1 {
+            self.layer
+                .use_text(latin1_text(text), size, Mm(x), Mm(y), font);
+        } else {
+            self.layer.use_text(text, size, Mm(x), Mm(y), font);
+        }
+    }
+
+    fn heading(&mut self, text: &str) {
+        self.reserve(14.0);
+        self.y -= 10.0;
+        self.text_at(text, 13.0, MARGIN, self.y, true);
+        self.y -= 4.0;
+    }
+
+    fn line(&mut self, text: &str) {
+        self.reserve(5.5);
+        self.y -= 5.5;
+        self.text_at(text, 10.0, MARGIN, self.y, false);
+    }
+
+    fn row(&mut self, label: &str, value: &str) {
+        self.reserve(5.5);
+        self.y -= 5.5;
+        self.text_at(label, 10.0, MARGIN, self.y, false);
+        self.text_at(value, 10.0, MARGIN + 70.0, self.y, false);
+    }
+
+    fn bar(&self, x: f32, y: f32, w: f32, h: f32, color: Color) {
+        self.layer.set_fill_color(color);
+        self.layer
+            .add_rect(Rect::new(Mm(x), Mm(y), Mm(x + w), Mm(y + h)).with_mode(PaintMode::Fill));
+    }
+
+    /// Vertical bars of the 24 hours with the hour below each bar.
+    fn hour_chart(&mut self, hours: &[usize; 24]) {
+        let height = 45.0;
+        self.reserve(height + 10.0);
+        let base = self.y - height;
+        let max = hours.iter().copied().max().unwrap_or(0).max(1) as f32;
+        let slot = (PAGE_WIDTH - 2.0 * MARGIN) / 24.0;
+        self.bar(MARGIN, base, PAGE_WIDTH - 2.0 * MARGIN, 0.3, gray(0.5));
+        for (hour, count) in hours.iter().enumerate() {
+            let x = MARGIN + hour as f32 * slot;
+            let h = (height - 8.0) * *count as f32 / max;
+            if *count > 0 {
+                self.bar(
+                    x + 1.0,
+                    base,
+                    slot - 2.0,
+                    h,
+                    Color::Rgb(Rgb::new(0.2, 0.45, 0.7, None)),
+                );
+                self.text_at(&count.to_string(), 6.0, x + 1.0, base + h + 1.0, false);
+            }
+            self.text_at(&format!("{:02}", hour), 6.0, x + 1.5, base - 4.0, false);
+        }
+        self.y = base - 6.0;
+    }
+
+    /// Horizontal bars, one line per entry.
+    fn bar_chart(&mut self, entries: &[(&String, &usize)]) {
+        let max = entries.iter().map(|(_, n)| **n).max().unwrap_or(0).max(1) as f32;
+        let width = PAGE_WIDTH - 2.0 * MARGIN - 75.0;
+        for (name, count) in entries {
+            self.reserve(6.0);
+            self.y -= 6.0;
+            self.text_at(name, 9.0, MARGIN, self.y, false);
+            let w = width * **count as f32 / max;
+            self.bar(
+                MARGIN + 60.0,
+                self.y - 0.5,
+                w.max(0.5),
+                4.0,
+                Color::Rgb(Rgb::new(0.3, 0.6, 0.35, None)),
+            );
+            self.text_at(&count.to_string(), 9.0, MARGIN + 62.0 + w, self.y, false);
+        }
+    }
+
+    /// Two images per row, scaled to half the page width.
+    fn images(&mut self, images: Vec<RgbImage>) {
+        let column = (PAGE_WIDTH - 2.0 * MARGIN - 6.0) / 2.0;
+        for row in images.chunks(2) {
+            let (w, h) = row[0].dimensions();
+            // millimeters to inches, the dpi that maps the tile width onto the column
+            let dpi = w as f32 / (column / 25.4);
+            let height = h as f32 / dpi * 25.4;
+            self.reserve(height + 6.0);
+            self.y -= height + 6.0;
+            for (i, img) in row.iter().enumerate() {
+                let (w, h) = img.dimensions();
+                let image = Image::from(ImageXObject {
+                    width: Px(w as usize),
+                    height: Px(h as usize),
+                    color_space: ColorSpace::Rgb,
+                    bits_per_component: ColorBits::Bit8,
+                    interpolate: true,
+                    image_data: img.as_raw().clone(),
+                    image_filter: None,
+                    smask: None,
+                    clipping_bbox: None,
+                });
+                image.add_to_layer(
+                    self.layer.clone(),
+                    ImageTransform {
+                        translate_x: Some(Mm(MARGIN + i as f32 * (column + 6.0))),
+                        translate_y: Some(Mm(self.y)),
+                        dpi: Some(dpi),
+                        ..Default::default()
+                    },
+                );
+            }
+        }
+    }
+
+    fn save(self, path: &Path) -> Result<()> {
+        self.doc.save(&mut BufWriter::new(File::create(path)?))?;
+        Ok(())
+    }
+}
+
+fn percent(part: usize, total: usize) -> String {
+    if total == 0 {
+        return "0".to_string();
+    }
+    format!("{} ({:.1}%)", part, part as f32 * 100.0 / total as f32)
+}
+
+/// Renders the summary, charts, errors and a few sample detections of a result file into
+/// a PDF, `report.pdf` next to the result unless `output` is given.
+pub fn generate_pdf_report(result: &Path, output: Option<&Path>) -> Result<PathBuf> {
+    let folder = result
+        .parent()
+        .ok_or_else(|| anyhow!("Invalid result path: {}", result.display()))?;
+    let frames = load_export(result)?;
+    let stats = RunStats::from_frames(&frames, folder);
+    let target = output.map_or_else(|| folder.join(REPORT_FILE), Path::to_path_buf);
+
+    let mut writer = Writer::new("Megascops report")?;
+    writer.y -= 8.0;
+    writer.text_at("Megascops report", 20.0, MARGIN, writer.y, true);
+    writer.line(&result.display().to_string());
+    writer.line(&format!(
+        "Generated {}",
+        chrono::Local::now().format("%Y-%m-%d %H:%M")
+    ));
+
+    writer.heading("Summary");
+    writer.row("Files", &stats.files.to_string());
+    writer.row("Frames", &stats.frames.to_string());
+    writer.row("With detections", &percent(stats.positives, stats.files));
+    writer.row("Blank", &percent(stats.blanks, stats.files));
+    writer.row("With errors", &percent(stats.errors, stats.files));
+
+    if !stats.labels.is_empty() {
+        writer.heading("Detections by label");
+        let labels: Vec<(&String, &usize)> = stats.labels.iter().collect();
+        writer.bar_chart(&labels);
+    }
+
+    writer.heading("Activity by hour of day");
+    writer.hour_chart(&stats.hours);
+
+    if !stats.sites.is_empty() {
+        writer.heading("Detections per site");
+        let mut sites: Vec<(&String, &usize)> = stats.sites.iter().collect();
+        sites.sort_by(|a, b| b.1.cmp(a.1));
+        if sites.len() > MAX_SITES {
+            writer.line(&format!("Top {} of {} sites", MAX_SITES, sites.len()));
+        }
+        sites.truncate(MAX_SITES);
+        writer.bar_chart(&sites);
+    }
+
+    writer.heading("Errors");
+    if stats.error_messages.is_empty() {
+        writer.line("No errors");
+    }
+    for (error, count) in stats.error_messages.iter().take(MAX_ERRORS) {
+        let error: String = error.chars().take(90).collect();
+        writer.line(&format!("{} x {}", count, error));
+    }
+
+    let samples: Vec<RgbImage> = pick_samples(&frames, SAMPLES)
+        .into_iter()
+        .filter_map(|frame| {
+            let path = folder.join(&frame.file.file_path);
+            match load_frame(&path, frame) {
+                Ok(img) => Some(tile(&img, frame.bboxes.as_deref().unwrap_or_default())),
+                Err(e) => {
+                    log::warn!("Skipping {} in report: {}", path.display(), e);
+                    None
+                }
+            }
+        })
+        .collect();
+    if !samples.is_empty() {
+        writer.heading("Sample detections");
+        writer.images(samples);
+    }
+
+    writer.save(&target)?;
+    log::info!("Wrote report to {}", target.display());
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::{testing, BlankSkip};
+
+    #[test]
+    fn test_run_stats() {
+        let animal = |path: &str, time: &str, score: f32| ExportFrame {
+            shoot_time: Some(time.to_string()),
+            bboxes: Some(vec![testing::bbox(0, score)]),
+            label: Some(vec!["Animal".to_string()]),
+            ..testing::frame(path)
+        };
+        let frames = vec![
+            animal("/r/a/1.jpg", "2024-05-01 06:10:00 +08:00", 0.9),
+            ExportFrame {
+                shoot_time: Some("2024-05-01 06:40:00 +08:00".to_string()),
+                ..testing::frame("/r/a/2.jpg")
+            },
+            animal("/r/b/3.jpg", "2024-05-02 22:00:00 +08:00", 0.7),
+            ExportFrame {
+                bboxes: None,
+                error: Some("decode failed".to_string()),
+                ..testing::frame("/r/b/4.jpg")
+            },
+        ];
+        let stats = RunStats::from_frames(&frames, Path::new("/r"));
+        assert_eq!(stats.files, 4);
+        assert_eq!(stats.positives, 2);
+        assert_eq!(stats.blanks, 1);
+        assert_eq!(stats.errors, 1);
+        assert_eq!(stats.labels["Animal"], 2);
+        assert_eq!(stats.hours[6], 1);
+        assert_eq!(stats.hours[22], 1);
+        assert_eq!(stats.sites["a"], 1);
+        assert_eq!(stats.sites["b"], 1);
+        assert_eq!(stats.error_messages, [("decode failed".to_string(), 1)]);
+
+        let samples = pick_samples(&frames, 6);
+        assert_eq!(samples.len(), 2);
+        // the most confident first
+        assert_eq!(samples[0].file.file_path, PathBuf::from("/r/a/1.jpg"));
+        assert_eq!(pick_samples(&frames, 1).len(), 1);
+    }
+
+    #[test]
+    fn test_video_stats() {
+        let video = ExportFrame {
+            shoot_time: Some("2024-05-01 23:59:00 +08:00".to_string()),
+            total_frames: 3,
+            ..testing::frame("/r/a/1.mp4")
+        };
+        let frames = vec![
+            // a frame the blank filters skipped doesn't make the file positive
+            ExportFrame {
+                bboxes: Some(vec![testing::bbox(0, 0.9)]),
+                label: Some(vec!["Animal".to_string()]),
+                skipped_blank: Some(BlankSkip::Prefilter),
+                ..video.clone()
+            },
+            ExportFrame {
+                frame_index: 1,
+                bboxes: Some(vec![testing::bbox(1, 0.8)]),
+                label: Some(vec!["Person".to_string()]),
+                ..video.clone()
+            },
+            ExportFrame {
+                frame_index: 2,
+                bboxes: Some(vec![testing::bbox(0, 0.6)]),
+                label: Some(vec!["Animal".to_string()]),
+                error: Some("Frame 2 has an unexpected size".to_string()),
+                ..video
+            },
+            // the same error in two files counts for both
+            ExportFrame {
+                shoot_time: Some("unknown".to_string()),
+                error: Some("decode failed".to_string()),
+                ..testing::frame("/r/b/2.jpg")
+            },
+            ExportFrame {
+                error: Some("decode failed".to_string()),
+                ..testing::frame("/r/3.jpg")
+            },
+        ];
+        let stats = RunStats::from_frames(&frames, Path::new("/r"));
+        assert_eq!((stats.files, stats.frames), (3, 5));
+        // a video with a detection and a failed frame is both positive and failed
+        assert_eq!((stats.positives, stats.blanks, stats.errors), (1, 0, 3));
+        assert_eq!(
+            stats.labels,
+            BTreeMap::from([("Animal".to_string(), 1), ("Person".to_string(), 1)])
+        );
+        // the hour of the first positive frame
+        assert_eq!(stats.hours[23], 1);
+        assert_eq!(stats.hours.iter().sum::<usize>(), 1);
+        assert_eq!(
+            stats.error_messages,
+            [
+                ("decode failed".to_string(), 2),
+                ("Frame 2 has an unexpected size".to_string(), 1)
+            ]
+        );
+        assert_eq!(pick_samples(&frames, 6)[0].frame_index, 1);
+    }
+
+    #[test]
+    fn test_report_text() {
+        assert_eq!(latin1_text("Café 红外相机"), "Café ????");
+        assert_eq!(percent(1, 4), "1 (25.0%)");
+        assert_eq!(percent(0, 0), "0");
+
+        // sample images that can't be read are left out of the report
+        let dir = testing::temp_dir();
+        let frames = vec![ExportFrame {
+            bboxes: Some(vec![testing::bbox(0, 0.9)]),
+            label: Some(vec!["Animal".to_string()]),
+            ..testing::frame("site/missing.jpg")
+        }];
+        let result = dir.join("result.json");
+        std::fs::write(&result, serde_json::to_string(&frames).unwrap()).unwrap();
+        let report = generate_pdf_report(&result, None).unwrap();
+        assert_eq!(report, dir.join(REPORT_FILE));
+        assert!(std::fs::read(&report).unwrap().starts_with(b"%PDF"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,680 @@
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+use crate::annotation::frame_labels;
+use crate::embedding::{EmbeddingStore, EMBEDDING_DB};
+use crate::export::{load_export, save_export, ExportFrame, CLASS_NAMES};
+use crate::triage::ReviewPart;
+use crate::utils::portable_path;
+
+/// Extension of the undo history kept next to a result file.
+pub const UNDO_EXTENSION: &str = "undo.json";
+/// Batches that can be undone, older ones are forgotten.
+const UNDO_DEPTH: usize = 50;
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FrameRef {
+    /// Path relative to the result's folder, as in the review queue.
+    pub file_path: String,
+    pub frame_index: usize,
+}
+
+/// Frames a batch applies to.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(
+    tag = "kind",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum ReviewSelection {
+    /// Listed frames, e.g. a page of the review queue.
+    Frames { frames: Vec<FrameRef> },
+    /// Every frame of a file and of the burst it leads.
+    Sequence { file_path: String },
+    /// The boxes whose crops are in a cluster.
+    Cluster { cluster_id: usize },
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "action", rename_all = "camelCase")]
+pub enum ReviewAction {
+    /// Keep the detections as they are.
+    Accept,
+    /// Drop the selected boxes, frames without boxes left are blank.
+    Reject,
+    Relabel {
+        label: String,
+    },
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchSummary {
+    pub frames: usize,
+    /// Batches that can be undone now.
+    pub undo_depth: usize,
+}
+
+/// Frames of a result as they were before a batch.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct UndoBatch {
+    action: ReviewAction,
+    frames: Vec<ExportFrame>,
+    #[serde(default)]
+    reviewer: Option<String>,
+}
+
+/// Labels one reviewer settled on for a frame, kept to compare reviewers.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Verdict {
+    pub reviewer: String,
+    pub frame: FrameRef,
+    pub labels: Vec<String>,
+}
+
+fn frame_ref(frame: &ExportFrame, folder: &Path) -> FrameRef {
+    FrameRef {
+        file_path: portable_path(&folder.join(&frame.file.file_path), Some(folder)),
+        frame_index: frame.frame_index,
+    }
+}
+
+pub fn undo_path(result: &Path) -> PathBuf {
+    result.with_extension(UNDO_EXTENSION)
+}
+
+/// Indices of the selected frames, with the selected boxes or `None` for all of them.
+/// `cluster_boxes` are the `(file_path, frame_index, bbox_index)` of a cluster selection.
+pub fn select(
+    frames: &[ExportFrame],
+    folder: &Path,
+    selection: &ReviewSelection,
+    cluster_boxes: &[(String, usize, usize)],
+) -> BTreeMap<usize, Option<Vec<usize>>> {
+    let relative = |path: &Path| portable_path(&folder.join(path), Some(folder));
+    let mut selected = BTreeMap::new();
+    match selection {
+        ReviewSelection::Frames { frames: refs } => {
+            let refs: HashSet<&FrameRef> = refs.iter().collect();
+            for (i, frame) in frames.iter().enumerate() {
+                let key = FrameRef {
+                    file_path: relative(&frame.file.file_path),
+                    frame_index: frame.frame_index,
+                };
+                if refs.contains(&key) {
+                    selected.insert(i, None);
+                }
+            }
+        }
+        ReviewSelection::Sequence { file_path } => {
+            for (i, frame) in frames.iter().enumerate() {
+                let source = frame.burst_source.as_deref().map(relative);
+                if relative(&frame.file.file_path) == *file_path
+                    || source.as_ref() == Some(file_path)
+                {
+                    selected.insert(i, None);
+                }
+            }
+        }
+        ReviewSelection::Cluster { .. } => {
+            for (i, frame) in frames.iter().enumerate() {
+                let key = portable_path(&folder.join(&frame.file.file_path), None);
+                let boxes: Vec<usize> = cluster_boxes
+                    .iter()
+                    .filter(|(path, index, _)| *path == key && *index == frame.frame_index)
+                    .map(|(_, _, bbox)| *bbox)
+                    .collect();
+                if !boxes.is_empty() {
+                    selected.insert(i, Some(boxes));
+                }
+            }
+        }
+    }
+    selected
+}
+
+/// Applies `action` to the `boxes` of `frame`, all of them when `None`, and marks it
+/// verified.
+pub fn apply(frame: &mut ExportFrame, boxes: Option<&[usize]>, action: &ReviewAction) {
+    let chosen = |i: usize| boxes.is_none_or(|boxes| boxes.contains(&i));
+    let mut bboxes = frame.bboxes.take().unwrap_or_default();
+    match action {
+        ReviewAction::Accept => (),
+        ReviewAction::Reject => {
+            let mut i = 0;
+            bboxes.retain(|_| {
+                i += 1;
+                !chosen(i - 1)
+            });
+        }
+        ReviewAction::Relabel { label } => {
+            let class = CLASS_NAMES.iter().position(|n| n == label);
+            for (_, bbox) in bboxes.iter_mut().enumerate().filter(|(i, _)| chosen(*i)) {
+                // labels beyond the detector classes are species of the detected class
+                bbox.class = class.unwrap_or(bbox.class);
+                bbox.label = class.is_none().then(|| label.clone());
+            }
+        }
+    }
+    frame.label = Some(frame_labels(&bboxes));
+    frame.bboxes = Some(bboxes);
+    frame.verified = true;
+}
+
+fn read_undo(result: &Path) -> Result<Vec<UndoBatch>> {
+    let path = undo_path(result);
+    if !path.is_file() {
+        return Ok(Vec::new());
+    }
+    Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+}
+
+fn write_undo(result: &Path, batches: &[UndoBatch]) -> Result<()> {
+    std::fs::write(undo_path(result), serde_json::to_string(batches)?)?;
+    Ok(())
+}
+
+/// Applies `action` to the selected frames of a result file in one go, keeping the frames
+/// as they were to undo it. The resulting labels are recorded as `reviewer`'s verdicts.
+pub fn review_batch(
+    result: &Path,
+    selection: &ReviewSelection,
+    action: &ReviewAction,
+    reviewer: Option<&str>,
+) -> Result<BatchSummary> {
+    let folder = result
+        .parent()
+        .ok_or_else(|| anyhow!("Invalid result path: {}", result.display()))?;
+    let mut frames = load_export(result)?;
+    let cluster_boxes = match selection {
+        ReviewSelection::Cluster { cluster_id } => {
+            EmbeddingStore::open(&folder.join(EMBEDDING_DB))?.cluster_boxes(*cluster_id)?
+        }
+        _ => Vec::new(),
+    };
+    let selected = select(&frames, folder, selection, &cluster_boxes);
+    let mut undo = read_undo(result)?;
+    if selected.is_empty() {
+        return Ok(BatchSummary {
+            frames: 0,
+            undo_depth: undo.len(),
+        });
+    }
+
+    undo.push(UndoBatch {
+        action: action.clone(),
+        frames: selected.keys().map(|&i| frames[i].clone()).collect(),
+        reviewer: reviewer.map(str::to_string),
+    });
+    if undo.len() > UNDO_DEPTH {
+        undo.remove(0);
+    }
+    for (&i, boxes) in &selected {
+        apply(&mut frames[i], boxes.as_deref(), action);
+    }
+    save_export(result, &frames)?;
+    write_undo(result, &undo)?;
+    if let Some(reviewer) = reviewer {
+        let verdicts: Vec<Verdict> = selected
+            .keys()
+            .map(|&i| Verdict {
+                reviewer: reviewer.to_string(),
+                frame: frame_ref(&frames[i], folder),
+                labels: frames[i].label.clone().unwrap_or_default(),
+            })
+            .collect();
+        SessionStore::open(&folder.join(EMBEDDING_DB))?.record(&verdicts)?;
+    }
+    log::info!(
+        "Applied {:?} to {} frames of {}",
+        action,
+        selected.len(),
+        result.display()
+    );
+    Ok(BatchSummary {
+        frames: selected.len(),
+        undo_depth: undo.len(),
+    })
+}
+
+/// Restores the frames changed by the last batch and forgets the verdicts it recorded.
+pub fn undo_review(result: &Path) -> Result<BatchSummary> {
+    let mut undo = read_undo(result)?;
+    let batch = undo
+        .pop()
+        .ok_or_else(|| anyhow!("Nothing to undo for {}", result.display()))?;
+    let folder = result
+        .parent()
+        .ok_or_else(|| anyhow!("Invalid result path: {}", result.display()))?;
+    let refs: Vec<FrameRef> = batch.frames.iter().map(|f| frame_ref(f, folder)).collect();
+    let mut frames = load_export(result)?;
+    let mut restored = 0;
+    for previous in batch.frames {
+        if let Some(frame) = frames.iter_mut().find(|f| {
+            f.file.file_path == previous.file.file_path && f.frame_index == previous.frame_index
+        }) {
+            *frame = previous;
+            restored += 1;
+        }
+    }
+    save_export(result, &frames)?;
+    write_undo(result, &undo)?;
+    if let Some(reviewer) = &batch.reviewer {
+        SessionStore::open(&folder.join(EMBEDDING_DB))?.forget(reviewer, &refs)?;
+    }
+    log::info!(
+        "Undid {:?} on {} frames of {}",
+        batch.action,
+        restored,
+        result.display()
+    );
+    Ok(BatchSummary {
+        frames: restored,
+        undo_depth: undo.len(),
+    })
+}
+
+/// Where a reviewer left off, saved with the run so a session survives restarts.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReviewSession {
+    pub reviewer: String,
+    /// Frame the reviewer was looking at.
+    #[serde(default)]
+    pub position: Option<FrameRef>,
+    /// Offset of the page of the review queue.
+    #[serde(default)]
+    pub offset: usize,
+    /// Filters of the review view, kept as the frontend sent them.
+    #[serde(default)]
+    pub filters: serde_json::Value,
+    /// Share of the queue when reviewers split the work.
+    #[serde(default)]
+    pub part: Option<ReviewPart>,
+    /// Frames the reviewer got through.
+    #[serde(default)]
+    pub reviewed: usize,
+    #[serde(default)]
+    pub updated_at: Option<String>,
+}
+
+/// Review sessions of a run, one per reviewer, and their verdicts in the run's database
+/// next to the crops.
+pub struct SessionStore {
+    conn: Connection,
+}
+
+impl SessionStore {
+    pub fn open(path: &Path) -> Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             CREATE TABLE IF NOT EXISTS review_sessions (
+                 reviewer TEXT PRIMARY KEY,
+                 session TEXT NOT NULL,
+                 updated_at TEXT NOT NULL
+             );
+             CREATE TABLE IF NOT EXISTS review_verdicts (
+                 reviewer TEXT NOT NULL,
+                 file_path TEXT NOT NULL,
+                 frame_index INTEGER NOT NULL,
+                 labels TEXT NOT NULL,
+                 PRIMARY KEY (reviewer, file_path, frame_index)
+             );",
+        )?;
+        Ok(Self { conn })
+    }
+
+    /// Stores `session` over the previous one of the same reviewer.
+    pub fn save(&self, session: &ReviewSession) -> Result<ReviewSession> {
+        if session.reviewer.trim().is_empty() {
+            return Err(anyhow!("A review session needs a reviewer"));
+        }
+        let session = ReviewSession {
+            updated_at: Some(chrono::Local::now().to_rfc3339()),
+            ..session.clone()
+        };
+        self.conn.execute(
+            "INSERT OR REPLACE INTO review_sessions (reviewer, session, updated_at)
+             VALUES (?1, ?2, ?3)",
+            params![
+                session.reviewer,
+                serde_json::to_string(&session)?,
+                session.updated_at
+            ],
+        )?;
+        Ok(session)
+    }
+
+    pub fn load(&self, reviewer: &str) -> Result<Option<ReviewSession>> {
+        let session: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT session FROM review_sessions WHERE reviewer = ?1",
+                [reviewer],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(match session {
+            Some(session) => Some(serde_json::from_str(&session)?),
+            None => None,
+        })
+    }
+
+    /// Every reviewer's session, the most recent first.
+    pub fn sessions(&self) -> Result<Vec<ReviewSession>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT session FROM review_sessions ORDER BY updated_at DESC")?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        let mut sessions = Vec::new();
+        for row in rows {
+            sessions.push(serde_json::from_str(&row?)?);
+        }
+        Ok(sessions)
+    }
+
+    /// Stores `verdicts` over earlier ones of the same reviewer and frame.
+    pub fn record(&mut self, verdicts: &[Verdict]) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT OR REPLACE INTO review_verdicts (reviewer, file_path, frame_index, labels)
+                 VALUES (?1, ?2, ?3, ?4)",
+            )?;
+            for verdict in verdicts {
+                stmt.execute(params![
+                    verdict.reviewer,
+                    verdict.frame.file_path,
+                    verdict.frame.frame_index as i64,
+                    serde_json::to_string(&verdict.labels)?
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn forget(&mut self, reviewer: &str, frames: &[FrameRef]) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        for frame in frames {
+            tx.execute(
+                "DELETE FROM review_verdicts
+                 WHERE reviewer = ?1 AND file_path = ?2 AND frame_index = ?3",
+                params![reviewer, frame.file_path, frame.frame_index as i64],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn verdicts(&self) -> Result<Vec<Verdict>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT reviewer, file_path, frame_index, labels FROM review_verdicts
+             ORDER BY file_path, frame_index, reviewer",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, i64>(2)?,
+                row.get::<_, String>(3)?,
+            ))
+        })?;
+        let mut verdicts = Vec::new();
+        for row in rows {
+            let (reviewer, file_path, frame_index, labels) = row?;
+            verdicts.push(Verdict {
+                reviewer,
+                frame: FrameRef {
+                    file_path,
+                    frame_index: frame_index as usize,
+                },
+                labels: serde_json::from_str(&labels)?,
+            });
+        }
+        Ok(verdicts)
+    }
+}
+
+/// Session store of the run a result file belongs to.
+pub fn session_store(result: &Path) -> Result<SessionStore> {
+    let folder = result
+        .parent()
+        .ok_or_else(|| anyhow!("Invalid result path: {}", result.display()))?;
+    SessionStore::open(&folder.join(EMBEDDING_DB))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::testing;
+
+    #[test]
+    fn test_select() {
+        let folder = Path::new("/run");
+        // a video, a photo and the burst sibling it leads, with an animal and a person each
+        let frames: Vec<ExportFrame> = [
+            ("/run/a.mp4", 0, None),
+            ("/run/a.mp4", 1, None),
+            ("/run/b.jpg", 0, None),
+            ("/run/c.jpg", 0, Some("/run/b.jpg")),
+        ]
+        .into_iter()
+        .map(|(path, frame_index, burst_source)| ExportFrame {
+            frame_index,
+            total_frames: 2,
+            bboxes: Some(vec![testing::bbox(0, 0.5), testing::bbox(1, 0.5)]),
+            burst_source: burst_source.map(PathBuf::from),
+            ..testing::frame(path)
+        })
+        .collect();
+        let indices = |selection: &ReviewSelection, boxes: &[(String, usize, usize)]| {
+            select(&frames, folder, selection, boxes)
+        };
+
+        let page = ReviewSelection::Frames {
+            frames: vec![
+                FrameRef {
+                    file_path: "a.mp4".to_string(),
+                    frame_index: 1,
+                },
+                // gone from the result since the page was shown
+                FrameRef {
+                    file_path: "a.mp4".to_string(),
+                    frame_index: 2,
+                },
+            ],
+        };
+        assert_eq!(indices(&page, &[]), BTreeMap::from([(1, None)]));
+        let sequence = ReviewSelection::Sequence {
+            file_path: "b.jpg".to_string(),
+        };
+        assert_eq!(
+            indices(&sequence, &[]).into_keys().collect::<Vec<_>>(),
+            [2, 3]
+        );
+        // a sibling doesn't lead a sequence of its own
+        let sibling = ReviewSelection::Sequence {
+            file_path: "c.jpg".to_string(),
+        };
+        assert_eq!(indices(&sibling, &[]).into_keys().collect::<Vec<_>>(), [3]);
+
+        let cluster = ReviewSelection::Cluster { cluster_id: 0 };
+        let boxes = [
+            ("/run/a.mp4".to_string(), 0, 1),
+            ("/run/a.mp4".to_string(), 0, 0),
+            ("/run/c.jpg".to_string(), 0, 1),
+            ("/run/b.jpg".to_string(), 1, 0),
+        ];
+        assert_eq!(
+            indices(&cluster, &boxes),
+            BTreeMap::from([(0, Some(vec![1, 0])), (3, Some(vec![1]))])
+        );
+        assert!(indices(&cluster, &[]).is_empty());
+    }
+
+    #[test]
+    fn test_apply() {
+        let frame = ExportFrame {
+            bboxes: Some(vec![testing::bbox(0, 0.5), testing::bbox(1, 0.5)]),
+            label: Some(vec!["Animal".to_string(), "Person".to_string()]),
+            ..testing::frame("/run/a.jpg")
+        };
+
+        let mut relabeled = frame.clone();
+        let leopard = ReviewAction::Relabel {
+            label: "Leopard".to_string(),
+        };
+        apply(&mut relabeled, Some(&[0]), &leopard);
+        assert!(relabeled.verified);
+        assert_eq!(
+            relabeled.label,
+            Some(vec!["Leopard".to_string(), "Person".to_string()])
+        );
+        // a detector class moves the box to that class instead of naming a species
+        let vehicle = ReviewAction::Relabel {
+            label: "Vehicle".to_string(),
+        };
+        apply(&mut relabeled, None, &vehicle);
+        let bboxes = relabeled.bboxes.as_ref().unwrap();
+        assert!(bboxes.iter().all(|b| b.class == 2 && b.label.is_none()));
+        assert_eq!(relabeled.label, Some(vec!["Vehicle".to_string()]));
+
+        let mut accepted = frame.clone();
+        apply(&mut accepted, None, &ReviewAction::Accept);
+        assert!(accepted.verified);
+        assert_eq!(accepted.label, frame.label);
+        assert_eq!(accepted.bboxes.as_ref().unwrap().len(), 2);
+
+        let mut rejected = frame.clone();
+        apply(&mut rejected, Some(&[1]), &ReviewAction::Reject);
+        assert_eq!(rejected.label, Some(vec!["Animal".to_string()]));
+        apply(&mut rejected, None, &ReviewAction::Reject);
+        assert_eq!(rejected.label, Some(vec!["Blank".to_string()]));
+
+        // frames that failed have no boxes to begin with
+        let mut failed = ExportFrame {
+            bboxes: None,
+            error: Some("Failed to decode".to_string()),
+            ..frame
+        };
+        apply(&mut failed, None, &ReviewAction::Accept);
+        assert_eq!(failed.label, Some(vec!["Blank".to_string()]));
+        assert!(failed.bboxes.as_ref().is_some_and(Vec::is_empty));
+    }
+
+    #[test]
+    fn test_review_batch() {
+        let root = testing::temp_dir();
+        let frames: Vec<ExportFrame> = [("a.mp4", None), ("b.jpg", None), ("c.jpg", Some("b.jpg"))]
+            .into_iter()
+            .map(|(name, burst_source)| ExportFrame {
+                bboxes: Some(vec![testing::bbox(0, 0.5), testing::bbox(1, 0.5)]),
+                label: Some(vec!["Animal".to_string(), "Person".to_string()]),
+                burst_source: burst_source.map(|source| root.join(source)),
+                ..testing::frame(root.join(name))
+            })
+            .collect();
+        let result = root.join("result.json");
+        save_export(&result, &frames).unwrap();
+        let sequence = ReviewSelection::Sequence {
+            file_path: "b.jpg".to_string(),
+        };
+
+        let nothing = ReviewSelection::Frames { frames: vec![] };
+        let summary = review_batch(&result, &nothing, &ReviewAction::Reject, Some("ann")).unwrap();
+        assert_eq!((summary.frames, summary.undo_depth), (0, 0));
+        assert!(!undo_path(&result).exists());
+
+        let summary = review_batch(&result, &sequence, &ReviewAction::Reject, Some("ann")).unwrap();
+        assert_eq!((summary.frames, summary.undo_depth), (2, 1));
+        let verdicts = session_store(&result).unwrap().verdicts().unwrap();
+        assert_eq!(verdicts.len(), 2);
+        assert_eq!(verdicts[0].frame.file_path, "b.jpg");
+        assert_eq!(verdicts[0].labels, ["Blank"]);
+        let saved = load_export(&result).unwrap();
+        assert!(saved[1].verified && saved[2].bboxes.as_ref().unwrap().is_empty());
+        assert!(!saved[0].verified);
+
+        // without a reviewer nothing is recorded, and undoing it forgets nothing
+        let a = ReviewSelection::Sequence {
+            file_path: "a.mp4".to_string(),
+        };
+        let summary = review_batch(&result, &a, &ReviewAction::Accept, None).unwrap();
+        assert_eq!((summary.frames, summary.undo_depth), (1, 2));
+        let summary = undo_review(&result).unwrap();
+        assert_eq!((summary.frames, summary.undo_depth), (1, 1));
+        assert_eq!(session_store(&result).unwrap().verdicts().unwrap().len(), 2);
+        assert!(!load_export(&result).unwrap()[0].verified);
+
+        let summary = undo_review(&result).unwrap();
+        assert_eq!((summary.frames, summary.undo_depth), (2, 0));
+        let saved = load_export(&result).unwrap();
+        assert!(!saved[1].verified && saved[2].bboxes.as_ref().unwrap().len() == 2);
+        assert!(session_store(&result)
+            .unwrap()
+            .verdicts()
+            .unwrap()
+            .is_empty());
+        assert!(undo_review(&result).is_err());
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_review_sessions() {
+        let root = testing::temp_dir();
+        let store = session_store(&root.join("result.json")).unwrap();
+        assert_eq!(store.load("ann").unwrap(), None);
+        assert!(store.sessions().unwrap().is_empty());
+        let session = ReviewSession {
+            reviewer: "ann".to_string(),
+            position: Some(FrameRef {
+                file_path: "a.mp4".to_string(),
+                frame_index: 1,
+            }),
+            offset: 100,
+            filters: serde_json::json!({ "label": "Animal" }),
+            part: Some(ReviewPart { index: 0, count: 2 }),
+            reviewed: 120,
+            updated_at: None,
+        };
+        let saved = store.save(&session).unwrap();
+        assert!(saved.updated_at.is_some());
+        store
+            .save(&ReviewSession {
+                reviewer: "bob".to_string(),
+                ..session.clone()
+            })
+            .unwrap();
+        let saved = store.save(&ReviewSession {
+            reviewed: 130,
+            ..session
+        });
+        let resumed = store.load("ann").unwrap().unwrap();
+        assert_eq!(resumed, saved.unwrap());
+        assert_eq!(resumed.filters["label"], "Animal");
+        let sessions = store.sessions().unwrap();
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].reviewer, "ann");
+        assert!(store
+            .save(&ReviewSession {
+                reviewer: " ".to_string(),
+                ..resumed
+            })
+            .is_err());
+        // sessions saved before parts and filters existed still load
+        let old: ReviewSession = serde_json::from_str(r#"{"reviewer": "cy"}"#).unwrap();
+        assert_eq!((old.offset, old.part, old.position), (0, None, None));
+        drop(store);
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,510 @@
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::events::EventSink;
+use crate::export::{self, ExportCompression, ExportFormat, ExportFrame};
+use crate::queue::{Job, JobStatus};
+use crate::utils::{self, portable_path};
+use crate::{Config, PostRunAction};
+
+/// Folder inside the shared one the batches write their results to, removed once merged.
+const SHARE_DIR: &str = ".megascops-share";
+/// How often a peer is asked about the batch it runs.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// How long an idle peer waits for a batch another one may give back.
+const IDLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// An instance taking batches through its remote API.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Peer {
+    /// Base address of the API, such as `http://10.0.0.12:47292`.
+    pub url: String,
+    pub token: String,
+    /// The shared folder as the peer mounts it, the coordinator's path when unset.
+    #[serde(default)]
+    pub root: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ShareOptions {
+    pub peers: Vec<Peer>,
+    /// Files handed out at once.
+    pub batch_size: usize,
+    /// Tries of a batch before its files are given up, and failures in a row after
+    /// which a peer gets no more batches.
+    pub max_attempts: u32,
+}
+
+impl Default for ShareOptions {
+    fn default() -> Self {
+        Self {
+            peers: Vec::new(),
+            batch_size: 500,
+            max_attempts: 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShareProgress {
+    pub files: usize,
+    pub done: usize,
+    pub failed: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShareSummary {
+    /// Merged result file.
+    pub result: PathBuf,
+    pub files: usize,
+    /// Files of batches no peer could process, left out of the result.
+    pub failed: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone)]
+struct Batch {
+    index: usize,
+    files: Vec<PathBuf>,
+    attempts: u32,
+}
+
+/// Sorted `files` cut into batches, so the files of a folder mostly stay together.
+fn make_batches(mut files: Vec<PathBuf>, size: usize) -> Vec<Batch> {
+    files.sort();
+    files
+        .chunks(size.max(1))
+        .enumerate()
+        .map(|(index, files)| Batch {
+            index,
+            files: files.to_vec(),
+            attempts: 0,
+        })
+        .collect()
+}
+
+/// `path` below `folder` as seen by a peer mounting `folder` at `root`.
+fn to_peer(path: &Path, folder: &Path, root: &str) -> String {
+    format!(
+        "{}/{}",
+        root.trim_end_matches(['/', '\\']),
+        portable_path(path, Some(folder))
+    )
+}
+
+/// A path of a peer's result back below `folder`. Paths outside `root`, resolved through
+/// a link for example, are matched to the batch `files` by their end.
+fn from_peer(path: &Path, root: &str, folder: &Path, files: &[PathBuf]) -> Option<PathBuf> {
+    let path = portable_path(path, None);
+    let root = portable_path(Path::new(root.trim_end_matches(['/', '\\'])), None);
+    // mounts of Windows peers differ in case only
+    let relative = path
+        .get(..root.len())
+        .filter(|prefix| prefix.eq_ignore_ascii_case(&root))
+        .and_then(|_| path[root.len()..].strip_prefix('/'));
+    if let Some(relative) = relative {
+        return Some(
+            relative
+                .split('/')
+                .fold(folder.to_path_buf(), |p, c| p.join(c)),
+        );
+    }
+    files
+        .iter()
+        .filter(|file| path.ends_with(&format!("/{}", portable_path(file, Some(folder)))))
+        .max_by_key(|file| file.as_os_str().len())
+        .cloned()
+}
+
+/// The message of an API error answer, the status alone when it has none.
+fn api_error(e: ureq::Error) -> anyhow::Error {
+    match e {
+        ureq::Error::Status(code, response) => {
+            let message = response
+                .into_json::<serde_json::Value>()
+                .ok()
+                .and_then(|body| body["error"].as_str().map(str::to_string));
+            anyhow!("HTTP {}: {}", code, message.unwrap_or_default())
+        }
+        e => e.into(),
+    }
+}
+
+struct PeerClient {
+    agent: ureq::Agent,
+    peer: Peer,
+}
+
+impl PeerClient {
+    fn new(peer: Peer) -> Self {
+        let agent = ureq::AgentBuilder::new()
+            .timeout(Duration::from_secs(60))
+            .build();
+        Self { agent, peer }
+    }
+
+    fn url(&self, route: &str) -> String {
+        format!("{}/api/v1/{}", self.peer.url.trim_end_matches('/'), route)
+    }
+
+    fn auth(&self) -> String {
+        format!("Bearer {}", self.peer.token)
+    }
+
+    fn get<T: DeserializeOwned>(&self, route: &str) -> Result<T> {
+        let response = self
+            .agent
+            .get(&self.url(route))
+            .set("Authorization", &self.auth())
+            .call()
+            .map_err(api_error)?;
+        Ok(response.into_json()?)
+    }
+
+    fn root(&self, folder: &Path) -> String {
+        self.peer
+            .root
+            .clone()
+            .unwrap_or_else(|| portable_path(folder, None))
+    }
+
+    /// Stops the job of an abandoned batch, removing it while queued. Other runs of the peer
+    /// go on.
+    fn abandon(&self, id: &str) {
+        let cancelled = self
+            .agent
+            .post(&self.url(&format!("jobs/{}/cancel", id)))
+            .set("Authorization", &self.auth())
+            .call();
+        if let Err(e) = cancelled {
+            log::warn!("Failed to stop job {} on {}: {}", id, self.peer.url, e);
+        }
+    }
+
+    /// Runs `batch` on the peer and returns its frames once it finished.
+    fn process(&self, batch: &Batch, share: &Share) -> Result<Vec<ExportFrame>> {
+        let root = self.root(&share.folder);
+        let results = share.dir.join(format!("batch-{:05}", batch.index));
+        std::fs::create_dir_all(&results)?;
+        let mut config = share.config.clone();
+        config.config_options.export_folder = Some(to_peer(&results, &share.folder, &root));
+        let paths: Vec<String> = batch
+            .files
+            .iter()
+            .map(|file| to_peer(file, &share.folder, &root))
+            .collect();
+        let job: Job = self
+            .agent
+            .post(&self.url("jobs"))
+            .set("Authorization", &self.auth())
+            .send_json(serde_json::json!({ "paths": paths, "config": config }))
+            .map_err(api_error)?
+            .into_json()?;
+        log::info!(
+            "Batch {} of {} files runs as job {} on {}",
+            batch.index,
+            batch.files.len(),
+            job.id,
+            self.peer.url
+        );
+        loop {
+            thread::sleep(POLL_INTERVAL);
+            if share.stop.is_cancelled() {
+                self.abandon(&job.id);
+                return Err(anyhow!("Sharing was cancelled"));
+            }
+            let job: Job = match self.get(&format!("jobs/{}", job.id)) {
+                Ok(job) => job,
+                Err(e) => {
+                    // the batch goes back to the queue, it must not run here as well
+                    self.abandon(&job.id);
+                    return Err(e);
+                }
+            };
+            match job.status {
+                JobStatus::Done => break,
+                JobStatus::Failed => return Err(anyhow!("Job {} failed", job.id)),
+                JobStatus::Queued | JobStatus::Running => (),
+            }
+        }
+        let mut frames: Vec<ExportFrame> = self.get(&format!("jobs/{}/export", job.id))?;
+        for frame in &mut frames {
+            match from_peer(&frame.file.file_path, &root, &share.folder, &batch.files) {
+                Some(path) => frame.file.file_path = path,
+                None => log::warn!(
+                    "{} of {} is outside the shared folder",
+                    frame.file.file_path.display(),
+                    self.peer.url
+                ),
+            }
+            if let Some(source) = &frame.burst_source {
+                frame.burst_source = from_peer(source, &root, &share.folder, &batch.files);
+            }
+        }
+        Ok(frames)
+    }
+
+    /// Takes batches until none are left, or until it failed too often in a row.
+    fn work(&self, share: &Share) {
+        let mut failures = 0;
+        while let Some(batch) = share.next() {
+            match self.process(&batch, share) {
+                Ok(frames) => {
+                    failures = 0;
+                    share.finish(batch, Some(frames));
+                }
+                Err(e) => {
+                    log::warn!("Batch {} failed on {}: {}", batch.index, self.peer.url, e);
+                    share.finish(batch, None);
+                    failures += 1;
+                    if failures >= share.options.max_attempts && !share.stop.is_cancelled() {
+                        log::error!("Leaving out {} after {} failures", self.peer.url, failures);
+                        share.sink.emit(
+                            "share-peer-lost",
+                            serde_json::json!({ "url": self.peer.url, "error": e.to_string() }),
+                        );
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct ShareState {
+    pending: VecDeque<Batch>,
+    in_flight: usize,
+    frames: Vec<ExportFrame>,
+    done: usize,
+    failed: Vec<PathBuf>,
+}
+
+/// A shared folder being processed, handed out to the peers batch by batch.
+struct Share {
+    folder: PathBuf,
+    /// Folder of the batch results.
+    dir: PathBuf,
+    /// Configuration sent to the peers.
+    config: Config,
+    options: ShareOptions,
+    files: usize,
+    sink: Arc<dyn EventSink>,
+    stop: CancellationToken,
+    state: Mutex<ShareState>,
+}
+
+impl Share {
+    /// The next batch to process, waiting while the last ones run elsewhere and may be
+    /// given back. `None` once all are done or sharing was cancelled.
+    fn next(&self) -> Option<Batch> {
+        while !self.stop.is_cancelled() {
+            {
+                let mut state = self.state.lock().unwrap();
+                if let Some(batch) = state.pending.pop_front() {
+                    state.in_flight += 1;
+                    return Some(batch);
+                }
+                if state.in_flight == 0 {
+                    return None;
+                }
+            }
+            thread::sleep(IDLE_INTERVAL);
+        }
+        None
+    }
+
+    /// Keeps the frames of a processed batch, or gives a failed one back to be tried
+    /// again.
+    fn finish(&self, mut batch: Batch, frames: Option<Vec<ExportFrame>>) {
+        let mut state = self.state.lock().unwrap();
+        state.in_flight -= 1;
+        match frames {
+            Some(frames) => {
+                state.done += batch.files.len();
+                state.frames.extend(frames);
+            }
+            None => {
+                batch.attempts += 1;
+                if batch.attempts < self.options.max_attempts && !self.stop.is_cancelled() {
+                    state.pending.push_back(batch);
+                } else {
+                    log::error!(
+                        "Gave up batch {} after {} tries",
+                        batch.index,
+                        batch.attempts
+                    );
+                    state.failed.extend(batch.files);
+                }
+            }
+        }
+        self.sink.emit(
+            "share-progress",
+            ShareProgress {
+                files: self.files,
+                done: state.done,
+                failed: state.failed.len(),
+            },
+        );
+    }
+}
+
+/// Processes the folder of `config` on the `options` peers, which must all reach it, and
+/// merges what they found into one result of the folder.
+pub fn run(
+    config: Config,
+    options: ShareOptions,
+    sink: Arc<dyn EventSink>,
+    stop: CancellationToken,
+) -> Result<ShareSummary> {
+    if options.peers.is_empty() {
+        return Err(anyhow!("No peers to share the work with"));
+    }
+    let folder = std::fs::canonicalize(&config.detect_options.selected_folder)?;
+    let mut files = Vec::new();
+    utils::walk_files(&folder, &config.config_options.index_options(), |file| {
+        files.push(file.file_path)
+    })?;
+    if files.is_empty() {
+        return Err(anyhow!("No media files in {}", folder.display()));
+    }
+    let batches = make_batches(files, options.batch_size);
+    let total = batches.iter().map(|b| b.files.len()).sum();
+    log::info!(
+        "Sharing {} files of {} in {} batches over {} peers",
+        total,
+        folder.display(),
+        batches.len(),
+        options.peers.len()
+    );
+
+    // the peers write plain results next to the media, the merge applies the real options
+    let mut peer_config = config.clone();
+    peer_config.config_options.export_format = ExportFormat::Json;
+    peer_config.config_options.export_compression = ExportCompression::None;
+    peer_config.config_options.relative_paths = false;
+    peer_config.config_options.anonymize = Default::default();
+    peer_config.config_options.export_fallback = None;
+    peer_config.config_options.buffer_path = None;
+    // nor anything beside the results, it would land in the batch folders removed below or
+    // act on the shared media
+    peer_config.config_options.annotate_images = false;
+    peer_config.config_options.export_crops = None;
+    peer_config.config_options.export_embeddings = false;
+    peer_config.config_options.post_run_action = PostRunAction::None;
+    let share = Share {
+        dir: folder
+            .join(SHARE_DIR)
+            .join(Uuid::new_v4().simple().to_string()),
+        folder: folder.clone(),
+        config: peer_config,
+        options: options.clone(),
+        files: total,
+        sink,
+        stop: stop.clone(),
+        state: Mutex::new(ShareState {
+            pending: batches.into(),
+            ..Default::default()
+        }),
+    };
+    thread::scope(|scope| {
+        for peer in &options.peers {
+            let client = PeerClient::new(peer.clone());
+            let share = &share;
+            scope.spawn(move || client.work(share));
+        }
+    });
+
+    if let Err(e) = std::fs::remove_dir_all(&share.dir) {
+        log::warn!("Failed to remove {}: {}", share.dir.display(), e);
+    }
+    // gone unless another share is running
+    let _ = std::fs::remove_dir(folder.join(SHARE_DIR));
+    if stop.is_cancelled() {
+        return Err(anyhow!("Sharing was cancelled"));
+    }
+    let mut state = share.state.into_inner().unwrap();
+    // batches nobody was left to take
+    for batch in state.pending.drain(..) {
+        state.failed.extend(batch.files);
+    }
+
+    let mut frames = state.frames;
+    frames.sort_by(|a, b| {
+        (&a.file.file_path, a.frame_index).cmp(&(&b.file.file_path, b.frame_index))
+    });
+    // ids of different peers collide, files are numbered again in path order
+    let mut file_id = 0;
+    let mut previous: Option<PathBuf> = None;
+    for frame in &mut frames {
+        if previous
+            .as_ref()
+            .is_some_and(|p| *p != frame.file.file_path)
+        {
+            file_id += 1;
+        }
+        previous = Some(frame.file.file_path.clone());
+        frame.file.file_id = file_id;
+    }
+    let mut config = config;
+    config.detect_options.selected_folder = folder.to_string_lossy().into_owned();
+    let export_options = config.config_options.export_options();
+    export::export(&folder, Arc::new(Mutex::new(frames)), &export_options)?;
+    let result = config.result_folder().join(export::result_file_name(
+        export_options.format,
+        export_options.compression,
+    ));
+    log::info!(
+        "Merged the shared results into {}, {} files failed",
+        result.display(),
+        state.failed.len()
+    );
+    Ok(ShareSummary {
+        result,
+        files: total,
+        failed: state.failed,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_share_paths() {
+        let files: Vec<PathBuf> = ["c.jpg", "a.jpg", "b/d.jpg", "b/e.jpg", "f.mp4"]
+            .iter()
+            .map(|f| Path::new("/data/share").join(f))
+            .collect();
+        let batches = make_batches(files.clone(), 2);
+        assert_eq!(batches.len(), 3);
+        assert_eq!(batches[0].files, [files[1].clone(), files[2].clone()]);
+        assert_eq!(batches[1].files, [files[3].clone(), files[0].clone()]);
+        assert_eq!(batches[2].index, 2);
+
+        let folder = Path::new("/data/share");
+        let path = folder.join("b/d.jpg");
+        assert_eq!(to_peer(&path, folder, r"Z:\"), "Z:/b/d.jpg");
+        assert_eq!(to_peer(&path, folder, "/mnt/share/"), "/mnt/share/b/d.jpg");
+
+        let back = |peer: &str, root: &str| from_peer(Path::new(peer), root, folder, &files);
+        assert_eq!(back("Z:/b/d.jpg", r"Z:\"), Some(path.clone()));
+        assert_eq!(back("z:/B/d.jpg", "Z:"), Some(folder.join("B/d.jpg")));
+        assert_eq!(back("/mnt/share/b/d.jpg", "/mnt/share"), Some(path.clone()));
+        // resolved through a link to the server's export
+        assert_eq!(back("/srv/export/b/d.jpg", "/mnt/share"), Some(path));
+        assert_eq!(back("/mnt/sharex/g.jpg", "/mnt/share"), None);
+    }
+}
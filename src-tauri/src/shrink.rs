@@ -0,0 +1,322 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use ffmpeg_sidecar::command::FfmpegCommand;
+use ffmpeg_sidecar::event::{FfmpegEvent, LogLevel};
+use serde::{Deserialize, Serialize};
+
+use crate::export::{load_export, ExportFrame};
+use crate::utils::is_video;
+
+/// Folder of the shrunk copies, next to the result.
+pub const SHRUNK_DIR: &str = "shrunk";
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShrinkOptions {
+    /// x264 constant rate factor, higher is smaller.
+    #[serde(default = "default_crf")]
+    pub crf: u32,
+    /// Videos taller than this are scaled down.
+    #[serde(default = "default_max_height")]
+    pub max_height: u32,
+    /// Only report what would be shrunk.
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Replace the originals. Otherwise the shrunk videos are written to `shrunk/` next to
+    /// the result, the only copy of the footage stays as it was.
+    #[serde(default)]
+    pub replace: bool,
+}
+
+fn default_crf() -> u32 {
+    35
+}
+
+fn default_max_height() -> u32 {
+    480
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShrinkSummary {
+    /// Confirmed-blank videos found in the results.
+    pub candidates: usize,
+    pub shrunk: usize,
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+    pub failed: Vec<(String, String)>,
+}
+
+/// The detector saw the frame and found nothing, frames the blank filters skipped before
+/// upload were never looked at.
+fn is_blank_frame(frame: &ExportFrame) -> bool {
+    frame.error.as_deref().unwrap_or_default().is_empty()
+        && frame.skipped_blank.is_none()
+        && frame.bboxes.as_ref().is_some_and(|b| b.is_empty())
+}
+
+/// Videos whose every frame was detected without error and has no detection. Anything
+/// partially processed, skipped by the blank filters or with a single detection is left
+/// alone.
+pub fn blank_videos(frames: &[ExportFrame]) -> Vec<PathBuf> {
+    let mut videos: HashMap<&PathBuf, (usize, usize, bool)> = HashMap::new();
+    for frame in frames {
+        if !is_video(&frame.file.file_path) {
+            continue;
+        }
+        let entry = videos
+            .entry(&frame.file.file_path)
+            .or_insert((0, frame.total_frames, true));
+        entry.0 += 1;
+        entry.2 &= is_blank_frame(frame);
+    }
+    let mut blank: Vec<PathBuf> = videos
+        .into_iter()
+        .filter(|(_, (seen, total, blank))| *blank && *total > 0 && seen == total)
+        .map(|(path, _)| path.clone())
+        .collect();
+    blank.sort();
+    blank
+}
+
+fn reencode(video: &Path, target: &Path, options: &ShrinkOptions) -> Result<()> {
+    let scale = format!("scale=-2:'min(ih,{})'", options.max_height);
+    let crf = options.crf.to_string();
+    let mut child = FfmpegCommand::new()
+        .input(video.to_string_lossy())
+        .args([
+            "-map_metadata",
+            "0",
+            "-vf",
+            scale.as_str(),
+            "-c:v",
+            "libx264",
+            "-preset",
+            "slow",
+            "-crf",
+            crf.as_str(),
+            "-c:a",
+            "aac",
+            "-b:a",
+            "32k",
+        ])
+        .overwrite()
+        .output(target.to_string_lossy())
+        .spawn()?;
+    let errors: Vec<String> = child
+        .iter()?
+        .filter_map(|event| match event {
+            FfmpegEvent::Error(e) | FfmpegEvent::Log(LogLevel::Error, e) => Some(e),
+            _ => None,
+        })
+        .collect();
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(anyhow!("ffmpeg failed: {}", errors.join("; ")));
+    }
+    Ok(())
+}
+
+/// Where the shrunk copy of `video` goes unless it replaces it, at its place below
+/// `folder` in `shrunk/`.
+pub fn shrunk_path(folder: &Path, video: &Path) -> PathBuf {
+    let relative = video
+        .strip_prefix(folder)
+        .unwrap_or_else(|_| Path::new(video.file_name().unwrap_or_default()));
+    folder.join(SHRUNK_DIR).join(relative)
+}
+
+/// Re-encodes one video to `target`, which may be the video itself. Nothing is kept when
+/// the result isn't smaller. Returns the size after.
+fn shrink_video(video: &Path, target: &Path, options: &ShrinkOptions) -> Result<u64> {
+    let metadata = std::fs::metadata(video)?;
+    let ext = video.extension().and_then(|e| e.to_str()).unwrap_or("mp4");
+    let tmp = video.with_extension(format!("shrink.{}", ext));
+    if let Err(e) = reencode(video, &tmp, options) {
+        let _ = std::fs::remove_file(&tmp);
+        return Err(e);
+    }
+    let size = std::fs::metadata(&tmp)?.len();
+    if size >= metadata.len() {
+        std::fs::remove_file(&tmp)?;
+        return Ok(metadata.len());
+    }
+    // video shoot times are read from the modification time, carry it over
+    File::options()
+        .write(true)
+        .open(&tmp)?
+        .set_modified(metadata.modified()?)?;
+    if let Some(parent) = target.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::rename(&tmp, target)?;
+    Ok(size)
+}
+
+/// Shrinks the confirmed-blank videos of a result file, positives are never touched.
+pub fn shrink_archive(result: &Path, options: &ShrinkOptions) -> Result<ShrinkSummary> {
+    let folder = result
+        .parent()
+        .ok_or_else(|| anyhow!("Invalid result path: {}", result.display()))?;
+    let frames = load_export(result)?;
+    let mut summary = ShrinkSummary::default();
+    for video in blank_videos(&frames) {
+        // exports with relative paths are relative to the folder holding the result
+        let video = folder.join(video);
+        let target = if options.replace {
+            video.clone()
+        } else {
+            shrunk_path(folder, &video)
+        };
+        if target != video && target.exists() {
+            continue;
+        }
+        let size = match std::fs::metadata(&video) {
+            Ok(metadata) => metadata.len(),
+            Err(e) => {
+                summary
+                    .failed
+                    .push((video.display().to_string(), e.to_string()));
+                continue;
+            }
+        };
+        summary.candidates += 1;
+        summary.bytes_before += size;
+        if options.dry_run {
+            summary.bytes_after += size;
+            continue;
+        }
+        match shrink_video(&video, &target, options) {
+            Ok(after) => {
+                if after < size {
+                    summary.shrunk += 1;
+                }
+                summary.bytes_after += after;
+            }
+            Err(e) => {
+                log::error!("Failed to shrink {}: {}", video.display(), e);
+                summary.bytes_after += size;
+                summary
+                    .failed
+                    .push((video.display().to_string(), e.to_string()));
+            }
+        }
+    }
+    log::info!(
+        "Shrunk {} of {} blank videos, saved {} bytes",
+        summary.shrunk,
+        summary.candidates,
+        summary.bytes_before - summary.bytes_after
+    );
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::{testing, Bbox, BlankSkip};
+
+    #[test]
+    fn test_blank_videos() {
+        // two frames of every video, answered without detections
+        let names = [
+            "blank.mp4",
+            "animal.mp4",
+            "skipped.mp4",
+            "failed.mp4",
+            "unanswered.mp4",
+            "csv.MP4",
+        ];
+        let mut frames: Vec<ExportFrame> = names
+            .iter()
+            .flat_map(|name| {
+                (0..2).map(|frame_index| ExportFrame {
+                    frame_index,
+                    total_frames: 2,
+                    ..testing::frame(*name)
+                })
+            })
+            .collect();
+        frames[3].bboxes = Some(vec![Bbox {
+            x1: 0.0,
+            y1: 0.0,
+            x2: 0.5,
+            y2: 0.5,
+            ..testing::bbox(0, 0.9)
+        }]);
+        // the blank filters skipping a frame doesn't confirm it blank
+        frames[5].skipped_blank = Some(BlankSkip::Prefilter);
+        // nor does a frame that failed or was never answered
+        frames[6].error = Some("Failed to decode".to_string());
+        frames[9].bboxes = None;
+        // an empty error is what csv exports read back for none
+        frames[10].error = Some(String::new());
+        frames.extend([
+            ExportFrame {
+                total_frames: 2,
+                ..testing::frame("partial.mp4")
+            },
+            testing::frame("blank.jpg"),
+        ]);
+        assert_eq!(
+            blank_videos(&frames),
+            [PathBuf::from("blank.mp4"), PathBuf::from("csv.MP4")]
+        );
+    }
+
+    #[test]
+    fn test_shrunk_path() {
+        assert_eq!(
+            shrunk_path(Path::new("/traps"), Path::new("/traps/site1/a.MP4")),
+            Path::new("/traps/shrunk/site1/a.MP4")
+        );
+        // a video outside the folder keeps its name only
+        assert_eq!(
+            shrunk_path(Path::new("/traps"), Path::new("/elsewhere/b.mp4")),
+            Path::new("/traps/shrunk/b.mp4")
+        );
+        let options: ShrinkOptions = serde_json::from_str("{}").unwrap();
+        assert_eq!((options.crf, options.max_height), (35, 480));
+        assert!(!options.replace && !options.dry_run);
+    }
+
+    #[test]
+    fn test_shrink_archive_dry_run() {
+        let dir = testing::temp_dir();
+        let result = dir.join("result.json");
+        let frames: Vec<ExportFrame> = ["site/a.mp4", "site/b.mp4", "site/gone.mp4"]
+            .into_iter()
+            .map(testing::frame)
+            .collect();
+        std::fs::create_dir_all(dir.join("site")).unwrap();
+        std::fs::write(dir.join("site/a.mp4"), [0u8; 64]).unwrap();
+        std::fs::write(dir.join("site/b.mp4"), [0u8; 32]).unwrap();
+        // b was shrunk by an earlier run
+        let shrunk = shrunk_path(&dir, &dir.join("site/b.mp4"));
+        std::fs::create_dir_all(shrunk.parent().unwrap()).unwrap();
+        std::fs::write(&shrunk, [0u8; 8]).unwrap();
+        std::fs::write(&result, serde_json::to_string(&frames).unwrap()).unwrap();
+
+        let options = ShrinkOptions {
+            dry_run: true,
+            ..serde_json::from_str("{}").unwrap()
+        };
+        let summary = shrink_archive(&result, &options).unwrap();
+        assert_eq!(summary.candidates, 1);
+        assert_eq!(summary.shrunk, 0);
+        assert_eq!((summary.bytes_before, summary.bytes_after), (64, 64));
+        assert_eq!(summary.failed.len(), 1);
+        assert!(summary.failed[0].0.ends_with("gone.mp4"));
+        // replacing shrinks b again in place
+        let options = ShrinkOptions {
+            replace: true,
+            ..options
+        };
+        assert_eq!(shrink_archive(&result, &options).unwrap().candidates, 2);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,303 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use crate::annotate::ANNOTATED_DIR;
+use crate::annotation::{ANNOTATION_DIR, PREVIEW_DIR};
+use crate::chips::CROPS_DIR;
+use crate::contact_sheet::CONTACT_SHEET_DIR;
+use crate::overlay::OVERLAY_DIR;
+use crate::review::UNDO_EXTENSION;
+use crate::unacked::UNACKED_FILE;
+use crate::utils::portable_path;
+
+/// What Megascops keeps besides the results themselves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+pub enum StorageKind {
+    /// Overlays, contact sheets, annotated copies, crops and annotation previews, all made
+    /// again from the results.
+    Thumbnails,
+    /// Media copied to the buffer folder, unanswered frames and writes cut off by a crash.
+    Spools,
+    /// Undo histories of reviews.
+    Histories,
+    Logs,
+}
+
+/// Image folders generated next to the results, relative to the result folder.
+fn thumbnail_dirs() -> [PathBuf; 5] {
+    [
+        PathBuf::from(OVERLAY_DIR),
+        PathBuf::from(CONTACT_SHEET_DIR),
+        PathBuf::from(ANNOTATED_DIR),
+        PathBuf::from(CROPS_DIR),
+        Path::new(ANNOTATION_DIR).join(PREVIEW_DIR),
+    ]
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredFile {
+    pub path: PathBuf,
+    pub kind: StorageKind,
+    pub bytes: u64,
+    pub modified: SystemTime,
+}
+
+fn stored_file(path: PathBuf, kind: StorageKind) -> Option<StoredFile> {
+    let metadata = std::fs::metadata(&path).ok()?;
+    Some(StoredFile {
+        path,
+        kind,
+        bytes: metadata.len(),
+        modified: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+    })
+}
+
+/// Every file below `dir`, as `kind`.
+fn files_below(dir: &Path, kind: StorageKind) -> Vec<StoredFile> {
+    if !dir.is_dir() {
+        return Vec::new();
+    }
+    jwalk::WalkDir::new(dir)
+        .skip_hidden(false)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .filter_map(|entry| stored_file(entry.path(), kind))
+        .collect()
+}
+
+/// Files Megascops generated in the result folder `folder`, the results and what was
+/// exported from them for others left out.
+pub fn folder_files(folder: &Path) -> Vec<StoredFile> {
+    let mut files: Vec<StoredFile> = thumbnail_dirs()
+        .iter()
+        .flat_map(|dir| files_below(&folder.join(dir), StorageKind::Thumbnails))
+        .collect();
+    let Ok(entries) = std::fs::read_dir(folder) else {
+        return files;
+    };
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let kind = if name == UNACKED_FILE || name.ends_with(".tmp") {
+            StorageKind::Spools
+        } else if name.ends_with(&format!(".{}", UNDO_EXTENSION)) {
+            StorageKind::Histories
+        } else {
+            continue;
+        };
+        if entry.file_type().is_ok_and(|t| t.is_file()) {
+            files.extend(stored_file(entry.path(), kind));
+        }
+    }
+    files
+}
+
+/// Files of the result folders, the buffer folder media is copied to and the log folder.
+pub fn stored_files(
+    folders: &[PathBuf],
+    buffer: Option<&Path>,
+    logs: Option<&Path>,
+) -> Vec<StoredFile> {
+    let mut files: Vec<StoredFile> = folders.iter().flat_map(|f| folder_files(f)).collect();
+    if let Some(buffer) = buffer {
+        files.extend(files_below(buffer, StorageKind::Spools));
+    }
+    if let Some(logs) = logs {
+        files.extend(files_below(logs, StorageKind::Logs));
+    }
+    files
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageEntry {
+    /// Result folder, buffer or log folder the files are in.
+    pub location: String,
+    pub kind: StorageKind,
+    pub files: usize,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StorageUsage {
+    /// By location and kind, the largest first.
+    pub entries: Vec<UsageEntry>,
+    pub total_bytes: u64,
+}
+
+/// Sums `files` up by kind under the location of `locations` holding them.
+pub fn storage_usage(files: &[StoredFile], locations: &[PathBuf]) -> StorageUsage {
+    let mut sums: BTreeMap<(String, StorageKind), (usize, u64)> = BTreeMap::new();
+    for file in files {
+        let location = locations
+            .iter()
+            .filter(|l| file.path.starts_with(l))
+            .max_by_key(|l| l.components().count())
+            .map_or_else(|| PathBuf::from(""), PathBuf::clone);
+        let sum = sums
+            .entry((portable_path(&location, None), file.kind))
+            .or_default();
+        sum.0 += 1;
+        sum.1 += file.bytes;
+    }
+    let mut entries: Vec<UsageEntry> = sums
+        .into_iter()
+        .map(|((location, kind), (files, bytes))| UsageEntry {
+            location,
+            kind,
+            files,
+            bytes,
+        })
+        .collect();
+    entries.sort_by_key(|e| std::cmp::Reverse(e.bytes));
+    StorageUsage {
+        total_bytes: entries.iter().map(|e| e.bytes).sum(),
+        entries,
+    }
+}
+
+/// What is kept of each kind. Files past either limit are removed, the oldest first.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RetentionPolicy {
+    /// Files older than this many days are removed, 0 keeps them whatever their age.
+    pub max_age_days: u64,
+    /// Bytes kept of each kind, 0 for no limit.
+    pub max_bytes: u64,
+    /// Kinds the policy applies to, all of them when empty.
+    pub kinds: Vec<StorageKind>,
+}
+
+/// Files of `files` the policy removes at `now`.
+pub fn plan_prune(
+    mut files: Vec<StoredFile>,
+    policy: &RetentionPolicy,
+    now: SystemTime,
+) -> Vec<StoredFile> {
+    files.retain(|f| policy.kinds.is_empty() || policy.kinds.contains(&f.kind));
+    // newest first, what comes after the size limit is dropped
+    files.sort_by_key(|f| std::cmp::Reverse(f.modified));
+    let max_age = Duration::from_secs(policy.max_age_days * 24 * 60 * 60);
+    let mut kept: BTreeMap<StorageKind, u64> = BTreeMap::new();
+    files
+        .into_iter()
+        .filter(|file| {
+            let age = now.duration_since(file.modified).unwrap_or_default();
+            let kept = kept.entry(file.kind).or_default();
+            let expired = policy.max_age_days > 0 && age > max_age;
+            let over = policy.max_bytes > 0 && *kept + file.bytes > policy.max_bytes;
+            if !expired && !over {
+                *kept += file.bytes;
+            }
+            expired || over
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PruneSummary {
+    pub removed: usize,
+    pub bytes: u64,
+    /// Files that could not be removed, such as a log still open.
+    pub failed: usize,
+    pub dry_run: bool,
+}
+
+/// Removes `files`, or only counts them with `dry_run`. Image folders left empty go as
+/// well.
+pub fn prune(files: &[StoredFile], dry_run: bool) -> PruneSummary {
+    let mut summary = PruneSummary {
+        dry_run,
+        ..Default::default()
+    };
+    for file in files {
+        if !dry_run {
+            if let Err(e) = std::fs::remove_file(&file.path) {
+                log::warn!("Failed to remove {}: {}", file.path.display(), e);
+                summary.failed += 1;
+                continue;
+            }
+            // remove_dir leaves folders with anything left in them alone
+            if let Some(parent) = file.path.parent() {
+                if file.kind == StorageKind::Thumbnails {
+                    let _ = std::fs::remove_dir(parent);
+                }
+            }
+        }
+        summary.removed += 1;
+        summary.bytes += file.bytes;
+    }
+    log::info!(
+        "{} {} files, {} bytes",
+        if dry_run { "Would remove" } else { "Removed" },
+        summary.removed,
+        summary.bytes
+    );
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::testing;
+
+    #[test]
+    fn test_retention() {
+        let root = testing::temp_dir();
+        let overlays = root.join(OVERLAY_DIR).join("site1");
+        std::fs::create_dir_all(&overlays).unwrap();
+        std::fs::write(overlays.join("a.jpg"), [0u8; 10]).unwrap();
+        std::fs::write(root.join(UNACKED_FILE), [0u8; 5]).unwrap();
+        std::fs::write(
+            root.join(format!("result.json.{}", UNDO_EXTENSION)),
+            [0u8; 3],
+        )
+        .unwrap();
+        std::fs::write(root.join("result.json"), [0u8; 7]).unwrap();
+        std::fs::write(root.join("IMG_0001.JPG"), [0u8; 7]).unwrap();
+
+        let files = stored_files(std::slice::from_ref(&root), None, None);
+        let usage = storage_usage(&files, std::slice::from_ref(&root));
+        assert_eq!(usage.total_bytes, 18);
+        assert_eq!(usage.entries[0].kind, StorageKind::Thumbnails);
+        assert_eq!(usage.entries[0].location, portable_path(&root, None));
+
+        let now = SystemTime::now();
+        let file = |name: &str, kind, bytes, days: u64| StoredFile {
+            path: PathBuf::from(name),
+            kind,
+            bytes,
+            modified: now - Duration::from_secs(days * 24 * 60 * 60),
+        };
+        let old = vec![
+            file("old.jpg", StorageKind::Thumbnails, 10, 40),
+            file("new.jpg", StorageKind::Thumbnails, 10, 1),
+            file("older.jpg", StorageKind::Thumbnails, 10, 20),
+            file("megascops.log", StorageKind::Logs, 100, 60),
+        ];
+        let policy = RetentionPolicy {
+            max_age_days: 30,
+            max_bytes: 15,
+            kinds: vec![StorageKind::Thumbnails],
+        };
+        let pruned: Vec<PathBuf> = plan_prune(old, &policy, now)
+            .into_iter()
+            .map(|f| f.path)
+            .collect();
+        assert_eq!(
+            pruned,
+            [PathBuf::from("older.jpg"), PathBuf::from("old.jpg")]
+        );
+
+        let summary = prune(&files, false);
+        assert_eq!((summary.removed, summary.bytes, summary.failed), (3, 18, 0));
+        assert!(!root.join(OVERLAY_DIR).join("site1").exists());
+        assert!(root.join("result.json").exists());
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
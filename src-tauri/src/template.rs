@@ -0,0 +1,238 @@
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::export::ExportFrame;
+
+/// Tokens understood by output path templates.
+pub const TOKENS: [&str; 5] = ["site", "date", "label", "dir", "filename"];
+
+/// Output layouts of the generated artifacts, relative to the selected folder.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct OutputTemplates {
+    pub organize: String,
+    /// Where the crops of the detections go, `{filename}` naming the frame and box.
+    pub crops: String,
+    /// Where the copies of the positives with their boxes drawn go.
+    pub previews: String,
+    /// Where the highlight clips of videos go.
+    pub proxies: String,
+}
+
+impl Default for OutputTemplates {
+    fn default() -> Self {
+        Self {
+            organize: "{dir}/{label}/{filename}".to_string(),
+            crops: "crops/{label}/{filename}".to_string(),
+            previews: "annotated/{dir}/{filename}".to_string(),
+            proxies: "overlays/{dir}/{filename}".to_string(),
+        }
+    }
+}
+
+impl OutputTemplates {
+    pub fn validate(&self) -> Result<()> {
+        for template in [&self.organize, &self.crops, &self.previews, &self.proxies] {
+            validate(template)?;
+        }
+        Ok(())
+    }
+}
+
+/// Values substituted into a template for one file.
+#[derive(Debug, Clone, Default)]
+pub struct TemplateContext {
+    /// First folder below the selected folder, usually the camera site.
+    pub site: String,
+    /// Shoot date as `YYYY-MM-DD`.
+    pub date: String,
+    pub label: String,
+    /// Folder of the file relative to the selected folder.
+    pub dir: String,
+    pub filename: String,
+}
+
+impl TemplateContext {
+    pub fn new(file_path: &Path, root: &Path, shoot_time: Option<&str>, label: &str) -> Self {
+        let relative = file_path.strip_prefix(root).unwrap_or(file_path);
+        let dir = relative.parent().unwrap_or(Path::new(""));
+        let site = match dir.components().next() {
+            Some(Component::Normal(site)) => site.to_string_lossy().into_owned(),
+            _ => "unknown".to_string(),
+        };
+        // shoot_time is exported as `YYYY-MM-DD hh:mm:ss +zz:zz`, the date is its first 10 chars
+        let date = shoot_time
+            .and_then(|t| t.get(..10))
+            .unwrap_or("unknown")
+            .to_string();
+        Self {
+            site,
+            date,
+            label: label.to_string(),
+            dir: dir.to_string_lossy().into_owned(),
+            filename: file_path
+                .file_name()
+                .map(|f| f.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn from_frame(frame: &ExportFrame, root: &Path, label: &str) -> Self {
+        Self::new(
+            &frame.file.file_path,
+            root,
+            frame.shoot_time.as_deref(),
+            label,
+        )
+    }
+
+    fn get(&self, token: &str) -> Option<&str> {
+        match token {
+            "site" => Some(&self.site),
+            "date" => Some(&self.date),
+            "label" => Some(&self.label),
+            "dir" => Some(&self.dir),
+            "filename" => Some(&self.filename),
+            _ => None,
+        }
+    }
+}
+
+pub fn validate(template: &str) -> Result<()> {
+    render(template, &TemplateContext::default()).map(|_| ())
+}
+
+/// The leading segments of `template` without a token, the folder everything it renders
+/// to is in.
+pub fn base_dir(template: &str) -> PathBuf {
+    template
+        .split(['/', '\\'])
+        .filter(|s| !s.is_empty())
+        .take_while(|s| !s.contains('{'))
+        .collect()
+}
+
+/// Keeps a substituted value from escaping its path segment.
+fn sanitize(value: &str) -> String {
+    let value: String = value
+        .chars()
+        .map(|c| match c {
+            ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c => c,
+        })
+        .collect();
+    if value.is_empty() || value == "." || value == ".." {
+        "_".to_string()
+    } else {
+        value
+    }
+}
+
+/// Renders `template` to a relative path. `{dir}` may expand to several segments, every
+/// other token is confined to a single one.
+pub fn render(template: &str, context: &TemplateContext) -> Result<PathBuf> {
+    let mut path = PathBuf::new();
+    for segment in template.split(['/', '\\']).filter(|s| !s.is_empty()) {
+        if segment == "{dir}" {
+            // a file outside the selected folder has an absolute one
+            for component in Path::new(&context.dir).components() {
+                match component {
+                    Component::Normal(part) => {
+                        path.push(sanitize(&part.to_string_lossy().replace('\\', "_")))
+                    }
+                    Component::CurDir => (),
+                    _ => {
+                        return Err(anyhow!(
+                            "Folder {} is outside the output folder",
+                            context.dir
+                        ))
+                    }
+                }
+            }
+            continue;
+        }
+        let mut rendered = String::new();
+        let mut rest = segment;
+        while let Some(start) = rest.find('{') {
+            rendered.push_str(&rest[..start]);
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| anyhow!("Unclosed token in template: {}", template))?;
+            let token = &rest[start + 1..start + end];
+            let value = context.get(token).ok_or_else(|| {
+                anyhow!(
+                    "Unknown token {{{}}} in template {}, expected one of {:?}",
+                    token,
+                    template,
+                    TOKENS
+                )
+            })?;
+            rendered.push_str(&sanitize(&value.replace(['/', '\\'], "_")));
+            rest = &rest[start + end + 1..];
+        }
+        rendered.push_str(rest);
+        if rendered == ".." {
+            return Err(anyhow!(
+                "Template must not leave the output folder: {}",
+                template
+            ));
+        }
+        path.push(rendered);
+    }
+    if path.as_os_str().is_empty() {
+        return Err(anyhow!("Empty output template"));
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_template() {
+        let context = TemplateContext::new(
+            Path::new("/traps/site1/cam2/IMG_0001.JPG"),
+            Path::new("/traps"),
+            Some("2024-05-01T06:30:00+08:00"),
+            "Animal",
+        );
+        let path = render("{site}/{date}/{label}/{filename}", &context).unwrap();
+        assert_eq!(path, Path::new("site1/2024-05-01/Animal/IMG_0001.JPG"));
+        let path = render(&OutputTemplates::default().organize, &context).unwrap();
+        assert_eq!(path, Path::new("site1/cam2/Animal/IMG_0001.JPG"));
+        assert!(render("{species}/{filename}", &context).is_err());
+        assert!(render("../{filename}", &context).is_err());
+
+        // files outside the selected folder can't be placed below it
+        let outside = TemplateContext::new(
+            Path::new("/elsewhere/IMG_0002.JPG"),
+            Path::new("/traps"),
+            None,
+            "Animal",
+        );
+        assert!(render(&OutputTemplates::default().organize, &outside).is_err());
+        assert!(render("{label}/{filename}", &outside).is_ok());
+        let escaping = TemplateContext {
+            dir: "site1/../../etc".to_string(),
+            ..context
+        };
+        assert!(render("{dir}/{filename}", &escaping).is_err());
+
+        assert_eq!(base_dir("crops/{label}/{filename}"), Path::new("crops"));
+        assert_eq!(base_dir("out/crops/{label}"), Path::new("out/crops"));
+        assert_eq!(base_dir("{dir}/{filename}"), Path::new(""));
+        // generated folders are skipped when indexing by their default names
+        let defaults = OutputTemplates::default();
+        assert_eq!(
+            base_dir(&defaults.previews),
+            Path::new(crate::annotate::ANNOTATED_DIR)
+        );
+        assert_eq!(
+            base_dir(&defaults.proxies),
+            Path::new(crate::overlay::OVERLAY_DIR)
+        );
+    }
+}
@@ -0,0 +1,361 @@
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+use crossbeam_channel::{Receiver, RecvTimeoutError};
+use serde::{Deserialize, Serialize};
+use sysinfo::Components;
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+
+use crate::events::EventSink;
+
+/// How often power and temperature are checked.
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+/// A hot machine resumes once it cooled down this much below the limit.
+const TEMPERATURE_HYSTERESIS: f32 = 5.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ThrottleAction {
+    None,
+    /// Run with `reduced_workers` media workers.
+    Reduce,
+    /// Stop decoding until the condition is over.
+    Pause,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ThrottleOptions {
+    pub on_battery: ThrottleAction,
+    /// CPU temperature in °C above which `on_hot` applies, never when unset.
+    pub max_temperature: Option<f32>,
+    pub on_hot: ThrottleAction,
+    pub reduced_workers: usize,
+}
+
+impl Default for ThrottleOptions {
+    fn default() -> Self {
+        Self {
+            on_battery: ThrottleAction::Reduce,
+            max_temperature: Some(90.0),
+            on_hot: ThrottleAction::Pause,
+            reduced_workers: 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PowerState {
+    /// `None` when it can't be told, e.g. on desktops without a battery API.
+    pub on_battery: Option<bool>,
+    /// Hottest CPU sensor in °C.
+    pub temperature: Option<f32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ThrottleReason {
+    Battery,
+    Temperature,
+}
+
+/// Emitted as `throttle-changed` whenever the allowed workers change.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ThrottleState {
+    /// Media workers allowed to run, 0 while paused.
+    pub workers: usize,
+    pub reason: Option<ThrottleReason>,
+    pub temperature: Option<f32>,
+}
+
+impl ThrottleOptions {
+    /// Workers allowed for `power`, out of `full`. `was_hot` keeps a hot machine throttled
+    /// until it cooled down by the hysteresis.
+    pub fn decide(&self, power: &PowerState, was_hot: bool, full: usize) -> ThrottleState {
+        let apply = |action: ThrottleAction| match action {
+            ThrottleAction::None => full,
+            ThrottleAction::Reduce => self.reduced_workers.clamp(1, full.max(1)),
+            ThrottleAction::Pause => 0,
+        };
+        let hot = match (self.max_temperature, power.temperature) {
+            (Some(max), Some(t)) if was_hot => t > max - TEMPERATURE_HYSTERESIS,
+            (Some(max), Some(t)) => t > max,
+            _ => false,
+        };
+        let mut state = ThrottleState {
+            workers: full,
+            reason: None,
+            temperature: power.temperature,
+        };
+        // the stricter of both conditions wins
+        if hot && apply(self.on_hot) < state.workers {
+            state.workers = apply(self.on_hot);
+            state.reason = Some(ThrottleReason::Temperature);
+        }
+        if power.on_battery == Some(true) && apply(self.on_battery) < state.workers {
+            state.workers = apply(self.on_battery);
+            state.reason = Some(ThrottleReason::Battery);
+        }
+        state
+    }
+}
+
+/// Limits how many media workers decode at the same time.
+pub struct Gate {
+    /// `(allowed, active)` workers.
+    state: Mutex<(usize, usize)>,
+    changed: Condvar,
+    /// Set while the user paused the run, independent of the throttling.
+    paused: watch::Sender<bool>,
+}
+
+pub struct Permit<'a> {
+    gate: &'a Gate,
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.gate.state.lock().unwrap().1 -= 1;
+        self.gate.changed.notify_one();
+    }
+}
+
+impl Default for Gate {
+    fn default() -> Self {
+        Self {
+            state: Mutex::new((usize::MAX, 0)),
+            changed: Condvar::new(),
+            paused: watch::channel(false).0,
+        }
+    }
+}
+
+impl Gate {
+    pub fn set_allowed(&self, allowed: usize) {
+        self.state.lock().unwrap().0 = allowed;
+        self.changed.notify_all();
+    }
+
+    /// Holds back every worker until resumed. Returns whether that changed anything.
+    pub fn set_paused(&self, paused: bool) -> bool {
+        let was_paused = self.paused.send_replace(paused);
+        // taking the lock makes sure a waiting worker sees the change
+        let _state = self.state.lock().unwrap();
+        self.changed.notify_all();
+        was_paused != paused
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.paused.borrow()
+    }
+
+    /// Returns once the run isn't paused.
+    pub async fn resumed(&self) {
+        let mut paused = self.paused.subscribe();
+        let _ = paused.wait_for(|paused| !paused).await;
+    }
+
+    /// Waits for a free slot, `None` once `stop` is cancelled.
+    pub fn acquire(&self, stop: &CancellationToken) -> Option<Permit<'_>> {
+        let mut state = self.state.lock().unwrap();
+        while state.1 >= state.0 || self.is_paused() {
+            if stop.is_cancelled() {
+                return None;
+            }
+            // a paused run has nothing to wake it but the monitor, check for a stop now and then
+            state = self
+                .changed
+                .wait_timeout(state, Duration::from_millis(500))
+                .unwrap()
+                .0;
+        }
+        state.1 += 1;
+        Some(Permit { gate: self })
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn on_battery() -> Option<bool> {
+    #[repr(C)]
+    #[allow(non_snake_case)]
+    struct SYSTEM_POWER_STATUS {
+        ACLineStatus: u8,
+        BatteryFlag: u8,
+        BatteryLifePercent: u8,
+        SystemStatusFlag: u8,
+        BatteryLifeTime: u32,
+        BatteryFullLifeTime: u32,
+    }
+    extern "system" {
+        fn GetSystemPowerStatus(status: *mut SYSTEM_POWER_STATUS) -> i32;
+    }
+    let mut status = std::mem::MaybeUninit::<SYSTEM_POWER_STATUS>::uninit();
+    // SAFETY: the struct matches the Win32 layout and is only read after a successful call
+    let status = unsafe {
+        if GetSystemPowerStatus(status.as_mut_ptr()) == 0 {
+            return None;
+        }
+        status.assume_init()
+    };
+    match status.ACLineStatus {
+        0 => Some(true),
+        1 => Some(false),
+        _ => None,
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn on_battery() -> Option<bool> {
+    let output = std::process::Command::new("pmset")
+        .args(["-g", "ps"])
+        .output()
+        .ok()?;
+    let output = String::from_utf8_lossy(&output.stdout);
+    Some(output.contains("'Battery Power'"))
+}
+
+#[cfg(target_os = "linux")]
+fn on_battery() -> Option<bool> {
+    // any online mains supply means plugged in, batteries alone mean running on them
+    let mut has_battery = false;
+    for entry in std::fs::read_dir("/sys/class/power_supply").ok()?.flatten() {
+        let path = entry.path();
+        let kind = std::fs::read_to_string(path.join("type")).unwrap_or_default();
+        match kind.trim() {
+            "Mains" => {
+                if std::fs::read_to_string(path.join("online")).is_ok_and(|o| o.trim() == "1") {
+                    return Some(false);
+                }
+            }
+            "Battery" => has_battery = true,
+            _ => (),
+        }
+    }
+    has_battery.then_some(true)
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "macos", target_os = "linux")))]
+fn on_battery() -> Option<bool> {
+    None
+}
+
+fn temperature() -> Option<f32> {
+    Components::new_with_refreshed_list()
+        .iter()
+        .filter_map(|c| c.temperature())
+        .filter(|t| t.is_finite() && *t > 0.0)
+        .reduce(f32::max)
+}
+
+pub fn power_state() -> PowerState {
+    PowerState {
+        on_battery: on_battery(),
+        temperature: temperature(),
+    }
+}
+
+/// Adjusts `gate` to the power state every `interval` until `stop` fires, emitting
+/// `throttle-changed` when the allowed workers change.
+pub fn monitor(
+    options: &ThrottleOptions,
+    gate: &Gate,
+    sink: &dyn EventSink,
+    stop: Receiver<()>,
+    interval: Duration,
+) {
+    let full = rayon::current_num_threads();
+    let mut current = ThrottleState {
+        workers: full,
+        reason: None,
+        temperature: None,
+    };
+    loop {
+        let state = options.decide(
+            &power_state(),
+            current.reason == Some(ThrottleReason::Temperature),
+            full,
+        );
+        if state.workers != current.workers {
+            match state.reason {
+                Some(reason) => log::warn!(
+                    "Throttling to {} workers ({:?}, {:?} °C)",
+                    state.workers,
+                    reason,
+                    state.temperature
+                ),
+                None => log::info!("Throttling lifted"),
+            }
+            gate.set_allowed(state.workers);
+            sink.emit("throttle-changed", &state);
+        }
+        current = state;
+        match stop.recv_timeout(interval) {
+            Err(RecvTimeoutError::Timeout) => (),
+            _ => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_throttle_decision() {
+        let options = ThrottleOptions::default();
+        let plugged = PowerState {
+            on_battery: Some(false),
+            temperature: Some(60.0),
+        };
+        assert_eq!(options.decide(&plugged, false, 8).workers, 8);
+
+        let battery = PowerState {
+            on_battery: Some(true),
+            ..plugged
+        };
+        let state = options.decide(&battery, false, 8);
+        assert_eq!(state.workers, 1);
+        assert_eq!(state.reason, Some(ThrottleReason::Battery));
+
+        let hot = PowerState {
+            temperature: Some(92.0),
+            ..battery
+        };
+        let state = options.decide(&hot, false, 8);
+        assert_eq!(state.workers, 0);
+        assert_eq!(state.reason, Some(ThrottleReason::Temperature));
+
+        // still paused while cooling down, resumed below the hysteresis
+        let cooling = PowerState {
+            on_battery: Some(false),
+            temperature: Some(87.0),
+        };
+        assert_eq!(options.decide(&cooling, true, 8).workers, 0);
+        assert_eq!(options.decide(&cooling, false, 8).workers, 8);
+    }
+
+    #[test]
+    fn test_gate() {
+        let gate = Gate::default();
+        let stop = CancellationToken::new();
+        gate.set_allowed(1);
+        let permit = gate.acquire(&stop).unwrap();
+        std::thread::scope(|scope| {
+            let waiting = scope.spawn(|| gate.acquire(&stop).is_some());
+            drop(permit);
+            assert!(waiting.join().unwrap());
+        });
+        assert!(gate.set_paused(true));
+        assert!(!gate.set_paused(true));
+        std::thread::scope(|scope| {
+            let waiting = scope.spawn(|| gate.acquire(&stop).is_some());
+            assert!(gate.set_paused(false));
+            assert!(waiting.join().unwrap());
+        });
+        gate.set_allowed(0);
+        stop.cancel();
+        assert!(gate.acquire(&stop).is_none());
+    }
+}
@@ -0,0 +1,277 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use chrono::{NaiveDateTime, TimeDelta};
+use serde::{Deserialize, Serialize};
+
+use crate::utils::{self, portable_path, IndexOptions};
+
+const EXIF_IFD_POINTER: u16 = 0x8769;
+const DATE_TIME_ORIGINAL: u16 = 0x9003;
+const EXIF_TIME_FORMAT: &str = "%Y:%m:%d %H:%M:%S";
+
+/// Corrections applied to the copied archive.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TimestampRepair {
+    /// Added to every DateTimeOriginal, e.g. to fix a camera clock set wrong.
+    #[serde(default)]
+    pub offset_seconds: i64,
+    /// Exact times by file path, relative to the folder or absolute, e.g. recovered
+    /// from the imprinted info bar. Format `YYYY-MM-DD HH:MM:SS`.
+    #[serde(default)]
+    pub corrections: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RepairSummary {
+    pub copied: usize,
+    pub repaired: usize,
+    /// Files copied unchanged, with the reason.
+    pub skipped: Vec<(String, String)>,
+}
+
+/// Location of the DateTimeOriginal value inside a JPEG.
+fn find_date_time_original(jpeg: &[u8]) -> Result<usize> {
+    if jpeg.get(..2) != Some(&[0xFF, 0xD8]) {
+        return Err(anyhow!("Not a JPEG file"));
+    }
+    let mut pos = 2;
+    while pos + 4 <= jpeg.len() {
+        if jpeg[pos] != 0xFF {
+            return Err(anyhow!("Invalid JPEG segment"));
+        }
+        let marker = jpeg[pos + 1];
+        let len = u16::from_be_bytes([jpeg[pos + 2], jpeg[pos + 3]]) as usize;
+        // start of scan, no metadata after it
+        if marker == 0xDA {
+            break;
+        }
+        let segment = jpeg
+            .get(pos + 4..pos + 2 + len)
+            .ok_or_else(|| anyhow!("Truncated JPEG segment"))?;
+        if marker == 0xE1 && segment.starts_with(b"Exif\0\0") {
+            let tiff = pos + 10;
+            let offset = find_in_tiff(&segment[6..])?;
+            return Ok(tiff + offset);
+        }
+        pos += 2 + len;
+    }
+    Err(anyhow!("No EXIF data"))
+}
+
+fn find_in_tiff(tiff: &[u8]) -> Result<usize> {
+    let little_endian = match tiff.get(..2) {
+        Some(b"II") => true,
+        Some(b"MM") => false,
+        _ => return Err(anyhow!("Invalid TIFF header")),
+    };
+    let u16_at = |at: usize| -> Result<u16> {
+        let b = tiff
+            .get(at..at + 2)
+            .ok_or_else(|| anyhow!("Truncated EXIF"))?;
+        Ok(if little_endian {
+            u16::from_le_bytes([b[0], b[1]])
+        } else {
+            u16::from_be_bytes([b[0], b[1]])
+        })
+    };
+    let u32_at = |at: usize| -> Result<u32> {
+        let b = tiff
+            .get(at..at + 4)
+            .ok_or_else(|| anyhow!("Truncated EXIF"))?;
+        Ok(if little_endian {
+            u32::from_le_bytes([b[0], b[1], b[2], b[3]])
+        } else {
+            u32::from_be_bytes([b[0], b[1], b[2], b[3]])
+        })
+    };
+    // returns the position of the entry with `tag` in the IFD at `ifd`
+    let find_entry = |ifd: usize, tag: u16| -> Result<Option<usize>> {
+        let count = u16_at(ifd)? as usize;
+        for i in 0..count {
+            let entry = ifd + 2 + i * 12;
+            if u16_at(entry)? == tag {
+                return Ok(Some(entry));
+            }
+        }
+        Ok(None)
+    };
+
+    let ifd0 = u32_at(4)? as usize;
+    let exif_entry =
+        find_entry(ifd0, EXIF_IFD_POINTER)?.ok_or_else(|| anyhow!("No EXIF sub-IFD"))?;
+    let exif_ifd = u32_at(exif_entry + 8)? as usize;
+    let entry =
+        find_entry(exif_ifd, DATE_TIME_ORIGINAL)?.ok_or_else(|| anyhow!("No DateTimeOriginal"))?;
+    // ASCII of 20 bytes, stored behind the offset
+    if u16_at(entry + 2)? != 2 || u32_at(entry + 4)? != 20 {
+        return Err(anyhow!("Unexpected DateTimeOriginal format"));
+    }
+    let value = u32_at(entry + 8)? as usize;
+    if value + 20 > tiff.len() {
+        return Err(anyhow!("Truncated EXIF"));
+    }
+    Ok(value)
+}
+
+pub fn read_date_time_original(jpeg: &[u8]) -> Result<NaiveDateTime> {
+    let at = find_date_time_original(jpeg)?;
+    let value = std::str::from_utf8(&jpeg[at..at + 19])?;
+    Ok(NaiveDateTime::parse_from_str(value, EXIF_TIME_FORMAT)?)
+}
+
+/// Overwrites DateTimeOriginal in place, the value has a fixed length so nothing else
+/// in the file moves.
+pub fn write_date_time_original(jpeg: &mut [u8], time: &NaiveDateTime) -> Result<()> {
+    let at = find_date_time_original(jpeg)?;
+    let value = time.format(EXIF_TIME_FORMAT).to_string();
+    jpeg[at..at + 19].copy_from_slice(value.as_bytes());
+    Ok(())
+}
+
+fn corrected_time(
+    repair: &TimestampRepair,
+    relative: &str,
+    absolute: &str,
+    original: Result<NaiveDateTime>,
+) -> Result<NaiveDateTime> {
+    match repair
+        .corrections
+        .get(relative)
+        .or_else(|| repair.corrections.get(absolute))
+    {
+        Some(time) => Ok(NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M:%S")?),
+        None => Ok(original? + TimeDelta::seconds(repair.offset_seconds)),
+    }
+}
+
+/// Copies every indexed file of `folder` to `output`, keeping the folder structure, and
+/// writes the corrected DateTimeOriginal into the copied JPEGs. The originals are never
+/// modified.
+pub fn repair_timestamps(
+    folder: &PathBuf,
+    output: &Path,
+    options: &IndexOptions,
+    repair: &TimestampRepair,
+) -> Result<RepairSummary> {
+    let folder = std::fs::canonicalize(folder)?;
+    std::fs::create_dir_all(output)?;
+    let output = std::fs::canonicalize(output)?;
+    if output.starts_with(&folder) {
+        return Err(anyhow!(
+            "The corrected archive must be outside the source folder"
+        ));
+    }
+
+    let index = utils::index_files_and_folders(&folder, options, |_| {})?;
+    let mut summary = RepairSummary::default();
+    for file in index.files {
+        let relative = file.file_path.strip_prefix(&folder)?;
+        let target = output.join(relative);
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let relative = portable_path(relative, None);
+        let is_jpeg = matches!(
+            file.file_path
+                .extension()
+                .and_then(|e| e.to_str())
+                .map(|e| e.to_lowercase())
+                .as_deref(),
+            Some("jpg" | "jpeg")
+        );
+        summary.copied += 1;
+        if !is_jpeg {
+            std::fs::copy(&file.file_path, &target)?;
+            continue;
+        }
+        // the value is only written once it was found, a failed repair copies as is
+        let mut jpeg = std::fs::read(&file.file_path)?;
+        let absolute = portable_path(&file.file_path, None);
+        let result = corrected_time(repair, &relative, &absolute, read_date_time_original(&jpeg))
+            .and_then(|time| write_date_time_original(&mut jpeg, &time));
+        match result {
+            Ok(_) => summary.repaired += 1,
+            Err(e) => {
+                log::warn!("Failed to repair timestamp of {}: {}", absolute, e);
+                summary.skipped.push((relative, e.to_string()));
+            }
+        }
+        std::fs::write(&target, jpeg)?;
+    }
+    log::info!(
+        "Repaired {} of {} files into {}",
+        summary.repaired,
+        summary.copied,
+        output.display()
+    );
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Minimal big-endian JPEG with IFD0 -> Exif IFD -> DateTimeOriginal.
+    fn jpeg_with_time(time: &str) -> Vec<u8> {
+        let mut tiff = Vec::new();
+        tiff.extend_from_slice(b"MM\0\x2a");
+        tiff.extend_from_slice(&8u32.to_be_bytes());
+        // IFD0 at 8 with the Exif pointer
+        tiff.extend_from_slice(&1u16.to_be_bytes());
+        tiff.extend_from_slice(&EXIF_IFD_POINTER.to_be_bytes());
+        tiff.extend_from_slice(&4u16.to_be_bytes());
+        tiff.extend_from_slice(&1u32.to_be_bytes());
+        tiff.extend_from_slice(&26u32.to_be_bytes());
+        tiff.extend_from_slice(&0u32.to_be_bytes());
+        // Exif IFD at 26
+        tiff.extend_from_slice(&1u16.to_be_bytes());
+        tiff.extend_from_slice(&DATE_TIME_ORIGINAL.to_be_bytes());
+        tiff.extend_from_slice(&2u16.to_be_bytes());
+        tiff.extend_from_slice(&20u32.to_be_bytes());
+        tiff.extend_from_slice(&44u32.to_be_bytes());
+        tiff.extend_from_slice(&0u32.to_be_bytes());
+        // value at 44
+        tiff.extend_from_slice(time.as_bytes());
+        tiff.push(0);
+
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE1];
+        jpeg.extend_from_slice(&((tiff.len() + 8) as u16).to_be_bytes());
+        jpeg.extend_from_slice(b"Exif\0\0");
+        jpeg.extend_from_slice(&tiff);
+        jpeg.extend_from_slice(&[0xFF, 0xD9]);
+        jpeg
+    }
+
+    #[test]
+    fn test_rewrite_date_time_original() {
+        let mut jpeg = jpeg_with_time("2023:05:01 10:00:00");
+        let len = jpeg.len();
+        let time = read_date_time_original(&jpeg).unwrap();
+        assert_eq!(time.to_string(), "2023-05-01 10:00:00");
+
+        let repair = TimestampRepair {
+            offset_seconds: 3600,
+            ..Default::default()
+        };
+        let fixed = corrected_time(&repair, "a.jpg", "/a.jpg", Ok(time)).unwrap();
+        write_date_time_original(&mut jpeg, &fixed).unwrap();
+        assert_eq!(jpeg.len(), len);
+        assert_eq!(
+            read_date_time_original(&jpeg).unwrap().to_string(),
+            "2023-05-01 11:00:00"
+        );
+
+        let mut repair = TimestampRepair::default();
+        repair
+            .corrections
+            .insert("a.jpg".to_string(), "2024-01-02 03:04:05".to_string());
+        let fixed = corrected_time(&repair, "a.jpg", "/a.jpg", Err(anyhow!("none"))).unwrap();
+        assert_eq!(fixed.to_string(), "2024-01-02 03:04:05");
+
+        assert!(read_date_time_original(&[0xFF, 0xD8, 0xFF, 0xD9]).is_err());
+    }
+}
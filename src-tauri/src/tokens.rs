@@ -0,0 +1,125 @@
+use serde::Serialize;
+
+/// Masks all but the ends of an access token so it can be written to results and logs.
+pub fn mask_token(token: &str) -> String {
+    let chars: Vec<char> = token.chars().collect();
+    if chars.len() <= 8 {
+        return "…".to_string();
+    }
+    let head: String = chars[..4].iter().collect();
+    let tail: String = chars[chars.len() - 4..].iter().collect();
+    format!("{}…{}", head, tail)
+}
+
+/// Frames answered while a token was in use.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenUsage {
+    /// The masked token.
+    pub token: String,
+    pub frames: usize,
+}
+
+/// Access tokens of a run, used one after another as their quotas run out.
+#[derive(Debug)]
+pub struct TokenPool {
+    tokens: Vec<String>,
+    frames: Vec<usize>,
+    current: usize,
+}
+
+impl TokenPool {
+    /// `primary` goes first, blank and repeated tokens are dropped.
+    pub fn new(primary: &str, extra: &[String]) -> Self {
+        let mut tokens: Vec<String> = Vec::new();
+        for token in std::iter::once(primary).chain(extra.iter().map(String::as_str)) {
+            let token = token.trim();
+            if !token.is_empty() && !tokens.iter().any(|t| t == token) {
+                tokens.push(token.to_string());
+            }
+        }
+        Self {
+            frames: vec![0; tokens.len()],
+            tokens,
+            current: 0,
+        }
+    }
+
+    pub fn current(&self) -> Option<&str> {
+        self.tokens.get(self.current).map(String::as_str)
+    }
+
+    /// Masked form of the current token.
+    pub fn label(&self) -> String {
+        self.current().map(mask_token).unwrap_or_default()
+    }
+
+    /// Tokens after the current one.
+    pub fn remaining(&self) -> &[String] {
+        self.tokens.get(self.current + 1..).unwrap_or_default()
+    }
+
+    /// Moves on to the next token, `None` once all of them are used up.
+    pub fn advance(&mut self) -> Option<String> {
+        if self.current < self.tokens.len() {
+            self.current += 1;
+        }
+        self.current().map(str::to_string)
+    }
+
+    /// Counts a frame answered with the current token.
+    pub fn record(&mut self) {
+        if let Some(frames) = self.frames.get_mut(self.current) {
+            *frames += 1;
+        }
+    }
+
+    pub fn usage(&self) -> Vec<TokenUsage> {
+        self.tokens
+            .iter()
+            .zip(&self.frames)
+            .map(|(token, frames)| TokenUsage {
+                token: mask_token(token),
+                frames: *frames,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_pool() {
+        let extra = vec![
+            " second-token-5678 ".to_string(),
+            "".to_string(),
+            "first-token-1234".to_string(),
+        ];
+        let mut pool = TokenPool::new("first-token-1234", &extra);
+        assert_eq!(pool.current(), Some("first-token-1234"));
+        assert_eq!(pool.label(), "firs…1234");
+        pool.record();
+        pool.record();
+        assert_eq!(pool.advance().as_deref(), Some("second-token-5678"));
+        pool.record();
+        assert_eq!(pool.advance(), None);
+        assert_eq!(pool.advance(), None);
+        pool.record();
+        assert_eq!(
+            pool.usage(),
+            vec![
+                TokenUsage {
+                    token: "firs…1234".to_string(),
+                    frames: 2,
+                },
+                TokenUsage {
+                    token: "seco…5678".to_string(),
+                    frames: 1,
+                },
+            ]
+        );
+        assert_eq!(mask_token("short"), "…");
+    }
+}
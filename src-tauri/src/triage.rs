@@ -0,0 +1,305 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::export::{Bbox, ExportFrame};
+use crate::report::has_error;
+use crate::utils::portable_path;
+
+const DEFAULT_PAGE_SIZE: usize = 100;
+
+/// Where a frame goes without being looked at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Band {
+    Accept,
+    Reject,
+    Review,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Thresholds {
+    /// Boxes at least this confident are accepted.
+    pub high: f32,
+    /// Boxes at most this confident are rejected.
+    pub low: f32,
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Self {
+            high: 0.8,
+            low: 0.2,
+        }
+    }
+}
+
+impl Thresholds {
+    pub fn band(&self, score: f32) -> Band {
+        if score >= self.high {
+            Band::Accept
+        } else if score <= self.low {
+            Band::Reject
+        } else {
+            Band::Review
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TriageOptions {
+    #[serde(flatten)]
+    pub thresholds: Thresholds,
+    /// Thresholds of single classes, by detector class or review label.
+    pub classes: BTreeMap<String, Thresholds>,
+}
+
+impl TriageOptions {
+    pub fn thresholds(&self, bbox: &Bbox) -> Thresholds {
+        self.classes
+            .get(&bbox.class_name())
+            .copied()
+            .unwrap_or(self.thresholds)
+    }
+
+    /// A frame is reviewed as soon as one box is uncertain, accepted when a box is
+    /// confident and rejected otherwise. Verified frames are accepted as they are.
+    pub fn band(&self, frame: &ExportFrame) -> Band {
+        if frame.verified {
+            return Band::Accept;
+        }
+        let mut band = Band::Reject;
+        for bbox in frame.bboxes.iter().flatten() {
+            match self.thresholds(bbox).band(bbox.score) {
+                Band::Review => return Band::Review,
+                Band::Accept => band = Band::Accept,
+                Band::Reject => (),
+            }
+        }
+        band
+    }
+}
+
+/// Share of the review queue of one of `count` reviewers. Files are split by a hash of
+/// their path, so every frame of a file goes to the same reviewer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReviewPart {
+    pub index: usize,
+    pub count: usize,
+}
+
+impl ReviewPart {
+    pub fn contains(&self, file_path: &str) -> bool {
+        if self.count <= 1 {
+            return true;
+        }
+        let digest = Sha256::digest(file_path.as_bytes());
+        let hash = u64::from_le_bytes(digest[..8].try_into().unwrap());
+        hash % self.count as u64 == self.index as u64
+    }
+}
+
+/// A frame waiting for review, with the boxes that put it there.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReviewItem {
+    /// Path relative to the result's folder when inside it.
+    pub file_path: String,
+    pub frame_index: usize,
+    pub labels: Vec<String>,
+    /// Indices of the boxes between the thresholds.
+    pub uncertain: Vec<usize>,
+    pub max_score: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReviewQueue {
+    pub accepted: usize,
+    pub rejected: usize,
+    /// Frames in review, of which `items` is the requested page.
+    pub total: usize,
+    pub items: Vec<ReviewItem>,
+}
+
+/// Routes the frames of a result into bands, frames that failed are left out. The review
+/// queue is in path order, only holding the frames of `part` when given.
+pub fn review_queue(
+    frames: &[ExportFrame],
+    folder: &Path,
+    options: &TriageOptions,
+    part: Option<&ReviewPart>,
+    offset: usize,
+    limit: Option<usize>,
+) -> ReviewQueue {
+    let mut queue = ReviewQueue {
+        accepted: 0,
+        rejected: 0,
+        total: 0,
+        items: Vec::new(),
+    };
+    let mut review = Vec::new();
+    for frame in frames.iter().filter(|f| !has_error(f)) {
+        match options.band(frame) {
+            Band::Accept => queue.accepted += 1,
+            Band::Reject => queue.rejected += 1,
+            Band::Review => {
+                let file_path = portable_path(&frame.file.file_path, Some(folder));
+                if part.is_none_or(|part| part.contains(&file_path)) {
+                    review.push(frame);
+                }
+            }
+        }
+    }
+    review.sort_by_key(|f| (&f.file.file_path, f.frame_index));
+    queue.total = review.len();
+    queue.items = review
+        .into_iter()
+        .skip(offset)
+        .take(limit.unwrap_or(DEFAULT_PAGE_SIZE))
+        .map(|frame| {
+            let bboxes = frame.bboxes.as_deref().unwrap_or_default();
+            ReviewItem {
+                file_path: portable_path(&frame.file.file_path, Some(folder)),
+                frame_index: frame.frame_index,
+                labels: frame.label.clone().unwrap_or_default(),
+                uncertain: bboxes
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, b)| options.thresholds(b).band(b.score) == Band::Review)
+                    .map(|(i, _)| i)
+                    .collect(),
+                max_score: bboxes.iter().map(|b| b.score).fold(0.0, f32::max),
+            }
+        })
+        .collect();
+    queue
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::testing;
+
+    #[test]
+    fn test_review_queue() {
+        let mut options = TriageOptions::default();
+        options.classes.insert(
+            "Person".to_string(),
+            Thresholds {
+                high: 0.5,
+                low: 0.1,
+            },
+        );
+        let bbox = testing::bbox;
+        let mut frames: Vec<ExportFrame> = [
+            ("/run/d.jpg", vec![bbox(0, 0.9)]),
+            ("/run/c.jpg", vec![bbox(0, 0.1), bbox(0, 0.5)]),
+            ("/run/b.jpg", vec![bbox(0, 0.15)]),
+            ("/run/a.jpg", vec![bbox(1, 0.6)]),
+            ("/run/e.jpg", vec![]),
+            ("/run/f.jpg", vec![bbox(0, 0.9), bbox(2, 0.3)]),
+        ]
+        .into_iter()
+        .map(|(path, bboxes)| ExportFrame {
+            bboxes: Some(bboxes),
+            label: Some(vec!["Animal".to_string()]),
+            ..testing::frame(path)
+        })
+        .collect();
+        assert_eq!(options.band(&frames[3]), Band::Accept);
+        let uncertain = ExportFrame {
+            bboxes: Some(vec![bbox(0, 0.5)]),
+            ..testing::frame("/run/g.jpg")
+        };
+        let verified = ExportFrame {
+            verified: true,
+            ..uncertain.clone()
+        };
+        assert_eq!(options.band(&verified), Band::Accept);
+        // failed frames are in no band
+        frames.push(ExportFrame {
+            error: Some("Invalid data".to_string()),
+            ..uncertain
+        });
+
+        let queue = review_queue(&frames, Path::new("/run"), &options, None, 0, None);
+        assert_eq!((queue.accepted, queue.rejected, queue.total), (2, 2, 2));
+        assert_eq!(queue.items[0].file_path, "c.jpg");
+        assert_eq!(queue.items[0].uncertain, vec![1]);
+        assert_eq!(queue.items[1].file_path, "f.jpg");
+        assert_eq!(queue.items[1].max_score, 0.9);
+
+        let page = review_queue(&frames, Path::new("/run"), &options, None, 1, Some(5));
+        assert_eq!(page.total, 2);
+        assert_eq!(page.items.len(), 1);
+    }
+
+    #[test]
+    fn test_thresholds() {
+        let options: TriageOptions = serde_json::from_str(
+            r#"{"high": 0.7, "low": 0.3, "classes": {"Leopard": {"high": 0.95}}}"#,
+        )
+        .unwrap();
+        // the bounds belong to the outer bands
+        assert_eq!(options.thresholds.band(0.7), Band::Accept);
+        assert_eq!(options.thresholds.band(0.3), Band::Reject);
+        assert_eq!(options.thresholds.band(0.5), Band::Review);
+
+        // a review label picks the thresholds of its class, unset ones are the defaults
+        let leopard = Bbox {
+            label: Some("Leopard".to_string()),
+            ..testing::bbox(0, 0.9)
+        };
+        assert_eq!(
+            options.thresholds(&leopard),
+            Thresholds {
+                high: 0.95,
+                low: 0.2
+            }
+        );
+        let frame = ExportFrame {
+            bboxes: Some(vec![leopard]),
+            ..testing::frame("/run/a.jpg")
+        };
+        assert_eq!(options.band(&frame), Band::Review);
+        assert_eq!(options.band(&testing::frame("/run/b.jpg")), Band::Reject);
+    }
+
+    #[test]
+    fn test_review_parts() {
+        let options = TriageOptions::default();
+        // every frame of a file goes to the same reviewer
+        let frames: Vec<ExportFrame> = ["a", "b", "c", "d", "e", "f"]
+            .iter()
+            .flat_map(|name| {
+                (0..2).map(move |frame_index| ExportFrame {
+                    frame_index,
+                    total_frames: 2,
+                    bboxes: Some(vec![testing::bbox(0, 0.5)]),
+                    ..testing::frame(format!("/run/{}.mp4", name))
+                })
+            })
+            .collect();
+        let parts = [0, 1].map(|index| ReviewPart { index, count: 2 });
+        let queues = parts
+            .map(|part| review_queue(&frames, Path::new("/run"), &options, Some(&part), 0, None));
+        assert_eq!(queues[0].total + queues[1].total, frames.len());
+        for queue in &queues {
+            for pair in queue.items.chunks(2) {
+                assert_eq!(pair[0].file_path, pair[1].file_path);
+                assert_eq!((pair[0].frame_index, pair[1].frame_index), (0, 1));
+            }
+        }
+        assert_ne!(parts[0].contains("c.mp4"), parts[1].contains("c.mp4"));
+        // a single reviewer, or none set, gets everything
+        let whole = ReviewPart { index: 0, count: 1 };
+        assert!(whole.contains("c.mp4"));
+        assert!(ReviewPart { index: 0, count: 0 }.contains("c.mp4"));
+    }
+}
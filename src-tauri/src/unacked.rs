@@ -0,0 +1,150 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+
+use anyhow::Result;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use serde::{Deserialize, Serialize};
+
+use crate::export::ExportFrame;
+use crate::md5rs::DetectRequest;
+
+/// Frames sent but never answered when a run ended, one per line, next to the result.
+pub const UNACKED_FILE: &str = "unacked.jsonl";
+
+/// A frame whose response never came, kept with its encoded image so a resumed run sends
+/// it again instead of decoding the whole file once more.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnackedFrame {
+    pub frame: ExportFrame,
+    /// Encoded image, base64.
+    pub image: String,
+    pub width: i32,
+    pub height: i32,
+    pub iou: f32,
+    pub score: f32,
+    pub iframe: bool,
+    pub codec: String,
+}
+
+impl UnackedFrame {
+    pub fn new(frame: ExportFrame, request: &DetectRequest) -> Self {
+        Self {
+            frame,
+            image: BASE64.encode(&request.image),
+            width: request.width,
+            height: request.height,
+            iou: request.iou,
+            score: request.score,
+            iframe: request.iframe,
+            codec: request.codec.clone(),
+        }
+    }
+
+    /// The request sending the frame again under `uuid`.
+    pub fn request(&self, uuid: String, embeddings: bool) -> Result<DetectRequest> {
+        Ok(DetectRequest {
+            uuid,
+            image: BASE64.decode(&self.image)?,
+            width: self.width,
+            height: self.height,
+            iou: self.iou,
+            score: self.score,
+            iframe: self.iframe,
+            embeddings,
+            codec: self.codec.clone(),
+            batch: Vec::new(),
+        })
+    }
+}
+
+/// Replaces the unacknowledged frames kept in `folder`, removing the file when there
+/// are none left.
+pub fn save_unacked(folder: &Path, frames: &[UnackedFrame]) -> Result<()> {
+    let path = folder.join(UNACKED_FILE);
+    if frames.is_empty() {
+        if path.exists() {
+            std::fs::remove_file(path)?;
+        }
+        return Ok(());
+    }
+    let mut file = File::create(path)?;
+    for frame in frames {
+        writeln!(file, "{}", serde_json::to_string(frame)?)?;
+    }
+    log::info!(
+        "Kept {} unacknowledged frames for the next run",
+        frames.len()
+    );
+    Ok(())
+}
+
+/// Unacknowledged frames a previous run left in `folder`. A line cut off by a crash is
+/// skipped, its file is decoded again.
+pub fn load_unacked(folder: &Path) -> Result<Vec<UnackedFrame>> {
+    let path = folder.join(UNACKED_FILE);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let mut frames = Vec::new();
+    for line in BufReader::new(File::open(path)?).lines() {
+        match serde_json::from_str(&line?) {
+            Ok(frame) => frames.push(frame),
+            Err(e) => log::warn!("Skipped an unacknowledged frame: {}", e),
+        }
+    }
+    Ok(frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::testing;
+
+    #[test]
+    fn test_unacked() {
+        let dir = testing::temp_dir();
+        let frame = ExportFrame {
+            frame_index: 2,
+            total_frames: 3,
+            bboxes: None,
+            iframe: true,
+            ..testing::frame(dir.join("a.mp4"))
+        };
+        let request = DetectRequest {
+            uuid: "old".to_string(),
+            image: vec![1, 2, 3],
+            width: 1280,
+            height: 720,
+            iframe: true,
+            codec: "jpeg".to_string(),
+            ..Default::default()
+        };
+        save_unacked(&dir, &[UnackedFrame::new(frame, &request)]).unwrap();
+        // a line cut off halfway is left out
+        let mut file = File::options()
+            .append(true)
+            .open(dir.join(UNACKED_FILE))
+            .unwrap();
+        write!(file, "{{\"frame\":").unwrap();
+
+        let unacked = load_unacked(&dir).unwrap();
+        assert_eq!(unacked.len(), 1);
+        assert_eq!(unacked[0].frame.file.file_path, dir.join("a.mp4"));
+        assert_eq!(unacked[0].frame.frame_index, 2);
+        let resent = unacked[0].request("new".to_string(), false).unwrap();
+        assert_eq!(
+            resent,
+            DetectRequest {
+                uuid: "new".to_string(),
+                ..request
+            }
+        );
+
+        save_unacked(&dir, &[]).unwrap();
+        assert!(!dir.join(UNACKED_FILE).exists());
+        assert!(load_unacked(&dir).unwrap().is_empty());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
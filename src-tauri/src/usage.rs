@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::{bounded, RecvTimeoutError, Sender};
+use serde::Serialize;
+use sysinfo::{Pid, ProcessRefreshKind, ProcessesToUpdate, System};
+
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Resources used by the app and the ffmpeg processes it started during a run.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceUsage {
+    /// Highest resident memory seen in one sample, in bytes.
+    pub peak_rss: u64,
+    /// CPU time summed over all cores, estimated from the usage between samples.
+    pub cpu_seconds: f64,
+    pub read_bytes: u64,
+    pub written_bytes: u64,
+    pub wall_seconds: f64,
+    pub samples: usize,
+}
+
+/// One process as seen in a sample, the disk counters are totals since it started.
+#[derive(Debug, Clone, Copy)]
+pub struct ProcessSample {
+    pub pid: u32,
+    pub memory: u64,
+    /// Percent of one core since the previous sample.
+    pub cpu_percent: f32,
+    pub read_bytes: u64,
+    pub written_bytes: u64,
+}
+
+/// Folds samples into a `ResourceUsage`. Disk totals are kept per process so the
+/// reads of an ffmpeg process that exited in the meantime still count.
+#[derive(Debug, Default)]
+pub struct UsageTotals {
+    usage: ResourceUsage,
+    disk: HashMap<u32, (u64, u64)>,
+}
+
+impl UsageTotals {
+    pub fn record(&mut self, samples: &[ProcessSample], elapsed: Duration) {
+        self.usage.samples += 1;
+        let rss: u64 = samples.iter().map(|s| s.memory).sum();
+        self.usage.peak_rss = self.usage.peak_rss.max(rss);
+        for sample in samples {
+            self.usage.cpu_seconds += sample.cpu_percent as f64 / 100.0 * elapsed.as_secs_f64();
+            let disk = self.disk.entry(sample.pid).or_default();
+            disk.0 = disk.0.max(sample.read_bytes);
+            disk.1 = disk.1.max(sample.written_bytes);
+        }
+    }
+
+    pub fn finish(mut self, wall: Duration) -> ResourceUsage {
+        self.usage.read_bytes = self.disk.values().map(|d| d.0).sum();
+        self.usage.written_bytes = self.disk.values().map(|d| d.1).sum();
+        self.usage.wall_seconds = wall.as_secs_f64();
+        self.usage
+    }
+}
+
+/// This process and its direct children.
+fn sample(system: &mut System, pid: Pid) -> Vec<ProcessSample> {
+    system.refresh_processes_specifics(
+        ProcessesToUpdate::All,
+        true,
+        ProcessRefreshKind::nothing()
+            .with_memory()
+            .with_cpu()
+            .with_disk_usage(),
+    );
+    system
+        .processes()
+        .values()
+        .filter(|p| p.pid() == pid || p.parent() == Some(pid))
+        .map(|p| {
+            let disk = p.disk_usage();
+            ProcessSample {
+                pid: p.pid().as_u32(),
+                memory: p.memory(),
+                cpu_percent: p.cpu_usage(),
+                read_bytes: disk.total_read_bytes,
+                written_bytes: disk.total_written_bytes,
+            }
+        })
+        .collect()
+}
+
+/// Samples the resource usage on its own thread until finished.
+pub struct UsageSampler {
+    stop: Sender<()>,
+    handle: JoinHandle<ResourceUsage>,
+}
+
+impl UsageSampler {
+    pub fn start(interval: Duration) -> Self {
+        let (stop, stop_r) = bounded(1);
+        let handle = thread::spawn(move || {
+            let start = Instant::now();
+            let mut totals = UsageTotals::default();
+            let Ok(pid) = sysinfo::get_current_pid() else {
+                log::warn!("Resource usage is not available on this platform");
+                return totals.finish(start.elapsed());
+            };
+            let mut system = System::new();
+            // the first refresh only sets the baseline for the CPU usage
+            sample(&mut system, pid);
+            let mut last = Instant::now();
+            loop {
+                match stop_r.recv_timeout(interval) {
+                    Err(RecvTimeoutError::Timeout) => (),
+                    _ => break,
+                }
+                let samples = sample(&mut system, pid);
+                totals.record(&samples, last.elapsed());
+                last = Instant::now();
+            }
+            let samples = sample(&mut system, pid);
+            totals.record(&samples, last.elapsed());
+            totals.finish(start.elapsed())
+        });
+        Self { stop, handle }
+    }
+
+    pub fn finish(self) -> ResourceUsage {
+        let _ = self.stop.send(());
+        self.handle.join().unwrap_or_else(|_| {
+            log::error!("Resource usage sampler panicked");
+            ResourceUsage::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_usage_totals() {
+        let app = |memory, read_bytes| ProcessSample {
+            pid: 1,
+            memory,
+            cpu_percent: 200.0,
+            read_bytes,
+            written_bytes: 10,
+        };
+        let ffmpeg = ProcessSample {
+            pid: 2,
+            memory: 50,
+            cpu_percent: 100.0,
+            read_bytes: 500,
+            written_bytes: 0,
+        };
+        let mut totals = UsageTotals::default();
+        totals.record(&[app(100, 1000), ffmpeg], Duration::from_secs(1));
+        // ffmpeg exited, its reads still count
+        totals.record(&[app(120, 3000)], Duration::from_secs(2));
+        let usage = totals.finish(Duration::from_secs(3));
+        assert_eq!(usage.peak_rss, 150);
+        assert_eq!(usage.cpu_seconds, 7.0);
+        assert_eq!(usage.read_bytes, 3500);
+        assert_eq!(usage.written_bytes, 10);
+        assert_eq!(usage.samples, 2);
+    }
+}
@@ -1,41 +1,46 @@
+use std::collections::HashMap;
 use std::collections::HashSet;
-use std::io::Write;
-use std::net::TcpStream;
+use std::hash::{Hash, Hasher};
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 use anyhow::Result;
-use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
-use rustls::ClientConfig;
-use rustls::RootCertStore;
-use rustls_native_certs::load_native_certs;
-use rustls_pki_types::{CertificateDer, ServerName};
-use serde::{Deserialize, Serialize};
-use url::Url;
-use walkdir::{DirEntry, WalkDir};
-
-pub fn sample_evenly<T: Clone>(list: &[T], sample_size: usize) -> Vec<T> {
-    let len = list.len();
-    if sample_size == 0 || len == 0 {
-        return Vec::new();
-    }
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use jwalk::{DirEntry, Parallelism, WalkDir};
+use megascops_media::MediaKind;
 
-    let step = len as f64 / sample_size as f64;
-    let mut sampled_elements = Vec::with_capacity(sample_size);
-    for i in 0..sample_size {
-        let index = (i as f64 * step).floor() as usize;
-        sampled_elements.push(list[index].clone());
-    }
-    sampled_elements
-}
+use crate::policy::{load_policy_or_warn, FolderPolicy};
+use serde::{Deserialize, Serialize};
+use unicode_normalization::UnicodeNormalization;
 
-#[derive(Debug, Clone, Serialize, PartialEq, Hash)]
+#[derive(Debug, Clone, Serialize)]
 pub struct FileItem {
     pub folder_id: usize,
     pub file_id: usize,
     pub file_path: PathBuf,
     #[serde(skip_serializing)]
     pub tmp_path: PathBuf,
+    /// Merged `megascops.toml` overrides of the folders containing this file.
+    #[serde(skip)]
+    pub policy: Option<Arc<FolderPolicy>>,
+}
+
+impl PartialEq for FileItem {
+    fn eq(&self, other: &Self) -> bool {
+        self.folder_id == other.folder_id
+            && self.file_id == other.file_id
+            && self.file_path == other.file_path
+            && self.tmp_path == other.tmp_path
+    }
+}
+
+impl Hash for FileItem {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.folder_id.hash(state);
+        self.file_id.hash(state);
+        self.file_path.hash(state);
+        self.tmp_path.hash(state);
+    }
 }
 
 impl<'de> Deserialize<'de> for FileItem {
@@ -52,16 +57,17 @@ impl<'de> Deserialize<'de> for FileItem {
             #[serde(default)]
             tmp_path: Option<PathBuf>,
         }
-        
+
         // 反序列化到临时结构
         let temp = FileItemTemp::deserialize(deserializer)?;
-        
+
         // 构建完整的 FileItem，设置 tmp_path 等于 file_path
         Ok(FileItem {
             folder_id: temp.folder_id,
             file_id: temp.file_id,
             file_path: temp.file_path.clone(),
             tmp_path: temp.tmp_path.unwrap_or_else(|| temp.file_path.clone()),
+            policy: None,
         })
     }
 }
@@ -81,134 +87,484 @@ impl FileItem {
                 file_id,
                 file_path,
                 tmp_path: tmp_path,
+                policy: None,
             },
             None => Self {
                 folder_id,
                 file_id,
                 file_path: file_path.clone(),
                 tmp_path: file_path,
+                policy: None,
             },
         }
     }
 }
 
-fn is_skip(entry: &DirEntry) -> bool {
-    let skip_dirs = ["Animal", "Person", "Vehicle", "Blank"];
-    entry
-        .file_name()
-        .to_str()
-        .map(|s| {
-            skip_dirs.contains(&s) || s.starts_with('.') || s == "result.csv" || s == "result.json"
-        })
-        .unwrap_or(false)
+const SYSTEM_NAMES: [&str; 9] = [
+    "Thumbs.db",
+    "desktop.ini",
+    "$RECYCLE.BIN",
+    "RECYCLER",
+    "System Volume Information",
+    ".Trashes",
+    ".Spotlight-V100",
+    ".fseventsd",
+    ".DS_Store",
+];
+
+fn is_hidden_or_system(entry: &DirEntry<((), ())>) -> bool {
+    let name = entry.file_name().to_string_lossy();
+    // `._*` AppleDouble forks and `.Trash-1000` style folders are covered by the dot prefix
+    if name.starts_with('.') || SYSTEM_NAMES.iter().any(|s| s.eq_ignore_ascii_case(&name)) {
+        return true;
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::MetadataExt;
+        const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+        const FILE_ATTRIBUTE_SYSTEM: u32 = 0x4;
+        if let Ok(metadata) = entry.metadata() {
+            return metadata.file_attributes() & (FILE_ATTRIBUTE_HIDDEN | FILE_ATTRIBUTE_SYSTEM)
+                != 0;
+        }
+    }
+    false
+}
+
+/// Whether a file or folder of this name is never indexed.
+pub(crate) fn is_skipped_name(name: &str) -> bool {
+    // organize targets and generated artifacts are never media to process
+    let skip_dirs = [
+        "Animal",
+        "Person",
+        "Vehicle",
+        "Blank",
+        crate::organize::EMPTY_LABEL,
+        crate::overlay::OVERLAY_DIR,
+        crate::contact_sheet::CONTACT_SHEET_DIR,
+        crate::annotation::ANNOTATION_DIR,
+        crate::yolo::YOLO_DIR,
+        crate::annotate::ANNOTATED_DIR,
+        crate::chips::CROPS_DIR,
+        crate::shrink::SHRUNK_DIR,
+    ];
+    skip_dirs.contains(&name) || crate::export::is_result_file_name(name)
+}
+
+fn is_skip(entry: &DirEntry<((), ())>, options: &IndexOptions) -> bool {
+    if entry.depth > 0 && options.skip_hidden && is_hidden_or_system(entry) {
+        return true;
+    }
+    entry.file_name().to_str().is_some_and(is_skipped_name)
+}
+
+#[derive(Debug, Clone)]
+pub struct IndexOptions {
+    /// Follow symlinks and junctions instead of skipping them.
+    pub follow_links: bool,
+    /// Skip hidden files, AppleDouble forks, `Thumbs.db` and recycle bins.
+    pub skip_hidden: bool,
+    /// Only walk these files and folders below the root, everything when empty.
+    pub include: Vec<PathBuf>,
 }
 
-pub fn index_files_and_folders(folder_path: &PathBuf) -> Result<HashSet<FileItem>> {
+impl Default for IndexOptions {
+    fn default() -> Self {
+        Self {
+            follow_links: false,
+            skip_hidden: true,
+            include: Vec::new(),
+        }
+    }
+}
+
+/// Whether `path` is one of the included paths, inside one, or a folder leading to one.
+fn is_included(path: &Path, is_dir: bool, include: &[PathBuf]) -> bool {
+    include.is_empty()
+        || include
+            .iter()
+            .any(|p| path.starts_with(p) || (is_dir && p.starts_with(path)))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum LinkSkipReason {
+    NotFollowed,
+    Cycle,
+    Broken,
+    Duplicate,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SkippedLink {
+    pub path: PathBuf,
+    pub reason: LinkSkipReason,
+}
+
+#[derive(Debug, Default)]
+pub struct FileIndex {
+    pub files: HashSet<FileItem>,
+    pub skipped_links: Vec<SkippedLink>,
+}
+
+/// Gitignore-style exclude list read from the root of the selected folder.
+pub const IGNORE_FILE: &str = ".megascopsignore";
+
+fn load_ignore(folder_path: &Path) -> Result<Option<Gitignore>> {
+    let path = folder_path.join(IGNORE_FILE);
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let mut builder = GitignoreBuilder::new(folder_path);
+    if let Some(e) = builder.add(&path) {
+        // invalid lines are reported but the valid ones still apply
+        log::warn!("Problem in {}: {}", path.display(), e);
+    }
+    let ignore = builder.build()?;
+    log::info!(
+        "Loaded {} patterns from {}",
+        ignore.num_ignores(),
+        path.display()
+    );
+    Ok(Some(ignore))
+}
+
+/// Number of indexed files between two progress reports.
+pub const INDEX_PROGRESS_BATCH: usize = 1000;
+
+/// Walks `folder_path` on a dedicated thread pool and hands every media file to `on_file`
+/// as soon as it is discovered, returning the links that were skipped on the way.
+pub fn walk_files<F>(
+    folder_path: &PathBuf,
+    options: &IndexOptions,
+    mut on_file: F,
+) -> Result<Vec<SkippedLink>>
+where
+    F: FnMut(FileItem),
+{
     let mut folder_id: usize = 0;
     let mut file_id: usize = 0;
-    let mut file_paths = HashSet::new();
+    let mut skipped_links = Vec::new();
+    // canonical paths already indexed, so a file reachable through several links is only processed once
+    let mut seen = HashSet::new();
+    // merged folder policies of the directories walked so far
+    let mut policies: HashMap<PathBuf, Option<Arc<FolderPolicy>>> = HashMap::new();
+    // policies of the folders read while filtering their parent, so each file is parsed once
+    let read_policies: Arc<Mutex<HashMap<PathBuf, FolderPolicy>>> = Arc::default();
+    let mut root_policy = load_policy_or_warn(folder_path);
+    if root_policy.as_ref().is_some_and(FolderPolicy::is_skip) {
+        log::info!(
+            "Skipping {} as its folder policy says",
+            folder_path.display()
+        );
+        return Ok(skipped_links);
+    }
 
-    for entry in WalkDir::new(folder_path)
-        .sort_by_file_name()
-        .into_iter()
-        .filter_entry(|e| !is_skip(e))
-    {
-        let entry = entry?;
+    let filter_options = options.clone();
+    let filter_policies = Arc::clone(&read_policies);
+    let ignore = load_ignore(folder_path)?;
+    let walker = WalkDir::new(folder_path)
+        .follow_links(options.follow_links)
+        .skip_hidden(false)
+        .sort(true)
+        .parallelism(Parallelism::RayonNewPool(0))
+        .process_read_dir(move |_, _, _, children| {
+            children.retain(|entry| {
+                entry
+                    .as_ref()
+                    .map(|e| {
+                        !is_skip(e, &filter_options)
+                            && is_included(&e.path(), e.file_type.is_dir(), &filter_options.include)
+                            && !ignore.as_ref().is_some_and(|ignore| {
+                                ignore.matched(e.path(), e.file_type.is_dir()).is_ignore()
+                            })
+                            && !(e.file_type.is_dir()
+                                && is_skip_policy(&e.path(), &filter_policies))
+                    })
+                    .unwrap_or(true)
+            });
+        });
+
+    for entry in walker {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                let reason = if e.loop_ancestor().is_some() {
+                    LinkSkipReason::Cycle
+                } else if options.follow_links
+                    && e.io_error().map(|e| e.kind()) == Some(std::io::ErrorKind::NotFound)
+                {
+                    LinkSkipReason::Broken
+                } else {
+                    return Err(e.into());
+                };
+                if let Some(path) = e.path() {
+                    log::warn!("Skipped link {}: {:?}", path.display(), reason);
+                    skipped_links.push(SkippedLink {
+                        path: path.to_path_buf(),
+                        reason,
+                    });
+                }
+                continue;
+            }
+        };
+        let path = entry.path();
+        if entry.path_is_symlink() && !options.follow_links {
+            log::info!("Skipped link {}: not followed", path.display());
+            skipped_links.push(SkippedLink {
+                path,
+                reason: LinkSkipReason::NotFollowed,
+            });
+            continue;
+        }
+        let parent_policy = if entry.depth > 0 {
+            policies.get(entry.parent_path()).cloned().flatten()
+        } else {
+            None
+        };
         if entry.file_type().is_dir() {
             folder_id += 1;
+            let own = if entry.depth == 0 {
+                root_policy.take()
+            } else {
+                read_policies.lock().unwrap().remove(&path)
+            };
+            let policy = match (own, parent_policy) {
+                (Some(own), Some(parent)) => Some(Arc::new(parent.merge(&own))),
+                (Some(own), None) => Some(Arc::new(own)),
+                (None, parent) => parent,
+            };
+            policies.insert(path, policy);
         } else if entry.file_type().is_file() {
-            if is_video_photo(entry.path()) {
-                file_paths.insert(FileItem::new(
-                    folder_id,
-                    file_id,
-                    entry.path().to_path_buf(),
-                    None,
-                ));
+            if is_video_photo(&path) {
+                if options.follow_links {
+                    let canonical = std::fs::canonicalize(&path)?;
+                    if !seen.insert(canonical) {
+                        skipped_links.push(SkippedLink {
+                            path,
+                            reason: LinkSkipReason::Duplicate,
+                        });
+                        continue;
+                    }
+                }
+                let mut file = FileItem::new(folder_id, file_id, path, None);
+                file.policy = parent_policy;
+                on_file(file);
                 file_id += 1;
             }
         }
     }
 
-    Ok(file_paths)
+    Ok(skipped_links)
+}
+
+/// Reads the policy of the folder `dir` into `policies`, returns whether it skips the
+/// folder.
+fn is_skip_policy(dir: &Path, policies: &Mutex<HashMap<PathBuf, FolderPolicy>>) -> bool {
+    let Some(policy) = load_policy_or_warn(dir) else {
+        return false;
+    };
+    if policy.is_skip() {
+        return true;
+    }
+    policies.lock().unwrap().insert(dir.to_path_buf(), policy);
+    false
 }
 
-fn is_video_photo(path: &Path) -> bool {
-    if let Some(extension) = path.extension() {
-        match extension.to_str().unwrap().to_lowercase().as_str() {
-            "mp4" | "avi" | "mkv" | "mov" => true,
-            "jpg" | "jpeg" | "png" => true,
-            _ => false,
+/// Collects the whole index up front, calling `on_progress` with the number of media files
+/// found so far after every batch.
+pub fn index_files_and_folders<F>(
+    folder_path: &PathBuf,
+    options: &IndexOptions,
+    on_progress: F,
+) -> Result<FileIndex>
+where
+    F: Fn(usize),
+{
+    let mut files = HashSet::new();
+    let skipped_links = walk_files(folder_path, options, |file| {
+        files.insert(file);
+        if files.len() % INDEX_PROGRESS_BATCH == 0 {
+            on_progress(files.len());
         }
+    })?;
+    on_progress(files.len());
+
+    Ok(FileIndex {
+        files,
+        skipped_links,
+    })
+}
+
+/// Indexing state reported while the walk runs alongside the pipeline.
+pub enum IndexProgress {
+    Found(usize),
+    Finished {
+        total: usize,
+        skipped_links: Vec<SkippedLink>,
+    },
+    Failed(String),
+}
+
+/// Render a path the same way on every platform: relative to `root` when given,
+/// without the Windows verbatim prefix, with `/` separators and in Unicode NFC.
+pub fn portable_path(path: &Path, root: Option<&Path>) -> String {
+    let path = root
+        .and_then(|root| path.strip_prefix(root).ok())
+        .unwrap_or(path)
+        .to_string_lossy();
+    let path = if let Some(unc) = path.strip_prefix(r"\\?\UNC\") {
+        format!(r"\\{}", unc)
     } else {
-        false
-    }
+        path.strip_prefix(r"\\?\").unwrap_or(&path).to_string()
+    };
+    path.replace('\\', "/").nfc().collect()
 }
 
-pub fn get_tls_certificate(url_str: &str) -> Result<String> {
-    let _ = rustls::crypto::ring::default_provider().install_default();
-    // Parse the URL to extract domain
-    let url = Url::parse(url_str)?;
-    let domain_str = url
-        .host_str()
-        .ok_or_else(|| anyhow::anyhow!("No host in URL"))?;
-    let domain = domain_str.to_string();
-    let port = url.port().unwrap_or(443);
+pub(crate) fn is_video_photo(path: &Path) -> bool {
+    MediaKind::of(path).is_some()
+}
+
+pub fn is_video(path: &Path) -> bool {
+    MediaKind::of(path) == Some(MediaKind::Video)
+}
 
-    let server_name = ServerName::try_from(domain.clone())?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::testing;
 
-    // Load root certificates from the system
-    let mut root_store = RootCertStore::empty();
-    let native_certs = load_native_certs();
-    for cert in native_certs.certs {
-        root_store.add(cert)?;
+    #[cfg(unix)]
+    #[test]
+    fn test_index_skips_link_cycle() {
+        let root = testing::temp_dir();
+        let sub = root.join("sub");
+        std::fs::create_dir_all(&sub).unwrap();
+        std::fs::write(sub.join("a.jpg"), b"").unwrap();
+        std::os::unix::fs::symlink(&root, sub.join("loop")).unwrap();
+
+        let options = IndexOptions {
+            follow_links: true,
+            ..Default::default()
+        };
+        let index = index_files_and_folders(&root, &options, |_| ()).unwrap();
+        assert_eq!(index.files.len(), 1);
+        assert_eq!(index.skipped_links[0].reason, LinkSkipReason::Cycle);
+
+        let index = index_files_and_folders(&root, &IndexOptions::default(), |_| ()).unwrap();
+        assert_eq!(index.files.len(), 1);
+        assert_eq!(index.skipped_links[0].reason, LinkSkipReason::NotFollowed);
+
+        std::fs::remove_dir_all(&root).unwrap();
     }
 
-    let config = ClientConfig::builder()
-        .with_root_certificates(root_store)
-        .with_no_client_auth();
+    #[test]
+    fn test_index_skips_system_files() {
+        let root = testing::temp_dir();
+        std::fs::create_dir_all(root.join("$RECYCLE.BIN")).unwrap();
+        std::fs::write(root.join("$RECYCLE.BIN").join("b.jpg"), b"").unwrap();
+        std::fs::write(root.join("._a.jpg"), b"").unwrap();
+        std::fs::write(root.join("a.jpg"), b"").unwrap();
 
-    let mut client = rustls::ClientConnection::new(Arc::new(config), server_name)?;
+        let index = index_files_and_folders(&root, &IndexOptions::default(), |_| ()).unwrap();
+        assert_eq!(index.files.len(), 1);
 
-    let mut stream = TcpStream::connect(format!("{}:{}", domain, port))?;
-    let mut tls_stream = rustls::Stream::new(&mut client, &mut stream);
+        let options = IndexOptions {
+            skip_hidden: false,
+            ..Default::default()
+        };
+        let index = index_files_and_folders(&root, &options, |_| ()).unwrap();
+        assert_eq!(index.files.len(), 3);
 
-    // Write HTTP request
-    tls_stream.write_all(
-        format!(
-            "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
-            url.path(),
-            domain
-        )
-        .as_bytes(),
-    )?;
-    let mut pem = String::new();
-    // Get certificate information
-    if let Some(certs) = tls_stream.conn.peer_certificates() {
-        for cert in certs.iter() {
-            let pem_content = cert_to_pem(cert)?;
-            pem.push_str(&pem_content);
-        }
-    } else {
-        log::error!("No certificate found for {}", domain);
+        std::fs::remove_dir_all(&root).unwrap();
     }
 
-    Ok(pem)
-}
+    #[test]
+    fn test_index_applies_folder_policy() {
+        let root = testing::temp_dir();
+        let site = root.join("site1").join("day1");
+        let skipped = root.join("calibration");
+        std::fs::create_dir_all(&site).unwrap();
+        std::fs::create_dir_all(&skipped).unwrap();
+        std::fs::write(
+            root.join("site1").join("megascops.toml"),
+            "max_frames = 5\n",
+        )
+        .unwrap();
+        std::fs::write(site.join("megascops.toml"), "iou_threshold = 0.3\n").unwrap();
+        std::fs::write(site.join("a.mp4"), b"").unwrap();
+        std::fs::write(skipped.join("megascops.toml"), "skip = true\n").unwrap();
+        std::fs::write(skipped.join("b.jpg"), b"").unwrap();
+        std::fs::write(root.join("c.jpg"), b"").unwrap();
 
-fn cert_to_pem(cert: &CertificateDer<'_>) -> Result<String> {
-    // Convert the certificate data to base64
-    let b64_data = BASE64.encode(cert.as_ref());
+        let index = index_files_and_folders(&root, &IndexOptions::default(), |_| ()).unwrap();
+        assert_eq!(index.files.len(), 2);
+        let video = index
+            .files
+            .iter()
+            .find(|f| f.file_path.ends_with("a.mp4"))
+            .unwrap();
+        let policy = video.policy.as_ref().unwrap();
+        assert_eq!(policy.max_frames, Some(5));
+        assert_eq!(policy.iou_threshold, Some(0.3));
+        let image = index
+            .files
+            .iter()
+            .find(|f| f.file_path.ends_with("c.jpg"))
+            .unwrap();
+        assert!(image.policy.is_none());
 
-    // Format with PEM headers and line wrapping (64 characters per line)
-    let mut pem_content = String::from("-----BEGIN CERTIFICATE-----\n");
+        // the selected folder's own policy applies too
+        std::fs::write(root.join("megascops.toml"), "confidence_threshold = 0.4\n").unwrap();
+        let index = index_files_and_folders(&root, &IndexOptions::default(), |_| ()).unwrap();
+        assert!(index
+            .files
+            .iter()
+            .all(|f| f.policy.as_ref().unwrap().confidence_threshold == Some(0.4)));
+        std::fs::write(root.join("megascops.toml"), "skip = true\n").unwrap();
+        let index = index_files_and_folders(&root, &IndexOptions::default(), |_| ()).unwrap();
+        assert!(index.files.is_empty());
 
-    // Add base64 data with line breaks every 64 characters
-    for chunk in b64_data.as_bytes().chunks(64) {
-        pem_content.push_str(&String::from_utf8_lossy(chunk));
-        pem_content.push('\n');
+        std::fs::remove_dir_all(&root).unwrap();
     }
 
-    pem_content.push_str("-----END CERTIFICATE-----\n");
+    #[test]
+    fn test_index_honors_ignore_file() {
+        let root = testing::temp_dir();
+        std::fs::create_dir_all(root.join("calibration")).unwrap();
+        std::fs::create_dir_all(root.join("site1")).unwrap();
+        std::fs::write(root.join("calibration").join("a.jpg"), b"").unwrap();
+        std::fs::write(root.join("site1").join("b.jpg"), b"").unwrap();
+        std::fs::write(root.join("site1").join("test_c.jpg"), b"").unwrap();
+        std::fs::write(root.join(IGNORE_FILE), "calibration/\ntest_*\n").unwrap();
+
+        let index = index_files_and_folders(&root, &IndexOptions::default(), |_| ()).unwrap();
+        assert_eq!(index.files.len(), 1);
+        assert!(index.files.iter().all(|f| f.file_path.ends_with("b.jpg")));
 
-    Ok(pem_content)
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_portable_path() {
+        let root = Path::new(r"\\?\C:\traps");
+        let file = Path::new(r"\\?\C:\traps\site1\IMG_0001.JPG");
+        assert_eq!(portable_path(file, None), "C:/traps/site1/IMG_0001.JPG");
+        assert_eq!(
+            portable_path(Path::new(r"\\?\UNC\nas\traps\a.jpg"), None),
+            "//nas/traps/a.jpg"
+        );
+        assert_eq!(
+            portable_path(
+                Path::new("/data/cafe\u{301}/a.jpg"),
+                Some(Path::new("/data"))
+            ),
+            "caf\u{e9}/a.jpg"
+        );
+        // Path::strip_prefix only understands `\\` separators on Windows
+        if cfg!(windows) {
+            assert_eq!(portable_path(file, Some(root)), "site1/IMG_0001.JPG");
+        }
+    }
 }
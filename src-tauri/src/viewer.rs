@@ -0,0 +1,287 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeSet;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::contact_sheet::is_positive;
+use crate::export::ExportFrame;
+use crate::report::{by_file, has_error};
+use crate::utils::portable_path;
+
+/// Events for a viewer window are emitted to that window only, prefixed so they can't be
+/// mistaken for the events of a run.
+pub const VIEWER_EVENT_PREFIX: &str = "viewer:";
+pub const VIEWER_LABEL_PREFIX: &str = "viewer-";
+const DEFAULT_PAGE_SIZE: usize = 100;
+
+/// Window label of the viewer for `result`, the same result always opens the same window.
+pub fn viewer_label(result: &Path) -> String {
+    let mut hasher = DefaultHasher::new();
+    portable_path(result, None).hash(&mut hasher);
+    format!("{}{:016x}", VIEWER_LABEL_PREFIX, hasher.finish())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ResultStatus {
+    #[default]
+    All,
+    Positive,
+    Blank,
+    Error,
+}
+
+/// Filter and page of the files shown in a viewer.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ResultQuery {
+    pub status: ResultStatus,
+    /// Only files with a detection of this label.
+    pub label: Option<String>,
+    /// Only files with a detection at least this confident.
+    pub min_score: Option<f32>,
+    /// Case-insensitive part of the file path.
+    pub search: Option<String>,
+    pub offset: usize,
+    pub limit: usize,
+}
+
+impl Default for ResultQuery {
+    fn default() -> Self {
+        Self {
+            status: ResultStatus::All,
+            label: None,
+            min_score: None,
+            search: None,
+            offset: 0,
+            limit: DEFAULT_PAGE_SIZE,
+        }
+    }
+}
+
+/// One file of a result, its frames merged.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResultFile {
+    /// Path relative to the result's folder when inside it.
+    pub file_path: String,
+    pub frames: usize,
+    pub positive: bool,
+    pub labels: Vec<String>,
+    pub max_score: f32,
+    pub shoot_time: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResultPage {
+    /// Files matching the query, of which `files` is the requested page.
+    pub total: usize,
+    pub files: Vec<ResultFile>,
+}
+
+fn result_file(frames: &[&ExportFrame], folder: &Path) -> ResultFile {
+    let first = frames[0];
+    let positives: Vec<&ExportFrame> = frames.iter().copied().filter(|f| is_positive(f)).collect();
+    let labels: BTreeSet<&String> = positives
+        .iter()
+        .flat_map(|f| f.label.iter().flatten())
+        .collect();
+    ResultFile {
+        file_path: portable_path(&first.file.file_path, Some(folder)),
+        frames: frames.len(),
+        positive: !positives.is_empty(),
+        labels: labels.into_iter().cloned().collect(),
+        max_score: positives
+            .iter()
+            .flat_map(|f| f.bboxes.iter().flatten())
+            .map(|b| b.score)
+            .fold(0.0, f32::max),
+        shoot_time: first.shoot_time.clone().filter(|t| !t.is_empty()),
+        error: frames
+            .iter()
+            .find(|f| has_error(f))
+            .and_then(|f| f.error.clone()),
+    }
+}
+
+impl ResultQuery {
+    fn matches(&self, file: &ResultFile) -> bool {
+        let status = match self.status {
+            ResultStatus::All => true,
+            ResultStatus::Positive => file.positive,
+            ResultStatus::Blank => !file.positive && file.error.is_none(),
+            ResultStatus::Error => file.error.is_some(),
+        };
+        status
+            && self
+                .label
+                .as_ref()
+                .is_none_or(|label| file.labels.contains(label))
+            && self.min_score.is_none_or(|score| file.max_score >= score)
+            && self.search.as_ref().is_none_or(|search| {
+                file.file_path
+                    .to_lowercase()
+                    .contains(&search.to_lowercase())
+            })
+    }
+}
+
+/// Files of a result matching `query`, in path order.
+pub fn query_results(frames: &[ExportFrame], folder: &Path, query: &ResultQuery) -> ResultPage {
+    let files: Vec<ResultFile> = by_file(frames)
+        .values()
+        .map(|frames| result_file(frames, folder))
+        .filter(|file| query.matches(file))
+        .collect();
+    ResultPage {
+        total: files.len(),
+        files: files
+            .into_iter()
+            .skip(query.offset)
+            .take(query.limit)
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::{testing, BlankSkip};
+
+    fn paths(page: &ResultPage) -> Vec<&str> {
+        page.files.iter().map(|f| f.file_path.as_str()).collect()
+    }
+
+    #[test]
+    fn test_query_results() {
+        let video = testing::frame("/run/b/4.mp4");
+        let frames = [
+            ExportFrame {
+                bboxes: Some(vec![testing::bbox(0, 0.8)]),
+                label: Some(vec!["Animal".to_string()]),
+                ..testing::frame("/run/a/1.jpg")
+            },
+            testing::frame("/run/a/2.jpg"),
+            ExportFrame {
+                error: Some("Failed to decode".to_string()),
+                ..testing::frame("/run/b/3.jpg")
+            },
+            video.clone(),
+            ExportFrame {
+                frame_index: 1,
+                bboxes: Some(vec![testing::bbox(1, 0.6)]),
+                label: Some(vec!["Person".to_string()]),
+                ..video
+            },
+        ];
+        let folder = Path::new("/run");
+
+        let all = query_results(&frames, folder, &ResultQuery::default());
+        assert_eq!(all.total, 4);
+        assert_eq!(all.files[3].file_path, "b/4.mp4");
+        assert_eq!(all.files[3].frames, 2);
+        assert!(all.files[3].positive);
+        assert_eq!(all.files[3].labels, ["Person"]);
+
+        let positives = ResultQuery {
+            status: ResultStatus::Positive,
+            ..Default::default()
+        };
+        assert_eq!(query_results(&frames, folder, &positives).total, 2);
+        let errors = ResultQuery {
+            status: ResultStatus::Error,
+            ..Default::default()
+        };
+        assert_eq!(paths(&query_results(&frames, folder, &errors)), ["b/3.jpg"]);
+
+        let search = ResultQuery {
+            status: ResultStatus::Blank,
+            search: Some("A/".to_string()),
+            ..Default::default()
+        };
+        let page = query_results(&frames, folder, &search);
+        assert_eq!(page.total, 1);
+        assert_eq!(page.files[0].file_path, "a/2.jpg");
+
+        let paged = ResultQuery {
+            offset: 1,
+            limit: 2,
+            ..Default::default()
+        };
+        let page = query_results(&frames, folder, &paged);
+        assert_eq!(page.total, 4);
+        assert_eq!(paths(&page), ["a/2.jpg", "b/3.jpg"]);
+        let past = ResultQuery {
+            offset: 10,
+            ..Default::default()
+        };
+        let page = query_results(&frames, folder, &past);
+        assert_eq!(page.total, 4);
+        assert!(page.files.is_empty());
+    }
+
+    #[test]
+    fn test_query_filters() {
+        let frames = [
+            ExportFrame {
+                shoot_time: Some("2024-05-01 06:10:00 +08:00".to_string()),
+                bboxes: Some(vec![testing::bbox(0, 0.9)]),
+                label: Some(vec!["Animal".to_string()]),
+                ..testing::frame("/run/a.jpg")
+            },
+            // the blank filters skipped it, its box doesn't count
+            ExportFrame {
+                shoot_time: Some(String::new()),
+                bboxes: Some(vec![testing::bbox(0, 0.95)]),
+                label: Some(vec!["Animal".to_string()]),
+                skipped_blank: Some(BlankSkip::Background),
+                ..testing::frame("/run/b.jpg")
+            },
+            // an empty error is none, as csv exports read it back
+            ExportFrame {
+                error: Some(String::new()),
+                ..testing::frame("/elsewhere/c.jpg")
+            },
+        ];
+        let folder = Path::new("/run");
+        let all = query_results(&frames, folder, &ResultQuery::default());
+        assert_eq!(paths(&all), ["/elsewhere/c.jpg", "a.jpg", "b.jpg"]);
+        assert_eq!(all.files[0].error, None);
+        assert_eq!(all.files[2].max_score, 0.0);
+        assert!(!all.files[2].positive);
+        assert_eq!(all.files[2].shoot_time, None);
+        assert!(all.files[1].shoot_time.is_some());
+
+        let blank = ResultQuery {
+            status: ResultStatus::Blank,
+            ..Default::default()
+        };
+        assert_eq!(query_results(&frames, folder, &blank).total, 2);
+        let confident = ResultQuery {
+            label: Some("Animal".to_string()),
+            min_score: Some(0.9),
+            ..Default::default()
+        };
+        assert_eq!(
+            paths(&query_results(&frames, folder, &confident)),
+            ["a.jpg"]
+        );
+        let person = ResultQuery {
+            label: Some("Person".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(query_results(&frames, folder, &person).total, 0);
+    }
+
+    #[test]
+    fn test_viewer_label() {
+        let label = viewer_label(Path::new("/run/result.json"));
+        assert!(label.starts_with(VIEWER_LABEL_PREFIX));
+        assert_eq!(label, viewer_label(Path::new("/run/result.json")));
+        assert_ne!(label, viewer_label(Path::new("/run2/result.json")));
+    }
+}
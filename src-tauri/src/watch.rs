@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::mpsc;
+
+use crate::utils::{is_skipped_name, is_video_photo};
+
+/// How long a new file has to stay untouched before its copy is taken to be complete.
+/// Card readers write large videos in bursts with pauses in between.
+pub const SETTLE_TIME: Duration = Duration::from_secs(10);
+/// How often settled files are checked for.
+pub const WATCH_TICK: Duration = Duration::from_secs(1);
+
+/// Files reported by the watcher, held back until they stopped changing.
+#[derive(Debug, Default)]
+pub struct PendingFiles {
+    touched: HashMap<PathBuf, Instant>,
+}
+
+impl PendingFiles {
+    pub fn touch(&mut self, path: PathBuf, now: Instant) {
+        self.touched.insert(path, now);
+    }
+
+    pub fn len(&self) -> usize {
+        self.touched.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.touched.is_empty()
+    }
+
+    /// Takes the files untouched for `settle` out, sorted.
+    pub fn take_settled(&mut self, now: Instant, settle: Duration) -> Vec<PathBuf> {
+        let mut settled: Vec<PathBuf> = self
+            .touched
+            .iter()
+            .filter(|(_, touched)| now.duration_since(**touched) >= settle)
+            .map(|(path, _)| path.clone())
+            .collect();
+        for path in &settled {
+            self.touched.remove(path);
+        }
+        settled.sort();
+        settled
+    }
+}
+
+/// Whether `path` is a media file to detect on, not a hidden file or one the app
+/// generated itself below `root`.
+pub fn is_watched(path: &Path, root: &Path) -> bool {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    is_video_photo(path)
+        && !relative.components().any(|c| match c {
+            Component::Normal(name) => name
+                .to_str()
+                .is_some_and(|name| name.starts_with('.') || is_skipped_name(name)),
+            _ => false,
+        })
+}
+
+/// Watches `folder` recursively and sends the media files created or written below it
+/// until the returned watcher is dropped.
+pub fn watch(folder: &Path, sender: mpsc::UnboundedSender<PathBuf>) -> Result<RecommendedWatcher> {
+    let root = folder.to_path_buf();
+    let mut watcher =
+        notify::recommended_watcher(move |event: notify::Result<Event>| match event {
+            Ok(event) if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) => {
+                for path in event.paths.into_iter().filter(|p| is_watched(p, &root)) {
+                    let _ = sender.send(path);
+                }
+            }
+            Ok(_) => (),
+            Err(e) => log::warn!("Folder watch error: {}", e),
+        })?;
+    watcher.watch(folder, RecursiveMode::Recursive)?;
+    log::info!("Watching {}", folder.display());
+    Ok(watcher)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pending_files() {
+        let start = Instant::now();
+        let mut pending = PendingFiles::default();
+        pending.touch(PathBuf::from("b.jpg"), start);
+        pending.touch(PathBuf::from("a.mp4"), start);
+        pending.touch(PathBuf::from("c.jpg"), start + Duration::from_secs(5));
+        // the video is still being written
+        pending.touch(PathBuf::from("a.mp4"), start + Duration::from_secs(8));
+
+        let settled = pending.take_settled(start + Duration::from_secs(12), SETTLE_TIME);
+        assert_eq!(settled, [PathBuf::from("b.jpg")]);
+        assert_eq!(pending.len(), 2);
+        let settled = pending.take_settled(start + Duration::from_secs(20), SETTLE_TIME);
+        assert_eq!(settled, [PathBuf::from("a.mp4"), PathBuf::from("c.jpg")]);
+
+        let root = Path::new("/traps");
+        assert!(is_watched(Path::new("/traps/site/IMG_0001.JPG"), root));
+        assert!(!is_watched(Path::new("/traps/site/notes.txt"), root));
+        assert!(!is_watched(
+            Path::new("/traps/crops/Animal/a_0_0.90.jpg"),
+            root
+        ));
+        assert!(!is_watched(Path::new("/traps/site/._IMG_0001.JPG"), root));
+    }
+}
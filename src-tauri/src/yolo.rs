@@ -0,0 +1,321 @@
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::annotation::preview_name;
+use crate::contact_sheet::load_frame;
+use crate::embedding::{EmbeddingStore, ReviewLabels, EMBEDDING_DB};
+use crate::export::{load_export, Bbox, ExportFrame, CLASS_NAMES};
+use crate::report::has_error;
+use crate::utils::{is_video, portable_path};
+
+pub const YOLO_DIR: &str = "yolo";
+pub const YOLO_IMAGES: &str = "images.txt";
+pub const YOLO_DATA: &str = "data.yaml";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum YoloImages {
+    /// List the original images, only video frames are written out. Trainers that look
+    /// for labels next to the images need the `labels/` folder passed separately.
+    #[default]
+    List,
+    /// Copy every image next to its labels.
+    Copy,
+    /// Write a crop per box into a folder per class instead, for classifiers.
+    Crop,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct YoloOptions {
+    pub images: YoloImages,
+    /// Detections below this score are left out.
+    pub min_score: f32,
+    /// Only verified frames and boxes labeled in review, their labels become the classes.
+    pub verified_only: bool,
+    /// Keep frames without boxes as background images.
+    pub include_blanks: bool,
+}
+
+impl Default for YoloOptions {
+    fn default() -> Self {
+        Self {
+            images: YoloImages::List,
+            min_score: 0.5,
+            verified_only: false,
+            include_blanks: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct YoloSummary {
+    pub output: PathBuf,
+    pub images: usize,
+    pub boxes: usize,
+    pub failed: Vec<(String, String)>,
+}
+
+/// `class cx cy w h` with coordinates normalized to the image.
+pub fn label_line(class: usize, bbox: &Bbox, width: u32, height: u32) -> String {
+    let (x, y, w, h) = bbox.pixel_rect(width, height);
+    let (width, height) = (width as f32, height as f32);
+    format!(
+        "{} {:.6} {:.6} {:.6} {:.6}",
+        class,
+        (x as f32 + w as f32 / 2.0) / width,
+        (y as f32 + h as f32 / 2.0) / height,
+        w as f32 / width,
+        h as f32 / height
+    )
+}
+
+/// Boxes of `frame` to train on with their class index into `names`.
+fn frame_boxes<'a>(
+    frame: &'a ExportFrame,
+    reviewed: Option<Vec<(usize, String)>>,
+    names: &[String],
+    options: &YoloOptions,
+) -> Vec<(usize, &'a Bbox)> {
+    let bboxes = frame.bboxes.as_deref().unwrap_or_default();
+    if options.verified_only {
+        return reviewed
+            .into_iter()
+            .flatten()
+            .filter_map(|(index, label)| {
+                let class = names.iter().position(|n| *n == label)?;
+                Some((class, bboxes.get(index)?))
+            })
+            .collect();
+    }
+    bboxes
+        .iter()
+        .filter(|b| b.score >= options.min_score && b.class < CLASS_NAMES.len())
+        .map(|b| (b.class, b))
+        .collect()
+}
+
+/// Image to train on for `frame`, written into `images_dir` as `{stem}.*` unless the
+/// original can be listed.
+fn dataset_image(
+    path: &Path,
+    frame: &ExportFrame,
+    images_dir: &Path,
+    stem: &str,
+    mode: YoloImages,
+) -> Result<PathBuf> {
+    // only photos can be listed or copied as they are
+    if !is_video(path) {
+        match mode {
+            YoloImages::List => return Ok(path.to_path_buf()),
+            YoloImages::Copy => {
+                let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("jpg");
+                let target = images_dir.join(format!("{}.{}", stem, extension));
+                std::fs::copy(path, &target)?;
+                return Ok(target);
+            }
+            YoloImages::Crop => (),
+        }
+    }
+    let target = images_dir.join(format!("{}.jpg", stem));
+    load_frame(path, frame)?.to_rgb8().save(&target)?;
+    Ok(target)
+}
+
+/// Writes a crop per box into `crops_dir/{class}/`.
+fn write_crops(
+    path: &Path,
+    frame: &ExportFrame,
+    boxes: &[(usize, &Bbox)],
+    names: &[String],
+    crops_dir: &Path,
+    stem: &str,
+) -> Result<()> {
+    let img = load_frame(path, frame)?;
+    for (i, (class, bbox)) in boxes.iter().enumerate() {
+        let (x, y, w, h) = bbox.pixel_rect(img.width(), img.height());
+        if w == 0 || h == 0 {
+            continue;
+        }
+        let class_dir = crops_dir.join(&names[*class]);
+        std::fs::create_dir_all(&class_dir)?;
+        img.crop_imm(x, y, w, h)
+            .to_rgb8()
+            .save(class_dir.join(format!("{}_{}.jpg", stem, i)))?;
+    }
+    Ok(())
+}
+
+fn data_yaml(dir: &Path, names: &[String]) -> String {
+    let mut yaml = format!(
+        "path: {}\ntrain: {}\nval: {}\nnames:\n",
+        portable_path(dir, None),
+        YOLO_IMAGES,
+        YOLO_IMAGES
+    );
+    for (i, name) in names.iter().enumerate() {
+        let _ = writeln!(yaml, "  {}: {:?}", i, name);
+    }
+    yaml
+}
+
+/// Writes a YOLO dataset of the detections in a result file into `yolo/` next to it:
+/// `labels/*.txt`, `images.txt` and `data.yaml`, or `crops/{class}/` for crops.
+pub fn export_yolo(result: &Path, options: &YoloOptions) -> Result<YoloSummary> {
+    let folder = result
+        .parent()
+        .ok_or_else(|| anyhow!("Invalid result path: {}", result.display()))?;
+    let folder = std::fs::canonicalize(folder)?;
+    let frames = load_export(result)?;
+    let db = folder.join(EMBEDDING_DB);
+    let review_labels = if db.is_file() {
+        EmbeddingStore::open(&db)?.review_labels()?
+    } else {
+        ReviewLabels::new()
+    };
+    let verified: Vec<(usize, String)> = frames
+        .iter()
+        .filter_map(ExportFrame::verified_labels)
+        .flatten()
+        .collect();
+    if options.verified_only && review_labels.is_empty() && verified.is_empty() {
+        return Err(anyhow!("No reviewed boxes in {}", folder.display()));
+    }
+    let names: Vec<String> = if options.verified_only {
+        let names: BTreeSet<&String> = review_labels
+            .values()
+            .flatten()
+            .chain(&verified)
+            .map(|l| &l.1)
+            .collect();
+        names.into_iter().cloned().collect()
+    } else {
+        CLASS_NAMES.iter().map(|n| n.to_string()).collect()
+    };
+
+    let dir = folder.join(YOLO_DIR);
+    let subdirs: &[&str] = match options.images {
+        YoloImages::Crop => &["crops"],
+        _ => &["images", "labels"],
+    };
+    for subdir in subdirs {
+        std::fs::create_dir_all(dir.join(subdir))?;
+    }
+    let mut summary = YoloSummary {
+        output: dir.clone(),
+        ..Default::default()
+    };
+    let mut images = Vec::new();
+    for frame in frames.iter().filter(|f| !has_error(f)) {
+        let path = folder.join(&frame.file.file_path);
+        let key = portable_path(&path, None);
+        let reviewed = frame
+            .verified_labels()
+            .or_else(|| review_labels.get(&(key, frame.frame_index)).cloned());
+        if options.verified_only && reviewed.is_none() {
+            continue;
+        }
+        let boxes = frame_boxes(frame, reviewed, &names, options);
+        if boxes.is_empty() && (!options.include_blanks || options.images == YoloImages::Crop) {
+            continue;
+        }
+        let source = portable_path(&path, Some(&folder));
+        let name = preview_name(&source, frame.frame_index);
+        let stem = name.trim_end_matches(".jpg");
+        let written = match options.images {
+            YoloImages::Crop => write_crops(&path, frame, &boxes, &names, &dir.join("crops"), stem)
+                .map(|_| dir.join("crops")),
+            mode => dataset_image(&path, frame, &dir.join("images"), stem, mode),
+        };
+        let image = match written {
+            Ok(image) => image,
+            Err(e) => {
+                log::warn!("Skipping {} in YOLO export: {}", path.display(), e);
+                summary.failed.push((source, e.to_string()));
+                continue;
+            }
+        };
+        summary.boxes += boxes.len();
+        if options.images == YoloImages::Crop {
+            continue;
+        }
+        let (width, height) = megascops_media::image_dimensions(&image)?;
+        let lines: Vec<String> = boxes
+            .iter()
+            .map(|(class, bbox)| label_line(*class, bbox, width, height))
+            .collect();
+        let mut labels = lines.join("\n");
+        if !labels.is_empty() {
+            labels.push('\n');
+        }
+        std::fs::write(dir.join("labels").join(format!("{}.txt", stem)), labels)?;
+        images.push(portable_path(&image, None));
+        summary.images += 1;
+    }
+
+    if options.images != YoloImages::Crop {
+        std::fs::write(dir.join(YOLO_IMAGES), images.join("\n") + "\n")?;
+        std::fs::write(dir.join(YOLO_DATA), data_yaml(&dir, &names))?;
+    }
+    log::info!(
+        "Exported {} images with {} boxes to {}",
+        summary.images,
+        summary.boxes,
+        dir.display()
+    );
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::testing;
+
+    fn bbox(x1: f32, class: usize, score: f32) -> Bbox {
+        Bbox {
+            x1,
+            y1: 0.0,
+            x2: x1 + 0.5,
+            ..testing::bbox(class, score)
+        }
+    }
+
+    #[test]
+    fn test_yolo_labels() {
+        assert_eq!(
+            label_line(1, &bbox(0.25, 1, 0.9), 200, 100),
+            "1 0.500000 0.250000 0.500000 0.500000"
+        );
+
+        let frame = ExportFrame {
+            bboxes: Some(vec![bbox(0.0, 0, 0.9), bbox(0.5, 1, 0.3)]),
+            label: Some(vec!["Animal".to_string()]),
+            ..testing::frame("/run/a.jpg")
+        };
+        let detector: Vec<String> = CLASS_NAMES.iter().map(|n| n.to_string()).collect();
+        let options = YoloOptions::default();
+        let boxes = frame_boxes(&frame, None, &detector, &options);
+        assert_eq!(boxes.len(), 1);
+        assert_eq!(boxes[0].0, 0);
+
+        let names = vec!["Leopard".to_string(), "Tiger".to_string()];
+        let reviewed = vec![(1, "Tiger".to_string())];
+        let verified = YoloOptions {
+            verified_only: true,
+            ..options
+        };
+        let boxes = frame_boxes(&frame, Some(reviewed), &names, &verified);
+        assert_eq!(boxes.len(), 1);
+        assert_eq!(boxes[0].0, 1);
+        assert_eq!(boxes[0].1.x1, 0.5);
+
+        let yaml = data_yaml(Path::new("/run/yolo"), &names);
+        assert!(yaml.contains("train: images.txt\n"));
+        assert!(yaml.contains("  1: \"Tiger\"\n"));
+    }
+}
@@ -8,6 +8,7 @@ export const dialogConfig = $state({
 
 export const detectStatus = $state({
     progress: 0,
+    indexing: false,
     isProcessing: false,
     showConfig: false,
     configIconAnimating: false,
@@ -21,21 +22,37 @@ export const detectStatus = $state({
 export interface DetectOptions {
     selectedFolder: string;
     grpcUrl: string;
+    grpcUrls?: string[];
+    loadBalance?: boolean;
     accessToken: string;
     resumePath: string | null;
     guess: boolean;
+    proxy?: string | null;
+    clientCert?: string | null;
+    clientKey?: string | null;
 }
 
 interface ConfigOptions {
     confidenceThreshold: number;
     iouThreshold: number;
     quality: number;
-    exportFormat: "Json" | "Csv"; // 可以使用联合类型限制可选值
+    exportFormat: "Json" | "Csv" | "MegaDetector" | "Coco"; // 可以使用联合类型限制可选值
     bufferPath: string | null;
     bufferSize: number;
     checkPoint: number;
     maxFrames: number;
     iframeOnly: boolean;
+    exportCompression?: "None" | "Gzip" | "Zstd";
+    streamCompression?: "None" | "Gzip" | "Zstd";
+    exportFolder?: string | null;
+    exportFallback?: string | null;
+    sessionRefresh?: number;
+    stopAtQuota?: boolean;
+    retention?: {
+        maxAgeDays: number;
+        maxBytes: number;
+        kinds: ("Thumbnails" | "Spools" | "Histories" | "Logs")[];
+    };
 }
 
 // 定义主配置接口
@@ -9,7 +9,7 @@ import {
 import { open, confirm, ask } from "@tauri-apps/plugin-dialog";
 import { invoke } from "@tauri-apps/api/core";
 import { load } from "@tauri-apps/plugin-store";
-import { Command, open as openFile } from "@tauri-apps/plugin-shell";
+import { open as openFile } from "@tauri-apps/plugin-shell";
 import { exists, BaseDirectory } from "@tauri-apps/plugin-fs";
 import { unwrapFunctionStore, format } from "svelte-i18n";
 
@@ -111,10 +111,21 @@ export function formatQuota(quota?: number): string {
     return quota.toString();
 }
 
+function connectOptions() {
+    return {
+        proxy: config.detectOptions.proxy ?? null,
+        clientCert: config.detectOptions.clientCert ?? null,
+        clientKey: config.detectOptions.clientKey ?? null,
+    };
+}
+
 export async function checkHealth() {
     try {
         console.log(`{ grpcUrl: ${config.detectOptions.grpcUrl} }`);
-        await invoke("check_health", { grpcUrl: config.detectOptions.grpcUrl });
+        await invoke("check_health", {
+            grpcUrl: config.detectOptions.grpcUrl,
+            connect: connectOptions(),
+        });
     } catch (err) {
         console.error("Health check failed:", err);
     }
@@ -125,6 +136,7 @@ export async function checkQuota() {
         await invoke("check_quota", {
             grpcUrl: config.detectOptions.grpcUrl,
             token: config.detectOptions.accessToken,
+            connect: connectOptions(),
         });
         console.log("Quota checked");
     } catch (err) {
@@ -164,8 +176,8 @@ export async function startProcessing() {
         return;
     }
 
-    const resultFileName = `result.${config.configOptions.exportFormat === "Json" ? "json" : "csv"}`;
-    const resultFilePath = `${config.detectOptions.selectedFolder}/${resultFileName}`;
+    const resultFilePath = resultFile();
+    const resultFileName = resultFilePath.split("/").pop();
 
     let proceed = true;
     let useResume = false;
@@ -255,93 +267,52 @@ export async function startProcessing() {
     }
 }
 
+function resultFile() {
+    const compression = { None: "", Gzip: ".gz", Zstd: ".zst" }[
+        config.configOptions.exportCompression ?? "None"
+    ];
+    return `${config.detectOptions.selectedFolder}/result${
+        config.configOptions.exportFormat === "Csv" ? ".csv" : ".json"
+    }${compression}`;
+}
+
 export async function organize() {
-    let command;
-    const resultFile = `${config.detectOptions.selectedFolder}/result${
-        config.configOptions.exportFormat === "Json" ? ".json" : ".csv"
-    }`;
-    const logFile = `${config.detectOptions.selectedFolder}/organize.log`;
-    if (config.detectOptions.guess) {
-        command = Command.sidecar(
-            "binaries/organize",
-            [
-                "--result",
-                resultFile,
-                "--mode",
-                "guess",
-                "--log-level",
-                "INFO",
-                "--log-file",
-                logFile,
-            ],
-            { encoding: "utf8" }
-        );
-    } else {
-        command = Command.sidecar(
-            "binaries/organize",
-            [
-                "--result",
-                resultFile,
-                "--mode",
-                "default",
-                "--log-level",
-                "INFO",
-                "--log-file",
-                logFile,
-            ],
-            { encoding: "utf8" }
-        );
-    }
     detectStatus.isOrganizing = true;
-    const output = await command.execute();
-    if (output.code !== 0) {
-        detectStatus.isOrganizing = false;
+    try {
+        const summary: { manifest: string } = await invoke("organize_files", {
+            result: resultFile(),
+        });
         showDialog(
-            $format("dialog.title.Error"),
-            `${$format("dialog.message.organizeFailed")}${logFile}`
+            $format("dialog.title.Organize"),
+            `${$format("dialog.message.organizeComplete")}${summary.manifest}`
         );
-    } else {
-        detectStatus.isOrganizing = false;
+    } catch (err) {
         showDialog(
-            $format("dialog.title.Organize"),
-            `${$format("dialog.message.organizeComplete")}${logFile}`
+            $format("dialog.title.Error"),
+            `${$format("dialog.message.organizeFailed")}${err}`
         );
+    } finally {
+        detectStatus.isOrganizing = false;
     }
 }
 
 export async function undo() {
-    const resultFile = `${config.detectOptions.selectedFolder}/result${
-        config.configOptions.exportFormat === "Json" ? ".json" : ".csv"
-    }`;
-    const logFile = `${config.detectOptions.selectedFolder}/organize.log`;
-    const command = Command.sidecar(
-        "binaries/organize",
-        [
-            "--result",
-            resultFile,
-            "--mode",
-            "undo",
-            "--log-level",
-            "INFO",
-            "--log-file",
-            logFile,
-        ],
-        { encoding: "utf8" }
-    );
     detectStatus.isUndoOrganizing = true;
-    const output = await command.execute();
-    if (output.code !== 0) {
-        detectStatus.isUndoOrganizing = false;
+    try {
+        const summary: { manifest: string } = await invoke("undo_organize", {
+            result: resultFile(),
+        });
         showDialog(
-            $format("dialog.title.Error"),
-            `${$format("dialog.message.undoFailed")}${logFile}`
+            $format("dialog.title.Undo"),
+            `${$format("dialog.message.undoComplete")}${summary.manifest}`
         );
-    } else {
-        detectStatus.isUndoOrganizing = false;
+    } catch (err) {
         showDialog(
-            $format("dialog.title.Undo"),
-            `${$format("dialog.message.undoComplete")}${logFile}`
+            $format("dialog.title.Error"),
+            `${$format("dialog.message.undoFailed")}${err}`
         );
+    } finally {
+        detectStatus.isUndoOrganizing = false;
     }
 }
 